@@ -1,14 +1,12 @@
-
-use crate::{component::FMComponentScript};
+use crate::{compile::diagnostic::Diagnostic, component::FMComponentScript};
 use super::{lexer, parser};
 
 pub struct BurnScriptCompiler {}
 
 impl BurnScriptCompiler {
-    pub fn compile_burn_script(code: &str) -> Vec<FMComponentScript> {
+    /* Spans of the scripts and diagnostics are relative to the start of `code`. */
+    pub fn compile_burn_script(code: &str) -> Result<Vec<FMComponentScript>, Vec<Diagnostic>> {
         let tokens = lexer::Lexer::new(code.to_string()).get_tokens();
-        let scripts = parser::Parser::new(tokens).parse().expect("Unable to parse script block.");
-        return scripts;
-
+        parser::Parser::new(tokens).parse()
     }
 }
@@ -1,5 +1,15 @@
 
-use crate::burn_script::tokens::*;
+use crate::{burn_script::tokens::*, compile::diagnostic::LineIndex};
+
+/* Lines of a comment, trimmed. Lines of a block comment may start with
+ * a "*", which is left out. */
+fn comment_text(text: &str) -> String {
+    text.trim().lines()
+        .map(|l| l.trim())
+        .map(|l| l.strip_prefix("* ").or(l.strip_prefix('*')).unwrap_or(l))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Lexer {
@@ -14,7 +24,10 @@ impl Lexer {
     pub fn get_tokens(&self) -> Vec<Token> {
         let mut result = vec![];
         let mut buffer = String::new();
-        let mut lex_iter = self.code.chars().into_iter().peekable();
+        let mut lex_iter = self.code.chars().enumerate().peekable();
+        let lines = LineIndex::new(&self.code);
+        /* Where the word in the buffer started. */
+        let mut start = 0;
         let mut scope = 0;
 
         let flush_buffer = |b: &str| {
@@ -35,7 +48,7 @@ impl Lexer {
                 }
             }
         };
-        while let Some(c) = &lex_iter.next() {
+        while let Some((i, c)) = &lex_iter.next() {
             if buffer.is_empty() && c.is_whitespace() {
                 continue;
             }
@@ -48,7 +61,7 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
                     Some(ret)
                 },
@@ -59,12 +72,16 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
 
                     let mut stack = 0;
                     let mut in_string = false;
-                    while let Some(c) = &lex_iter.next() {
+                    let mut arg_start = *i + 1;
+                    while let Some((j, c)) = &lex_iter.next() {
+                        if buffer.is_empty() {
+                            arg_start = *j;
+                        }
                         match c {
                             ' ' => {
                                 if !in_string && buffer.is_empty() {
@@ -77,9 +94,16 @@ impl Lexer {
                                 in_string = !in_string;
                                 buffer.push(*c);
                             }
+                            /* An escaped quote doesn't end the string. */
+                            '\\' if in_string => {
+                                buffer.push(*c);
+                                if let Some((_, next)) = lex_iter.next() {
+                                    buffer.push(next);
+                                }
+                            }
                             ')' => {
                                 if stack == 0 {
-                                    ret.push(Token::with_value(TokenType::Argument, &buffer));
+                                    ret.push(Token::with_value(TokenType::Argument, &buffer).at(lines.span(arg_start, *j)));
                                     buffer.clear();
                                     break;
                                 } else {
@@ -93,7 +117,7 @@ impl Lexer {
                             }
                             ',' => {
                                 if stack == 0 {
-                                    ret.push(Token::with_value(TokenType::Argument, &buffer));
+                                    ret.push(Token::with_value(TokenType::Argument, &buffer).at(lines.span(arg_start, *j)));
                                     buffer.clear();
                                 } else {
                                     buffer.push(*c);
@@ -112,9 +136,9 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    ret.push(Token::new(TokenType::OpenBracket));
+                    ret.push(Token::new(TokenType::OpenBracket).at(lines.span(*i, *i + 1)));
                     scope += 1;
                     Some(ret)
                 },
@@ -124,9 +148,9 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    ret.push(Token::new(TokenType::CloseBracket));
+                    ret.push(Token::new(TokenType::CloseBracket).at(lines.span(*i, *i + 1)));
                     scope -= 1;
                     Some(ret)
                 },
@@ -138,9 +162,9 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    ret.push(Token::new(TokenType::Comma));
+                    ret.push(Token::new(TokenType::Comma).at(lines.span(*i, *i + 1)));
                     Some(ret)
                 },
                 ';' =>
@@ -149,9 +173,9 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    ret.push(Token::new(TokenType::SemiColon));
+                    ret.push(Token::new(TokenType::SemiColon).at(lines.span(*i, *i + 1)));
                     Some(ret)
                 }
                 '!' =>  
@@ -160,13 +184,13 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    if *lex_iter.peek().unwrap() != '=' {
-                        ret.push(Token::new(TokenType::Assign));
+                    if lex_iter.peek().map(|n| n.1) != Some('=') {
+                        ret.push(Token::new(TokenType::Assign).at(lines.span(*i, *i + 1)));
                     } else {
                         lex_iter.next();
-                        ret.push(Token::new(TokenType::Neq));
+                        ret.push(Token::new(TokenType::Neq).at(lines.span(*i, *i + 2)));
                     }
                     Some(ret)
                 }
@@ -176,13 +200,13 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    if *lex_iter.peek().unwrap() != '=' {
-                        ret.push(Token::new(TokenType::Assign));
+                    if lex_iter.peek().map(|n| n.1) != Some('=') {
+                        ret.push(Token::new(TokenType::Assign).at(lines.span(*i, *i + 1)));
                     } else {
                         lex_iter.next();
-                        ret.push(Token::new(TokenType::Eq));
+                        ret.push(Token::new(TokenType::Eq).at(lines.span(*i, *i + 2)));
                     }
                     Some(ret)
                 },
@@ -192,14 +216,13 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    if *lex_iter.peek().unwrap() == '=' {
+                    if lex_iter.peek().map(|n| n.1) == Some('=') {
                         lex_iter.next();
-                        ret.push(Token::new(TokenType::Geq));
+                        ret.push(Token::new(TokenType::Geq).at(lines.span(*i, *i + 2)));
                     } else {
-                        lex_iter.next();
-                        ret.push(Token::new(TokenType::Gt));
+                        ret.push(Token::new(TokenType::Gt).at(lines.span(*i, *i + 1)));
                     }
                     Some(ret)
                 }
@@ -209,14 +232,13 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    if *lex_iter.peek().unwrap() == '=' {
+                    if lex_iter.peek().map(|n| n.1) == Some('=') {
                         lex_iter.next();
-                        ret.push(Token::new(TokenType::Leq));
+                        ret.push(Token::new(TokenType::Leq).at(lines.span(*i, *i + 2)));
                     } else {
-                        lex_iter.next();
-                        ret.push(Token::new(TokenType::Lt));
+                        ret.push(Token::new(TokenType::Lt).at(lines.span(*i, *i + 1)));
                     }
                     Some(ret)
                 }
@@ -226,9 +248,9 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    ret.push(Token::new(TokenType::Plus));
+                    ret.push(Token::new(TokenType::Plus).at(lines.span(*i, *i + 1)));
                     Some(ret)
                 },
                 '-' =>
@@ -237,9 +259,9 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    ret.push(Token::new(TokenType::Minus));
+                    ret.push(Token::new(TokenType::Minus).at(lines.span(*i, *i + 1)));
                     Some(ret)
                 },
                 '*' =>
@@ -248,9 +270,33 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
+                    }
+                    ret.push(Token::new(TokenType::Multiply).at(lines.span(*i, *i + 1)));
+                    Some(ret)
+                },
+                /* Comments keep their text, without the markers around it. */
+                '/' if matches!(lex_iter.peek().map(|n| n.1), Some('/') | Some('*')) =>
+                {
+                    let mut ret: Vec<Token> = vec![];
+                    if !buffer.is_empty() {
+                        let b = flush_buffer(buffer.as_str());
+                        buffer.clear();
+                        ret.push(b.at(lines.span(start, *i)));
+                    }
+                    let block = lex_iter.next().map(|n| n.1) == Some('*');
+                    let mut text = String::new();
+                    let mut end = *i + 2;
+                    while let Some((j, c)) = lex_iter.next_if(|n| block || n.1 != '\n') {
+                        end = j + 1;
+                        if block && c == '*' && lex_iter.peek().map(|n| n.1) == Some('/') {
+                            lex_iter.next();
+                            end = j + 2;
+                            break;
+                        }
+                        text.push(c);
                     }
-                    ret.push(Token::new(TokenType::Multiply));
+                    ret.push(Token::with_value(TokenType::Comment, &comment_text(&text)).at(lines.span(*i, end)));
                     Some(ret)
                 },
                 '/' =>
@@ -259,9 +305,9 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    ret.push(Token::new(TokenType::Divide));
+                    ret.push(Token::new(TokenType::Divide).at(lines.span(*i, *i + 1)));
                     Some(ret)
                 },
                 '&' =>
@@ -270,12 +316,15 @@ impl Lexer {
                     if buffer.len() > 0 {
                         let b = flush_buffer(buffer.as_str());
                         buffer.clear();
-                        ret.push(b);
+                        ret.push(b.at(lines.span(start, *i)));
                     }
-                    ret.push(Token::new(TokenType::Ampersand));
+                    ret.push(Token::new(TokenType::Ampersand).at(lines.span(*i, *i + 1)));
                     Some(ret)
                 },
                 _ => {
+                    if buffer.is_empty() {
+                        start = *i;
+                    }
                     buffer.push(*c);
                     None
                 }
@@ -1,7 +1,7 @@
 use std::{str::FromStr};
 
 
-use crate::{component::FMComponentScript, fm_script_engine::fm_script_engine_instructions::{Instruction, ScriptStep}};
+use crate::{compile::diagnostic::Diagnostic, component::FMComponentScript, fm_script_engine::fm_script_engine_instructions::{Instruction, ScriptStep}};
 
 use super::tokens::{Token, TokenType};
 
@@ -18,8 +18,11 @@ impl Parser {
         }
     }
 
-    pub fn parse(&self) -> Result<Vec<FMComponentScript>, &str> {
+    /* Parses every script defined in the block. Mistakes are collected, and
+     * parsing carries on with the next step or script. */
+    pub fn parse(&self) -> Result<Vec<FMComponentScript>, Vec<Diagnostic>> {
         let mut scripts = vec![];
+        let mut diagnostics = vec![];
         let mut parser_iter = self.tokens.iter().peekable();
 
         let mut punc_stack: Vec<Instruction> = vec![];
@@ -28,9 +31,15 @@ impl Parser {
             match t.ttype {
                 TokenType::Define => {
                     let mut tmp = FMComponentScript::new();
-                    let peeked = parser_iter.next().unwrap();
-                    if peeked.ttype == TokenType::Identifier {
-                        tmp.script_name = peeked.value.clone();
+                    match parser_iter.next() {
+                        Some(peeked) if peeked.ttype == TokenType::Identifier => {
+                            tmp.script_name = peeked.value.clone();
+                            tmp.span = peeked.span;
+                        },
+                        _ => {
+                            diagnostics.push(Diagnostic::error("Expected script name after \"define\".", t.span));
+                            break;
+                        }
                     }
 
                     /* Parse arguments to script */
@@ -51,11 +60,18 @@ impl Parser {
                             TokenType::OpenBracket => {
                                 break;
                             }
-                            _ => { eprintln!("invalid token."); }
+                            TokenType::Comment => {
+                                continue;
+                            }
+                            _ => {
+                                diagnostics.push(Diagnostic::error(
+                                        format!("Unexpected {:?} in arguments of {}.", t.ttype, tmp.script_name), t.span));
+                            }
                         };
                     }
 
                     /* Parse the instructions inside the script with their options */
+                    let mut closed = false;
                     while let Some(t) = parser_iter.next() {
                         match t.ttype {
                             TokenType::Identifier => {
@@ -63,6 +79,10 @@ impl Parser {
                                     let mut step = ScriptStep {
                                         opcode: op,
                                         index: 0,
+                                        enabled: true,
+                                        comment: None,
+                                        span: t.span,
+                                        switch_spans: vec![],
                                         switches: vec![],
                                     };
 
@@ -70,10 +90,12 @@ impl Parser {
                                         match t.ttype {
                                             TokenType::Argument => {
                                                 step.switches.push(t.value.clone());
+                                                step.switch_spans.push(t.span);
                                             }
                                             _ => {
                                                 if t.ttype != TokenType::SemiColon {
-                                                    panic!("unterminated script step: {:?}", t.ttype);
+                                                    diagnostics.push(Diagnostic::error(
+                                                            format!("Expected \";\" after {:?} step.", step.opcode), t.span));
                                                 }
                                                 tmp.instructions.insert(tmp.instructions.len(), step);
                                                 break;
@@ -81,13 +103,17 @@ impl Parser {
                                         }
                                     }
                                 } else {
-                                    eprintln!("Invalid script step: {}", t.value);
+                                    diagnostics.push(Diagnostic::error(format!("Unknown script step {}.", t.value), t.span));
+                                    /* Skip its arguments. */
+                                    while parser_iter.next_if(|t| t.ttype != TokenType::SemiColon).is_some() {}
+                                    parser_iter.next();
                                 }
                             },
 
                             TokenType::CloseBracket => {
                                 let top = punc_stack.last();
                                 if top.is_none() {
+                                    closed = true;
                                     break;
                                 }
                                 let n = parser_iter.peek();
@@ -111,27 +137,49 @@ impl Parser {
                                         op = Instruction::EndLoop;
                                     },
                                     _ => {
-                                        return Err("Invalid scope signifier.");
+                                        diagnostics.push(Diagnostic::error("Invalid scope signifier.", t.span));
+                                        punc_stack.pop();
+                                        continue;
                                     }
                                 }
                                 punc_stack.pop();
                                 let step = ScriptStep {
                                     opcode: op,
                                     index: 0,
+                                    enabled: true,
+                                    comment: None,
+                                    span: t.span,
+                                    switch_spans: vec![],
                                     switches: vec![],
                                 };
                                 tmp.instructions.insert(tmp.instructions.len(), step);
                             },
                             TokenType::OpenBracket => {},
+                            /* Comments are kept as comment steps, an empty one is a blank line. */
+                            TokenType::Comment => {
+                                let step = ScriptStep {
+                                    opcode: Instruction::BlankLineComment,
+                                    index: 0,
+                                    enabled: true,
+                                    comment: Some(t.value.clone()).filter(|c| !c.is_empty()),
+                                    span: t.span,
+                                    switch_spans: vec![],
+                                    switches: vec![],
+                                };
+                                tmp.instructions.insert(tmp.instructions.len(), step);
+                            },
                             TokenType::Loop => {
-                                let check = parser_iter.next();
-                                if check.unwrap().ttype != TokenType::OpenBracket {
-                                    eprintln!("Expected '{{' after loop.");
-                                    break;
+                                if parser_iter.next_if(|t| t.ttype == TokenType::OpenBracket).is_none() {
+                                    diagnostics.push(Diagnostic::error("Expected \"{\" after loop.", t.span));
+                                    continue;
                                 }
                                 let step = ScriptStep {
                                     opcode: Instruction::Loop,
                                     index: 0,
+                                    enabled: true,
+                                    comment: None,
+                                    span: t.span,
+                                    switch_spans: vec![],
                                     switches: vec![],
                                 };
                                 tmp.instructions.insert(tmp.instructions.len(), step);
@@ -141,6 +189,10 @@ impl Parser {
                                 let mut step = ScriptStep {
                                     opcode: Instruction::ElseIf,
                                     index: 0,
+                                    enabled: true,
+                                    comment: None,
+                                    span: t.span,
+                                    switch_spans: vec![],
                                     switches: vec![]
                                 };
 
@@ -148,10 +200,11 @@ impl Parser {
                                     match t.ttype {
                                         TokenType::Argument => {
                                             step.switches.push(t.value.clone());
+                                            step.switch_spans.push(t.span);
                                         }
                                         _ => {
                                             if t.ttype != TokenType::OpenBracket {
-                                                panic!("missing open bracket on elif");
+                                                diagnostics.push(Diagnostic::error("Expected \"{\" after elif.", t.span));
                                             }
                                             tmp.instructions.insert(tmp.instructions.len(), step);
                                             break;
@@ -163,16 +216,21 @@ impl Parser {
                                 let mut step = ScriptStep {
                                     opcode: Instruction::If,
                                     index: 0,
+                                    enabled: true,
+                                    comment: None,
+                                    span: t.span,
+                                    switch_spans: vec![],
                                     switches: vec![]
                                 };
                                 while let Some(t) = parser_iter.next() {
                                     match t.ttype {
                                         TokenType::Argument => {
                                             step.switches.push(t.value.clone());
+                                            step.switch_spans.push(t.span);
                                         }
                                         _ => {
                                             if t.ttype != TokenType::OpenBracket {
-                                                panic!("Missing open bracket on if");
+                                                diagnostics.push(Diagnostic::error("Expected \"{\" after if.", t.span));
                                             }
                                             tmp.instructions.insert(tmp.instructions.len(), step);
                                             break;
@@ -182,37 +240,49 @@ impl Parser {
                                 punc_stack.push(Instruction::If);
                             },
                             TokenType::Else => {
-                                let check = parser_iter.next();
-                                if check.unwrap().ttype != TokenType::OpenBracket {
-                                    eprintln!("Expected '{{' after loop.");
-                                    break;
+                                if parser_iter.next_if(|t| t.ttype == TokenType::OpenBracket).is_none() {
+                                    diagnostics.push(Diagnostic::error("Expected \"{\" after else.", t.span));
+                                    continue;
                                 }
                                 let step = ScriptStep {
                                     opcode: Instruction::Else,
                                     index: 0,
+                                    enabled: true,
+                                    comment: None,
+                                    span: t.span,
+                                    switch_spans: vec![],
                                     switches: vec![]
                                 };
                                 tmp.instructions.insert(tmp.instructions.len(), step);
                             },
                             _ => { 
-                                eprintln!("Invalid token in script: {:?}", t); 
+                                diagnostics.push(Diagnostic::error(
+                                        format!("Unexpected {:?} in script {}.", t.ttype, tmp.script_name), t.span));
                             }
                         }
                     }
+                    if !closed {
+                        diagnostics.push(Diagnostic::error(format!("Script {} is missing its closing \"}}\".", tmp.script_name), tmp.span));
+                    }
                     scripts.push(tmp);
                 },
+                TokenType::Comment => {},
                 _ => {
-                    eprintln!("Unrecognized token");
+                    diagnostics.push(Diagnostic::error("Expected \"define\" to start a script.", t.span));
                 }
             }
         }
-        return Ok(scripts);
+        if diagnostics.is_empty() {
+            Ok(scripts)
+        } else {
+            Err(diagnostics)
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{burn_script::lexer, fm_script_engine::fm_script_engine_instructions::{Instruction, ScriptStep}};
+    use crate::{burn_script::lexer, compile::diagnostic::Span, fm_script_engine::fm_script_engine_instructions::{Instruction, ScriptStep}};
 
     use super::Parser;
 
@@ -245,54 +315,128 @@ mod tests {
             ScriptStep { opcode: Instruction::SetVariable,
                          switches: vec!["i".to_string(), "x".to_string()],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::Loop,
                          switches: vec![],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::ExitLoopIf,
                          switches: vec!["i == y".to_string()],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::SetVariable,
                          switches: vec!["i".to_string(), "(i + 1)".to_string()],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::If,
                          switches: vec!["i == 7".to_string()],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::SetVariable,
                          switches: vec!["x".to_string(), "20".to_string()],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::Else,
                          switches: vec![],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::SetVariable,
                          switches: vec!["x".to_string(), "\"Jeff\" & \" Keighly\"".to_string()],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::EndIf,
                          switches: vec![],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::EndLoop,
                          switches: vec![],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::Assert,
                          switches: vec!["1 == 1".to_string()],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
             ScriptStep { opcode: Instruction::ExitScript,
                          switches: vec!["i".to_string()],
                          index: 0,
+                         enabled: true,
+                         comment: None,
+                         span: Span::default(),
+                         switch_spans: vec![],
             },
         ];
         for (i, step) in steps_actual.iter().enumerate() {
             assert_eq!(*step, handle.instructions.get(i).unwrap().clone());
         }
     }
+
+    #[test]
+    pub fn parse_comment_test() {
+        let code = "
+        // Scripts for hiring.
+        define hire(x) {
+            // Hire someone.
+            set_variable(y, x / 2); // halved
+            /* Two
+             * lines */
+            //
+        }";
+        let tokens = lexer::Lexer::new(code.to_string()).get_tokens();
+        let scripts = Parser::new(tokens).parse().expect("Unable to parse token stream");
+        let steps = scripts[0].instructions.iter()
+            .map(|s| (s.opcode.clone(), s.comment.as_deref(), s.switches.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(steps, vec![
+            (Instruction::BlankLineComment, Some("Hire someone."), vec![]),
+            (Instruction::SetVariable, None, vec!["y".to_string(), "x / 2".to_string()]),
+            (Instruction::BlankLineComment, Some("halved"), vec![]),
+            (Instruction::BlankLineComment, Some("Two\nlines"), vec![]),
+            (Instruction::BlankLineComment, None, vec![]),
+        ]);
+        assert_eq!(scripts[0].instructions[0].span, Span::new(4, 13, 16));
+    }
 }
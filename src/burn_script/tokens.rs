@@ -1,3 +1,4 @@
+use crate::compile::diagnostic::Span;
 
 #[derive(Debug, PartialEq)]
 pub enum TokenType {
@@ -29,12 +30,15 @@ pub enum TokenType {
     NumericLiteral,
     String,
     Argument,
+    Comment,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Debug)]
 pub struct Token {
     pub ttype: TokenType,
     pub value: String,
+    /* Relative to the start of the script block. */
+    pub span: Span,
 }
 
 impl Token {
@@ -42,12 +46,26 @@ impl Token {
         Self {
             ttype: t,
             value: String::new(),
+            span: Span::default(),
         }
     }
     pub fn with_value(t:TokenType, val: &str) -> Self {
         Self {
             ttype: t,
             value: val.to_string(),
+            span: Span::default(),
         }
     }
+
+    pub fn at(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+}
+
+/* Tokens are the same if they read the same, wherever they were found. */
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.ttype == other.ttype && self.value == other.value
+    }
 }
@@ -0,0 +1,124 @@
+/* Byte codes used in FileMaker calculations. Calculations are stored twice:
+ * key 5 holds the tokens as written, including whitespace, and key 6 holds
+ * the same expression in prefix order without parentheses or whitespace. */
+
+pub const CALC_OPEN_PAREN: u8 = 0x4;
+pub const CALC_CLOSE_PAREN: u8 = 0x5;
+/* The ";" between the arguments of a function. Our own encoding, no file
+ * written by FileMaker with a function of more than one argument has been decoded. */
+pub const CALC_SEPARATOR: u8 = 0x6;
+/* Followed by a string holding the whitespace as written. */
+pub const CALC_WHITESPACE: u8 = 0xC;
+/* Followed by a fixed size block. Bytes 8 to 15 hold the digits of the number
+ * as a little endian integer and byte 16 how many of them follow the decimal
+ * point. FileMaker's files only have whole numbers up to 255, which leave
+ * everything past byte 8 empty, so larger numbers and decimals are our own. */
+pub const CALC_NUMBER: u8 = 0x10;
+pub const CALC_NUMBER_LEN: usize = 17;
+pub const CALC_NUMBER_PREFIX: [u8; 8] = [2, 0, 1, 0, 16, 0, 0, 0];
+/* Written after each number in the infix form only. */
+pub const CALC_NUMBER_END: [u8; 2] = [0, 0x20];
+/* Length prefixed, encrypted, and terminated by a zero byte. */
+pub const CALC_STRING: u8 = 0x13;
+/* Occurrence, table and field id, 2 bytes each. An occurrence of 0 refers to
+ * the table the calculation is defined in. This encoding is our own and
+ * hasn't been checked against a file written by FileMaker. */
+pub const CALC_FIELD: u8 = 0x16;
+pub const CALC_VARIABLE: u8 = 0x1a;
+pub const CALC_NEGATE: u8 = 0x1e;
+/* "not", and the "and", "or" and "xor" operators below, are our own codes. */
+pub const CALC_NOT: u8 = 0x1f;
+/* Selects the value read by Get(). Only the infix form uses this prefix. */
+pub const CALC_GET_SELECTOR: u8 = 0x9c;
+/* Prefix for 2 byte function codes. */
+pub const CALC_EXTENDED_FUNCTION: u8 = 0xfb;
+
+/* Operators with more than one spelling are written with the first. */
+pub const CALC_OPERATORS: [(&str, u8); 22] = [
+    ("+", 0x25),
+    ("-", 0x26),
+    ("*", 0x27),
+    ("/", 0x28),
+    ("^", 0x29),
+    ("and", 0x2a),
+    ("or", 0x2b),
+    ("xor", 0x2c),
+    ("not", CALC_NOT),
+    ("!", CALC_NOT),
+    ("<", 0x41),
+    ("<=", 0x43),
+    ("≤", 0x43),
+    ("==", 0x44),
+    ("=", 0x44),
+    ("!=", 0x46),
+    ("<>", 0x46),
+    ("≠", 0x46),
+    (">=", 0x47),
+    ("≥", 0x47),
+    (">", 0x49),
+    ("&", 0x50),
+];
+
+/* Name, code and number of arguments. */
+pub const CALC_FUNCTIONS: [(&str, &[u8], usize); 4] = [
+    ("Abs", &[0x2d], 1),
+    ("Get", &[0x9b], 1),
+    ("Acos", &[0x9d], 1),
+    ("Char", &[CALC_EXTENDED_FUNCTION, 0x3], 1),
+];
+
+pub const CALC_GET_FUNCTIONS: [(&str, u8); 5] = [
+    ("CurrentTime", 0x1d),
+    ("AccountName", 0x20),
+    ("DocumentsPath", 0x49),
+    ("DocumentsPathListing", 0x5d),
+    ("UUID", 0x65),
+];
+
+pub fn operator_name(code: u8) -> Option<&'static str> {
+    CALC_OPERATORS.iter().find(|op| op.1 == code).map(|op| op.0)
+}
+
+pub fn function_name(code: &[u8]) -> Option<&'static str> {
+    CALC_FUNCTIONS.iter().find(|f| f.1 == code).map(|f| f.0)
+}
+
+pub fn get_function_name(code: u8) -> Option<&'static str> {
+    CALC_GET_FUNCTIONS.iter().find(|f| f.1 == code).map(|f| f.0)
+}
+
+/* Binding strength of binary operators, higher binds tighter. Comparisons
+ * bind at 2, which is also what "not" applies to. */
+pub const CALC_COMPARISON_PRECEDENCE: u8 = 2;
+
+pub fn operator_precedence(code: u8) -> u8 {
+    match code {
+        0x29 => 6,
+        0x27 | 0x28 => 5,
+        0x25 | 0x26 => 4,
+        0x50 => 3,
+        0x2a => 1,
+        0x2b | 0x2c => 0,
+        _ => CALC_COMPARISON_PRECEDENCE,
+    }
+}
+
+/* Text as it's written in a calculation, with quotes and backslashes escaped. */
+pub fn quoted_text(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/* Reads the number block following CALC_NUMBER. */
+pub fn number_text(block: &[u8]) -> String {
+    let mut digits = [0; 8];
+    for (n, byte) in block.iter().skip(8).take(8).enumerate() {
+        digits[n] = *byte;
+    }
+    let text = u64::from_le_bytes(digits).to_string();
+    let scale = block.get(16).copied().unwrap_or(0) as usize;
+    if scale == 0 {
+        return text;
+    }
+    let text = format!("{:0>width$}", text, width = scale + 1);
+    format!("{}.{}", &text[..text.len() - scale], &text[text.len() - scale..])
+}
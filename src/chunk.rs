@@ -44,6 +44,18 @@ impl<'a> Chunk<'a> {
 }
 
 pub fn get_chunk_from_code<'a>(code: &'a[u8], offset: &mut usize, path: &mut Vec<String>, local : usize) -> Result<Chunk<'a>, &'static str> {
+    let mut chunk = read_chunk(code, offset, path, local)?;
+    chunk.path = path.clone();
+    Ok(chunk)
+}
+
+/* Moves past the chunk at `offset`, keeping `path` up to date, without
+ * copying out the path for it. */
+pub fn skip_chunk(code: &[u8], offset: &mut usize, path: &mut Vec<String>, local : usize) -> Result<(), &'static str> {
+    read_chunk(code, offset, path, local).map(|_| ())
+}
+
+fn read_chunk<'a>(code: &'a[u8], offset: &mut usize, path: &mut Vec<String>, local : usize) -> Result<Chunk<'a>, &'static str> {
     let mut chunk_code = code[*offset];
     let mut ctype = ChunkType::Noop;
     let mut data: Option<&[u8]> = None;
@@ -291,7 +303,7 @@ pub fn get_chunk_from_code<'a>(code: &'a[u8], offset: &mut usize, path: &mut Vec
                       chunk_code.into(),
                       data,
                       ref_data,
-                      path.clone(),
+                      vec![],
                       segidx,
                       ref_simple));
 }
@@ -1,8 +1,10 @@
 use clap::Parser;
 
 #[derive(Parser)]
-#[command(arg_required_else_help(true))]
+#[command(arg_required_else_help(true), subcommand_negates_reqs(true))]
 pub struct CLI {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[clap(flatten)]
     pub op: MainOperations,
     #[clap(short = 't')]
@@ -10,12 +12,87 @@ pub struct CLI {
     #[clap(long = "print-header")]
     pub print_header: bool,
     #[clap(long = "no-testing")]
-    pub no_testing: bool
+    pub no_testing: bool,
+    /// Export embedded container data to DIR/table/field/recordid/filename. The container layout is unverified.
+    #[clap(long = "export-containers", requires = "decompile")]
+    pub export_containers: Option<String>,
+    /// Write each decompiled file as Burn source to PATH. A directory, or a path ending in '/', gets NAME.burn.
+    #[clap(long = "emit-burn", requires = "decompile")]
+    pub emit_burn: Option<String>,
+    /// Write each decompiled file as JSON to PATH. A directory, or a path ending in '/', gets NAME.json.
+    #[clap(long = "emit-json", requires = "decompile")]
+    pub emit_json: Option<String>,
+    /// Decompile, reassemble and decompile again, reporting anything that changed. Exits with 1 if anything did.
+    #[clap(long = "check-round-trip", requires = "decompile")]
+    pub check_round_trip: bool,
+    /// Only decompile these components, skipping the rest of the file. Defaults to everything.
+    #[clap(long = "components", value_enum, value_delimiter = ',', requires = "decompile", conflicts_with = "check_round_trip")]
+    pub components: Option<Vec<Component>>,
+    /// Record ACCOUNT as the creator of every component, for reproducible builds.
+    #[clap(long = "creator", requires = "compile")]
+    pub creator: Option<String>,
+    /// Name of the full access account. Defaults to Admin. Its password is always empty, passwords can't be set yet.
+    #[clap(long = "admin-account", requires = "compile")]
+    pub admin_account: Option<String>,
+    /// Apply the compiled schema to FILE, rewriting only the sectors it changes. FILE is patched in place and its original kept as FILE.bak, unless -o names another path. Experimental.
+    #[clap(long = "patch", requires = "compile")]
+    pub patch: Option<String>,
+    /// With --patch, remove fields of the schema's tables, scripts and value lists that the schema doesn't have. Refused for anything still in use.
+    #[clap(long = "prune", requires = "patch")]
+    pub prune: bool,
+    /// Write compiled output to PATH. A directory, or a path ending in '/', gets NAME.fmp12 and NAME.json.
+    #[clap(short = 'o', long = "output", requires = "compile")]
+    pub output: Option<String>,
+    /// What to write when compiling.
+    #[clap(long = "emit", value_enum, default_value_t = Emit::Fmp12)]
+    pub emit: Emit,
+    /// How compile errors are reported: rendered with the source, or as one JSON object per line.
+    #[clap(long = "error-format", value_enum, default_value_t = ErrorFormat::Human)]
+    pub error_format: ErrorFormat,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Run a language server for Burn files, speaking the language server protocol over stdin and stdout.
+    Lsp,
+    /// Format Burn files in place, or the Burn files in the directories given. Defaults to the current directory.
+    Fmt {
+        /// Only report files that aren't formatted, exiting with 1 if there are any.
+        #[clap(long = "check")]
+        check: bool,
+        paths: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ErrorFormat {
+    Human,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Component {
+    Tables,
+    Relationships,
+    Layouts,
+    Scripts,
+    DataSources,
+    Records,
+    ValueLists,
+    Security,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Emit {
+    Json,
+    Fmp12,
+    Both,
 }
 
 #[derive(Debug, clap::Args)]
 #[group(required = true, multiple = false)]
 pub struct MainOperations {
+    /// Burn files to compile into one file, or a burn.toml or directory holding one.
     #[clap(short = 'c', conflicts_with="decompile")]
     pub compile: Option<Vec<String>>,
     #[clap(short = 'd', conflicts_with="compile")]
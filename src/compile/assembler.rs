@@ -1,30 +1,146 @@
-use crate::{dbcharconv::encode_text, encoding_util::{fm_string_encrypt, get_int, get_path_int, put_int, put_path_int}, FmpFile};
+use crate::{component::{FMComponentField, FMComponentLayout, FMComponentPrivilegeSet, FMComponentRecord, FMComponentRelationship, FMComponentScript, FMComponentTable, FMComponentTableOccurence, FMComponentValueList, PrivilegeAccess, ValueListSource}, dbcharconv::encode_text, encoding_util::{derived_uuid, fm_string_encrypt, get_int, get_path_int, put_int, put_path_int}, FmpFile};
 
-use std::{fs::{write, File}, io::Read, path::Path};
+use std::{collections::BTreeMap, fs::{create_dir_all, write, File}, io::{Read, Write}, path::Path};
+use crate::fm_script_engine::fm_script_engine_instructions::{active_steps, Instruction, ScriptStep, STEP_FLAGS_OFFSET, STEP_OPCODE_OFFSET, STEP_RECORD_SIZE};
+use super::calc::{compile_calculation, unsupported_functions};
 use super::header::HEADER_INIT;
+use super::patch::PatchTarget;
+use super::security;
+
+/* Largest data segment written in a single chunk. */
+const SEGMENT_SIZE: usize = 1000;
+/* Flags set on every step record written by FileMaker. */
+const STEP_DEFAULT_FLAGS: u8 = 0x40;
+const SECTOR_SIZE: usize = 4096;
+const SECTOR_HEADER_SIZE: usize = 20;
+/* Sectors holding the schema are leaves of the block tree. */
+const SECTOR_LEVEL: u8 = 0;
+/* The chunk stream starts at this sector. Sector 1 holds the block count. */
+const FIRST_DATA_SECTOR: usize = 2;
+/* Kinds of layout parts and objects, the first value of their geometry. */
+const LAYOUT_PART_HEADER: u8 = 12;
+const LAYOUT_PART_BODY: u8 = 4;
+const LAYOUT_OBJECT_FIELD: u8 = 5;
+const LAYOUT_HEADER_HEIGHT: u16 = 80;
+const LAYOUT_MARGIN: u16 = 20;
+const LAYOUT_WIDTH: u16 = 700;
+const RECORD_METADATA_KEY: u16 = 252;
+/* The highest key a two byte path int holds, see encoding_util::get_path_int. */
+const MAX_RECORD_FIELD_KEY: u16 = 0x80 + 0xFF;
 use crate::encoding_util;
 use crate::dbcharconv;
 
 pub struct Assembler<'a> {
     idx: usize,
-    local_idx: u16,
+    sector: usize,
+    /* Push instructions of the directories currently open, reissued at the
+     * start of each new sector. */
+    path: Vec<Vec<u8>>,
     buffer: Vec<u8>,
     file: &'a FmpFile,
+    /* Account and user name written as the creator of every component, in place of their own. */
+    creator: Option<(String, String)>,
+    /* Name of the full access account created in every file. */
+    admin_account: String,
+    /* Option arrays of fields that already exist in a file being patched, by table and field id. */
+    original_options: BTreeMap<(usize, u16), Vec<u8>>,
 }
 
 impl<'a> Assembler<'a> {
     pub fn new(input: &'a FmpFile) -> Self {
         Self {
             idx: 0,
-            local_idx: 0,
+            sector: 0,
+            path: vec![],
             buffer: vec![],
             file: input,
+            creator: None,
+            admin_account: String::from("Admin"),
+            original_options: BTreeMap::new(),
+        }
+    }
+
+    /* Records the same creator on every component, so the output doesn't
+     * depend on who last edited the source. */
+    pub fn with_creator(mut self, account: &str, user: &str) -> Self {
+        self.creator = Some((account.to_string(), user.to_string()));
+        self
+    }
+
+    /* Renames the full access account. It always has an empty password, see `security`. */
+    pub fn with_admin_account(mut self, name: &str) -> Self {
+        self.admin_account = name.to_string();
+        self
+    }
+
+    pub fn admin_account(&self) -> &str {
+        &self.admin_account
+    }
+
+    /* Fields found in `options` keep the bits of their option array that weren't changed, see `merge_options`. */
+    pub(super) fn with_original_options(mut self, options: BTreeMap<(usize, u16), Vec<u8>>) -> Self {
+        self.original_options = options;
+        self
+    }
+
+    fn sector_end(&self) -> usize {
+        (self.sector + 1) * SECTOR_SIZE
+    }
+
+    /* Appends a blank sector linked to the previous one and moves the write position to its payload. */
+    fn start_sector(&mut self) {
+        let previous = self.sector;
+        self.sector = self.buffer.len() / SECTOR_SIZE;
+        self.buffer.resize((self.sector + 1) * SECTOR_SIZE, 0);
+        let start = self.sector * SECTOR_SIZE;
+        self.buffer[start + 1] = SECTOR_LEVEL;
+        if self.sector > FIRST_DATA_SECTOR {
+            self.buffer[start + 4..start + 8].copy_from_slice(&(previous as u32).to_be_bytes());
+            let previous_start = previous * SECTOR_SIZE;
+            self.buffer[previous_start + 8..previous_start + 12].copy_from_slice(&(self.sector as u32).to_be_bytes());
+        }
+        self.idx = start + SECTOR_HEADER_SIZE;
+    }
+
+    /* Closes every open directory, then reopens them at the start of a new
+     * sector, so each sector can be read on its own. */
+    fn next_sector(&mut self) {
+        let depth = self.path.len();
+        self.buffer[self.idx..self.idx + depth].fill(0x40);
+        self.idx += depth;
+        self.finish_sector();
+        self.start_sector();
+        for push in self.path.clone() {
+            self.write(&push);
         }
     }
 
+    /* Records how much of the current sector's payload is left unused. */
+    fn finish_sector(&mut self) {
+        let start = self.sector * SECTOR_SIZE;
+        let unused = (self.sector_end() - self.idx) as u32;
+        self.buffer[start + 12..start + 16].copy_from_slice(&unused.to_be_bytes());
+    }
+
+    fn write(&mut self, chunk: &[u8]) {
+        self.buffer[self.idx..self.idx + chunk.len()].copy_from_slice(chunk);
+        self.idx += chunk.len();
+    }
+
+    /* Writes a whole chunk, moving on to a new sector when it doesn't fit in
+     * the current one. Room is always kept to close the open directories. */
+    fn emit(&mut self, chunk: &[u8]) {
+        let reserved = self.path.len() + 1;
+        if self.idx + chunk.len() + reserved > self.sector_end() {
+            self.next_sector();
+            assert!(self.idx + chunk.len() + reserved <= self.sector_end(),
+                "Chunk of {} bytes doesn't fit in a sector.", chunk.len());
+        }
+        self.write(chunk);
+    }
+
     fn emit_noop(&mut self) {
-        self.buffer[self.idx] = 0x80;
-        self.idx += 1;
+        self.emit(&[0x80]);
     }
 
 
@@ -39,87 +155,97 @@ impl<'a> Assembler<'a> {
         ((val.len() + 1).div_ceil(2)) as u8
     }
 
-    fn emit_simple_data_1b(&mut self, data: &[u8]) {
-        self.buffer[self.idx] = 0x1b;
-        self.idx += 1;
-        self.buffer[self.idx] = data.len() as u8;
-        self.idx += 1;
-        self.buffer.splice(self.idx..self.idx+data.len(), data.to_vec());
-        self.idx += data.len() + 2 * (0x1b as usize - 0x19);
+    fn emit_simple_data_1b(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut chunk = vec![0x1b, value_length(data)?];
+        chunk.extend(data);
+        chunk.resize(chunk.len() + 2 * (0x1b_usize - 0x19), 0);
+        self.emit(&chunk);
+        Ok(())
     }
 
-    fn emit_simple_data(&mut self, data: &[u8]) {
-
+    /* Data followed by the id it refers to, used for name indexes. The id
+     * is a single byte, so only ids up to 255 can be indexed. */
+    fn emit_indexed_data(&mut self, data: &[u8], id: usize) -> Result<(), String> {
+        let id = u8::try_from(id)
+            .map_err(|_| format!("Id {} doesn't fit in a name index, only ids up to 255 can be written yet.", id))?;
+        let mut chunk = vec![0x1a, value_length(data)?];
+        chunk.extend(data);
+        chunk.extend([1, id]);
+        self.emit(&chunk);
+        Ok(())
     }
 
-    fn emit_long_kv(&mut self, key: &[u8], val: &[u8]) {
+    /* Data too long for a single key is split into numbered segments. Segment
+     * numbers are a single byte, so at most 255 segments can be written. */
+    fn emit_segmented_data(&mut self, data: &[u8]) -> Result<(), String> {
+        if data.len() > SEGMENT_SIZE * u8::MAX as usize {
+            return Err(format!("{} bytes don't fit in {} data segments.", data.len(), u8::MAX));
+        }
+        for (n, segment) in data.chunks(SEGMENT_SIZE).enumerate() {
+            let mut chunk = vec![0x07, n as u8 + 1];
+            chunk.extend((segment.len() as u16).to_be_bytes());
+            chunk.extend(segment);
+            self.emit(&chunk);
+        }
+        Ok(())
+    }
 
+    fn emit_long_kv(&mut self, key: &[u8], val: &[u8]) -> Result<(), String> {
         let code = 0x1e;
-        self.buffer[self.idx] = code;
-        self.idx += 1;
-        self.buffer[self.idx] = key.len() as u8;
-        self.idx += 1;
+        let mut chunk = vec![code, value_length(key)?];
         // let db_encoding : Vec<(u8, u8)> = encoding
         //     .chunks_exact(2)
         //     .map(|chunk| (chunk[0], chunk[1]))
         //     .collect();
-        self.buffer.splice(self.idx..self.idx+key.len(), key.to_vec());
-        self.idx += key.len();
-        self.buffer[self.idx] = val.len() as u8;
-        self.idx += 1;
-        self.buffer.splice(self.idx..self.idx+val.len(), val.to_vec());
-        self.idx += val.len();
+        chunk.extend(key);
+        chunk.push(value_length(val)?);
+        chunk.extend(val);
+        self.emit(&chunk);
+        Ok(())
     }
     
-    fn emit_simple_kv_e(&mut self, key: u32, val: &[u8]) {
-
-        self.buffer[self.idx] = 0x0e;
-        self.idx += 1;
-        let n = put_path_int(key);
-        let n_len = n.len();
-        println!("Pushing {:?}", n);
-        self.buffer.splice(self.idx..self.idx+n_len, n);
-        self.idx += n_len;
-        self.buffer[self.idx] = val.len() as u8;
-        self.idx += 1;
-        self.buffer.splice(self.idx..self.idx+val.len(), val.to_vec());
-        self.idx += val.len();
-    }
-
-    fn emit_simple_kv(&mut self, key: u8, val: &[u8]) {
+    fn emit_simple_kv_e(&mut self, key: u32, val: &[u8]) -> Result<(), String> {
+        let mut chunk = vec![0x0e];
+        chunk.extend(put_path_int(key));
+        chunk.push(value_length(val)?);
+        chunk.extend(val);
+        self.emit(&chunk);
+        Ok(())
+    }
+
+    fn emit_simple_kv(&mut self, key: u8, val: &[u8]) -> Result<(), String> {
         let ins = match key {
             16 => { 0x6 }
             216 => { 0x6 }
             _ => { self.calc_small_kv_ins(val) }
         };
+        /* Small instructions only fit values of exactly 1, 2, 4, 6 or 8 bytes. */
+        let ins = match ins {
+            0x01..=0x05 if val.len() != (ins == 1) as usize + 2 * (ins as usize - 1) => 0x6,
+            _ => ins,
+        };
 
-        self.buffer[self.idx] = ins;
-        self.idx += 1;
-
+        let mut chunk = vec![ins];
 
         match ins {
             0x01 | 0x02 | 0x03 | 0x04 | 0x05 => {
-                self.buffer[self.idx] = key as u8;
-                self.idx += 1;
-                self.buffer.splice(self.idx..self.idx+val.len(), val.to_vec());
-                self.idx += val.len();
+                chunk.push(key);
+                chunk.extend(val);
             }
             0x6 => {
-                self.buffer[self.idx] = key as u8;
-                println!("Ins: {} -> key {} :: {:?} :: len {}", ins, key, val, val.len() as u8);
-                self.idx += 1;
-                self.buffer[self.idx] = val.len() as u8;
-                self.idx += 1;
-                self.buffer.splice(self.idx..self.idx+val.len(), val.to_vec());
-                self.idx += val.len();
+                chunk.push(key);
+                chunk.push(value_length(val)?);
+                chunk.extend(val);
             }
             _ => {}
         }
+        self.emit(&chunk);
+        Ok(())
     }
 
     fn pop_directory(&mut self) {
-        self.buffer[self.idx] = 0x40;
-        self.idx += 1;
+        self.emit(&[0x40]);
+        self.path.pop();
     }
 
     fn push_directory(&mut self, dir: u32) {
@@ -130,35 +256,28 @@ impl<'a> Assembler<'a> {
             _ => { 0x48 }
         };
 
-        println!("Ins: {:x}, dir: {:?}", ins, d);
-
-        self.buffer[self.idx] = ins;
-        self.idx += 1;
-        self.buffer.splice(self.idx..self.idx + d.len(), d.clone());
-        self.idx += d.len();
+        let mut chunk = vec![ins];
+        chunk.extend(d);
+        self.emit(&chunk);
+        self.path.push(chunk);
         self.emit_noop();
     }
 
-    pub fn append_blank_chunk(&mut self) {
-        self.buffer.resize(self.buffer.len() + 4096, 0);
-        self.idx = self.buffer.len() - 4095;
-        self.local_idx = 0;
-    }
-
-    pub fn emit_init_blobs(&mut self) {
+    pub fn emit_init_blobs(&mut self) -> Result<(), String> {
         self.push_directory(2);
-        self.emit_simple_kv(3, &[120, 104, 106, 116, 107, 120]);
-        self.emit_simple_kv(5, &[4, 184]);
-        self.emit_simple_kv(6, &[1, 10, 4, 179, 4, 115, 8, 179, 0, 19, 0, 0, 3, 124, 4, 0, 18, 5, 66, 128, 2, 0, 0, 0, 0, 66, 144, 0, 0, 0, 8, 2, 0, 0, 0, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
-        self.emit_simple_kv(8, &[78, 152, 78, 152, 78, 152, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 23, 10, 10, 40, 53, 122, 104, 106, 116, 107, 116, 104, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 8, 107, 122, 107, 110, 116, 107, 116, 107, 0, 0, 0, 21, 6, 107, 122, 107, 110, 116, 111, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        self.emit_simple_kv(9, &[0, 0, 0, 0, 11, 251, 83, 44, 0, 0, 0, 0, 11, 251, 83, 44, 0, 0, 0, 0, 11, 251, 83, 44, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 23, 9, 104, 106, 107, 106, 104, 106, 104, 106, 110, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
-        self.emit_simple_kv(11, &[0, 8, 7, 60, 51, 54, 63, 55, 59, 57, 98, 65, 50, 218, 23, 59, 57, 51, 52, 46, 53, 41, 50, 122, 18, 30, 218, 27, 42, 42, 54, 51, 57, 59, 46, 51, 53, 52, 41, 218, 28, 51, 54, 63, 23, 59, 49, 63, 40, 122, 10, 40, 53, 116, 59, 42, 42, 218, 25, 53, 52, 46, 63, 52, 46, 41, 218, 8, 63, 41, 53, 47, 40, 57, 63, 41, 218, 19, 52, 41, 46, 59, 54, 54, 63, 40, 218, 31, 34, 46, 63, 52, 41, 51, 53, 52, 41, 218, 30, 51, 57, 46, 51, 53, 52, 59, 40, 51, 63, 41, 218, 12, 47, 49, 63, 52, 61, 54, 41, 50, 116, 55, 42, 40, 7, 60, 51, 54, 63, 55, 59, 57, 90, 65, 50, 218, 23, 59, 57, 51, 52, 46, 53, 41, 50, 122, 18, 30, 218, 15, 41, 63, 40, 41, 218, 55, 63, 40, 51, 55, 59, 49, 218, 22, 51, 56, 40, 59, 40, 35, 218, 27, 42, 42, 54, 51, 57, 59, 46, 51, 53, 52, 122, 9, 47, 42, 42, 53, 40, 46, 218, 28, 51, 54, 63, 23, 59, 49, 63, 40, 218, 31, 34, 46, 63, 52, 41, 51, 53, 52, 41, 218, 30, 51, 57, 46, 51, 53, 52, 59, 40, 51, 63, 41, 218, 8, 15, 41, 63, 40, 116, 47, 42, 40]);
-        self.emit_simple_kv(24, &[0, 0, 4, 3, 1, 1, 0, 0, 2, 1, 2, 0, 1, 0, 0, 0, 1, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 100, 0, 1, 0, 0]);
+        self.emit_simple_kv(3, &[120, 104, 106, 116, 107, 120])?;
+        self.emit_simple_kv(5, &[4, 184])?;
+        self.emit_simple_kv(6, &[1, 10, 4, 179, 4, 115, 8, 179, 0, 19, 0, 0, 3, 124, 4, 0, 18, 5, 66, 128, 2, 0, 0, 0, 0, 66, 144, 0, 0, 0, 8, 2, 0, 0, 0, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1])?;
+        self.emit_simple_kv(8, &[78, 152, 78, 152, 78, 152, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 23, 10, 10, 40, 53, 122, 104, 106, 116, 107, 116, 104, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2, 8, 107, 122, 107, 110, 116, 107, 116, 107, 0, 0, 0, 21, 6, 107, 122, 107, 110, 116, 111, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])?;
+        self.emit_simple_kv(9, &[0, 0, 0, 0, 11, 251, 83, 44, 0, 0, 0, 0, 11, 251, 83, 44, 0, 0, 0, 0, 11, 251, 83, 44, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 23, 9, 104, 106, 107, 106, 104, 106, 104, 106, 110, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])?;
+        self.emit_simple_kv(11, &[0, 8, 7, 60, 51, 54, 63, 55, 59, 57, 98, 65, 50, 218, 23, 59, 57, 51, 52, 46, 53, 41, 50, 122, 18, 30, 218, 27, 42, 42, 54, 51, 57, 59, 46, 51, 53, 52, 41, 218, 28, 51, 54, 63, 23, 59, 49, 63, 40, 122, 10, 40, 53, 116, 59, 42, 42, 218, 25, 53, 52, 46, 63, 52, 46, 41, 218, 8, 63, 41, 53, 47, 40, 57, 63, 41, 218, 19, 52, 41, 46, 59, 54, 54, 63, 40, 218, 31, 34, 46, 63, 52, 41, 51, 53, 52, 41, 218, 30, 51, 57, 46, 51, 53, 52, 59, 40, 51, 63, 41, 218, 12, 47, 49, 63, 52, 61, 54, 41, 50, 116, 55, 42, 40, 7, 60, 51, 54, 63, 55, 59, 57, 90, 65, 50, 218, 23, 59, 57, 51, 52, 46, 53, 41, 50, 122, 18, 30, 218, 15, 41, 63, 40, 41, 218, 55, 63, 40, 51, 55, 59, 49, 218, 22, 51, 56, 40, 59, 40, 35, 218, 27, 42, 42, 54, 51, 57, 59, 46, 51, 53, 52, 122, 9, 47, 42, 42, 53, 40, 46, 218, 28, 51, 54, 63, 23, 59, 49, 63, 40, 218, 31, 34, 46, 63, 52, 41, 51, 53, 52, 41, 218, 30, 51, 57, 46, 51, 53, 52, 59, 40, 51, 63, 41, 218, 8, 15, 41, 63, 40, 116, 47, 42, 40])?;
+        self.emit_simple_kv(24, &[0, 0, 4, 3, 1, 1, 0, 0, 2, 1, 2, 0, 1, 0, 0, 0, 1, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 100, 0, 1, 0, 0])?;
         self.pop_directory();
+        Ok(())
     }
 
 
-    pub fn emit_table_metadata(&mut self) {
+    pub fn emit_table_metadata(&mut self) -> Result<(), String> {
         self.push_directory(3);
         self.push_directory(16);
         self.push_directory(1);
@@ -170,7 +289,9 @@ impl<'a> Assembler<'a> {
             encoding.push(0x0);
             encoding.push(0x0);
             /* TODO: second argument shoudl not be hard coded in this way */
-            self.emit_long_kv(&encoding, &[2, 128, (*t.0 as u8)]);
+            let id = u8::try_from(*t.0)
+                .map_err(|_| format!("Table {} has id {}, only tables with ids up to 255 can be written yet.", t.1.table_name, t.0))?;
+            self.emit_long_kv(&encoding, &[2, 128, id])?;
         }
 
         self.pop_directory();
@@ -178,59 +299,432 @@ impl<'a> Assembler<'a> {
 
         for t in &self.file.tables {
             let key = put_int(*t.0);
-            println!("pushing {:?}", (*t.0 as u32).to_be_bytes());
-            self.emit_long_kv(&key, &(*t.0 as u32).to_be_bytes());
+            self.emit_long_kv(&key, &(*t.0 as u32).to_be_bytes())?;
         }
         self.pop_directory();
-        self.emit_simple_kv(4, &put_int(self.file.tables.len()));
-        self.emit_simple_kv(216, &fm_string_encrypt("hello".to_string()));
-        self.emit_simple_kv_e(129, &vec![59, 62, 55, 51, 52]);
-        self.emit_simple_kv_e(130, &vec![59, 62, 55, 51, 52]);
+        self.emit_simple_kv(4, &put_int(self.file.tables.len()))?;
+        self.emit_simple_kv(216, &fm_string_encrypt("hello".to_string()))?;
+        self.emit_simple_kv_e(129, &vec![59, 62, 55, 51, 52])?;
+        self.emit_simple_kv_e(130, &vec![59, 62, 55, 51, 52])?;
+        self.pop_directory();
 
         self.push_directory(5);
 
-        for t in &self.file.tables {
-            self.push_directory(128 + *t.0 as u32);
+        for (id, table) in &self.file.tables {
+            self.push_directory(128 + *id as u32);
+            self.emit_simple_kv(16, &fm_string_encrypt(table.table_name.clone()))?;
+            self.emit_creator_metadata(&table.created_by_account, &table.create_by_user)?;
             self.pop_directory();
         }
         self.pop_directory();
         self.pop_directory();
+        Ok(())
+    }
+
+    fn creator_names(&self, account: &str, user: &str) -> (String, String) {
+        let (account, user) = match &self.creator {
+            Some((account, user)) => (account.clone(), user.clone()),
+            None => (account.to_string(), user.to_string()),
+        };
+        let account = if account.is_empty() { String::from("admin") } else { account };
+        let user = if user.is_empty() { String::from("Admin") } else { user };
+        (account, user)
+    }
+
+    fn emit_creator_metadata(&mut self, account: &str, user: &str) -> Result<(), String> {
+        let (account, user) = self.creator_names(account, user);
+        self.emit_simple_kv_e(129, &fm_string_encrypt(account))?;
+        self.emit_simple_kv_e(130, &fm_string_encrypt(user))?;
+        Ok(())
+    }
+
+    /* Catalogs end with an id, a modification count and their creator. A
+     * file we write is new, so nothing has been modified more than once. */
+    fn emit_catalog_metadata(&mut self, catalog: &str, account: &str, user: &str) -> Result<(), String> {
+        self.emit_simple_kv(216, &derived_uuid(&format!("catalog\0{}\0{}", self.file.name, catalog)))?;
+        self.emit_simple_kv(252, &[1, 1])?;
+        self.emit_creator_metadata(account, user)?;
+        Ok(())
+    }
+
+    /* Writes the field name index and the field definitions for a table, at [128 + table].[3]. */
+    fn emit_field_data(&mut self, table_id: usize, table: &FMComponentTable) -> Result<(), String> {
+        self.push_directory(3);
+        self.emit_field_index(table)?;
+        self.push_directory(5);
+        for (id, field) in &table.fields {
+            self.emit_field_definition(table_id, *id, field)?;
+        }
+        self.pop_directory();
         self.pop_directory();
+        Ok(())
     }
 
-    pub fn emit_relationship_data(&mut self) {
+    /* [128 + table].[3].[1] */
+    fn emit_field_index(&mut self, table: &FMComponentTable) -> Result<(), String> {
+        self.push_directory(1);
+        self.emit_catalog_index(&table.fields.iter()
+            .map(|(id, f)| (*id as usize, f.field_name.as_str()))
+            .collect::<Vec<_>>())
+            .map_err(|e| format!("Unable to index the fields of table {}. {}", table.table_name, e))?;
+
+        /* Fields by data type and name. blank.fmp12 lists text fields under 4
+         * and timestamps under 20, four times the type in the option array. */
+        self.push_directory(6);
+        let mut types = table.fields.iter()
+            .map(|(id, f)| {
+                let mut entry = vec![1, 4 * f.data_type_code()];
+                entry.extend(encode_text(&f.field_name));
+                entry.extend([0, 0]);
+                (entry, *id as usize)
+            })
+            .collect::<Vec<_>>();
+        types.sort();
+        for (entry, id) in types {
+            self.emit_indexed_data(&entry, id)?;
+        }
+        self.pop_directory();
+        self.emit_catalog_metadata(&format!("fields\0{}", table.table_name), &table.created_by_account, &table.create_by_user)?;
+        self.pop_directory();
+        Ok(())
+    }
+
+    /* [128 + table].[3].[5].[field] */
+    fn emit_field_definition(&mut self, table_id: usize, id: u16, field: &FMComponentField) -> Result<(), String> {
+        /* Calculations calling functions without a known code are left out, the checker warns about them. */
+        let mut field = field.clone();
+        for calc in [&mut field.auto_enter_calc, &mut field.validation.calculation] {
+            if calc.as_deref().is_some_and(|c| !unsupported_functions(c).is_empty()) {
+                *calc = None;
+            }
+        }
+        let field = &field;
+        self.push_directory(id as u32);
+        let options = match self.original_options.get(&(table_id, id)) {
+            Some(original) => field.merge_options(original),
+            None => field.options_to_bytes(),
+        };
+        self.emit_simple_kv(2, &options)?;
+        if !field.field_description.is_empty() {
+            self.emit_simple_kv(3, &fm_string_encrypt(field.field_description.clone()))?;
+        }
+        if let Some(calc) = &field.auto_enter_calc {
+            self.push_directory(5);
+            self.emit_calculation(calc, Some(table_id))
+                .map_err(|e| format!("Auto-enter calculation of field {}: {}", field.field_name, e))?;
+            self.pop_directory();
+        }
+        /* Where FileMaker keeps validation isn't known, so this is our own layout. Keys 7 to 9
+         * are used by FileMaker for something else, the value list and message go at 10 and 11.
+         * Validation read from a file without them is empty and only keeps its option bits. */
+        let validation = &field.validation;
+        if let Some(calc) = validation.calculation.as_ref().filter(|c| !c.is_empty()) {
+            self.push_directory(6);
+            self.emit_calculation(calc, Some(table_id))
+                .map_err(|e| format!("Validation calculation of field {}: {}", field.field_name, e))?;
+            self.pop_directory();
+        }
+        if let Some(list) = validation.value_list.as_ref().filter(|l| !l.is_empty()) {
+            let id = self.file.value_lists.iter()
+                .find(|(_, vl)| vl.list_name == *list)
+                .map(|(id, _)| *id)
+                .ok_or_else(|| format!("Field {} is validated against unknown value list {}.", field.field_name, list))?;
+            let id = u16::try_from(id)
+                .map_err(|_| format!("Value list {} has id {}, only value lists with ids up to 65535 can be written.", list, id))?;
+            self.emit_simple_kv(10, &id.to_be_bytes())?;
+        }
+        if let Some(message) = validation.message.as_ref().filter(|m| !m.is_empty()) {
+            self.emit_simple_kv(11, &fm_string_encrypt(message.clone()))?;
+        }
+        if let Some(serial) = &field.auto_enter_serial {
+            self.emit_simple_kv(12, &fm_string_encrypt(serial.next_value.to_string()))?;
+            self.emit_simple_kv(13, &fm_string_encrypt(serial.increment.to_string()))?;
+        }
+        self.emit_simple_kv(16, &fm_string_encrypt(field.field_name.clone()))?;
+        self.emit_simple_kv(216, &derived_uuid(&format!("field\0{}\0{}\0{}", self.file.name, table_id, id)))?;
+        self.emit_creator_metadata(&field.created_by_account, &field.created_by_user)?;
+        self.pop_directory();
+        Ok(())
+    }
+
+    /* Table occurrence directories are pushed with a 3 byte reference rather than a path integer. */
+    fn push_occurrence_directory(&mut self, id: u16) {
+        let [hi, lo] = id.to_be_bytes();
+        let chunk = vec![0x30, 208, hi, lo];
+        self.emit(&chunk);
+        self.path.push(chunk);
+        self.emit_noop();
+    }
+
+    fn occurrence_reference(id: u16) -> [u8; 5] {
+        let [hi, lo] = id.to_be_bytes();
+        [4, 3, 208, hi, lo]
+    }
+
+    /* Relationships may refer to occurrences by name only, as they do when parsed from Burn. */
+    fn resolve_occurrence(&self, id: u16, name: &str) -> u16 {
+        if id != 0 || name.is_empty() {
+            return id;
+        }
+        self.file.table_occurrences.iter()
+            .find(|(_, to)| to.table_occurence_name == name)
+            .map(|(id, _)| *id as u16)
+            .unwrap_or(0)
+    }
+
+    fn resolve_table(&self, occurrence: &FMComponentTableOccurence) -> u16 {
+        if occurrence.table_actual != 0 || occurrence.table_actual_name.is_empty() {
+            return occurrence.table_actual;
+        }
+        self.file.tables.iter()
+            .find(|(_, t)| t.table_name == occurrence.table_actual_name)
+            .map(|(id, _)| *id as u16)
+            .unwrap_or(0)
+    }
+
+    fn resolve_field(&self, occurrence: u16, id: u16, name: &str) -> u16 {
+        if id != 0 || name.is_empty() {
+            return id;
+        }
+        self.file.table_occurrences.get(&(occurrence as usize))
+            .and_then(|to| self.file.tables.get(&(self.resolve_table(to) as usize)))
+            .and_then(|t| t.fields.iter().find(|(_, f)| f.field_name == name))
+            .map(|(id, _)| *id)
+            .unwrap_or(0)
+    }
+
+    /* Returns each relationship with both sides resolved to occurrence ids, sorted by id. */
+    fn resolved_relationships(&self) -> Vec<(usize, u16, u16, &'a FMComponentRelationship)> {
+        self.file.relationships.iter()
+            .map(|(id, rel)| (*id,
+                    self.resolve_occurrence(rel.table1, &rel.table1_name),
+                    self.resolve_occurrence(rel.table2, &rel.table2_name),
+                    rel))
+            .collect()
+    }
+
+    pub fn emit_relationship_data(&mut self) -> Result<(), String> {
+        let occurrences = self.file.table_occurrences.iter().collect::<Vec<_>>();
+        let relationships = self.resolved_relationships();
+
         self.push_directory(17);
         self.push_directory(1);
-        self.emit_simple_kv(0, &[3, 208, 0, 9]);
+        if let Some((id, to)) = occurrences.iter().find(|to| *to.0 > u16::MAX as usize) {
+            return Err(format!("Table occurrence {} has id {}, only occurrences with ids up to {} can be written.", to.table_occurence_name, id, u16::MAX));
+        }
+        let max_occurrence = occurrences.last().map(|to| *to.0 as u16).unwrap_or(0);
+        let [hi, lo] = max_occurrence.to_be_bytes();
+        self.emit_simple_kv(0, &[3, 208, hi, lo])?;
         self.push_directory(1);
 
-        for to in &self.file.table_occurrences {
-            let name = &encode_text(&(to.1.table_occurence_name.clone() + "\0\0"));
-            self.emit_simple_data_1b(&name);
+        for to in &occurrences {
+            let name = encode_text(&(to.1.table_occurence_name.clone() + "\0\0"));
+            self.emit_simple_data_1b(&name)?;
         }
 
         self.pop_directory();
         self.push_directory(3);
-        for to in &self.file.table_occurrences {
-            self.emit_simple_data_1b(&put_int(*to.0));
+        for to in &occurrences {
+            self.emit_simple_data_1b(&put_int(*to.0))?;
         }
 
         self.pop_directory();
         self.push_directory(8);
         self.pop_directory();
         self.pop_directory();
+
+        /* Occurrence definitions, each with the graph of relationships it takes part in. */
+        self.push_directory(5);
+        for (id, to) in &occurrences {
+            let id = **id as u16;
+            self.push_occurrence_directory(id);
+            let table = u8::try_from(self.resolve_table(to)).ok().filter(|t| *t != 0)
+                .ok_or(format!("Table occurrence {} is based on table {}, only tables with ids from 1 to 255 can be written yet.",
+                    to.table_occurence_name, self.resolve_table(to)))?;
+            let mut definition = vec![2, 1, 0, 3, 2, 128, table];
+            definition.extend([65, 160, 0, 0, 65, 160, 0, 0, 67, 3, 0, 0, 66, 232, 0, 0]);
+            definition.extend([0, 0, 0, 0, 0, 0, 0, 0, 120, 120, 120, 255]);
+            self.emit_simple_kv(2, &definition)?;
+            self.emit_simple_kv(16, &fm_string_encrypt(to.table_occurence_name.clone()))?;
+
+            self.push_directory(251);
+            for (rel_id, to1, to2, _) in &relationships {
+                let other = if *to1 == id { *to2 } else if *to2 == id { *to1 } else { continue };
+                let [hi, lo] = other.to_be_bytes();
+                self.emit(&[0x12, 208, hi, lo, 1, relationship_id(*rel_id)?]);
+            }
+            self.pop_directory();
+            self.emit_creator_metadata(&to.created_by_account, &to.create_by_user)?;
+            self.pop_directory();
+        }
+        self.pop_directory();
         self.pop_directory();
+
+        /* Relationship definitions and their criteria. */
+        self.push_directory(251);
+        self.push_directory(1);
+        let max_relationship = relationships.last().map(|r| r.0).unwrap_or(0);
+        self.emit_simple_kv(0, &[1, relationship_id(max_relationship)?])?;
+        self.emit_simple_kv(4, &put_int(relationships.len()))?;
         self.pop_directory();
+
+        self.push_directory(5);
+        for (rel_id, to1, to2, rel) in &relationships {
+            self.push_directory(*rel_id as u32);
+            let mut definition = Self::occurrence_reference(*to1).to_vec();
+            definition.extend(Self::occurrence_reference(*to2));
+            definition.push(rel.table1_options.to_byte());
+            definition.push(rel.table2_options.to_byte());
+            definition.resize(22, 0);
+            self.emit_simple_kv(2, &definition)?;
+
+            if rel.criteria.len() > u8::MAX as usize {
+                return Err(format!("Relationship {} has {} criteria, at most 255 can be written.", rel_id, rel.criteria.len()));
+            }
+            self.push_directory(3);
+            for (n, criterion) in rel.criteria.iter().enumerate() {
+                let fields = [
+                    self.resolve_field(*to1, criterion.field1, &criterion.field1_name),
+                    self.resolve_field(*to2, criterion.field2, &criterion.field2_name),
+                ];
+                let (Some(field1), Some(field2)) = fields.map(|f| u8::try_from(f).ok().filter(|f| *f != 0)).into() else {
+                    return Err(format!("Criterion {} of relationship {} compares fields {} and {}, only fields with ids from 1 to 255 can be written yet.",
+                        n + 1, rel_id, fields[0], fields[1]));
+                };
+                self.emit_simple_kv(n as u8 + 1, &[
+                    criterion.comparison.to_byte(),
+                    2, 1, field1,
+                    2, 1, field2,
+                ])?;
+            }
+            self.pop_directory();
+            self.emit_creator_metadata("", "")?;
+            self.pop_directory();
+        }
+        self.pop_directory();
+        self.pop_directory();
+        self.pop_directory();
+        Ok(())
+    }
+
+    /* Keys 0 and 4 and directories 1 and 3 of a catalog, listing its components by name and in order. */
+    fn emit_catalog_index(&mut self, entries: &[(usize, &str)]) -> Result<(), String> {
+        self.emit_encoded_catalog_index(&entries.iter()
+            .map(|(id, name)| (*id, encode_text(name)))
+            .collect::<Vec<_>>())?;
+        Ok(())
+    }
+
+    /* Same as `emit_catalog_index`, for names that are already encoded. */
+    fn emit_encoded_catalog_index(&mut self, entries: &[(usize, Vec<u8>)]) -> Result<(), String> {
+        let max_id = entries.iter().map(|e| e.0).max().unwrap_or(0);
+        let max_id = u8::try_from(max_id)
+            .map_err(|_| format!("Id {} doesn't fit in a name index, only ids up to 255 can be written yet.", max_id))?;
+        self.emit_simple_kv(0, &[1, max_id])?;
+
+        /* Names are stored in the encoding `dbcharconv` uses for sorting, so
+         * sorting the encoded bytes gives the order FileMaker writes them in. */
+        self.push_directory(1);
+        let mut names = entries.iter()
+            .map(|(id, name)| {
+                let mut name = name.clone();
+                name.extend([0, 0, 0]);
+                (name, *id)
+            })
+            .collect::<Vec<_>>();
+        names.sort();
+        for (name, id) in names {
+            self.emit_indexed_data(&name, id)?;
+        }
+        self.pop_directory();
+
+        self.push_directory(3);
+        for (n, (id, _)) in entries.iter().enumerate() {
+            self.emit_indexed_data(&put_int(n + 1), *id)?;
+        }
+        self.pop_directory();
+        self.emit_simple_kv(4, &put_int(entries.len()))?;
+        Ok(())
+    }
+
+    /* Encoded the way FileMaker writes layout parts: a kind followed by
+     * doubles tagged 33, 41, 49 and 57 for top, left, bottom and right. */
+    fn layout_geometry(kind: u8, top: u16, left: u16, bottom: u16, right: u16) -> Vec<u8> {
+        let mut geometry = vec![8, kind, 16, 0, 24, 0];
+        for (tag, value) in [(33, top), (41, left), (49, bottom), (57, right)] {
+            geometry.push(tag);
+            geometry.extend((value as f64).to_le_bytes());
+        }
+        geometry
     }
 
-    pub fn emit_layout_data(&mut self) {
+    pub fn emit_layout_data(&mut self) -> Result<(), String> {
+        let layouts = self.file.layouts.iter()
+            .map(|(id, layout)| (*id, layout, self.resolve_occurrence(layout.table_occurrence as u16,
+                        &layout.table_occurrence_name)))
+            .collect::<Vec<_>>();
+
         self.push_directory(4);
         self.push_directory(1);
-        self.emit_simple_kv(0, &[1, self.file.layouts.len() as u8]);
+        self.emit_catalog_index(&layouts.iter()
+            .map(|(id, layout, _)| (*id, layout.layout_name.as_str()))
+            .collect::<Vec<_>>())?;
+        self.push_directory(7);
+        for (id, layout, occurrence) in &layouts {
+            /* The occurrence is stored as 128 plus its id, in a single byte. */
+            let reference = u8::try_from(*occurrence).ok().filter(|o| *o < 128).ok_or(format!(
+                "Layout {} is based on table occurrence {}, only occurrences with ids below 128 can be written yet.",
+                layout.layout_name, occurrence))?;
+            self.push_directory(*id as u32);
+            self.emit_simple_kv(2, &[16, 128 + reference, 129, 65, 24, 0])?;
+            self.emit_simple_kv(16, &fm_string_encrypt(layout.layout_name.clone()))?;
+            self.emit_simple_kv(48, &[0, 0, 0, 0])?;
+            self.emit_simple_kv(240, &[0; 8])?;
+            self.emit_simple_kv(241, &[0; 8])?;
+            self.pop_directory();
+        }
+        self.pop_directory();
+        self.pop_directory();
+
         self.push_directory(5);
+        for (id, layout, occurrence) in &layouts {
+            self.emit_layout(*id, layout, *occurrence)?;
+        }
         self.pop_directory();
         self.pop_directory();
+        Ok(())
+    }
+
+    /* [4].[5].[layout]: a header and a body part, and the fields placed on them. */
+    fn emit_layout(&mut self, id: usize, layout: &FMComponentLayout, occurrence: u16) -> Result<(), String> {
+        let bottom = layout.fields.iter().map(|f| f.top + f.height).max().unwrap_or(0)
+            .max(LAYOUT_HEADER_HEIGHT) + LAYOUT_MARGIN;
+        self.push_directory(id as u32);
+        self.push_directory(3);
+        self.emit_simple_kv(0, &[1, 2])?;
+        let parts = [(LAYOUT_PART_HEADER, 0, LAYOUT_HEADER_HEIGHT), (LAYOUT_PART_BODY, LAYOUT_HEADER_HEIGHT, bottom)];
+        for (n, (kind, top, bottom)) in parts.into_iter().enumerate() {
+            self.push_directory(n as u32 + 1);
+            self.emit_simple_kv(2, &Self::layout_geometry(kind, top, 0, bottom, LAYOUT_WIDTH))?;
+            self.emit_simple_kv(7, &[0, 0, 0, 0])?;
+            self.pop_directory();
+        }
         self.pop_directory();
+
+        self.push_directory(13);
+        self.push_directory(3);
+        for (n, placed) in layout.fields.iter().enumerate() {
+            let field = self.resolve_field(occurrence, placed.field, &placed.field_name);
+            self.push_directory(n as u32 + 1);
+            self.emit_simple_kv(2, &Self::layout_geometry(LAYOUT_OBJECT_FIELD, placed.top, placed.left,
+                    placed.top + placed.height, placed.left + placed.width))?;
+            self.emit_simple_kv(4, &field.to_be_bytes())?;
+            self.pop_directory();
+        }
+        self.pop_directory();
+        self.pop_directory();
+        self.pop_directory();
+        Ok(())
     }
     
     pub fn emit_theme_data(&mut self) {
@@ -238,30 +732,411 @@ impl<'a> Assembler<'a> {
         self.pop_directory();
     }
 
-    pub fn emit_script_data(&mut self) {
+    /* Scripts written to the file. The rest can only be run by tests. */
+    fn exported_scripts(&self) -> impl Iterator<Item = (&'a usize, &'a FMComponentScript)> {
+        self.file.scripts.iter().filter(|s| s.1.export)
+    }
+
+    /* Step ids are kept from decompiled scripts. Scripts compiled from Burn
+     * don't have any, so their steps are numbered in order. */
+    fn step_ids(script: &FMComponentScript) -> Vec<u32> {
+        let mut ids = script.instructions.iter().map(|s| s.index as u32).collect::<Vec<_>>();
+        ids.sort();
+        ids.dedup();
+        if ids.len() == script.instructions.len() && !ids.contains(&0) {
+            script.instructions.iter().map(|s| s.index as u32).collect()
+        } else {
+            (1..=script.instructions.len() as u32).collect()
+        }
+    }
+
+    fn step_record(&self, id: u32, step: &ScriptStep) -> Vec<u8> {
+        let mut record = vec![0; STEP_RECORD_SIZE];
+        let id = put_path_int(id);
+        record[0] = id.len() as u8 + 1;
+        record[1] = id.len() as u8;
+        record[2..2 + id.len()].copy_from_slice(&id);
+        if step.opcode == Instruction::PerformScript {
+            let target = step.switches.first()
+                .and_then(|name| self.exported_scripts().find(|s| s.1.script_name == *name));
+            record[8] = target.map(|s| *s.0 as u8).unwrap_or(0);
+        }
+        record[STEP_OPCODE_OFFSET] = step.opcode.clone() as u8;
+        record[STEP_FLAGS_OFFSET] = STEP_DEFAULT_FLAGS;
+        record[STEP_FLAGS_OFFSET + 1] = 4;
+        record
+    }
+
+    /* Writes both forms of a calculation into the current directory, see `calc_bytecode`. */
+    fn emit_calculation(&mut self, calc: &str, table: Option<usize>) -> Result<(), String> {
+        let calculation = compile_calculation(calc, self.file, table)
+            .map_err(|e| format!("Unable to compile calculation \"{}\". {}", calc, e))?;
+        self.emit_simple_kv(4, &[1, 0])?;
+        self.emit_simple_kv(5, &calculation.infix)?;
+        self.emit_simple_kv(6, &calculation.prefix)?;
+        Ok(())
+    }
+
+    fn emit_calculation_parameter(&mut self, dir: u32, calc: &str) -> Result<(), String> {
+        if !unsupported_functions(calc).is_empty() {
+            return Ok(());
+        }
+        self.push_directory(dir);
+        self.push_directory(5);
+        self.emit_calculation(calc, None)?;
+        self.pop_directory();
+        self.pop_directory();
+        Ok(())
+    }
+
+    /* Parameters live in numbered directories under the step, starting at 128.
+     * Set Variable keeps its name as text and its value and repetition as calculations. */
+    fn emit_step_parameters(&mut self, step: &ScriptStep) -> Result<(), String> {
+        match step.opcode {
+            Instruction::SetVariable => {
+                if let Some(name) = step.switches.first() {
+                    self.push_directory(128);
+                    self.emit_simple_kv(1, &fm_string_encrypt(name.clone()))?;
+                    self.pop_directory();
+                }
+                if let Some(value) = step.switches.get(1) {
+                    self.emit_calculation_parameter(129, value)?;
+                }
+                self.emit_calculation_parameter(130, "1")?;
+            },
+            Instruction::BlankLineComment | Instruction::CommentedOut => {
+                if let Some(comment) = &step.comment {
+                    self.push_directory(128);
+                    self.emit_simple_kv(1, &fm_string_encrypt(comment.replace('\n', "\r")))?;
+                    self.pop_directory();
+                }
+            },
+            Instruction::PerformScript => {
+                for (n, calc) in step.switches.iter().enumerate().skip(1) {
+                    self.emit_calculation_parameter(128 + n as u32, calc)?;
+                }
+            },
+            _ => {
+                for (n, calc) in step.switches.iter().enumerate() {
+                    self.emit_calculation_parameter(128 + n as u32, calc)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn emit_script_data(&mut self) -> Result<(), String> {
         self.push_directory(17);
         self.push_directory(1);
-        self.emit_simple_kv(0, &[1, self.file.scripts.len() as u8]);
+        self.emit_script_index()?;
+        self.emit_creator_metadata("", "")?;
+        self.pop_directory();
 
-        self.push_directory(1);
+        self.push_directory(5);
+        for (id, script) in self.exported_scripts() {
+            self.emit_script(*id, script)?;
+        }
+        self.pop_directory();
+        self.pop_directory();
+        Ok(())
+    }
+
+    /* Keys 0 and 4 and directories 1, 3 and 7 of [17].[1], listing the scripts by name and in order. */
+    fn emit_script_index(&mut self) -> Result<(), String> {
+        let scripts = self.exported_scripts().collect::<Vec<_>>();
+        self.emit_catalog_index(&scripts.iter()
+            .map(|(id, script)| (**id, script.script_name.as_str()))
+            .collect::<Vec<_>>())?;
 
-        for script in &self.file.scripts {
-            self.emit_simple_data(&encode_text(&script.1.script_name));
+        self.push_directory(7);
+        for (id, script) in &scripts {
+            self.push_directory(**id as u32);
+            self.emit_simple_kv(16, &fm_string_encrypt(script.script_name.clone()))?;
+            self.pop_directory();
         }
         self.pop_directory();
+        Ok(())
+    }
+
+    /* [17].[5].[script] */
+    fn emit_script(&mut self, id: usize, script: &FMComponentScript) -> Result<(), String> {
+        /* The disabled flag is only read from files so far, so steps that
+         * don't run are left out rather than written with a guessed flag.
+         * The script runs the same either way. */
+        let active = active_steps(&script.instructions);
+        let mut script = script.clone();
+        script.instructions = script.instructions.into_iter().zip(active)
+            .filter_map(|(step, active)| active.then_some(step))
+            .collect();
+        let script = &script;
+        self.push_directory(id as u32);
+        let ids = Self::step_ids(script);
+        let records = script.instructions.iter().zip(&ids)
+            .flat_map(|(step, id)| self.step_record(*id, step))
+            .collect::<Vec<u8>>();
+        if records.len() > u8::MAX as usize {
+            self.push_directory(4);
+            self.emit_segmented_data(&records)
+                .map_err(|e| format!("Script {} is too long. {}", script.script_name, e))?;
+            self.pop_directory();
+        } else if !records.is_empty() {
+            self.emit_simple_kv(4, &records)?;
+        }
+
+        self.push_directory(5);
+        for (step, id) in script.instructions.iter().zip(&ids) {
+            self.push_directory(*id);
+            self.emit_step_parameters(step)
+                .map_err(|e| format!("Script {}: {}", script.script_name, e))?;
+            self.pop_directory();
+        }
         self.pop_directory();
+        self.emit_creator_metadata(&script.created_by_account, &script.create_by_user)?;
         self.pop_directory();
+        Ok(())
+    }
+
+    /* Directories pushed with the 16 byte key of the component they index. */
+    fn push_keyed_directory(&mut self, key: &[u8]) {
+        let mut chunk = vec![0x38, key.len() as u8];
+        chunk.extend(key);
+        self.emit(&chunk);
+        self.path.push(chunk);
+        self.emit_noop();
+    }
+
+    /* FileMaker writes this after the creator of every account and privilege set. */
+    fn emit_security_marker(&mut self) {
+        self.emit(&[0x08, 252, 2]);
+    }
+
+    /* Privilege sets only record the account that created them. */
+    fn emit_security_creator(&mut self, account: &str) -> Result<(), String> {
+        let (account, _) = self.creator_names(account, "");
+        self.emit_simple_kv_e(129, &fm_string_encrypt(account))?;
+        self.emit_security_marker();
+        Ok(())
     }
 
-    pub fn emit_security_data(&mut self) {
+    /* The standard sets come first, numbered the way FileMaker numbers them,
+     * followed by the custom sets of the file. */
+    fn privilege_sets(&self) -> Vec<(usize, FMComponentPrivilegeSet, Vec<u8>, Vec<u8>)> {
+        let mut sets = PrivilegeAccess::STANDARD.iter()
+            .map(|access| {
+                let mut set = self.file.privilege_sets.values()
+                    .find(|s| s.set_name == access.set_name())
+                    .cloned()
+                    .unwrap_or(FMComponentPrivilegeSet::new());
+                set.set_name = access.set_name().to_string();
+                set.access = *access;
+                set
+            })
+            .enumerate()
+            .map(|(n, set)| {
+                let (key, uuid) = security::standard_set_ids(set.access);
+                (n + 1, set, key.to_vec(), uuid.to_vec())
+            })
+            .collect::<Vec<_>>();
+        let custom = self.file.privilege_sets.values()
+            .filter(|s| !PrivilegeAccess::STANDARD.iter().any(|a| a.set_name() == s.set_name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for set in custom {
+            let id = sets.len() + 1;
+            let (key, uuid) = security::custom_set_ids(&self.file.name, id, &set.set_name);
+            sets.push((id, set, key, uuid));
+        }
+        sets
+    }
+
+    /* [23].[1] holds accounts, [23].[2] privilege sets. Both have a catalog at
+     * [1] and the definitions at [5]. */
+    pub fn emit_security_data(&mut self) -> Result<(), String> {
         self.push_directory(23);
+        self.emit_account_data()?;
+        self.emit_privilege_set_data()?;
+        self.pop_directory();
+        Ok(())
+    }
+
+    fn emit_account_data(&mut self) -> Result<(), String> {
+        let admin = self.admin_account.clone();
+        self.push_directory(1);
+        self.push_directory(1);
+        self.emit_encoded_catalog_index(&[
+            (security::GUEST_ACCOUNT_ID, security::GUEST_INDEX_NAME.to_vec()),
+            (security::ADMIN_ACCOUNT_ID, encode_text(&admin)),
+        ])?;
+        /* The account the file is opened with. */
+        self.emit_simple_kv(16, &fm_string_encrypt(admin.clone()))?;
+        self.pop_directory();
+
+        let full_access = PrivilegeAccess::STANDARD.iter().position(|a| *a == PrivilegeAccess::FullAccess).unwrap() + 1;
+        let read_only = PrivilegeAccess::STANDARD.iter().position(|a| *a == PrivilegeAccess::ReadOnlyAccess).unwrap() + 1;
+        self.push_directory(5);
+        self.push_directory(security::GUEST_ACCOUNT_ID as u32);
+        self.emit_simple_kv(4, security::GUEST_KEY)?;
+        self.emit_simple_kv(6, security::GUEST_VERIFIER)?;
+        self.emit_simple_kv(10, security::GUEST_OPTIONS)?;
+        self.emit_simple_kv(11, &[1, read_only as u8])?;
+        self.push_directory(16);
+        for (key, value) in security::GUEST_SETTINGS.iter().enumerate() {
+            self.emit_simple_kv(key as u8, value)?;
+        }
+        self.pop_directory();
+        self.emit_simple_kv(216, security::GUEST_UUID)?;
+        self.emit_security_creator("")?;
+        self.pop_directory();
+
+        self.push_directory(security::ADMIN_ACCOUNT_ID as u32);
+        self.emit_simple_kv(4, security::ADMIN_KEY)?;
+        self.emit_simple_kv(6, security::ADMIN_VERIFIER)?;
+        self.emit_simple_kv(10, security::ADMIN_OPTIONS)?;
+        self.emit_simple_kv(11, &[1, full_access as u8])?;
+        self.emit_simple_kv(16, &fm_string_encrypt(admin))?;
+        self.emit_simple_kv(216, security::ADMIN_UUID)?;
+        self.emit_simple_kv(252, &[1, 1])?;
+        self.emit_creator_metadata("", "")?;
+        self.pop_directory();
+        self.pop_directory();
+        self.pop_directory();
+        Ok(())
+    }
+
+    fn emit_privilege_set_data(&mut self) -> Result<(), String> {
+        let sets = self.privilege_sets();
+        self.push_directory(2);
+        self.push_directory(1);
+        self.emit_catalog_index(&sets.iter()
+            .map(|(id, set, _, _)| (*id, set.set_name.as_str()))
+            .collect::<Vec<_>>())?;
+        /* Sets indexed by their key, in key order. */
+        self.push_directory(8);
+        let mut keys = sets.iter().map(|(id, _, key, _)| (key.clone(), *id)).collect::<Vec<_>>();
+        keys.sort();
+        for (key, id) in keys {
+            self.push_keyed_directory(&key);
+            self.emit(&[0x00, id as u8]);
+            self.pop_directory();
+        }
+        self.pop_directory();
+        self.emit_security_creator("")?;
+        self.pop_directory();
 
+        self.push_directory(5);
+        for (id, set, key, uuid) in &sets {
+            let (flags, categories, options) = set.access.to_flags();
+            self.push_directory(*id as u32);
+            self.emit_simple_kv(2, &flags)?;
+            if !set.set_description.is_empty() {
+                self.emit_simple_kv(3, &fm_string_encrypt(set.set_description.clone()))?;
+            }
+            self.emit_simple_kv(4, key)?;
+            self.push_directory(9);
+            for (category, value) in [4, 17, 33].into_iter().zip(categories) {
+                self.emit_simple_kv(category, &[value])?;
+            }
+            self.pop_directory();
+            self.emit_simple_kv(10, &options)?;
+            self.emit_simple_kv(16, &fm_string_encrypt(set.set_name.clone()))?;
+            self.emit_simple_kv(216, uuid)?;
+            self.emit_security_creator(&set.created_by_account)?;
+            self.pop_directory();
+        }
+        self.pop_directory();
         self.pop_directory();
+        Ok(())
     }
 
-    pub fn emit_value_list_data(&mut self) {
+    pub fn emit_value_list_data(&mut self) -> Result<(), String> {
         self.push_directory(33);
+        self.push_directory(1);
+        self.emit_catalog_index(&self.file.value_lists.iter()
+            .map(|(id, list)| (*id, list.list_name.as_str()))
+            .collect::<Vec<_>>())?;
+        self.push_directory(7);
+        for (id, list) in &self.file.value_lists {
+            self.push_directory(*id as u32);
+            self.emit_simple_kv(16, &fm_string_encrypt(list.list_name.clone()))?;
+            self.emit_creator_metadata(&list.created_by_account, &list.create_by_user)?;
+            self.pop_directory();
+        }
         self.pop_directory();
+        self.emit_catalog_metadata("value lists", "", "")?;
+        self.pop_directory();
+
+        self.push_directory(5);
+        for (id, list) in &self.file.value_lists {
+            self.emit_value_list(*id, list)?;
+        }
+        self.pop_directory();
+        self.pop_directory();
+        Ok(())
+    }
+
+    /* [32] holds the files a file refers to. Like other catalogs, [32].[1]
+     * indexes them by name, with one [0x00, id] chunk per source at [7]. */
+    pub fn emit_data_source_data(&mut self) -> Result<(), String> {
+        if self.file.data_sources.is_empty() {
+            return Ok(());
+        }
+        self.push_directory(32);
+        self.push_directory(1);
+        self.emit_catalog_index(&self.file.data_sources.iter()
+            .map(|(id, source)| (*id, source.source_name.as_str()))
+            .collect::<Vec<_>>())?;
+        self.push_directory(7);
+        for id in self.file.data_sources.keys() {
+            let id = u8::try_from(*id)
+                .map_err(|_| format!("Data source {} doesn't fit in a byte, only data sources with ids up to 255 can be written yet.", id))?;
+            self.emit(&[0x00, id]);
+        }
+        self.pop_directory();
+        self.emit_catalog_metadata("data sources", "", "")?;
+        self.pop_directory();
+
+        self.push_directory(5);
+        for (id, source) in &self.file.data_sources {
+            let location = source.source_location.to_bytes()
+                .map_err(|e| format!("Data source {}: {}", source.source_name, e))?;
+            self.push_directory(*id as u32);
+            self.emit_simple_kv(16, &fm_string_encrypt(source.source_name.clone()))?;
+            self.emit_simple_kv(130, &location)?;
+            self.emit_simple_kv(216, &derived_uuid(&format!("data source\0{}\0{}", self.file.name, id)))?;
+            self.emit_creator_metadata(&source.created_by_account, &source.created_by_user)?;
+            self.pop_directory();
+        }
+        self.pop_directory();
+        self.pop_directory();
+        Ok(())
+    }
+
+    /* [33].[5].[list]: key 2 holds the kind of source. Custom values are kept
+     * as one text at key 7, a field source as a reference at key 4. */
+    fn emit_value_list(&mut self, id: usize, list: &FMComponentValueList) -> Result<(), String> {
+        self.push_directory(id as u32);
+        self.emit_simple_kv(2, &[list.source.to_byte()])?;
+        match &list.source {
+            ValueListSource::CustomValues(values) => {
+                let values = fm_string_encrypt(values.join("\r"));
+                if values.len() > u8::MAX as usize {
+                    self.push_directory(7);
+                    self.emit_segmented_data(&values)
+                        .map_err(|e| format!("Value list {} is too long. {}", list.list_name, e))?;
+                    self.pop_directory();
+                } else {
+                    self.emit_simple_kv(7, &values)?;
+                }
+            },
+            ValueListSource::Field { table_occurrence, table_occurrence_name, field, field_name } => {
+                let occurrence = self.resolve_occurrence(*table_occurrence, table_occurrence_name);
+                let mut reference = Self::occurrence_reference(occurrence).to_vec();
+                reference.extend(self.resolve_field(occurrence, *field, field_name).to_be_bytes());
+                self.emit_simple_kv(4, &reference)?;
+            },
+        }
+        self.pop_directory();
+        Ok(())
     }
 
     pub fn emit_font_data(&mut self) {
@@ -274,51 +1149,859 @@ impl<'a> Assembler<'a> {
         self.pop_directory();
     }
 
-    pub fn emit_table_data(&mut self) {
+    pub fn emit_table_data(&mut self) -> Result<(), String> {
+        for (id, table) in &self.file.tables {
+            self.push_directory(128 + *id as u32);
+            let records = self.file.records.get(id).filter(|r| !r.is_empty());
+            if let Some(records) = records {
+                self.emit_record_count(records)?;
+            }
+            self.emit_field_data(*id, table)?;
+            if let Some(records) = records {
+                self.emit_records(records)
+                    .map_err(|e| format!("Records of table {}: {}", table.table_name, e))?;
+            }
+            self.pop_directory();
+        }
+        Ok(())
+    }
 
+    /* [128 + table] key 0 holds the highest record id, [1].[1] key 16 the record count twice. */
+    fn emit_record_count(&mut self, records: &BTreeMap<usize, FMComponentRecord>) -> Result<(), String> {
+        let max_id = put_int(records.keys().max().copied().unwrap_or(0));
+        let start = max_id.iter().position(|b| *b != 0).unwrap_or(3);
+        let mut key0 = vec![(4 - start) as u8];
+        key0.extend(&max_id[start..]);
+        self.emit_simple_kv(0, &key0)?;
+        self.push_directory(1);
+        self.push_directory(1);
+        let mut count = (records.len() as u64).to_be_bytes().to_vec();
+        count.extend((records.len() as u64).to_be_bytes());
+        self.emit_simple_kv(16, &count)?;
+        self.pop_directory();
+        self.pop_directory();
+        Ok(())
     }
 
+    /* [128 + table].[5].[record], one key per field holding its text. */
+    fn emit_records(&mut self, records: &BTreeMap<usize, FMComponentRecord>) -> Result<(), String> {
+        self.push_directory(5);
+        for (id, record) in records {
+            self.push_directory(*id as u32);
+            for (field, value) in &record.fields {
+                /* Key 252 is the record's metadata, and keys past 383 don't
+                 * fit the two byte path ints we read. */
+                if *field == RECORD_METADATA_KEY || *field > MAX_RECORD_FIELD_KEY {
+                    return Err(format!(
+                        "Record {} has a value for field {}, only fields with ids up to {} other than {} can be written yet.",
+                        id, field, MAX_RECORD_FIELD_KEY, RECORD_METADATA_KEY));
+                }
+                let value = fm_string_encrypt(value.clone());
+                match u8::try_from(*field) {
+                    Ok(key) => self.emit_simple_kv(key, &value),
+                    Err(_) => self.emit_simple_kv_e(*field as u32, &value),
+                }.map_err(|e| format!("Value of field {} in record {}: {}", field, id, e))?;
+            }
+            self.emit_simple_kv(RECORD_METADATA_KEY as u8, &[1, 1])?;
+            self.pop_directory();
+        }
+        self.pop_directory();
+        Ok(())
+    }
 
-    pub fn assemble_fmp12(&mut self, schema: &FmpFile) {
+
+    /* Fails when something in the file can't be written, like a calculation
+     * that doesn't compile. */
+    pub fn assemble_fmp12(&mut self, schema: &FmpFile) -> Result<(), String> {
 
         self.buffer.extend(HEADER_INIT);
 
         let name_header = schema.name.clone() + "/";
         self.buffer.splice(3082..3082+name_header.len(), name_header.as_bytes().to_vec());
-        // println!("{} == {:?}", name_header, &self.buffer[3082..3082+name_header.len()]);
 
-        self.append_blank_chunk();
-        self.buffer.splice((self.idx+8) as usize..(self.idx+12) as usize, 4_u32.to_be_bytes());
-        self.append_blank_chunk();
-        self.buffer.splice((self.idx+8) as usize..(self.idx+12) as usize, 3_u32.to_be_bytes());
-        self.idx += 20;
+        self.buffer.resize(SECTOR_SIZE, 0);
+
+        /* Sector 1 isn't part of the chunk stream. */
+        self.buffer.resize(2 * SECTOR_SIZE, 0);
+        self.start_sector();
         self.emit_noop();
-        self.emit_init_blobs();
-        self.emit_table_metadata();
-        self.emit_relationship_data();
-        self.emit_layout_data();
+        self.emit_init_blobs()?;
+        self.emit_table_metadata()?;
+        self.emit_relationship_data()?;
+        self.emit_layout_data()?;
         self.emit_theme_data();
-        self.emit_script_data();
-        self.emit_security_data();
+        self.emit_script_data()?;
+        self.emit_security_data()?;
         self.emit_theme_data();
         self.emit_font_data();
+        self.emit_data_source_data()?;
+        self.emit_value_list_data()?;
         self.emit_toolbar_data();
-        self.append_blank_chunk();
-        self.buffer.splice((self.idx+8) as usize..(self.idx+12) as usize, 4_u32.to_be_bytes());
-        self.idx += 20;
-        self.append_blank_chunk();
-        self.buffer.splice((self.idx+8) as usize..(self.idx+12) as usize, 0_u32.to_be_bytes());
+        self.emit_table_data()?;
+
+        self.finish_sector();
+        self.write_block_count();
+        Ok(())
+    }
+
+    fn write_block_count(&mut self) {
+        let blocks = (self.buffer.len() / SECTOR_SIZE - 1) as u32;
+        self.buffer[SECTOR_SIZE + 8..SECTOR_SIZE + 12].copy_from_slice(&blocks.to_be_bytes());
+    }
+
+    /* Writes `targets` on their own, each from the root of the tree, for
+     * `patch` to take the chunks it replaces from. */
+    pub(super) fn emit_patch_targets(mut self, targets: &[PatchTarget]) -> Result<Vec<u8>, String> {
+        self.start_sector();
+        for target in targets {
+            self.emit_patch_target(target)?;
+        }
+        self.finish_sector();
+        Ok(self.buffer)
+    }
+
+    fn emit_patch_target(&mut self, target: &PatchTarget) -> Result<(), String> {
+        match *target {
+            PatchTarget::FieldIndex(table) => {
+                self.push_directory(128 + table as u32);
+                self.push_directory(3);
+                self.emit_field_index(&self.file.tables[&table])?;
+                self.pop_directory();
+                self.pop_directory();
+            },
+            PatchTarget::Field(table, field) => {
+                self.push_directory(128 + table as u32);
+                self.push_directory(3);
+                self.push_directory(5);
+                self.emit_field_definition(table, field, &self.file.tables[&table].fields[&field])?;
+                self.pop_directory();
+                self.pop_directory();
+                self.pop_directory();
+            },
+            PatchTarget::ScriptIndex => {
+                self.push_directory(17);
+                self.push_directory(1);
+                self.emit_script_index()?;
+                self.pop_directory();
+                self.pop_directory();
+            },
+            PatchTarget::Script(id) => {
+                self.push_directory(17);
+                self.push_directory(5);
+                self.emit_script(id, &self.file.scripts[&id])?;
+                self.pop_directory();
+                self.pop_directory();
+            },
+            PatchTarget::ValueLists => self.emit_value_list_data()?,
+            /* Nothing is written for what's taken out. */
+            PatchTarget::RemovedField(..) | PatchTarget::RemovedScript(_) => {},
+        }
+        Ok(())
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn into_buffer(self) -> Vec<u8> {
+        self.buffer
     }
 
-    pub fn emit_assembly(&self, filename: &str) {
-        write(format!("{}.fmp12", filename), &self.buffer).expect("unable to write to file.");
+    pub fn write_to(&self, out: &mut impl Write) -> std::io::Result<()> {
+        out.write_all(&self.buffer)
+    }
+
+    /* Writes the assembled file to `path`, creating its directory if needed. */
+    pub fn emit_assembly(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            create_dir_all(dir)?;
+        }
+        self.write_to(&mut File::create(path)?)
     }
 }
 
+/* Values are preceded by their length in a single byte. */
+fn value_length(val: &[u8]) -> Result<u8, String> {
+    u8::try_from(val.len())
+        .map_err(|_| format!("{} bytes don't fit in a value, at most {} do.", val.len(), u8::MAX))
+}
+
+/* Relationships are referred to by a single byte, in the catalog and in the graph of each occurrence. */
+fn relationship_id(id: usize) -> Result<u8, String> {
+    u8::try_from(id)
+        .map_err(|_| format!("Relationship {} doesn't fit in a byte, only relationships with ids up to 255 can be written yet.", id))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
     use super::Assembler;
+    use super::super::security;
+    use crate::component::{AutoEnterPreset, FMComponentDataSource, FMComponentField, FMComponentRecord, FMComponentRelationship, FMComponentTable,
+        FMComponentTableOccurence, FMComponentValueList, FMRelationCriterion, FMSerialNumber, PrivilegeAccess, RelationComparison, SourceFileLocation, ValueListSource};
+    use crate::conformance::FmpTree;
+    use crate::encoding_util::fm_string_encrypt;
+    use crate::compile::seed::seed_records;
+    use crate::decompile::components::Components;
+    use crate::burn_script::compiler::BurnScriptCompiler;
+    use crate::compile::compiler::compile_burn;
+    use crate::compile::diagnostic::Span;
+    use crate::decompile::decompiler::{decode_sector, get_sector_chain, Decompiler};
+    use crate::fm_script_engine::fm_script_engine_instructions::{Instruction, ScriptStep};
     use crate::FmpFile;
+
+    #[test]
+    fn reproducible_output_test() {
+        let code = std::fs::read_to_string("doc/example.burn").expect("Unable to read file.");
+        let assemble = || {
+            let mut file = compile_burn(&code);
+            file.name = String::from("example");
+            let mut assembler = Assembler::new(&file);
+            assembler.assemble_fmp12(&file).unwrap();
+            assembler.buffer
+        };
+        let first = assemble();
+        for _ in 0..4 {
+            assert!(assemble() == first);
+        }
+
+        let mut file = compile_burn(&code);
+        let mut field = FMComponentField::new();
+        field.field_name = String::from("name");
+        field.created_by_account = String::from("jeff");
+        file.tables.values_mut().next().unwrap().fields.insert(1, field);
+        let mut assembler = Assembler::new(&file).with_creator("build", "Build");
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_reproducible_output_test.fmp12")).with(Components::TABLES).run_on(assembler.buffer());
+        let fields = result.tables.values().flat_map(|t| t.fields.values()).collect::<Vec<_>>();
+        assert!(!fields.is_empty());
+        for field in fields {
+            assert_eq!(field.created_by_account, "build");
+            assert_eq!(field.created_by_user, "Build");
+        }
+    }
+
+    #[test]
+    fn field_definition_test() {
+        let mut file = FmpFile::new();
+        file.name = String::from("field_definition_test");
+        let mut table = FMComponentTable::new();
+        table.table_name = String::from("Person");
+
+        let mut key = FMComponentField::new();
+        key.field_name = String::from("PrimaryKey");
+        key.field_description = String::from("Used as a primary key");
+        key.data_type = String::from("Text");
+        key.validation.unique = true;
+        key.validation.required = true;
+        key.validation.user_override = false;
+        key.validation.calculation = Some(String::from("Abs ( PrimaryKey ) > 0"));
+        key.validation.message = Some(String::from("A primary key is needed"));
+        table.fields.insert(1, key);
+
+        let mut created = FMComponentField::new();
+        created.field_name = String::from("created");
+        created.data_type = String::from("Timestamp");
+        created.prohibit_modification = true;
+        created.auto_enter_preset = Some(AutoEnterPreset::CreationTimestamp);
+        table.fields.insert(2, created);
+
+        let mut label = FMComponentField::new();
+        label.field_name = String::from("label");
+        label.data_type = String::from("Text");
+        label.auto_enter_calc = Some(String::from("PrimaryKey & \" \" & Get ( UUID ) & label"));
+        label.auto_enter_replace = true;
+        table.fields.insert(3, label);
+
+        let mut setting = FMComponentField::new();
+        setting.field_name = String::from("setting");
+        setting.data_type = String::from("Number");
+        setting.global = true;
+        setting.repetitions = 3;
+        table.fields.insert(4, setting);
+
+        /* Ids don't have to follow each other. */
+        let mut size = FMComponentField::new();
+        size.field_name = String::from("size");
+        size.data_type = String::from("Text");
+        size.validation.value_list = Some(String::from("Sizes"));
+        table.fields.insert(7, size);
+        file.tables.insert(1, table);
+        let mut sizes = FMComponentValueList::new();
+        sizes.list_name = String::from("Sizes");
+        sizes.source = ValueListSource::CustomValues(vec![String::from("Small"), String::from("Large")]);
+        file.value_lists.insert(2, sizes);
+
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_field_definition_test.fmp12")).with(Components::ALL).run_on(assembler.buffer());
+        let expected = &file.tables[&1];
+        let actual = &result.tables[&1];
+        assert_eq!(actual.table_name, "Person");
+        assert_eq!(actual.fields.keys().collect::<Vec<_>>(), vec![&1, &2, &3, &4, &7]);
+        for (id, field) in &expected.fields {
+            let decoded = &actual.fields[id];
+            assert_eq!(decoded.field_name, field.field_name);
+            assert_eq!(decoded.field_description, field.field_description);
+            assert_eq!(decoded.data_type, field.data_type);
+            assert_eq!(decoded.global, field.global);
+            assert_eq!(decoded.repetitions, field.repetitions);
+            assert_eq!(decoded.prohibit_modification, field.prohibit_modification);
+            assert_eq!(decoded.auto_enter_preset, field.auto_enter_preset);
+            assert_eq!(decoded.validation, field.validation);
+            assert_eq!(decoded.auto_enter_calc, field.auto_enter_calc);
+            assert_eq!(decoded.auto_enter_replace, field.auto_enter_replace);
+            assert_eq!(decoded.created_by_account, "admin");
+        }
+
+        /* A calculation calling a function without a known code is left out with its option bit. */
+        let file = compile_burn("table T: key: Text, {Abs(1)}, !{not IsEmpty(key)}, end table;");
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_field_definition_test.fmp12")).with(Components::TABLES).run_on(assembler.buffer());
+        let key = &result.tables[&1].fields[&1];
+        assert_eq!(key.auto_enter_calc.as_deref(), Some("Abs(1)"));
+        assert_eq!(key.validation.calculation, None);
+
+        /* Every data type reads back as written. */
+        let types = ["Text", "Number", "Date", "Time", "Timestamp", "Container"];
+        let fields = types.iter().map(|t| format!("{}_field: {},", t.to_lowercase(), t)).collect::<Vec<_>>();
+        let file = compile_burn(&format!("table T: {} end table;", fields.join(" ")));
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_field_definition_test.fmp12")).with(Components::TABLES).run_on(assembler.buffer());
+        let decoded = result.tables[&1].fields.values().map(|f| f.data_type.as_str()).collect::<Vec<_>>();
+        assert_eq!(decoded, types);
+    }
+
+    #[test]
+    fn sector_overflow_test() {
+        let mut file = FmpFile::new();
+        file.name = String::from("sector_overflow_test");
+        for table_id in 1..=3 {
+            let mut table = FMComponentTable::new();
+            table.table_name = format!("Table{}", table_id);
+            for field_id in 1..=150 {
+                let mut field = FMComponentField::new();
+                field.field_name = format!("field_{}", field_id);
+                field.field_description = String::from("A description long enough to take up some room.");
+                field.data_type = String::from("Text");
+                table.fields.insert(field_id, field);
+            }
+            file.tables.insert(table_id, table);
+        }
+
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let buffer = &assembler.buffer;
+        assert_eq!(buffer.len() % 4096, 0);
+        let last = buffer.len() / 4096 - 1;
+        assert!(last > 3);
+        assert_eq!(&buffer[4096 + 8..4096 + 12], &(last as u32).to_be_bytes());
+        for sector in 2..=last {
+            let header = &buffer[sector * 4096..sector * 4096 + 20];
+            let previous = if sector == 2 { 0 } else { sector - 1 };
+            let next = if sector == last { 0 } else { sector + 1 };
+            assert_eq!(header[1], 0);
+            assert_eq!(&header[4..8], &(previous as u32).to_be_bytes());
+            assert_eq!(&header[8..12], &(next as u32).to_be_bytes());
+        }
+
+        let result = Decompiler::new(Path::new("burn_sector_overflow_test.fmp12")).with(Components::TABLES).run_on(buffer);
+        for (table_id, table) in &file.tables {
+            let actual = &result.tables[table_id];
+            assert_eq!(actual.table_name, table.table_name);
+            assert_eq!(actual.fields.len(), table.fields.len());
+            for (id, field) in &table.fields {
+                assert_eq!(actual.fields[id].field_name, field.field_name);
+                assert_eq!(actual.fields[id].field_description, field.field_description);
+            }
+        }
+    }
+
+    #[test]
+    fn relationship_test() {
+        let mut file = FmpFile::new();
+        file.name = String::from("relationship_test");
+        for (id, name) in [(1, "Person"), (2, "Invoice")] {
+            let mut table = FMComponentTable::new();
+            table.table_name = String::from(name);
+            for (field_id, field_name) in [(1, "PrimaryKey"), (2, "ForeignKey"), (3, "Amount")] {
+                let mut field = FMComponentField::new();
+                field.field_name = String::from(field_name);
+                table.fields.insert(field_id, field);
+            }
+            file.tables.insert(id, table);
+        }
+        for (id, name, table) in [(1, "Person", "Person"), (2, "Invoice", "Invoice"), (10, "Person 2", "Person")] {
+            let mut occurrence = FMComponentTableOccurence::new();
+            occurrence.table_occurence_name = String::from(name);
+            occurrence.table_actual_name = String::from(table);
+            file.table_occurrences.insert(id, occurrence);
+        }
+
+        let mut invoices = FMComponentRelationship::new();
+        invoices.table1_name = String::from("Person");
+        invoices.table2_name = String::from("Invoice");
+        invoices.table2_options.allow_creation = true;
+        invoices.table2_options.delete_related = true;
+        let mut key = FMRelationCriterion::new();
+        key.field1_name = String::from("PrimaryKey");
+        key.field2_name = String::from("ForeignKey");
+        let mut amount = FMRelationCriterion::new();
+        amount.field1 = 3;
+        amount.field2 = 3;
+        amount.comparison = RelationComparison::GreaterEqual;
+        invoices.criteria = vec![key, amount];
+        file.relationships.insert(1, invoices);
+
+        let mut everyone = FMComponentRelationship::new();
+        everyone.table1 = 2;
+        everyone.table2 = 10;
+        let mut cartesian = FMRelationCriterion::new();
+        cartesian.field1 = 1;
+        cartesian.field2 = 1;
+        cartesian.comparison = RelationComparison::Cartesian;
+        everyone.criteria = vec![cartesian];
+        file.relationships.insert(2, everyone);
+
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_relationship_test.fmp12")).with(Components::TABLES | Components::RELATIONSHIPS).run_on(assembler.buffer());
+        let mut occurrences = result.table_occurrences.keys().collect::<Vec<_>>();
+        occurrences.sort();
+        assert_eq!(occurrences, vec![&1, &2, &10]);
+        assert_eq!(result.table_occurrences[&10].table_occurence_name, "Person 2");
+        assert_eq!(result.table_occurrences[&10].table_actual, 1);
+
+        let invoices = &result.relationships[&1];
+        assert_eq!((invoices.table1, invoices.table2), (1, 2));
+        assert_eq!(invoices.table2_name, "Invoice");
+        assert!(!invoices.table1_options.allow_creation);
+        assert!(invoices.table2_options.allow_creation && invoices.table2_options.delete_related);
+        assert_eq!(invoices.criteria.len(), 2);
+        assert_eq!((invoices.criteria[0].field1, invoices.criteria[0].field2), (1, 2));
+        assert_eq!(invoices.criteria[0].field2_name, "ForeignKey");
+        assert_eq!(invoices.criteria[0].comparison, RelationComparison::Equal);
+        assert_eq!(invoices.criteria[1].field1_name, "Amount");
+        assert_eq!(invoices.criteria[1].comparison, RelationComparison::GreaterEqual);
+
+        let everyone = &result.relationships[&2];
+        assert_eq!((everyone.table1, everyone.table2), (2, 10));
+        assert_eq!(everyone.table2_name, "Person 2");
+        assert_eq!(everyone.criteria, file.relationships[&2].criteria.iter().map(|c| {
+            let mut c = c.clone();
+            c.field1_name = String::from("PrimaryKey");
+            c.field2_name = String::from("PrimaryKey");
+            c
+        }).collect::<Vec<_>>());
+
+        file.relationships.get_mut(&1).unwrap().criteria[1].field2 = 300;
+        let err = Assembler::new(&file).assemble_fmp12(&file).unwrap_err();
+        assert_eq!(err, "Criterion 2 of relationship 1 compares fields 3 and 300, only fields with ids from 1 to 255 can be written yet.");
+        file.relationships.get_mut(&1).unwrap().criteria[1].field2 = 3;
+
+        let everyone = file.relationships.remove(&2).unwrap();
+        file.relationships.insert(256, everyone);
+        let err = Assembler::new(&file).assemble_fmp12(&file).unwrap_err();
+        assert_eq!(err, "Relationship 256 doesn't fit in a byte, only relationships with ids up to 255 can be written yet.");
+        file.relationships.remove(&256);
+
+        file.table_occurrences.get_mut(&10).unwrap().table_actual_name = String::from("Nothing");
+        let err = Assembler::new(&file).assemble_fmp12(&file).unwrap_err();
+        assert_eq!(err, "Table occurrence Person 2 is based on table 0, only tables with ids from 1 to 255 can be written yet.");
+    }
+
+    #[test]
+    fn layout_and_value_list_test() {
+        let code = "
+            table Person:
+              name: Text,
+              age: Number,
+            end table;
+            table_occurence People:
+              table: Person,
+            end table_occurence;
+            table_occurence Owner:
+              table: Person,
+            end table_occurence;
+            value_list sexes:
+              \"male\", \"female\"
+            end value_list;
+            value_list names:
+              field: People::name,
+            end value_list;
+            layout Details:
+              table_occurence: People,
+              name, age,
+            end layout;
+        ";
+        let mut file = compile_burn(code);
+        file.name = String::from("layout_and_value_list_test");
+        /* Owner has no layout of its own, so it's given a default one. */
+        assert_eq!(file.layouts.len(), 2);
+        assert_eq!(file.layouts[&2].layout_name, "Owner");
+        assert_eq!(file.layouts[&2].fields.len(), 2);
+
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_layout_and_value_list_test.fmp12")).run_on(assembler.buffer());
+
+        let details = &result.layouts[&1];
+        assert_eq!(details.layout_name, "Details");
+        assert_eq!(details.table_occurrence_name, "People");
+        assert_eq!(details.fields, file.layouts[&1].fields.iter().zip([1, 2]).map(|(f, id)| {
+            let mut f = f.clone();
+            f.field = id;
+            f
+        }).collect::<Vec<_>>());
+        assert_eq!(result.layouts[&2].table_occurrence, 2);
+
+        assert_eq!(result.value_lists[&1].list_name, "sexes");
+        assert_eq!(result.value_lists[&1].source,
+            ValueListSource::CustomValues(vec![String::from("male"), String::from("female")]));
+        assert_eq!(result.value_lists[&2].source, ValueListSource::Field {
+            table_occurrence: 1,
+            table_occurrence_name: String::from("People"),
+            field: 1,
+            field_name: String::from("name"),
+        });
+
+        /* Occurrence references are a single byte above 128. */
+        file.layouts.get_mut(&1).unwrap().table_occurrence = 128;
+        let err = Assembler::new(&file).assemble_fmp12(&file).unwrap_err();
+        assert_eq!(err, "Layout Details is based on table occurrence 128, only occurrences with ids below 128 can be written yet.");
+    }
+
+    #[test]
+    fn seeded_records_test() {
+        let code = "
+            table Status:
+              id: Number,
+              name: Text,
+              rank: Number,
+            end table;
+            data Status:
+              name, rank;
+              \"Open\", 1;
+              \"Closed\", 2.0;
+            end data;
+        ";
+        let mut file = compile_burn(code);
+        file.name = String::from("seeded_records_test");
+        file.tables.get_mut(&1).unwrap().fields.get_mut(&1).unwrap().auto_enter_serial = Some(FMSerialNumber::new());
+        seed_records(&mut file).unwrap();
+
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_seeded_records_test.fmp12")).run_on(assembler.buffer());
+
+        let records = &result.records[&1];
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[&1].fields, BTreeMap::from([
+            (1, String::from("1")), (2, String::from("Open")), (3, String::from("1"))]));
+        assert_eq!(records[&2].fields[&3], "2");
+        let serial = result.tables[&1].fields[&1].auto_enter_serial.as_ref().unwrap();
+        assert_eq!(serial.next_value, 3);
+        assert_eq!(serial.increment, 1);
+    }
+
+    #[test]
+    fn record_limits_test() {
+        let code = "
+            table Notes:
+              body: Text,
+            end table;
+        ";
+        let mut file = compile_burn(code);
+        file.name = String::from("record_limits_test");
+
+        let mut record = FMComponentRecord::new();
+        record.fields.insert(1, "x".repeat(256));
+        file.records.insert(1, BTreeMap::from([(1, record)]));
+        let err = Assembler::new(&file).assemble_fmp12(&file).unwrap_err();
+        assert_eq!(err, "Records of table Notes: Value of field 1 in record 1: 256 bytes don't fit in a value, at most 255 do.");
+
+        for field in [252, 384] {
+            let mut record = FMComponentRecord::new();
+            record.fields.insert(field, String::from("x"));
+            file.records.insert(1, BTreeMap::from([(1, record)]));
+            let err = Assembler::new(&file).assemble_fmp12(&file).unwrap_err();
+            assert_eq!(err, format!("Records of table Notes: Record 1 has a value for field {}, only fields with ids up to 383 other than 252 can be written yet.", field));
+        }
+
+        /* Fields past 255 are keyed with a path int. */
+        let mut record = FMComponentRecord::new();
+        for field in [200, 256, 300, 383] {
+            record.fields.insert(field, field.to_string());
+        }
+        file.records.insert(1, BTreeMap::from([(1, record.clone())]));
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_record_limits_test.fmp12")).run_on(assembler.buffer());
+        assert_eq!(result.records[&1][&1].fields, record.fields);
+
+        let mut record = FMComponentRecord::new();
+        record.fields.insert(1, "x".repeat(255));
+        file.records.insert(1, BTreeMap::from([(1, record)]));
+        Assembler::new(&file).assemble_fmp12(&file).unwrap();
+    }
+
+    /* Entries of a name index directory in the order they were written. */
+    fn index_entries(buffer: &[u8], path: &[&str]) -> Vec<Vec<u8>> {
+        get_sector_chain(buffer).into_iter()
+            .flat_map(|idx| decode_sector(buffer, idx, Components::TABLES).chunks)
+            .filter(|c| c.path == path && c.code == 0x1a)
+            .map(|c| c.data.unwrap().to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn field_index_test() {
+        let buffer = std::fs::read("tests/input/blank.fmp12").expect("Unable to read file.");
+        let path = Path::new("tests/input/blank.fmp12");
+        let original = Decompiler::new(path).with(Components::ALL).run_on(&buffer);
+        let mut assembler = Assembler::new(&original);
+        assembler.assemble_fmp12(&original).unwrap();
+        for dir in ["1", "3", "6"] {
+            let expected = index_entries(&buffer, &["129", "3", "1", dir]);
+            assert!(!expected.is_empty());
+            assert_eq!(index_entries(assembler.buffer(), &["129", "3", "1", dir]), expected);
+        }
+
+        let tree = FmpTree::from_buffer(assembler.buffer());
+        let uuid = &tree.directories[&vec![String::from("129"), String::from("3"), String::from("5"), String::from("1")]]["216"];
+        assert_eq!(uuid.len(), 16);
+        assert_eq!(uuid[6] >> 4, 4);
+        assert_ne!(uuid, &tree.directories[&vec![String::from("129"), String::from("3"), String::from("5"), String::from("2")]]["216"]);
+
+        let mut file = compile_burn("
+            table Notes:
+              body: Text,
+            end table;
+        ");
+        let table = file.tables.get_mut(&1).unwrap();
+        let field = table.fields.remove(&1).unwrap();
+        table.fields.insert(300, field);
+        let err = Assembler::new(&file).assemble_fmp12(&file).unwrap_err();
+        assert_eq!(err, "Unable to index the fields of table Notes. Id 300 doesn't fit in a name index, only ids up to 255 can be written yet.");
+    }
+
+    #[test]
+    fn write_to_test() {
+        let mut file = FmpFile::new();
+        file.name = String::from("write_to_test");
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let mut out = vec![];
+        assembler.write_to(&mut out).unwrap();
+        assert_eq!(out, assembler.buffer());
+        assert_eq!(assembler.into_buffer(), out);
+    }
+
+    #[test]
+    fn security_test() {
+        let code = "
+            privilege_set Sales:
+              access: read_only,
+              description: \"Sales staff\",
+            end privilege_set;
+        ";
+        let mut file = compile_burn(code);
+        file.name = String::from("security_test");
+        let mut assembler = Assembler::new(&file).with_admin_account("Owner");
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_security_test.fmp12")).run_on(assembler.buffer());
+
+        let sets = result.privilege_sets.values()
+            .map(|s| (s.set_name.as_str(), s.access))
+            .collect::<Vec<_>>();
+        assert_eq!(sets, vec![
+            ("[Full Access]", PrivilegeAccess::FullAccess),
+            ("[Data Entry Only]", PrivilegeAccess::DataEntryOnly),
+            ("[Read-Only Access]", PrivilegeAccess::ReadOnlyAccess),
+            ("Sales", PrivilegeAccess::ReadOnlyAccess),
+        ]);
+        assert_eq!(result.privilege_sets[&4].set_description, "Sales staff");
+
+        /* The admin account is given full access. */
+        let tree = FmpTree::from_buffer(&assembler.buffer);
+        let admin = &tree.directories[&["23", "1", "5", "2"].map(String::from).to_vec()];
+        assert_eq!(admin["16"], fm_string_encrypt(String::from("Owner")));
+        assert_eq!(admin["11"], vec![1, 1]);
+
+        /* Custom sets get ids of their own, which change with the file. */
+        let sales = &tree.directories[&["23", "2", "5", "4"].map(String::from).to_vec()];
+        let (key, uuid) = security::custom_set_ids("security_test", 4, "Sales");
+        assert_eq!((&sales["4"], &sales["216"]), (&key, &uuid));
+        assert_ne!(security::custom_set_ids("other_file", 4, "Sales"), (key, uuid));
+    }
+
+    #[test]
+    fn script_round_trip_test() {
+        let code = "
+        define helper() {
+            exit_script(1);
+        }
+        define looper(x, y) {
+            set_variable(i, x);
+            loop {
+                exit_loop_if(i == y);
+                set_variable(i, (i + 1));
+                if(i == 7) {
+                    set_variable(x, 20);
+                } else {
+                    set_variable(x, \"Jeff\" & \" Keighly\");
+                }
+            }
+            perform_script(helper);
+            exit_script(i);
+        }";
+        let mut file = FmpFile::new();
+        file.name = String::from("script_round_trip_test");
+        for script in BurnScriptCompiler::compile_burn_script(code).unwrap() {
+            file.scripts.insert(file.scripts.len() + 1, script);
+        }
+        let mut comment = ScriptStep {
+            opcode: Instruction::BlankLineComment,
+            index: 0,
+            switches: vec![],
+            enabled: true,
+            comment: Some(String::from("first line\nsecond line")),
+            span: Span::default(),
+            switch_spans: vec![],
+        };
+        file.scripts.get_mut(&1).unwrap().instructions.insert(0, comment.clone());
+        comment.opcode = Instruction::CommentedOut;
+        file.scripts.get_mut(&1).unwrap().instructions.insert(1, comment);
+
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_script_round_trip_test.fmp12")).with(Components::SCRIPTS).run_on(assembler.buffer());
+        assert_eq!(result.scripts.len(), 2);
+        /* The second script is long enough to be split into data segments. */
+        assert!(file.scripts[&2].instructions.len() * 28 > u8::MAX as usize);
+        for (id, script) in &file.scripts {
+            let decoded = &result.scripts[id];
+            assert_eq!(decoded.script_name, script.script_name);
+            assert_eq!(decoded.instructions.len(), script.instructions.len());
+            for (n, (expected, actual)) in script.instructions.iter().zip(&decoded.instructions).enumerate() {
+                assert_eq!(actual.index, n + 1);
+                assert_eq!(actual.opcode, expected.opcode);
+                assert_eq!(actual.switches, expected.switches);
+                assert!(actual.enabled);
+                assert_eq!(actual.comment, expected.comment);
+            }
+        }
+
+        /* Steps that don't run are left out, with the markers of a block
+         * whose If is disabled. */
+        let expected = file.scripts[&2].instructions.iter()
+            .filter(|s| !matches!(s.opcode, Instruction::If | Instruction::Else | Instruction::EndIf))
+            .map(|s| s.opcode.clone())
+            .collect::<Vec<_>>();
+        let looper = file.scripts.get_mut(&2).unwrap();
+        let mut disabled = looper.instructions[3].clone();
+        disabled.enabled = false;
+        looper.instructions.insert(4, disabled);
+        let at = looper.instructions.iter().position(|s| s.opcode == Instruction::If).unwrap();
+        looper.instructions[at].enabled = false;
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("burn_script_round_trip_test.fmp12")).with(Components::SCRIPTS).run_on(assembler.buffer());
+        let decoded = &result.scripts[&2].instructions;
+        assert_eq!(decoded.iter().map(|s| s.opcode.clone()).collect::<Vec<_>>(), expected);
+        assert!(decoded.iter().all(|s| s.enabled));
+    }
+
+    #[test]
+    fn calculation_error_test() {
+        let mut file = FmpFile::new();
+        file.name = String::from("calculation_error_test");
+        for script in BurnScriptCompiler::compile_burn_script("define broken() { set_variable(x, 1 +); }").unwrap() {
+            file.scripts.insert(file.scripts.len() + 1, script);
+        }
+        let mut assembler = Assembler::new(&file);
+        assert_eq!(assembler.assemble_fmp12(&file).err().unwrap(),
+            "Script broken: Unable to compile calculation \"1 +\". Unexpected end of calculation.");
+    }
+
+    #[test]
+    fn field_calculation_error_test() {
+        let mut file = FmpFile::new();
+        let mut table = FMComponentTable::new();
+        table.table_name = String::from("Person");
+        let mut field = FMComponentField::new();
+        field.field_name = String::from("age");
+        field.data_type = String::from("Number");
+        field.auto_enter_calc = Some(String::from("Abs(age"));
+        table.fields.insert(1, field);
+        file.tables.insert(1, table);
+        let mut assembler = Assembler::new(&file);
+        assert_eq!(assembler.assemble_fmp12(&file).err().unwrap(),
+            "Auto-enter calculation of field age: Unable to compile calculation \"Abs(age\". Expected \")\" in calculation, found the end of the calculation.");
+    }
+
+    #[test]
+    fn segment_limit_test() {
+        let mut file = FmpFile::new();
+        let mut list = FMComponentValueList::new();
+        list.list_name = String::from("numbers");
+        list.source = ValueListSource::CustomValues(vec![String::from("12345"); 50_000]);
+        file.value_lists.insert(1, list);
+        let mut assembler = Assembler::new(&file);
+        assert_eq!(assembler.assemble_fmp12(&file).err().unwrap(),
+            "Value list numbers is too long. 299999 bytes don't fit in 255 data segments.");
+    }
+
+    #[test]
+    fn unexported_script_test() {
+        let mut file = FmpFile::new();
+        file.name = String::from("unexported_script_test");
+        for script in BurnScriptCompiler::compile_burn_script("define main() { exit_script(1); } define setup() { exit_script(2); }").unwrap() {
+            file.scripts.insert(file.scripts.len() + 1, script);
+        }
+        file.scripts.get_mut(&2).unwrap().export = false;
+
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("unexported_script_test.fmp12")).with(Components::SCRIPTS).run_on(assembler.buffer());
+        assert_eq!(result.scripts.values().map(|s| s.script_name.as_str()).collect::<Vec<_>>(), vec!["main"]);
+    }
+
+    #[test]
+    fn data_source_test() {
+        let mut file = FmpFile::new();
+        file.name = String::from("data_source_test");
+        let mut source = FMComponentDataSource::new();
+        source.source_name = String::from("Invoices");
+        source.source_location.source_type = String::from("file");
+        source.source_location.source_path = String::from("../shared/");
+        source.source_location.source_filename = String::from("Invoices");
+        source.created_by_account = String::from("Admin");
+        source.created_by_user = String::from("admin");
+        file.data_sources.insert(1, source);
+
+        let bytes = file.data_sources[&1].source_location.to_bytes().unwrap();
+        let location = SourceFileLocation::from_bytes(&bytes);
+        assert_eq!((location.source_type.as_str(), location.source_path.as_str(), location.source_filename.as_str()),
+            ("file", "../shared/", "Invoices"));
+
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let result = Decompiler::new(Path::new("data_source_test.fmp12")).with(Components::DATA_SOURCES).run_on(assembler.buffer());
+        let source = &result.data_sources[&1];
+        assert_eq!(source.source_name, "Invoices");
+        assert_eq!(source.source_location.source_path, "../shared/");
+        assert_eq!(source.source_location.source_filename, "Invoices");
+        assert_eq!((source.created_by_account.as_str(), source.created_by_user.as_str()), ("Admin", "admin"));
+
+        let mut long = file.data_sources[&1].source_location.clone();
+        long.source_filename = "x".repeat(300);
+        assert!(long.to_bytes().is_err());
+    }
+
     #[test]
     fn kv_instruction_test() {
         let tmp = FmpFile::new();
@@ -338,4 +2021,3 @@ mod tests {
                 0, 0, 0]));
     }
 }
-
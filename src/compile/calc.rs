@@ -0,0 +1,535 @@
+use std::iter::Peekable;
+use std::slice::Iter;
+
+use crate::calc_bytecode::*;
+use crate::encoding_util::fm_string_encrypt;
+use crate::file::FmpFile;
+
+/* Both stored forms of a calculation, see `calc_bytecode`. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calculation {
+    pub infix: Vec<u8>,
+    pub prefix: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CalcToken {
+    Whitespace(String),
+    Number(String),
+    Text(String),
+    Name(String),
+    Operator(u8),
+    OpenParen,
+    CloseParen,
+    Separator,
+}
+
+/* What a token means once names have been resolved. Whitespace has no term. */
+#[derive(Debug, Clone, PartialEq)]
+enum Term {
+    Value(Vec<u8>),
+    Function(Vec<u8>),
+    GetSelector(u8),
+    Binary(u8),
+    Negate,
+    Not,
+    Open,
+    Close,
+    Separator,
+}
+
+fn tokenize(text: &str) -> Result<Vec<CalcToken>, String> {
+    let mut tokens = vec![];
+    let mut it = text.chars().peekable();
+
+    while let Some(c) = it.next() {
+        let token = match c {
+            '(' => CalcToken::OpenParen,
+            ')' => CalcToken::CloseParen,
+            ';' => CalcToken::Separator,
+            '"' => {
+                let mut s = String::new();
+                loop {
+                    match it.next() {
+                        Some('"') => break,
+                        /* A backslash keeps the quote or backslash after it in the text. */
+                        Some('\\') if matches!(it.peek(), Some('"') | Some('\\')) => s.push(it.next().unwrap()),
+                        Some(c) => s.push(c),
+                        None => return Err("Unterminated string in calculation.".to_string()),
+                    }
+                }
+                CalcToken::Text(s)
+            }
+            c if c.is_whitespace() => {
+                let mut s = c.to_string();
+                while let Some(d) = it.next_if(|d| d.is_whitespace()) {
+                    s.push(d);
+                }
+                CalcToken::Whitespace(s)
+            }
+            c if c.is_ascii_digit() => {
+                let mut n = c.to_string();
+                while let Some(d) = it.next_if(|d| d.is_ascii_digit()) {
+                    n.push(d);
+                }
+                if let Some(point) = it.next_if_eq(&'.') {
+                    n.push(point);
+                    while let Some(d) = it.next_if(|d| d.is_ascii_digit()) {
+                        n.push(d);
+                    }
+                }
+                CalcToken::Number(n)
+            }
+            c if c.is_alphabetic() || c == '_' || c == '$' => {
+                let mut name = c.to_string();
+                loop {
+                    if let Some(d) = it.next_if(|d| d.is_alphanumeric() || *d == '_' || *d == '$') {
+                        name.push(d);
+                    } else if it.peek() == Some(&':') {
+                        /* Qualified field names, Occurrence::Field */
+                        it.next();
+                        if it.next() != Some(':') {
+                            return Err(format!("Expected \"::\" after {} in calculation.", name));
+                        }
+                        name.push_str("::");
+                    } else {
+                        break;
+                    }
+                }
+                /* Word operators are spelled in any case. */
+                match CALC_OPERATORS.iter().find(|op| op.0.eq_ignore_ascii_case(&name)) {
+                    Some(op) => CalcToken::Operator(op.1),
+                    None => CalcToken::Name(name),
+                }
+            }
+            _ => {
+                let two = it.peek().map(|d| format!("{}{}", c, d));
+                if let Some(op) = two.and_then(|s| CALC_OPERATORS.iter().find(|op| op.0 == s)) {
+                    it.next();
+                    CalcToken::Operator(op.1)
+                } else if let Some(op) = CALC_OPERATORS.iter().find(|op| op.0 == c.to_string()) {
+                    CalcToken::Operator(op.1)
+                } else {
+                    return Err(format!("Unexpected '{}' in calculation.", c));
+                }
+            }
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/* The text of a token as it's written in a calculation. */
+fn token_text(token: &CalcToken) -> String {
+    match token {
+        CalcToken::Whitespace(s) | CalcToken::Number(s) | CalcToken::Name(s) => s.clone(),
+        CalcToken::Text(s) => quoted_text(s),
+        CalcToken::Operator(op) => operator_name(*op).unwrap_or_default().to_string(),
+        CalcToken::OpenParen => String::from("("),
+        CalcToken::CloseParen => String::from(")"),
+        CalcToken::Separator => String::from(";"),
+    }
+}
+
+fn encode_text(code: u8, text: &str, terminated: bool) -> Result<Vec<u8>, String> {
+    let encrypted = fm_string_encrypt(text.to_string());
+    if encrypted.len() > u8::MAX as usize {
+        return Err(format!("Calculation token too long: {}", text));
+    }
+    let mut bytes = vec![code, encrypted.len() as u8];
+    bytes.extend(encrypted);
+    if terminated {
+        bytes.push(0);
+    }
+    Ok(bytes)
+}
+
+/* The digits go in as one integer, followed by how many of them are decimals. */
+fn encode_number(text: &str) -> Result<Vec<u8>, String> {
+    let (whole, decimals) = text.split_once('.').unwrap_or((text, ""));
+    let digits = format!("{}{}", whole, decimals).parse::<u64>()
+        .map_err(|_| format!("Number {} in calculation has too many digits, up to 19 can be written.", text))?;
+    let mut bytes = vec![CALC_NUMBER];
+    bytes.extend(CALC_NUMBER_PREFIX);
+    bytes.extend(digits.to_le_bytes());
+    bytes.push(decimals.len() as u8);
+    Ok(bytes)
+}
+
+/* Resolves a name to a field reference. Unqualified names are looked up in
+ * `table`, qualified ones through the named table occurrence. */
+fn resolve_field(name: &str, file: &FmpFile, table: Option<usize>) -> Option<Vec<u8>> {
+    let (occurrence, table, field) = match name.split_once("::") {
+        Some((to_name, field)) => {
+            let (id, to) = file.table_occurrences.iter()
+                .find(|(_, to)| to.table_occurence_name == to_name)?;
+            let table = if to.table_actual != 0 {
+                to.table_actual as usize
+            } else {
+                *file.tables.iter().find(|(_, t)| t.table_name == to.table_actual_name)?.0
+            };
+            (*id, table, field)
+        },
+        None => (0, table?, name),
+    };
+    let field = *file.tables.get(&table)?
+        .fields.iter()
+        .find(|(_, f)| f.field_name == field)?.0;
+    let mut bytes = vec![CALC_FIELD];
+    bytes.extend((occurrence as u16).to_be_bytes());
+    bytes.extend((table as u16).to_be_bytes());
+    bytes.extend(field.to_be_bytes());
+    Some(bytes)
+}
+
+/* Terms are kept with the text they were read from, for error messages. */
+struct Parser<'a> {
+    terms: Peekable<Iter<'a, (Term, String)>>,
+}
+
+/* Names a term in an error message. */
+fn found(term: Option<&(Term, String)>) -> String {
+    match term {
+        Some((_, text)) => format!("\"{}\"", text),
+        None => String::from("the end of the calculation"),
+    }
+}
+
+fn unexpected(term: Option<&(Term, String)>) -> String {
+    match term {
+        Some((_, text)) => format!("Unexpected \"{}\" in calculation.", text),
+        None => String::from("Unexpected end of calculation."),
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn expect(&mut self, term: Term, text: &str) -> Result<(), String> {
+        match self.terms.next() {
+            Some((t, _)) if *t == term => Ok(()),
+            t => Err(format!("Expected \"{}\" in calculation, found {}.", text, found(t))),
+        }
+    }
+
+    fn parse_expression(&mut self, min_precedence: u8) -> Result<Vec<u8>, String> {
+        let mut lhs = self.parse_unary()?;
+        while let Some((Term::Binary(op), _)) = self.terms.peek() {
+            let op = *op;
+            let precedence = operator_precedence(op);
+            if precedence < min_precedence {
+                break;
+            }
+            self.terms.next();
+            let rhs = self.parse_expression(precedence + 1)?;
+            let mut expr = vec![op];
+            expr.extend(lhs);
+            expr.extend(rhs);
+            lhs = expr;
+        }
+        Ok(lhs)
+    }
+
+    /* A minus applies to the value right after it, "not" to a whole comparison. */
+    fn parse_unary(&mut self) -> Result<Vec<u8>, String> {
+        if self.terms.next_if(|t| t.0 == Term::Negate).is_some() {
+            let mut expr = vec![CALC_NEGATE];
+            expr.extend(self.parse_unary()?);
+            return Ok(expr);
+        }
+        if self.terms.next_if(|t| t.0 == Term::Not).is_some() {
+            let mut expr = vec![CALC_NOT];
+            expr.extend(self.parse_expression(CALC_COMPARISON_PRECEDENCE)?);
+            return Ok(expr);
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Vec<u8>, String> {
+        match self.terms.next() {
+            Some((Term::Value(prefix), _)) => Ok(prefix.clone()),
+            Some((Term::Open, _)) => {
+                let expr = self.parse_expression(0)?;
+                self.expect(Term::Close, ")")?;
+                Ok(expr)
+            },
+            Some((Term::Function(code), name)) => {
+                let mut expr = code.clone();
+                self.expect(Term::Open, "(")?;
+                if let Some((Term::GetSelector(selector), _)) = self.terms.peek() {
+                    expr.push(*selector);
+                    self.terms.next();
+                    self.expect(Term::Close, ")")?;
+                    return Ok(expr);
+                }
+                /* The prefix form lists the arguments one after another. */
+                let mut arguments = 0;
+                loop {
+                    expr.extend(self.parse_expression(0)?);
+                    arguments += 1;
+                    if self.terms.next_if(|t| t.0 == Term::Separator).is_none() {
+                        break;
+                    }
+                }
+                self.expect(Term::Close, ")")?;
+                let expected = CALC_FUNCTIONS.iter().find(|f| f.1 == code.as_slice()).map(|f| f.2).unwrap_or(arguments);
+                if arguments != expected {
+                    return Err(format!("{} takes {} argument{}, found {}.", name, expected,
+                            if expected == 1 { "" } else { "s" }, arguments));
+                }
+                Ok(expr)
+            },
+            t => Err(unexpected(t)),
+        }
+    }
+}
+
+/* Compiles calculation text into the bytecode read by `decompile_calculation`.
+ * `table` is the table the calculation is evaluated in, if any, which lets
+ * fields be referenced without naming an occurrence. */
+pub fn compile_calculation(text: &str, file: &FmpFile, table: Option<usize>) -> Result<Calculation, String> {
+    let tokens = tokenize(text)?;
+    let mut infix = vec![];
+    let mut terms = vec![];
+
+    for (i, token) in tokens.iter().enumerate() {
+        let next = tokens[i + 1..].iter().find(|t| !matches!(t, CalcToken::Whitespace(_)));
+        let (bytes, term) = match token {
+            CalcToken::Whitespace(s) => {
+                let mut bytes = vec![CALC_WHITESPACE];
+                bytes.extend(encode_text(CALC_STRING, s, true)?);
+                (bytes, None)
+            },
+            CalcToken::Number(n) => {
+                let prefix = encode_number(n)?;
+                let mut bytes = prefix.clone();
+                bytes.extend(CALC_NUMBER_END);
+                (bytes, Some(Term::Value(prefix)))
+            },
+            CalcToken::Text(s) => {
+                let bytes = encode_text(CALC_STRING, s, true)?;
+                (bytes.clone(), Some(Term::Value(bytes)))
+            },
+            CalcToken::Name(name) if next == Some(&CalcToken::OpenParen) => {
+                let function = CALC_FUNCTIONS.iter()
+                    .find(|f| f.0.eq_ignore_ascii_case(name))
+                    .ok_or(format!("Unknown function in calculation: {}", name))?;
+                (function.1.to_vec(), Some(Term::Function(function.1.to_vec())))
+            },
+            CalcToken::Name(name) if matches!(terms.as_slice(), [.., (Term::Function(f), _), (Term::Open, _)] if f == &[0x9b]) => {
+                let code = CALC_GET_FUNCTIONS.iter()
+                    .find(|f| f.0.eq_ignore_ascii_case(name))
+                    .ok_or(format!("Unknown Get() option in calculation: {}", name))?.1;
+                (vec![CALC_GET_SELECTOR, code], Some(Term::GetSelector(code)))
+            },
+            CalcToken::Name(name) => {
+                let bytes = match resolve_field(name, file, table) {
+                    Some(field) => field,
+                    None => encode_text(CALC_VARIABLE, name, false)?,
+                };
+                (bytes.clone(), Some(Term::Value(bytes)))
+            },
+            CalcToken::Operator(CALC_NOT) => (vec![CALC_NOT], Some(Term::Not)),
+            CalcToken::Operator(op) => {
+                /* A minus with no value before it negates what follows. */
+                let unary = *op == 0x26 && matches!(terms.last().map(|t| &t.0),
+                    None | Some(Term::Binary(_)) | Some(Term::Negate) | Some(Term::Not) | Some(Term::Open) | Some(Term::Separator));
+                if unary {
+                    (vec![CALC_NEGATE], Some(Term::Negate))
+                } else {
+                    (vec![*op], Some(Term::Binary(*op)))
+                }
+            },
+            CalcToken::OpenParen => (vec![CALC_OPEN_PAREN], Some(Term::Open)),
+            CalcToken::CloseParen => (vec![CALC_CLOSE_PAREN], Some(Term::Close)),
+            CalcToken::Separator => (vec![CALC_SEPARATOR], Some(Term::Separator)),
+        };
+        infix.extend(bytes);
+        terms.extend(term.map(|t| (t, token_text(token))));
+    }
+
+    let mut parser = Parser { terms: terms.iter().peekable() };
+    let prefix = if terms.is_empty() { vec![] } else { parser.parse_expression(0)? };
+    if let Some(t) = parser.terms.next() {
+        return Err(unexpected(Some(t)));
+    }
+    Ok(Calculation { infix, prefix })
+}
+
+/* Functions a calculation calls that `compile_calculation` has no code for.
+ * Calculations that can't be read call none. */
+pub fn unsupported_functions(text: &str) -> Vec<String> {
+    let Ok(tokens) = tokenize(text) else {
+        return vec![];
+    };
+    let tokens = tokens.iter().filter(|t| !matches!(t, CalcToken::Whitespace(_))).collect::<Vec<_>>();
+    let mut names = vec![];
+    for pair in tokens.windows(2) {
+        if let [CalcToken::Name(name), CalcToken::OpenParen] = pair {
+            if !CALC_FUNCTIONS.iter().any(|f| f.0.eq_ignore_ascii_case(name)) && !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+    }
+    names
+}
+
+/* Prints a calculation with one space around binary operators and none
+ * inside parentheses. Calculations that can't be read are only trimmed. */
+pub fn canonical_calculation(text: &str) -> String {
+    let Ok(tokens) = tokenize(text) else {
+        return text.trim().to_string();
+    };
+    let mut result = String::new();
+    let mut operand = false;
+    for token in tokens.iter().filter(|t| !matches!(t, CalcToken::Whitespace(_))) {
+        match token {
+            CalcToken::Operator(CALC_NOT) => {
+                if operand {
+                    result.push(' ');
+                }
+                result.push_str("not ");
+            },
+            /* A minus with no value before it negates what follows. */
+            CalcToken::Operator(op) if operand || *op != 0x26 => {
+                result.push_str(&format!(" {} ", operator_name(*op).unwrap_or_default()));
+            },
+            CalcToken::Operator(op) => result.push_str(operator_name(*op).unwrap_or_default()),
+            CalcToken::Number(_) | CalcToken::Name(_) | CalcToken::Text(_) => {
+                if operand {
+                    result.push(' ');
+                }
+                result.push_str(&token_text(token));
+            },
+            CalcToken::OpenParen => result.push('('),
+            CalcToken::CloseParen => result.push(')'),
+            CalcToken::Separator => result.push_str("; "),
+            CalcToken::Whitespace(_) => {},
+        }
+        operand = matches!(token, CalcToken::Number(_) | CalcToken::Name(_) | CalcToken::Text(_) | CalcToken::CloseParen);
+    }
+    result.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_calculation, compile_calculation};
+    use crate::calc_bytecode::number_text;
+    use crate::encoding_util::fm_string_decrypt;
+    use crate::component::{FMComponentField, FMComponentTable, FMComponentTableOccurence};
+    use crate::file::FmpFile;
+
+    fn number(n: u8) -> Vec<u8> {
+        vec![0x10, 2, 0, 1, 0, 16, 0, 0, 0, n, 0, 0, 0, 0, 0, 0, 0, 0]
+    }
+
+    #[test]
+    fn precedence_test() {
+        let file = FmpFile::new();
+        let calc = compile_calculation("1+2*3", &file, None).unwrap();
+        let mut expected = vec![0x25];
+        expected.extend(number(1));
+        expected.push(0x27);
+        expected.extend(number(2));
+        expected.extend(number(3));
+        assert_eq!(calc.prefix, expected);
+
+        let calc = compile_calculation("-(1-2)", &file, None).unwrap();
+        assert_eq!(calc.infix[..3], [0x1e, 0x4, 0x10]);
+        assert_eq!(calc.prefix[..3], [0x1e, 0x26, 0x10]);
+
+        assert_eq!(compile_calculation("(1", &file, None).err().unwrap(), "Expected \")\" in calculation, found the end of the calculation.");
+        assert_eq!(compile_calculation("1 2", &file, None).err().unwrap(), "Unexpected \"2\" in calculation.");
+        assert_eq!(compile_calculation("1 +", &file, None).err().unwrap(), "Unexpected end of calculation.");
+        assert!(compile_calculation("Unknown(1)", &file, None).is_err());
+        assert!(compile_calculation("\"open", &file, None).is_err());
+    }
+
+    #[test]
+    fn operator_test() {
+        let file = FmpFile::new();
+        let prefix = |text: &str| compile_calculation(text, &file, None).unwrap().prefix;
+        /* FileMaker's spellings are the same operators. */
+        assert_eq!(prefix("1 = 2"), prefix("1 == 2"));
+        assert_eq!(prefix("1 ≠ 2"), prefix("1 != 2"));
+        assert_eq!(prefix("1 <> 2"), prefix("1 != 2"));
+        assert_eq!(prefix("1 ≤ 2"), prefix("1 <= 2"));
+        assert_eq!(prefix("1 ≥ 2"), prefix("1 >= 2"));
+
+        /* "not" takes a whole comparison, "and" binds tighter than "or". */
+        let mut expected = vec![0x2b, 0x2a, 0x1f, 0x44];
+        expected.extend(number(1));
+        expected.extend(number(2));
+        expected.extend(number(3));
+        expected.extend(number(4));
+        assert_eq!(prefix("not 1 = 2 AND 3 or 4"), expected);
+        assert_eq!(compile_calculation("not 1", &file, None).unwrap().infix[..2], [0x1f, 0xc]);
+    }
+
+    #[test]
+    fn number_test() {
+        let file = FmpFile::new();
+        let prefix = |text: &str| compile_calculation(text, &file, None).unwrap().prefix;
+        assert_eq!(prefix("300")[9..11], [44, 1]);
+        let decimal = prefix("12.50");
+        assert_eq!(decimal[9..11], [226, 4]);
+        assert_eq!(decimal[17], 2);
+        assert_eq!(number_text(&decimal[1..]), "12.50");
+        assert_eq!(number_text(&prefix("0.05")[1..]), "0.05");
+        assert_eq!(number_text(&prefix("300")[1..]), "300");
+        /* Negative numbers are negated. */
+        assert_eq!(prefix("-7")[..2], [0x1e, 0x10]);
+        assert!(compile_calculation("123456789012345678901", &file, None).is_err());
+    }
+
+    #[test]
+    fn function_test() {
+        let file = FmpFile::new();
+        let calc = compile_calculation("Char(Abs(-1))", &file, None).unwrap();
+        assert_eq!(calc.prefix[..4], [0xfb, 0x3, 0x2d, 0x1e]);
+        assert_eq!(compile_calculation("Abs(1; 2)", &file, None).err().unwrap(), "Abs takes 1 argument, found 2.");
+        assert_eq!(compile_calculation("Abs(1;)", &file, None).err().unwrap(), "Unexpected \")\" in calculation.");
+    }
+
+    #[test]
+    fn string_test() {
+        let file = FmpFile::new();
+        let calc = compile_calculation("\"say \\\"hi\\\" \\\\\"", &file, None).unwrap();
+        assert_eq!(calc.infix[1], 10);
+        assert_eq!(fm_string_decrypt(&calc.infix[2..12]), "say \"hi\" \\");
+        assert!(compile_calculation("\"open \\\"", &file, None).is_err());
+    }
+
+    #[test]
+    fn field_reference_test() {
+        let mut file = FmpFile::new();
+        let mut table = FMComponentTable::new();
+        table.table_name = String::from("Person");
+        let mut field = FMComponentField::new();
+        field.field_name = String::from("name");
+        table.fields.insert(3, field);
+        file.tables.insert(2, table);
+        let mut occurrence = FMComponentTableOccurence::new();
+        occurrence.table_occurence_name = String::from("People");
+        occurrence.table_actual_name = String::from("Person");
+        file.table_occurrences.insert(7, occurrence);
+
+        let calc = compile_calculation("name", &file, Some(2)).unwrap();
+        assert_eq!(calc.infix, vec![0x16, 0, 0, 0, 2, 0, 3]);
+        let calc = compile_calculation("People::name & $x", &file, None).unwrap();
+        assert_eq!(calc.prefix, vec![0x50, 0x16, 0, 7, 0, 2, 0, 3, 0x1a, 2, b'$' ^ 0x5a, b'x' ^ 0x5a]);
+        /* Without a table, unqualified names are variables. */
+        assert_eq!(compile_calculation("name", &file, None).unwrap().infix[0], 0x1a);
+    }
+
+    #[test]
+    fn canonical_calculation_test() {
+        assert_eq!(canonical_calculation("3 ^ 2 +  (- 0 * 4)"), "3 ^ 2 + (-0 * 4)");
+        assert_eq!(canonical_calculation(" Get ( UUID ) "), "Get(UUID)");
+        assert_eq!(canonical_calculation("Char(88)&\"c\""), "Char(88) & \"c\"");
+        assert_eq!(canonical_calculation("People::name<=-$x"), "People::name <= -$x");
+        assert_eq!(canonical_calculation("not(a=1)AND b≠1.5"), "not (a == 1) and b != 1.5");
+        assert_eq!(canonical_calculation("Abs(1;2)&\"a\\\"b\""), "Abs(1; 2) & \"a\\\"b\"");
+        assert_eq!(canonical_calculation(" !isEmpty(x) "), "not isEmpty(x)");
+        /* Left alone when it can't be read. */
+        assert_eq!(canonical_calculation(" a # b "), "a # b");
+    }
+}
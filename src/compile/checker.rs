@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+
+use crate::compile::calc::{compile_calculation, unsupported_functions};
+use crate::compile::diagnostic::{Diagnostic, Span};
+use crate::compile::seed::encode_value;
+use crate::compile::symbols::literal_name;
+use crate::component::{FMComponentField, FMComponentTable, RelationComparison, ValueListSource};
+use crate::file::FmpFile;
+use crate::fm_script_engine::fm_script_engine_instructions::{Instruction, ScriptStep};
+
+/* Adds `name` to `names`, unless it's already there. */
+fn define<'a, T>(names: &mut HashMap<&'a str, T>, name: &'a str, value: T) -> bool {
+    if names.contains_key(name) {
+        return false;
+    }
+    names.insert(name, value);
+    true
+}
+
+/* Every name a Burn file defines, and what it refers to. Names can be used
+ * before they're defined, so the whole file is read first. */
+struct Checker<'a> {
+    file: &'a FmpFile,
+    tables: HashMap<&'a str, &'a FMComponentTable>,
+    /* Occurrences name the table they're based on. */
+    occurrences: HashMap<&'a str, &'a str>,
+    layouts: HashMap<&'a str, Span>,
+    value_lists: HashMap<&'a str, Span>,
+    /* Scripts are mapped to whether they're exported. */
+    scripts: HashMap<&'a str, bool>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(file: &'a FmpFile) -> Self {
+        let mut checker = Self {
+            file,
+            tables: HashMap::new(),
+            occurrences: HashMap::new(),
+            layouts: HashMap::new(),
+            value_lists: HashMap::new(),
+            scripts: HashMap::new(),
+            diagnostics: vec![],
+        };
+        let mut duplicates = vec![];
+        for table in file.tables.values() {
+            if !define(&mut checker.tables, &table.table_name, table) {
+                duplicates.push(("Table", table.table_name.as_str(), table.span));
+            }
+        }
+        for to in file.table_occurrences.values() {
+            if !define(&mut checker.occurrences, &to.table_occurence_name, &to.table_actual_name) {
+                duplicates.push(("Table occurrence", to.table_occurence_name.as_str(), to.span));
+            }
+        }
+        for layout in file.layouts.values() {
+            if !define(&mut checker.layouts, &layout.layout_name, layout.span) {
+                duplicates.push(("Layout", layout.layout_name.as_str(), layout.span));
+            }
+        }
+        for list in file.value_lists.values() {
+            if !define(&mut checker.value_lists, &list.list_name, list.span) {
+                duplicates.push(("Value list", list.list_name.as_str(), list.span));
+            }
+        }
+        for script in file.scripts.values() {
+            if !define(&mut checker.scripts, &script.script_name, script.export) {
+                duplicates.push(("Script", script.script_name.as_str(), script.span));
+            }
+        }
+        let mut sets = HashMap::new();
+        for set in file.privilege_sets.values() {
+            if !define(&mut sets, &set.set_name, ()) {
+                duplicates.push(("Privilege set", set.set_name.as_str(), set.span));
+            }
+        }
+        let mut tests = HashMap::new();
+        for test in &file.tests {
+            if !define(&mut tests, &test.test_name, ()) {
+                duplicates.push(("Test", test.test_name.as_str(), test.span));
+            }
+        }
+        for (kind, name, span) in duplicates {
+            checker.error(format!("{} {} is defined more than once.", kind, name), span);
+        }
+        checker
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.diagnostics.push(Diagnostic::error(message, span));
+    }
+
+    /* Compiles a calculation the way the assembler does, so one that can't
+     * be written is reported where it was written instead of failing the build.
+     * Ones calling functions without a known code are left out by the assembler. */
+    fn calculation(&mut self, what: String, calc: &str, table: Option<usize>, span: Span) {
+        let unsupported = unsupported_functions(calc);
+        if !unsupported.is_empty() {
+            self.diagnostics.push(Diagnostic::warning(format!("Leaving out {}, it calls {} which can't be written to a file yet.",
+                    what, unsupported.join(", ")), span));
+            return;
+        }
+        if let Err(e) = compile_calculation(calc, self.file, table) {
+            self.error(format!("Unable to compile {}. {}", what, e), span);
+        }
+    }
+
+    /* The table behind an occurrence. Occurrences of unknown tables are
+     * reported where they're defined, so only unknown occurrences are here. */
+    fn table_of(&mut self, occurrence: &str, span: Span) -> Option<&'a FMComponentTable> {
+        match self.occurrences.get(occurrence) {
+            Some(table) => self.tables.get(table).copied(),
+            None => {
+                self.error(format!("Unknown table occurrence {}.", occurrence), span);
+                None
+            }
+        }
+    }
+
+    fn field_of(&mut self, table: &'a FMComponentTable, name: &str, span: Span) -> Option<&'a FMComponentField> {
+        let field = table.fields.values().find(|f| f.field_name == name);
+        if field.is_none() {
+            self.error(format!("Table {} has no field named {}.", table.table_name, name), span);
+        }
+        field
+    }
+
+    /* Looks up "Occurrence::field". */
+    fn field(&mut self, occurrence: &str, name: &str, span: Span) -> Option<&'a FMComponentField> {
+        let table = self.table_of(occurrence, span)?;
+        self.field_of(table, name, span)
+    }
+
+    fn check_tables(&mut self) {
+        for to in self.file.table_occurrences.values() {
+            if !self.tables.contains_key(to.table_actual_name.as_str()) {
+                self.error(format!("Table occurrence {} refers to unknown table {}.",
+                        to.table_occurence_name, to.table_actual_name), to.span);
+            }
+        }
+        for (id, table) in &self.file.tables {
+            for field in table.fields.values() {
+                if let Some(calc) = &field.auto_enter_calc {
+                    self.calculation(format!("auto-enter calculation of field {}", field.field_name), calc, Some(*id), field.span);
+                }
+                if let Some(calc) = &field.validation.calculation {
+                    self.calculation(format!("validation calculation of field {}", field.field_name), calc, Some(*id), field.span);
+                }
+                let Some(list) = &field.validation.value_list else {
+                    continue;
+                };
+                if !self.value_lists.contains_key(list.as_str()) {
+                    self.error(format!("Field {} is validated against unknown value list {}.", field.field_name, list), field.span);
+                }
+            }
+        }
+    }
+
+    /* Both sides of every predicate have to exist. Container fields can't be
+     * matched on, and comparing fields of different types is allowed but
+     * rarely what was meant. */
+    fn check_relationships(&mut self) {
+        for rel in self.file.relationships.values() {
+            let table1 = self.table_of(&rel.table1_name, rel.span);
+            let table2 = self.table_of(&rel.table2_name, rel.span);
+            let (Some(table1), Some(table2)) = (table1, table2) else {
+                continue;
+            };
+            for criterion in &rel.criteria {
+                let field1 = self.field_of(table1, &criterion.field1_name, rel.span);
+                let field2 = self.field_of(table2, &criterion.field2_name, rel.span);
+                let (Some(field1), Some(field2)) = (field1, field2) else {
+                    continue;
+                };
+                if criterion.comparison == RelationComparison::Cartesian {
+                    continue;
+                }
+                for field in [field1, field2] {
+                    if field.data_type == "Container" {
+                        self.error(format!("Container field {} can't be used in a relationship.", field.field_name), rel.span);
+                    }
+                }
+                if field1.data_type != field2.data_type {
+                    self.diagnostics.push(Diagnostic::warning(format!("Relationship compares {} ({}) with {} ({}).",
+                            field1.field_name, field1.data_type, field2.field_name, field2.data_type), rel.span));
+                }
+            }
+        }
+    }
+
+    fn check_layouts(&mut self) {
+        for list in self.file.value_lists.values() {
+            if let ValueListSource::Field { table_occurrence_name, field_name, .. } = &list.source {
+                self.field(table_occurrence_name, field_name, list.span);
+            }
+        }
+        for layout in self.file.layouts.values() {
+            let Some(table) = self.table_of(&layout.table_occurrence_name, layout.span) else {
+                continue;
+            };
+            for field in &layout.fields {
+                self.field_of(table, &field.field_name, layout.span);
+            }
+        }
+    }
+
+    /* Rows given in Burn are checked against the types of their columns.
+     * Rows read from files are checked when they're loaded. */
+    fn check_seeds(&mut self) {
+        for seed in &self.file.seeds {
+            let Some(table) = self.tables.get(seed.table_name.as_str()).copied() else {
+                self.error(format!("Data for unknown table {}.", seed.table_name), seed.span);
+                continue;
+            };
+            if seed.source.is_some() {
+                continue;
+            }
+            let columns = seed.columns.iter()
+                .map(|c| self.field_of(table, c, seed.span))
+                .collect::<Option<Vec<_>>>();
+            let Some(columns) = columns else {
+                continue;
+            };
+            for (n, row) in seed.rows.iter().enumerate() {
+                if row.len() != columns.len() {
+                    self.error(format!("Row {} of {} has {} values, expected {}.",
+                            n + 1, seed.table_name, row.len(), columns.len()), seed.span);
+                    continue;
+                }
+                for (field, value) in columns.iter().zip(row) {
+                    if value.trim().is_empty() {
+                        continue;
+                    }
+                    if let Err(e) = encode_value(field, value) {
+                        self.error(e, seed.span);
+                    }
+                }
+            }
+        }
+    }
+
+    /* Scripts, layouts and fields named by script steps. Test scripts aren't
+     * checked, they run against whichever file they're given with. Exported
+     * scripts performing ones that aren't exported are warned about, as the
+     * step is left without a target once assembled. */
+    fn check_scripts(&mut self) {
+        self.check_unused_scripts();
+        for script in self.file.scripts.values() {
+            for step in &script.instructions {
+                if script.export {
+                    self.check_step_calculations(step);
+                }
+                let Some(param) = step.switches.first() else {
+                    continue;
+                };
+                match step.opcode {
+                    Instruction::PerformScript => {
+                        let Some(name) = literal_name(param) else {
+                            continue;
+                        };
+                        match self.scripts.get(name) {
+                            None => self.error(format!("Unknown script {}.", name), step.span),
+                            Some(false) if script.export => self.diagnostics.push(Diagnostic::warning(
+                                format!("Script {} isn't exported, so {} can't perform it in the file.", name, script.script_name), step.span)),
+                            _ => {},
+                        }
+                    },
+                    Instruction::GoToLayout => {
+                        if let Some(name) = literal_name(param).filter(|n| !self.layouts.contains_key(n)) {
+                            self.error(format!("Unknown layout {}.", name), step.span);
+                        }
+                    },
+                    Instruction::SetField => match param.split_once("::") {
+                        Some((occurrence, field)) => {
+                            self.field(occurrence.trim(), field.trim(), step.span);
+                        },
+                        None => self.error(format!("Expected \"Occurrence::field\" in set_field, found {}.", param), step.span),
+                    },
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    /* Scripts that aren't exported are only there for tests and other
+     * scripts to perform, one that nothing performs is never run. */
+    fn check_unused_scripts(&mut self) {
+        let scripts = self.file.scripts.values().chain(self.file.tests.iter().map(|t| &t.script));
+        let mut performed = HashMap::new();
+        for script in scripts {
+            let targets = script.instructions.iter()
+                .filter(|s| s.opcode == Instruction::PerformScript)
+                .filter_map(|s| s.switches.first().and_then(|p| literal_name(p)))
+                .filter(|name| *name != script.script_name);
+            for name in targets {
+                define(&mut performed, name, ());
+            }
+        }
+        for script in self.file.scripts.values().filter(|s| !s.export) {
+            if !performed.contains_key(script.script_name.as_str()) {
+                self.diagnostics.push(Diagnostic::warning(format!("Script {} isn't exported and no script or test performs it.",
+                        script.script_name), script.span));
+            }
+        }
+    }
+
+    /* Switches the assembler writes as calculations, see `emit_step_parameters`.
+     * Only exported scripts are written, so only theirs are compiled. */
+    fn check_step_calculations(&mut self, step: &ScriptStep) {
+        let calculations = match step.opcode {
+            Instruction::BlankLineComment | Instruction::CommentedOut => 0..0,
+            Instruction::SetVariable => 1..2,
+            Instruction::PerformScript => 1..step.switches.len(),
+            _ => 0..step.switches.len(),
+        };
+        for n in calculations {
+            let Some(calc) = step.switches.get(n) else {
+                continue;
+            };
+            let span = step.switch_spans.get(n).copied().unwrap_or(step.span);
+            self.calculation(format!("calculation \"{}\"", calc), calc, None, span);
+        }
+    }
+}
+
+/* Checks that every name used in a file refers to something it defines,
+ * that nothing is defined twice, and that values fit the fields they're
+ * meant for. Diagnostics are returned in source order. */
+pub fn check(file: &FmpFile) -> Vec<Diagnostic> {
+    let mut checker = Checker::new(file);
+    checker.check_tables();
+    checker.check_relationships();
+    checker.check_layouts();
+    checker.check_seeds();
+    checker.check_scripts();
+    let mut diagnostics = checker.diagnostics;
+    diagnostics.sort_by_key(|d| (d.span.line, d.span.column));
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::{compiler::compile_burn_file, diagnostic::{Severity, Span}};
+
+    fn check_code(code: &str) -> Vec<(Severity, String, Span)> {
+        let diagnostics = compile_burn_file(code, "").map(|(_, warnings)| warnings).unwrap_or_else(|e| e);
+        diagnostics.into_iter().map(|d| (d.severity, d.message, d.span)).collect()
+    }
+
+    #[test]
+    fn undefined_reference_test() {
+        let code = "
+            table Person:
+              name: Text, foundIn[names],
+              age: Number,
+            end table;
+            table_occurence People:
+              table: Persons,
+            end table_occurence;
+            table_occurence Person:
+              table: Person,
+            end table_occurence;
+            script: [
+              define main() {
+                perform_script(\"helper\");
+                go_to_layout(\"Person\");
+                go_to_layout(\"Nowhere\");
+                set_field(Person::name, \"Kevin\");
+                set_field(Person::height, 2);
+                set_field(Persons::name, \"Kevin\");
+              }
+            ]
+        ";
+        assert_eq!(check_code(code), vec![
+            (Severity::Error, String::from("Field name is validated against unknown value list names."), Span::new(3, 15, 4)),
+            (Severity::Error, String::from("Table occurrence People refers to unknown table Persons."), Span::new(6, 29, 6)),
+            (Severity::Warning, String::from("Script main isn't exported and no script or test performs it."), Span::new(13, 22, 4)),
+            (Severity::Error, String::from("Unknown script helper."), Span::new(14, 17, 14)),
+            (Severity::Error, String::from("Unknown layout Nowhere."), Span::new(16, 17, 12)),
+            (Severity::Error, String::from("Table Person has no field named height."), Span::new(18, 17, 9)),
+            (Severity::Error, String::from("Unknown table occurrence Persons."), Span::new(19, 17, 9)),
+        ]);
+    }
+
+    #[test]
+    fn duplicate_and_type_test() {
+        let code = "
+            table Person:
+              id: Number,
+              photo: Container,
+            end table;
+            table Person:
+              id: Text,
+            end table;
+            table Job:
+              person: Text,
+            end table;
+            table_occurence People:
+              table: Person,
+            end table_occurence;
+            table_occurence Jobs:
+              table: Job,
+            end table_occurence;
+            relationship:
+              People:Jobs,
+              id == person,
+            end relationship;
+            data Person:
+              id, photo;
+              \"seven\", \"\";
+            end data;
+        ";
+        assert_eq!(check_code(code), vec![
+            (Severity::Error, String::from("Table Person is defined more than once."), Span::new(6, 19, 6)),
+            (Severity::Warning, String::from("Relationship compares id (Number) with person (Text)."), Span::new(19, 15, 6)),
+            (Severity::Error, String::from("\"seven\" isn't a valid number for id."), Span::new(22, 18, 6)),
+        ]);
+    }
+
+    #[test]
+    fn export_test() {
+        let code = "
+            script export: [
+              define main() {
+                perform_script(\"helper\");
+                perform_script(\"other\");
+              }
+            ]
+            script: [
+              define helper() {
+                perform_script(\"main\");
+              }
+              define other() {
+                perform_script(\"helper\");
+              }
+              define tested() {
+              }
+              define unused() {
+                perform_script(\"unused\");
+              }
+            ]
+            test Tested:
+              script: [
+                define run() {
+                  perform_script(\"tested\");
+                }
+              ],
+            end test;
+        ";
+        assert_eq!(check_code(code), vec![
+            (Severity::Warning, String::from("Script helper isn't exported, so main can't perform it in the file."), Span::new(4, 17, 14)),
+            (Severity::Warning, String::from("Script other isn't exported, so main can't perform it in the file."), Span::new(5, 17, 14)),
+            (Severity::Warning, String::from("Script unused isn't exported and no script or test performs it."), Span::new(17, 22, 6)),
+        ]);
+    }
+
+    #[test]
+    fn validation_calculation_test() {
+        let code = "
+            table Person:
+              age: Number, !{age > 0},
+            end table;
+        ";
+        assert_eq!(check_code(code), vec![]);
+
+        let code = "
+            table Person:
+              PrimaryKey: Text, !{not IsEmpty(PrimaryKey)},
+            end table;
+        ";
+        assert_eq!(check_code(code), vec![
+            (Severity::Warning, String::from("Leaving out validation calculation of field PrimaryKey, it calls IsEmpty which can't be written to a file yet."), Span::new(3, 15, 10)),
+        ]);
+    }
+
+    #[test]
+    fn calculation_test() {
+        let code = "
+            table Person:
+              age: Number, {Abs(-1},
+              name: Text, !{IsEmpty(name)},
+            end table;
+            script export: [
+              define main() {
+                set_variable($x, 1 ! 2);
+                perform_script(\"helper\", Foo(1));
+                // Not a calculation: Foo(1)
+                go_to_layout(\"Person\");
+              }
+            ]
+            script: [
+              define helper() {
+                set_variable($y, Foo(2));
+              }
+            ]
+        ";
+        let errors = check_code(code).into_iter()
+            .filter(|d| d.1.starts_with("Unable to compile") || d.1.starts_with("Leaving out"))
+            .collect::<Vec<_>>();
+        /* Functions without a known code only leave the calculation out. */
+        assert_eq!(errors, vec![
+            (Severity::Error, String::from("Unable to compile auto-enter calculation of field age. Expected \")\" in calculation, found the end of the calculation."), Span::new(3, 15, 3)),
+            (Severity::Warning, String::from("Leaving out validation calculation of field name, it calls IsEmpty which can't be written to a file yet."), Span::new(4, 15, 4)),
+            (Severity::Error, String::from("Unable to compile calculation \"1 ! 2\". Unexpected \"not\" in calculation."), Span::new(8, 34, 5)),
+            (Severity::Warning, String::from("Leaving out calculation \"Foo(1)\", it calls Foo which can't be written to a file yet."), Span::new(9, 42, 6)),
+        ]);
+    }
+}
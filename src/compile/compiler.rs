@@ -1,11 +1,199 @@
+use crate::compile::checker;
+use crate::compile::diagnostic::{Diagnostic, Severity};
 use crate::compile::parser;
 use crate::compile::lexer;
+use crate::component::{FMComponentLayout, FMComponentTable};
 use crate::file::FmpFile;
 
+/* Lexes and parses one file, marking every span with the file's index in
+ * its project. Whatever could be parsed is returned with the mistakes
+ * found, which leaves the file empty if it couldn't be lexed. */
+pub fn parse_burn(code: &str, source: usize) -> (FmpFile, Vec<Diagnostic>) {
+    let mut tokens = match lexer::tokenize(code) {
+        Ok(tokens) => tokens,
+        Err(diagnostics) => return (FmpFile::new(), diagnostics.into_iter().map(|mut d| {
+            d.span.source = source;
+            d
+        }).collect()),
+    };
+    for t in &mut tokens {
+        t.span.source = source;
+    }
+    parser::Parser::new(tokens).parse_partial()
+}
+
+/* Checks a parsed file, or all the files of a project merged into one, and
+ * resolves the references between its components. Warnings are returned
+ * on success, or with the errors if there are any. */
+pub fn link(file: &mut FmpFile) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    /* Default layouts are made first, scripts can go to them. */
+    add_default_layouts(file);
+    let diagnostics = checker::check(file);
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        return Err(diagnostics);
+    }
+    resolve_relationships(file)?;
+    Ok(diagnostics)
+}
+
+/* Compiles Burn source read from `path`, collecting every mistake found
+ * rather than stopping at the first. Imports are followed by projects, see
+ * `compile::project`. */
+pub fn compile_burn_file(code: &str, path: &str) -> Result<(FmpFile, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let in_file = |diagnostics: Vec<Diagnostic>| diagnostics.into_iter().map(|d| d.in_file(path)).collect::<Vec<_>>();
+    let (mut file, errors) = parse_burn(code, 0);
+    if !errors.is_empty() {
+        return Err(in_file(errors));
+    }
+    if let Some((import, span)) = file.imports.first() {
+        return Err(in_file(vec![Diagnostic::error(format!("Can't import {} here, only files compiled from disk can.", import), *span)]));
+    }
+    let warnings = link(&mut file).map_err(in_file)?;
+    Ok((file, in_file(warnings)))
+}
+
 pub fn compile_burn(code: &str) -> FmpFile {
-    let tokens = lexer::tokenize(&code);
-    let p = parser::Parser::new(tokens);
-    p.parse_program().expect("unable to parse program. ")
+    match compile_burn_file(code, "") {
+        Ok((file, _)) => file,
+        Err(diagnostics) => {
+            let rendered = diagnostics.iter().map(|d| d.render(code)).collect::<String>();
+            panic!("unable to compile program.\n{}", rendered)
+        }
+    }
 }
 
+/* Fills in the table behind each occurrence, and the occurrence and field ids
+ * of each relationship, from the names given in Burn. */
+fn resolve_relationships(file: &mut FmpFile) -> Result<(), Vec<Diagnostic>> {
+    let mut diagnostics = vec![];
+    for occurrence in file.table_occurrences.values_mut() {
+        if occurrence.table_actual != 0 {
+            continue;
+        }
+        match file.tables.iter().find(|(_, t)| t.table_name == occurrence.table_actual_name) {
+            Some((id, _)) => occurrence.table_actual = *id as u16,
+            None => diagnostics.push(Diagnostic::error(format!("Table occurrence {} refers to unknown table {}.",
+                    occurrence.table_occurence_name, occurrence.table_actual_name), occurrence.span)),
+        };
+    }
 
+    let occurrence = |name: &str| -> Result<(u16, &FMComponentTable), String> {
+        let (id, to) = file.table_occurrences.iter()
+            .find(|(_, to)| to.table_occurence_name == name)
+            .ok_or(format!("Relationship refers to unknown table occurrence {}.", name))?;
+        let table = file.tables.get(&(to.table_actual as usize))
+            .ok_or(format!("Table occurrence {} has no table.", name))?;
+        Ok((*id as u16, table))
+    };
+    let field = |table: &FMComponentTable, name: &str| -> Result<u16, String> {
+        table.fields.iter()
+            .find(|(_, f)| f.field_name == name)
+            .map(|(id, _)| *id)
+            .ok_or(format!("Table {} has no field named {}.", table.table_name, name))
+    };
+
+    let mut resolved = vec![];
+    for (id, rel) in &file.relationships {
+        let sides = occurrence(&rel.table1_name).and_then(|side1| Ok((side1, occurrence(&rel.table2_name)?)));
+        let ((table1, fields1), (table2, fields2)) = match sides {
+            Ok(sides) => sides,
+            Err(e) => {
+                diagnostics.push(Diagnostic::error(e, rel.span));
+                continue;
+            }
+        };
+        let criteria = rel.criteria.iter()
+            .map(|c| Ok((field(fields1, &c.field1_name)?, field(fields2, &c.field2_name)?)))
+            .collect::<Result<Vec<_>, String>>();
+        match criteria {
+            Ok(criteria) => resolved.push((*id, table1, table2, criteria)),
+            Err(e) => diagnostics.push(Diagnostic::error(e, rel.span)),
+        }
+    }
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    for (id, table1, table2, criteria) in resolved {
+        let rel = file.relationships.get_mut(&id).unwrap();
+        rel.table1 = table1;
+        rel.table2 = table2;
+        for (criterion, (field1, field2)) in rel.criteria.iter_mut().zip(criteria) {
+            criterion.field1 = field1;
+            criterion.field2 = field2;
+        }
+    }
+    Ok(())
+}
+
+/* Every table occurrence without a layout of its own gets a form layout
+ * named after it, showing all fields of its table. The layout is found
+ * where the occurrence is defined. */
+fn add_default_layouts(file: &mut FmpFile) {
+    let occurrences = file.table_occurrences.values()
+        .filter(|to| !file.layouts.values().any(|l| l.table_occurrence_name == to.table_occurence_name))
+        .map(|to| (to.table_occurence_name.clone(), to.table_actual_name.clone(), to.span))
+        .collect::<Vec<_>>();
+    for (occurrence, table, span) in occurrences {
+        let mut layout = FMComponentLayout::new();
+        layout.layout_name = occurrence.clone();
+        layout.table_occurrence_name = occurrence;
+        layout.span = span;
+        if let Some(table) = file.tables.values().find(|t| t.table_name == table) {
+            for field in table.fields.values() {
+                layout.place_field(&field.field_name);
+            }
+        }
+        let id = file.layouts.keys().max().copied().unwrap_or(0) + 1;
+        file.layouts.insert(id, layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::{diagnostic::Span, lexer::tokenize, parser::Parser};
+    use super::resolve_relationships;
+
+    const SCHEMA: &str = "
+        table Person:
+          PrimaryKey: Text,
+        end table;
+        table Job:
+          PrimaryKey: Text,
+          PersonKey: Text,
+        end table;
+        table_occurence Jobs:
+          table: Job,
+        end table_occurence;
+        table_occurence People:
+          table: Person,
+        end table_occurence;
+    ";
+
+    #[test]
+    fn resolve_relationships_test() {
+        let code = SCHEMA.to_string() + "
+            relationship:
+              People:Jobs,
+              PrimaryKey == PersonKey,
+              PrimaryKey != PrimaryKey,
+            end relationship;
+        ";
+        let mut file = Parser::new(tokenize(&code).unwrap()).parse_program().unwrap();
+        resolve_relationships(&mut file).unwrap();
+        assert_eq!(file.table_occurrences[&1].table_actual, 2);
+        let rel = &file.relationships[&1];
+        assert_eq!((rel.table1, rel.table2), (2, 1));
+        assert_eq!(rel.criteria.iter().map(|c| (c.field1, c.field2)).collect::<Vec<_>>(), vec![(1, 2), (1, 1)]);
+
+        let code = SCHEMA.to_string() + "
+            relationship:
+              People:Jobs,
+              PrimaryKey == JobKey,
+            end relationship;
+        ";
+        let mut file = Parser::new(tokenize(&code).unwrap()).parse_program().unwrap();
+        let errors = resolve_relationships(&mut file).unwrap_err();
+        assert_eq!(errors.iter().map(|d| (d.message.as_str(), d.span)).collect::<Vec<_>>(),
+            vec![("Table Job has no field named JobKey.", Span::new(17, 15, 6))]);
+    }
+}
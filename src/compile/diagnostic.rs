@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+
+/* Where a token or component was written in a Burn file. Lines and columns
+ * start at 1 and count characters, len is the number of characters covered.
+ * Components parsed from Burn keep the span of their name, which isn't
+ * serialized. The default span, line 0, is used for anything that wasn't
+ * read from source. */
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+    /* Which file of a project the span is in, see `compile::project`. */
+    #[serde(skip)]
+    pub source: usize,
+}
+
+impl Span {
+    pub fn new(line: usize, column: usize, len: usize) -> Self {
+        Self { line, column, len, source: 0 }
+    }
+
+    /* Moves a span found in text that was cut out of a file at `base`, like
+     * the body of a script block, back to its place in the file. */
+    pub fn relative_to(self, base: Span) -> Span {
+        let span = if self.line <= 1 {
+            Span::new(base.line, base.column + self.column.max(1) - 1, self.len)
+        } else {
+            Span::new(base.line + self.line - 1, self.column, self.len)
+        };
+        Span { source: base.source, ..span }
+    }
+}
+
+/* Character offsets of every line start, to turn offsets into spans. */
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(text.chars().enumerate().filter(|(_, c)| *c == '\n').map(|(i, _)| i + 1));
+        Self { starts }
+    }
+
+    /* Span of the characters from `start` up to, not including, `end`. */
+    pub fn span(&self, start: usize, end: usize) -> Span {
+        let line = self.starts.partition_point(|s| *s <= start);
+        Span::new(line, start - self.starts[line - 1] + 1, end.saturating_sub(start).max(1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub file: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            file: String::new(),
+            span,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message: message.into(),
+            file: String::new(),
+            span,
+        }
+    }
+
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+
+    /* Renders the diagnostic the way rustc does, with the line it points at:
+     *
+     * error: Unknown option Indexed for field name.
+     *  --> app.burn:3:15
+     *   |
+     * 3 |   name: Text, Indexed,
+     *   |               ^^^^^^^
+     */
+    pub fn render(&self, source: &str) -> String {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let mut out = format!("{}: {}\n", severity, self.message);
+        let Some(line) = source.lines().nth(self.span.line.wrapping_sub(1)) else {
+            if !self.file.is_empty() {
+                out.push_str(&format!(" --> {}\n", self.file));
+            }
+            return out;
+        };
+        let number = self.span.line.to_string();
+        let gutter = " ".repeat(number.len());
+        out.push_str(&format!("{}--> {}:{}:{}\n", gutter, self.file, self.span.line, self.span.column));
+        out.push_str(&format!("{} |\n", gutter));
+        out.push_str(&format!("{} | {}\n", number, line));
+        /* Carets stop at the end of the line for spans covering several. */
+        let width = line.chars().count();
+        let column = self.span.column.clamp(1, width + 1);
+        let len = self.span.len.min(width + 1 - column).max(1);
+        out.push_str(&format!("{} | {}{}\n", gutter, " ".repeat(column - 1), "^".repeat(len)));
+        out
+    }
+
+    /* One line of JSON, for editors reading diagnostics from the command line. */
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Unable to generate json diagnostic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, LineIndex, Span};
+
+    #[test]
+    fn span_test() {
+        let index = LineIndex::new("table Person:\n  name: Text,\n");
+        assert_eq!(index.span(0, 5), Span::new(1, 1, 5));
+        assert_eq!(index.span(16, 20), Span::new(2, 3, 4));
+        assert_eq!(Span::new(1, 4, 2).relative_to(Span::new(3, 10, 0)), Span::new(3, 13, 2));
+        assert_eq!(Span::new(2, 4, 2).relative_to(Span::new(3, 10, 0)), Span::new(4, 4, 2));
+    }
+
+    #[test]
+    fn render_test() {
+        let source = "table Person:\n  name: Text, Indexed,\nend table;\n";
+        let diagnostic = Diagnostic::error("Unknown option Indexed for field name.", Span::new(2, 15, 7))
+            .in_file("app.burn");
+        assert_eq!(diagnostic.render(source), "\
+error: Unknown option Indexed for field name.
+ --> app.burn:2:15
+  |
+2 |   name: Text, Indexed,
+  |               ^^^^^^^
+");
+        assert_eq!(diagnostic.to_json(), "{\"severity\":\"error\",\"message\":\"Unknown option Indexed for field name.\",\
+\"file\":\"app.burn\",\"span\":{\"line\":2,\"column\":15,\"len\":7}}");
+    }
+}
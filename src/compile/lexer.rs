@@ -1,309 +1,313 @@
+use crate::compile::{diagnostic::{Diagnostic, LineIndex}, token::*};
 
-use crate::compile::token::*;
+/* Words with a meaning of their own in Burn, outside of scripts. */
+pub const KEYWORDS: [&str; 21] = [
+    "table", "relationship", "value_list", "layout", "data", "privilege_set", "table_occurence", "script", "test",
+    "import", "end", "Unique", "Required", "Existing", "foundIn", "Text", "Number", "Date", "Time", "Timestamp",
+    "Container",
+];
 
-pub fn tokenize(code: &str) -> Vec<Token> {
-    let mut list = Vec::<Token>::new();
-    let mut buffer = String::new();
-    let mut in_string = false;
-    let mut in_calc = false;
-    let mut in_script = false;
-    let mut in_assertion = false;
-    let mut lex_iter = code.chars().into_iter().enumerate().peekable();
-
-    let flush_buffer = |b: &str| -> Result<Token, String> {
-        match b {
-            "table" => Ok(Token::new(TokenType::Table)),
-            "relationship" => Ok(Token::new(TokenType::Relationship)),
-            "value_list" => Ok(Token::new(TokenType::ValueList)),
-            "table_occurence" => Ok(Token::new(TokenType::TableOccurence)),
-            "script" => Ok(Token::new(TokenType::Script)),
-            "test" => Ok(Token::new(TokenType::Test)),
-            "end" => Ok(Token::new(TokenType::End)),
-            _ => {
-                let n = b.parse::<f64>();
-                if n.is_ok() {
-                    Ok(Token::with_value(TokenType::NumericLiteral, n.unwrap().to_string()))
-                } else if !b.as_bytes()[0].is_ascii_digit() {
-                    Ok(Token::with_value(TokenType::Identifier, b.to_string()))
-                } else {
-                    Err("Invalid Identifier".to_string())
-                }
+fn flush_buffer(b: &str) -> Result<Token, String> {
+    match b {
+        "table" => Ok(Token::new(TokenType::Table)),
+        "relationship" => Ok(Token::new(TokenType::Relationship)),
+        "value_list" => Ok(Token::new(TokenType::ValueList)),
+        "layout" => Ok(Token::new(TokenType::Layout)),
+        "data" => Ok(Token::new(TokenType::Data)),
+        "privilege_set" => Ok(Token::new(TokenType::PrivilegeSet)),
+        "table_occurence" => Ok(Token::new(TokenType::TableOccurence)),
+        "script" => Ok(Token::new(TokenType::Script)),
+        "test" => Ok(Token::new(TokenType::Test)),
+        "import" => Ok(Token::new(TokenType::Import)),
+        "end" => Ok(Token::new(TokenType::End)),
+        "Unique" => Ok(Token::new(TokenType::Unique)),
+        "Required" => Ok(Token::new(TokenType::Required)),
+        "Existing" => Ok(Token::new(TokenType::Existing)),
+        "foundIn" => Ok(Token::new(TokenType::FoundIn)),
+        "Text" | "Number" | "Date" | "Time" | "Timestamp" | "Container"
+            => Ok(Token::with_value(TokenType::DataType, b.to_string())),
+        _ => {
+            let n = b.parse::<f64>();
+            if n.is_ok() {
+                Ok(Token::with_value(TokenType::NumericLiteral, n.unwrap().to_string()))
+            } else if !b.as_bytes()[0].is_ascii_digit() {
+                Ok(Token::with_value(TokenType::Identifier, b.to_string()))
+            } else {
+                Err(std::format!("Invalid identifier {}.", b))
             }
         }
-    };
+    }
+}
 
-    while let Some((idx, mut c)) = &lex_iter.next() {
-        if c.is_whitespace() && buffer.is_empty() {
-            continue;
-        }
+/* Characters that end a word. */
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || "=[]()\"!{};:,<>*×".contains(c)
+}
 
-        while in_string == true {
-            if c == '"' {
-                list.push(Token { ttype: TokenType::String, text: buffer.to_string()});
-                in_string = false;
-                buffer.clear();
-            }
-            buffer.push(c);
-            c = lex_iter.next().unwrap().1;
-        }
+/* Whether a line or block comment starts at `i`. */
+fn starts_comment(chars: &[char], i: usize) -> bool {
+    chars[i] == '/' && matches!(chars.get(i + 1), Some('/') | Some('*'))
+}
 
-        while in_script == true {
-            if c == ']' {
-                list.push(Token { ttype: TokenType::Script, text: buffer.to_string()});
-                in_script = false;
-                buffer.clear();
-                break;
-            }
-            buffer.push(c);
-            c = lex_iter.next().unwrap().1;
+/* Where the comment starting at `i` ends, just past its last character.
+ * Line comments run to the end of the line. */
+fn comment_end(chars: &[char], i: usize) -> Option<usize> {
+    if chars[i + 1] == '/' {
+        return Some(find(chars, i, '\n').unwrap_or(chars.len()));
+    }
+    (i + 3..chars.len()).find(|j| chars[*j - 1] == '*' && chars[*j] == '/').map(|j| j + 1)
+}
+
+/* The "]" closing a script block. Brackets in the strings and comments of
+ * the block don't close it. */
+fn script_end(chars: &[char], from: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        match chars[i] {
+            ']' => return Some(i),
+            '"' => i = string_end(chars, i + 1)? + 1,
+            _ if starts_comment(chars, i) => i = comment_end(chars, i)?,
+            _ => i += 1,
         }
+    }
+    None
+}
 
-        while in_calc == true {
-            if c == '}' {
-                list.push(Token { ttype: TokenType::Calculation, text: buffer.to_string()});
-                in_calc = false;
-                buffer.clear();
-            }
-            buffer.push(c);
-            c = lex_iter.next().unwrap().1;
+/* The quote closing a string whose text starts at `from`. A backslash
+ * escapes the character after it, so "\"" and "\\" can be written. */
+pub(crate) fn string_end(chars: &[char], from: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '"' => return Some(i),
+            _ => i += 1,
         }
+    }
+    None
+}
 
-        let mut scope = 1;
-        while in_assertion == true {
-            if scope == 0 {
-                list.push(Token { ttype: TokenType::Assertion, text: buffer.to_string()});
-                buffer.clear();
-                while c.is_whitespace() {
-                    c = lex_iter.next().unwrap().1;
-                    continue;
-                }
-                if c == ',' {
-                    c = lex_iter.next().unwrap().1;
-                    while c.is_whitespace() {
-                        c = lex_iter.next().unwrap().1;
-                    }
-                    if c == '(' {
-                        scope = 1;
-                    } else {
-                        in_assertion = false;
-                    }
-                } else {
-                    in_assertion = false;
-                }
-            } else if c == '(' {
-                scope += 1;
-            } else if c == ')' {
-                scope -= 1;
-            }
-            buffer.push(c);
-            c = lex_iter.next().unwrap().1;
+/* The text of a string, with \" and \\ read as the character they escape.
+ * Other backslashes are kept as they were written. */
+fn unescape(chars: &[char]) -> String {
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match (chars[i], chars.get(i + 1)) {
+            ('\\', Some(c @ ('"' | '\\'))) => {
+                text.push(*c);
+                i += 2;
+            },
+            (c, _) => {
+                text.push(c);
+                i += 1;
+            },
         }
+    }
+    text
+}
 
-        let b = buffer.as_str();
+fn find(chars: &[char], from: usize, c: char) -> Option<usize> {
+    chars.iter().skip(from).position(|x| *x == c).map(|n| n + from)
+}
 
-        let tmp = match c {
-            x if x.is_whitespace() => 
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    buffer.clear();
-                    }
-                }
-                ret
+fn skip_whitespace(chars: &[char], from: usize) -> usize {
+    chars.iter().skip(from).position(|c| !c.is_whitespace()).map(|n| n + from).unwrap_or(chars.len())
+}
 
-            },
-            '=' =>  
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    }
-                    buffer.clear();
-                }
+/* Splits Burn source into tokens, each with the span it was read from.
+ * Strings, calculations and script blocks are kept whole, they're compiled
+ * by their own layers. */
+pub fn tokenize(code: &str) -> Result<Vec<Token>, Vec<Diagnostic>> {
+    let mut list = Vec::<Token>::new();
+    let mut diagnostics = vec![];
+    let chars = code.chars().collect::<Vec<_>>();
+    let lines = LineIndex::new(code);
+    let mut i = 0;
 
-                if let Some(n) = lex_iter.peek() {
-                    if n.1 == '=' {
-                        ret.push(Token::new(TokenType::EComparison));
-                        lex_iter.next();
-                    } else {
-                        ret.push(Token::new(TokenType::Assign));
-                    }
-                }
-                ret
-            },
-            '[' => 
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    }
-                    buffer.clear();
-                }
-                ret.push(Token::new(TokenType::OpenSquare));
-                if list.last().unwrap().ttype != TokenType::FoundIn {
-                    in_script = true;
-                }
-                ret
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        match c {
+            /* Comments are only kept by the script blocks they're in. */
+            '/' if starts_comment(&chars, i) => {
+                let Some(end) = comment_end(&chars, i) else {
+                    diagnostics.push(Diagnostic::error("Unterminated comment, expected \"*/\".", lines.span(i, i + 2)));
+                    break;
+                };
+                i = end;
             },
-            ']' => 
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    }
-                    buffer.clear();
-                }
-                ret.push(Token::new(TokenType::CloseSquare));
-                ret
+            '"' => {
+                let Some(end) = string_end(&chars, i + 1) else {
+                    diagnostics.push(Diagnostic::error("Unterminated string.", lines.span(i, i + 1)));
+                    break;
+                };
+                let text = unescape(&chars[i + 1..end]);
+                list.push(Token::with_value(TokenType::String, text).at(lines.span(i, end + 1)));
+                i = end + 1;
             },
-            '(' => 
-            {
-                if list[list.len() - 2].ttype == TokenType::AssertionBlock {
-                    in_assertion = true;       
-                    buffer.push(c);
-                    vec![]
-                } else {
-                    let mut ret: Vec<Token> = vec![];
-                    if !buffer.is_empty() {
-                        let out = flush_buffer(b);
-                        if out.is_ok() {
-                            ret.push(out.unwrap());
-                        }
-                        buffer.clear();
-                    }
-                    ret.push(Token::new(TokenType::OpenParen));
-                    ret
+            '[' => {
+                let after_found_in = matches!(list.last(), Some(t) if t.ttype == TokenType::FoundIn);
+                list.push(Token::new(TokenType::OpenSquare).at(lines.span(i, i + 1)));
+                i += 1;
+                if !after_found_in {
+                    /* The closing bracket is left to be read as its own token. */
+                    let start = skip_whitespace(&chars, i);
+                    let Some(end) = script_end(&chars, start) else {
+                        diagnostics.push(Diagnostic::error("Unterminated script block, expected \"]\".", lines.span(i - 1, i)));
+                        break;
+                    };
+                    let text = chars[start..end].iter().collect();
+                    list.push(Token::with_value(TokenType::Script, text).at(lines.span(start, end)));
+                    i = end;
                 }
-
             },
-            ')' => 
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    }
-                    buffer.clear();
-                }
-                ret.push(Token::new(TokenType::CloseParen));
-                ret
-
+            '{' => {
+                list.push(Token::new(TokenType::OpenCurly).at(lines.span(i, i + 1)));
+                let start = skip_whitespace(&chars, i + 1);
+                let Some(end) = find(&chars, start, '}') else {
+                    diagnostics.push(Diagnostic::error("Unterminated calculation, expected \"}\".", lines.span(i, i + 1)));
+                    break;
+                };
+                let text = chars[start..end].iter().collect::<String>().trim_end().to_string();
+                list.push(Token::with_value(TokenType::Calculation, text).at(lines.span(start, end)));
+                list.push(Token::new(TokenType::CloseCurly).at(lines.span(end, end + 1)));
+                i = end + 1;
             },
-            '"' =>  
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
+            /* Assertions keep their parentheses: "assertions: (x == 1), (y == 2)" */
+            '(' if list.len() >= 2 && (list[list.len() - 2].ttype == TokenType::AssertionBlock
+                || list[list.len() - 2].ttype == TokenType::Assertion) => {
+                let mut scope = 0;
+                let mut end = i;
+                while end < chars.len() {
+                    match chars[end] {
+                        '(' => scope += 1,
+                        ')' => scope -= 1,
+                        _ => {},
                     }
-                    buffer.clear();
-                }
-                in_string = true;
-                ret
-            },
-            '!' =>  
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
+                    end += 1;
+                    if scope == 0 {
+                        break;
                     }
-                    buffer.clear();
                 }
-                ret.push(Token::new(TokenType::Exclamation));
-                ret
-            },
-            '{' => 
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    }
-                    buffer.clear();
+                if scope != 0 {
+                    diagnostics.push(Diagnostic::error("Unterminated assertion, expected \")\".", lines.span(i, i + 1)));
+                    break;
                 }
-                ret.push(Token::new(TokenType::OpenCurly));
-                in_calc = true;
-                ret
+                let text = chars[i..end].iter().collect();
+                list.push(Token::with_value(TokenType::Assertion, text).at(lines.span(i, end)));
+                i = end;
             },
-            '}' => {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    }
-                    buffer.clear();
-                }
-                ret.push(Token::new(TokenType::CloseCurly));
-                ret
+            '=' | '!' | '<' | '>' => {
+                let or_equal = chars.get(i + 1) == Some(&'=');
+                let ttype = match (c, or_equal) {
+                    ('=', true) => TokenType::EComparison,
+                    ('=', false) => TokenType::Assign,
+                    ('!', true) => TokenType::NEComparison,
+                    ('!', false) => TokenType::Exclamation,
+                    ('<', true) => TokenType::LEComparison,
+                    ('<', false) => TokenType::LComparison,
+                    (_, true) => TokenType::GEComparison,
+                    (_, false) => TokenType::GComparison,
+                };
+                let len = if or_equal { 2 } else { 1 };
+                list.push(Token::new(ttype).at(lines.span(i, i + len)));
+                i += len;
             },
-            ';' => 
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    }
-                    buffer.clear();
-                }
-                ret.push(Token::new(TokenType::SemiColon));
-                ret
+            '(' | ')' | ']' | '}' | ';' | ':' | ',' | '*' | '×' => {
+                let ttype = match c {
+                    '(' => TokenType::OpenParen,
+                    ')' => TokenType::CloseParen,
+                    ']' => TokenType::CloseSquare,
+                    '}' => TokenType::CloseCurly,
+                    ';' => TokenType::SemiColon,
+                    ':' => TokenType::Colon,
+                    ',' => TokenType::Comma,
+                    _ => TokenType::CComparison,
+                };
+                list.push(Token::new(ttype).at(lines.span(i, i + 1)));
+                i += 1;
             },
-            ':' =>
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    }
-                    buffer.clear();
+            _ => {
+                let start = i;
+                while i < chars.len() && !is_delimiter(chars[i]) && !starts_comment(&chars, i) {
+                    i += 1;
                 }
-                ret.push(Token::new(TokenType::Colon));
-                ret
-            }
-            ',' =>  
-            {
-                let mut ret: Vec<Token> = vec![];
-                if !buffer.is_empty() {
-                    let out = flush_buffer(b);
-                    if out.is_ok() {
-                        ret.push(out.unwrap());
-                    }
-                    buffer.clear();
+                let word = chars[start..i].iter().collect::<String>();
+                match flush_buffer(&word) {
+                    Ok(t) => list.push(t.at(lines.span(start, i))),
+                    Err(e) => diagnostics.push(Diagnostic::error(e, lines.span(start, i))),
                 }
-                ret.push(Token::new(TokenType::Comma));
-                ret
             }
-            _ => 
-            {
-                buffer.push(c);
-                vec![]
-            }
-        };
-        if !tmp.is_empty() {
-            for t in tmp {
-                list.push(t);
-            }
-            buffer.clear();
         }
     }
 
-    return list;
+    if diagnostics.is_empty() {
+        Ok(list)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::{diagnostic::Span, token::TokenType};
+    use super::tokenize;
+
+    #[test]
+    fn span_test() {
+        let tokens = tokenize("table Person:\n  key: Text, {Get ( UUID )},\n  age: Number, \"Age\",\nend table;").unwrap();
+        let spans = tokens.iter().map(|t| (&t.ttype, t.span)).collect::<Vec<_>>();
+        assert_eq!(spans[..3], [
+            (&TokenType::Table, Span::new(1, 1, 5)),
+            (&TokenType::Identifier, Span::new(1, 7, 6)),
+            (&TokenType::Colon, Span::new(1, 13, 1))]);
+        assert_eq!(spans[5], (&TokenType::DataType, Span::new(2, 8, 4)));
+        assert_eq!(spans[8], (&TokenType::Calculation, Span::new(2, 15, 12)));
+        assert_eq!(spans[9], (&TokenType::CloseCurly, Span::new(2, 27, 1)));
+        assert_eq!(spans[15], (&TokenType::String, Span::new(3, 16, 5)));
+        assert_eq!(tokens[15].text, "Age");
+    }
+
+    #[test]
+    fn lex_error_test() {
+        let errors = tokenize("table 3Person:\n  name: Text, \"Unterminated").unwrap_err();
+        let errors = errors.iter().map(|d| (d.message.as_str(), d.span)).collect::<Vec<_>>();
+        assert_eq!(errors, vec![
+            ("Invalid identifier 3Person.", Span::new(1, 7, 7)),
+            ("Unterminated string.", Span::new(2, 15, 1))]);
+    }
+
+    #[test]
+    fn comment_test() {
+        let tokens = tokenize("// People\ntable Person: /* the [key] */\n  key: Text, // why\nend table;\nscript: [ // see [x]\n]").unwrap();
+        let types = tokens.iter().map(|t| &t.ttype).collect::<Vec<_>>();
+        assert_eq!(types, [&TokenType::Table, &TokenType::Identifier, &TokenType::Colon, &TokenType::Identifier,
+            &TokenType::Colon, &TokenType::DataType, &TokenType::Comma, &TokenType::End, &TokenType::Table,
+            &TokenType::SemiColon, &TokenType::Script, &TokenType::Colon, &TokenType::OpenSquare, &TokenType::Script,
+            &TokenType::CloseSquare]);
+        assert_eq!(tokens[13].text, "// see [x]\n");
+        assert_eq!(tokens[1].span, Span::new(2, 7, 6));
 
+        let errors = tokenize("table /* Person:").unwrap_err();
+        assert_eq!(errors[0].message, "Unterminated comment, expected \"*/\".");
+    }
+
+    #[test]
+    fn string_escape_test() {
+        let tokens = tokenize(r#"value_list Sizes: "6\" \"small\"", "C:\\dir", "C:\temp" end value_list;"#).unwrap();
+        let strings = tokens.iter().filter(|t| t.ttype == TokenType::String).map(|t| t.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(strings, [r#"6" "small""#, r"C:\dir", r"C:\temp"]);
+
+        /* An escaped quote doesn't end a string in a script block either. */
+        let tokens = tokenize(r#"script: [ define f() { set_variable(x, "]\""); } ]"#).unwrap();
+        assert_eq!(tokens.last().unwrap().ttype, TokenType::CloseSquare);
+
+        let errors = tokenize(r#"value_list Sizes: "open\" end value_list;"#).unwrap_err();
+        assert_eq!(errors[0].message, "Unterminated string.");
+    }
 }
@@ -1,7 +1,15 @@
 pub mod compiler;
 pub mod assembler;
-mod lexer;
+pub mod calc;
+pub mod patch;
+pub mod seed;
+pub mod diagnostic;
+pub mod checker;
+pub mod project;
+pub mod symbols;
+pub mod lexer;
 mod parser;
 mod compound_chunk;
 mod token;
 mod header;
+mod security;
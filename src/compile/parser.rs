@@ -1,5 +1,203 @@
+use std::{iter::Peekable, slice::Iter};
 
-use crate::{burn_script, compile::token::*, component::{FMComponentRelationship, FMComponentScript, FMComponentTable, FMComponentTableOccurence, FMComponentTest, FMComponentValueList}, file::FmpFile};
+use crate::{burn_script, compile::{diagnostic::{Diagnostic, Span}, symbols::Symbol, token::*}, component::{FMComponentField, FMComponentLayout, FMComponentPrivilegeSet, FMComponentRelationship, FMComponentScript, FMComponentSeed, FMComponentTable, FMComponentTableOccurence, FMComponentTest, FMComponentValueList, FMRelationCriterion, PrivilegeAccess, RelationComparison, ValueListSource}, file::FmpFile};
+
+/* Tokens of one top level construct. The span of the last token read is
+ * kept, so running out of tokens is reported where the construct stops. */
+struct Tokens<'a> {
+    iter: Peekable<Iter<'a, Token>>,
+    last: Span,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a Token;
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let t = self.iter.next();
+        if let Some(t) = t {
+            self.last = t.span;
+        }
+        t
+    }
+}
+
+impl<'a> Tokens<'a> {
+    fn new(tokens: &'a [Token]) -> Self {
+        Self {
+            iter: tokens.iter().peekable(),
+            last: tokens.first().map(|t| t.span).unwrap_or_default(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<&'a Token> {
+        self.iter.peek().copied()
+    }
+
+    /* Reports `message` at the next token, or at the end if there isn't one. */
+    fn error(&mut self, message: impl Into<String>) -> Diagnostic {
+        let span = self.peek().map(|t| t.span).unwrap_or(self.last);
+        Diagnostic::error(message, span)
+    }
+
+    fn next_or(&mut self, message: impl Into<String>) -> Result<&'a Token, Diagnostic> {
+        match self.next() {
+            Some(t) => Ok(t),
+            None => Err(Diagnostic::error(message, self.last)),
+        }
+    }
+
+    /* Reads the next token if it's a `ttype`, reporting `message` otherwise. */
+    fn expect(&mut self, ttype: TokenType, message: impl Into<String>) -> Result<&'a Token, Diagnostic> {
+        match self.peek() {
+            Some(t) if t.ttype == ttype => Ok(self.next().unwrap()),
+            _ => Err(self.error(message)),
+        }
+    }
+}
+
+/* How a token is named in diagnostics. */
+fn describe(t: &Token) -> String {
+    if t.text.is_empty() {
+        std::format!("{:?}", t.ttype)
+    } else {
+        t.text.clone()
+    }
+}
+
+fn is_top_level(ttype: &TokenType) -> bool {
+    matches!(ttype, TokenType::Table | TokenType::Relationship | TokenType::ValueList | TokenType::Layout
+        | TokenType::Data | TokenType::PrivilegeSet | TokenType::Script | TokenType::TableOccurence | TokenType::Test
+        | TokenType::Import)
+}
+
+/* Reads "KEYWORD;" after "end", closing the construct `keyword` started. */
+fn parse_end(parser_iter: &mut Tokens, keyword: &str, ttype: TokenType) -> Result<(), Diagnostic> {
+    let n = parser_iter.next_or(std::format!("Expected \"{}\" after \"end\"", keyword))?;
+    if n.ttype != ttype {
+        return Err(Diagnostic::error(std::format!("Unexpected {} after \"end\"", describe(n)), n.span));
+    }
+    parser_iter.expect(TokenType::SemiColon, "Please end top level constructs with \";\"")?;
+    Ok(())
+}
+
+/* Reads "Occurrence::field" into the tokens of its two names. */
+fn parse_field_reference<'a>(parser_iter: &mut Tokens<'a>) -> Result<(&'a Token, &'a Token), Diagnostic> {
+    let occurrence = parser_iter.expect(TokenType::Identifier, "Expected table occurrence name in field reference.")?;
+    for _ in 0..2 {
+        parser_iter.expect(TokenType::Colon, std::format!("Expected \"::\" after {} in field reference.", occurrence.text))?;
+    }
+    let field = parser_iter.expect(TokenType::Identifier, std::format!("Expected field name after {}::", occurrence.text))?;
+    Ok((occurrence, field))
+}
+
+/* Reads one option of a field declaration, starting from its first token.
+ * Value lists named by the option are added to `references`. */
+fn parse_field_option(parser_iter: &mut Tokens, token: &Token, field: &mut FMComponentField,
+    references: &mut Vec<(Symbol, Span)>) -> Result<(), Diagnostic> {
+    match token.ttype {
+        TokenType::DataType => {
+            if !field.data_type.is_empty() {
+                return Err(Diagnostic::error(std::format!("Field {} has more than one data type.", field.field_name), token.span));
+            }
+            field.data_type = token.text.clone();
+        },
+        TokenType::Unique => field.validation.unique = true,
+        TokenType::Required => field.validation.required = true,
+        TokenType::Existing => field.validation.existing = true,
+        TokenType::String => field.field_description = token.text.clone(),
+        TokenType::OpenCurly => field.auto_enter_calc = Some(parse_field_calculation(parser_iter, &field.field_name)?),
+        /* "!{calculation}" validates the field, "!\"message\"" is shown when it fails. */
+        TokenType::Exclamation => match parser_iter.peek() {
+            Some(t) if t.ttype == TokenType::OpenCurly => {
+                parser_iter.next();
+                field.validation.calculation = Some(parse_field_calculation(parser_iter, &field.field_name)?);
+            },
+            Some(t) if t.ttype == TokenType::String => {
+                parser_iter.next();
+                field.validation.message = Some(t.text.clone());
+            },
+            _ => return Err(parser_iter.error(std::format!("Expected validation calculation or message after \"!\" for field {}.", field.field_name))),
+        },
+        TokenType::FoundIn => {
+            parser_iter.expect(TokenType::OpenSquare, std::format!("Expected \"[\" after foundIn for field {}.", field.field_name))?;
+            let list = parser_iter.expect(TokenType::Identifier, std::format!("Expected value list name in foundIn for field {}.", field.field_name))?;
+            field.validation.value_list = Some(list.text.clone());
+            references.push((Symbol::ValueList(list.text.clone()), list.span));
+            parser_iter.expect(TokenType::CloseSquare, std::format!("Expected \"]\" after foundIn value list for field {}.", field.field_name))?;
+        },
+        TokenType::Identifier => return Err(Diagnostic::error(
+                std::format!("Unknown option {} for field {}.", token.text, field.field_name), token.span)),
+        _ => return Err(Diagnostic::error(
+                std::format!("Unexpected {} in declaration of field {}.", describe(token), field.field_name), token.span)),
+    }
+    Ok(())
+}
+
+/* Reads the calculation and closing brace after "{". */
+fn parse_field_calculation(parser_iter: &mut Tokens, field_name: &str) -> Result<String, Diagnostic> {
+    let calc = parser_iter.expect(TokenType::Calculation, std::format!("Expected calculation for field {}.", field_name))?;
+    parser_iter.expect(TokenType::CloseCurly, std::format!("Expected \"}}\" after calculation for field {}.", field_name))?;
+    Ok(calc.text.clone())
+}
+
+/* Reads the number after "id", which fixes the id of a field. */
+fn parse_field_id(parser_iter: &mut Tokens, field_name: &str) -> Result<u16, Diagnostic> {
+    let number = parser_iter.expect(TokenType::NumericLiteral, std::format!("Expected field id after \"id\" for field {}.", field_name))?;
+    match number.text.parse::<u16>() {
+        Ok(id) if id > 0 => Ok(id),
+        _ => Err(Diagnostic::error(
+                std::format!("Field {} has id {}, field ids go from 1 to 65535.", field_name, number.text), number.span)),
+    }
+}
+
+/* Fields without an id follow the highest id given so far. */
+fn add_field(table: &mut FMComponentTable, field: FMComponentField, id: Option<u16>) -> Result<(), Diagnostic> {
+    if field.data_type.is_empty() {
+        return Err(Diagnostic::error(
+                std::format!("Field {} in table {} needs a data type.", field.field_name, table.table_name), field.span));
+    }
+    if table.fields.values().any(|f| f.field_name == field.field_name) {
+        return Err(Diagnostic::error(
+                std::format!("Table {} already has a field named {}.", table.table_name, field.field_name), field.span));
+    }
+    let next = table.fields.keys().next_back().map(|id| id.checked_add(1));
+    let Some(id) = id.or(next.unwrap_or(Some(1))) else {
+        return Err(Diagnostic::error(
+                std::format!("Field {} follows field id 65535 and needs an id of its own.", field.field_name), field.span));
+    };
+    if let Some(other) = table.fields.get(&id) {
+        return Err(Diagnostic::error(
+                std::format!("Field {} has id {}, which field {} already has.", field.field_name, id, other.field_name), field.span));
+    }
+    table.fields.insert(id, field);
+    Ok(())
+}
+
+/* The comparison between two fields of a relationship predicate. Cartesian
+ * is written "*", or "×" as FileMaker shows it. */
+fn parse_comparison(token: &Token) -> Option<RelationComparison> {
+    match token.ttype {
+        TokenType::EComparison => Some(RelationComparison::Equal),
+        TokenType::NEComparison => Some(RelationComparison::NotEqual),
+        TokenType::LComparison => Some(RelationComparison::Less),
+        TokenType::LEComparison => Some(RelationComparison::LessEqual),
+        TokenType::GComparison => Some(RelationComparison::Greater),
+        TokenType::GEComparison => Some(RelationComparison::GreaterEqual),
+        TokenType::CComparison => Some(RelationComparison::Cartesian),
+        _ => None,
+    }
+}
+
+/* Script spans are found in the text of the script block, starting at `block`. */
+fn place_script(script: &mut FMComponentScript, block: Span) {
+    script.span = script.span.relative_to(block);
+    for step in &mut script.instructions {
+        step.span = step.span.relative_to(block);
+        for span in &mut step.switch_spans {
+            *span = span.relative_to(block);
+        }
+    }
+}
 
 pub struct Parser {
     tokens: Vec<Token>,
@@ -12,239 +210,650 @@ impl Parser {
         }
     }
 
-    pub fn parse_program(&self) -> Result<FmpFile, String> {
+    /* Splits the tokens into top level constructs, which are parsed on their
+     * own so a mistake in one doesn't hide mistakes in the others. A construct
+     * runs from its keyword to "end KEYWORD;", or to the "]" closing a script
+     * block. Without an end, it runs to the next keyword following a ";". */
+    fn constructs(&self) -> Vec<&[Token]> {
+        let tokens = &self.tokens;
+        let mut ret = vec![];
+        let mut start = 0;
+        while start < tokens.len() {
+            let keyword = &tokens[start].ttype;
+            let closed = (start + 1..tokens.len()).find(|i| match keyword {
+                TokenType::Script => tokens[*i].ttype == TokenType::CloseSquare,
+                _ if is_top_level(keyword) => tokens[*i - 1].ttype == TokenType::End && tokens[*i].ttype == *keyword,
+                _ => false,
+            });
+            let end = match closed {
+                Some(i) if tokens.get(i + 1).map(|t| &t.ttype) == Some(&TokenType::SemiColon) => i + 2,
+                Some(i) => i + 1,
+                None => (start + 1..tokens.len())
+                    .find(|i| is_top_level(&tokens[*i].ttype)
+                        && (!is_top_level(keyword) || tokens[*i - 1].ttype == TokenType::SemiColon))
+                    .unwrap_or(tokens.len()),
+            };
+            ret.push(&tokens[start..end]);
+            start = end;
+        }
+        ret
+    }
+
+    /* Parses every construct, collecting the mistakes found in each. */
+    pub fn parse_program(&self) -> Result<FmpFile, Vec<Diagnostic>> {
+        let (ret, diagnostics) = self.parse_partial();
+        if diagnostics.is_empty() {
+            Ok(ret)
+        } else {
+            Err(diagnostics)
+        }
+    }
 
+    /* Parses every construct, keeping whatever was read before a mistake.
+     * Constructs are added as they're read, so the file is only partly
+     * built when there are mistakes. */
+    pub fn parse_partial(&self) -> (FmpFile, Vec<Diagnostic>) {
         let mut ret = FmpFile::new();
-        let mut parser_iter = self.tokens.iter().peekable().clone();
-
-        while let Some(t) = parser_iter.next() {
-            match t.ttype {
-                TokenType::Table => {
-                    let mut table = FMComponentTable::new();
-                    if parser_iter.peek().unwrap().ttype != TokenType::Identifier {
-                        return Err("Expected Table name after \"table\" keyword.".to_string())
-                    } else {
-                        table.table_name = parser_iter.next().unwrap().text.clone();
-                    }
-                    while let Some(mut n) = parser_iter.next() {
-                        match n.ttype {
-                            TokenType::End => 
-                            {
-                                n = parser_iter.next().unwrap();
-                                if n.ttype == TokenType::Table {
-                                    if parser_iter.next().unwrap().ttype != TokenType::SemiColon {
-                                        return Err("Please end top level constructs with \";\"".to_string());
-                                    }
-                                    break;
-                                } else {
-                                    return Err(std::format!("Unexpected {} after \"end\"", n.text).to_string());
-                                }
+        let mut diagnostics = vec![];
+        for construct in self.constructs() {
+            if let Err(e) = Self::parse_construct(construct, &mut ret, &mut diagnostics) {
+                diagnostics.push(e);
+            }
+        }
+        (ret, diagnostics)
+    }
+
+    /* Scripts are compiled by their own layer, which can report several
+     * mistakes at once. Those are added to `diagnostics` directly. */
+    fn parse_construct(tokens: &[Token], ret: &mut FmpFile, diagnostics: &mut Vec<Diagnostic>) -> Result<(), Diagnostic> {
+        let mut parser_iter = Tokens::new(tokens);
+        let Some(t) = parser_iter.next() else {
+            return Ok(());
+        };
+
+        match t.ttype {
+            TokenType::Table => {
+                let mut table = FMComponentTable::new();
+                let name = parser_iter.expect(TokenType::Identifier, "Expected Table name after \"table\" keyword.")?;
+                table.table_name = name.text.clone();
+                table.span = name.span;
+                parser_iter.expect(TokenType::Colon, std::format!("Expected colon after table name {}.", table.table_name))?;
+                /* Fields are numbered in the order they're declared, unless given an id. */
+                let mut field: Option<FMComponentField> = None;
+                let mut field_id = None;
+                loop {
+                    let n = parser_iter.next_or("Expected \"end table;\"")?;
+                    match n.ttype {
+                        TokenType::Identifier if parser_iter.peek().map(|t| &t.ttype) == Some(&TokenType::Colon) => {
+                            parser_iter.next();
+                            if let Some(done) = field.take() {
+                                add_field(&mut table, done, field_id.take())?;
                             }
-                            _ => {
+                            let mut next = FMComponentField::new();
+                            next.field_name = n.text.clone();
+                            next.field_type = String::from("Simple");
+                            next.span = n.span;
+                            field = Some(next);
+                        },
+                        TokenType::End => {
+                            parse_end(&mut parser_iter, "table", TokenType::Table)?;
+                            break;
+                        },
+                        TokenType::Comma => {
+                            continue;
+                        },
+                        _ => {
+                            let Some(field) = field.as_mut() else {
+                                return Err(Diagnostic::error(std::format!("Expected field name in table {}.", table.table_name), n.span));
+                            };
+                            if n.ttype == TokenType::Identifier && n.text == "id" {
+                                if field_id.is_some() {
+                                    return Err(Diagnostic::error(std::format!("Field {} has more than one id.", field.field_name), n.span));
+                                }
+                                field_id = Some(parse_field_id(&mut parser_iter, &field.field_name)?);
                                 continue;
                             }
+                            parse_field_option(&mut parser_iter, n, field, &mut ret.references)?;
                         }
                     }
-                    ret.tables.insert(ret.tables.len() + 1, table);
-                },
-                TokenType::Relationship => {
-                    let mut relationship = FMComponentRelationship::new();
-                    if parser_iter.peek().unwrap().ttype != TokenType::Colon {
-                        return Err("Expected colon after \"relationship\" keyword.".to_string())
-                    } 
-                    while let Some(mut n) = parser_iter.next() {
-                        match n.ttype {
-                            TokenType::Identifier => {
-                                if parser_iter.peek().unwrap().ttype == TokenType::Colon {
-                                    relationship.table1_name = n.text.clone();
-                                    parser_iter.next();
-                                    let n = parser_iter.next().unwrap();
-                                    relationship.table2_name = n.text.clone();
-                                } else {
-
-                                }
-                            }
-                            TokenType::End => 
-                            {
-                                n = parser_iter.next().unwrap();
-                                if n.ttype == TokenType::Relationship {
-                                    if parser_iter.next().unwrap().ttype != TokenType::SemiColon {
-                                        return Err("Please end top level constructs with \";\"".to_string());
-                                    }
-                                    break;
-                                } else {
-                                    return Err(std::format!("Unexpected {} after \"end\"", n.text).to_string());
-                                }
+                }
+                if let Some(done) = field.take() {
+                    add_field(&mut table, done, field_id.take())?;
+                }
+                ret.tables.insert(ret.tables.len() + 1, table);
+            },
+            TokenType::Relationship => {
+                let mut relationship = FMComponentRelationship::new();
+                parser_iter.expect(TokenType::Colon, "Expected colon after \"relationship\" keyword.")?;
+                relationship.span = t.span;
+                loop {
+                    let n = parser_iter.next_or("Expected \"end relationship;\"")?;
+                    match n.ttype {
+                        /* The two table occurrences: "Person:Job" */
+                        TokenType::Identifier if parser_iter.peek().map(|t| &t.ttype) == Some(&TokenType::Colon) => {
+                            if !relationship.table1_name.is_empty() {
+                                return Err(Diagnostic::error(std::format!("Relationship {}:{} names its table occurrences twice.",
+                                        relationship.table1_name, relationship.table2_name), n.span));
                             }
-                            _ => {
-                                continue;
+                            parser_iter.next();
+                            relationship.table1_name = n.text.clone();
+                            relationship.span = n.span;
+                            let table2 = parser_iter.expect(TokenType::Identifier,
+                                std::format!("Expected second table occurrence after {}:", relationship.table1_name))?;
+                            relationship.table2_name = table2.text.clone();
+                            ret.references.push((Symbol::TableOccurrence(n.text.clone()), n.span));
+                            ret.references.push((Symbol::TableOccurrence(table2.text.clone()), table2.span));
+                        },
+                        /* A predicate, field from the first occurrence then the second: "jobid == PrimaryKey" */
+                        TokenType::Identifier => {
+                            if relationship.table1_name.is_empty() {
+                                return Err(Diagnostic::error("Expected \"Occurrence1:Occurrence2\" before relationship predicates.", n.span));
                             }
+                            let mut criterion = FMRelationCriterion::new();
+                            criterion.field1_name = n.text.clone();
+                            criterion.comparison = match parser_iter.peek().and_then(parse_comparison) {
+                                Some(comparison) => comparison,
+                                None => return Err(parser_iter.error(std::format!("Expected comparison after {} in relationship {}:{}.",
+                                        criterion.field1_name, relationship.table1_name, relationship.table2_name))),
+                            };
+                            parser_iter.next();
+                            let field2 = parser_iter.expect(TokenType::Identifier,
+                                std::format!("Expected field name after comparison with {}.", criterion.field1_name))?;
+                            criterion.field2_name = field2.text.clone();
+                            ret.references.push((Symbol::OccurrenceField(relationship.table1_name.clone(), n.text.clone()), n.span));
+                            ret.references.push((Symbol::OccurrenceField(relationship.table2_name.clone(), field2.text.clone()), field2.span));
+                            relationship.criteria.push(criterion);
+                        },
+                        TokenType::Comma => {
+                            continue;
+                        },
+                        TokenType::End => {
+                            parse_end(&mut parser_iter, "relationship", TokenType::Relationship)?;
+                            break;
+                        }
+                        _ => {
+                            return Err(Diagnostic::error(std::format!("Unexpected {} in relationship.", describe(n)), n.span));
                         }
                     }
-                    ret.relationships.insert(ret.tables.len() + 1, relationship);
-                },
-                TokenType::ValueList => {
-                    let mut value_list = FMComponentValueList::new();
-                    if parser_iter.peek().unwrap().ttype != TokenType::Identifier {
-                        return Err("Expected identifier after \"value_list\" keyword.".to_string())
-                    } else {
-                        value_list.list_name = parser_iter.next().unwrap().text.clone();
+                }
+                if relationship.criteria.is_empty() {
+                    return Err(Diagnostic::error(std::format!("Relationship {}:{} needs at least one predicate.",
+                            relationship.table1_name, relationship.table2_name), relationship.span));
+                }
+                ret.relationships.insert(ret.relationships.len() + 1, relationship);
+            },
+            TokenType::ValueList => {
+                let mut value_list = FMComponentValueList::new();
+                let name = parser_iter.expect(TokenType::Identifier, "Expected identifier after \"value_list\" keyword.")?;
+                value_list.list_name = name.text.clone();
+                value_list.span = name.span;
+                parser_iter.expect(TokenType::Colon, std::format!("Expected colon after value list name {}.", value_list.list_name))?;
+                let mut values = vec![];
+                loop {
+                    let n = parser_iter.next_or("Expected \"end value_list;\"")?;
+                    match n.ttype {
+                        TokenType::String => {
+                            values.push(n.text.clone());
+                        },
+                        /* Values taken from a field: "field: Occurrence::field" */
+                        TokenType::Identifier if n.text == "field" => {
+                            parser_iter.expect(TokenType::Colon, "Expected colon after \"field\" in value list.")?;
+                            let (occurrence, field) = parse_field_reference(&mut parser_iter)?;
+                            ret.references.push((Symbol::TableOccurrence(occurrence.text.clone()), occurrence.span));
+                            ret.references.push((Symbol::OccurrenceField(occurrence.text.clone(), field.text.clone()), field.span));
+                            value_list.source = ValueListSource::Field {
+                                table_occurrence: 0,
+                                table_occurrence_name: occurrence.text.clone(),
+                                field: 0,
+                                field_name: field.text.clone(),
+                            };
+                        },
+                        TokenType::Comma => {
+                            continue;
+                        },
+                        TokenType::End => {
+                            parse_end(&mut parser_iter, "value_list", TokenType::ValueList)?;
+                            break;
+                        }
+                        _ => {
+                            return Err(Diagnostic::error(
+                                    std::format!("Unexpected {} in value list {}.", describe(n), value_list.list_name), n.span));
+                        }
                     }
-                    while let Some(mut n) = parser_iter.next() {
-                        match n.ttype {
-                            TokenType::End => 
-                            {
-                                n = parser_iter.next().unwrap();
-                                if n.ttype == TokenType::ValueList {
-                                    if parser_iter.next().unwrap().ttype != TokenType::SemiColon {
-                                        return Err("Please end top level constructs with \";\"".to_string());
-                                    }
-                                    break;
-                                } else {
-                                    return Err(std::format!("Unexpected {} after \"end\"", n.text).to_string());
-                                }
+                }
+                if let ValueListSource::CustomValues(custom) = &mut value_list.source {
+                    *custom = values;
+                } else if !values.is_empty() {
+                    return Err(Diagnostic::error(
+                            std::format!("Value list {} has both custom values and a field.", value_list.list_name), value_list.span));
+                }
+                ret.value_lists.insert(ret.value_lists.len() + 1, value_list);
+            },
+            TokenType::Layout => {
+                let mut layout = FMComponentLayout::new();
+                let name = parser_iter.expect(TokenType::Identifier, "Expected identifier after \"layout\" keyword.")?;
+                layout.layout_name = name.text.clone();
+                layout.span = name.span;
+                parser_iter.expect(TokenType::Colon, std::format!("Expected colon after layout name {}.", layout.layout_name))?;
+                let mut fields = vec![];
+                loop {
+                    let n = parser_iter.next_or("Expected \"end layout;\"")?;
+                    match n.ttype {
+                        TokenType::TableOccurence => {
+                            parser_iter.expect(TokenType::Colon, "Expected colon after table_occurence specifier.")?;
+                            let occurrence = parser_iter.expect(TokenType::Identifier, "Expected Identifier for table occurrence reference.")?;
+                            layout.table_occurrence_name = occurrence.text.clone();
+                            ret.references.push((Symbol::TableOccurrence(occurrence.text.clone()), occurrence.span));
+                        },
+                        /* Every other name is a field, placed in the order listed. */
+                        TokenType::Identifier => {
+                            layout.place_field(&n.text);
+                            fields.push(n);
+                        },
+                        TokenType::Comma => {
+                            continue;
+                        },
+                        TokenType::End => {
+                            parse_end(&mut parser_iter, "layout", TokenType::Layout)?;
+                            break;
+                        }
+                        _ => {
+                            return Err(Diagnostic::error(
+                                    std::format!("Unexpected {} in layout {}.", describe(n), layout.layout_name), n.span));
+                        }
+                    }
+                }
+                if layout.table_occurrence_name.is_empty() {
+                    return Err(Diagnostic::error(std::format!("Layout {} needs a table_occurence.", layout.layout_name), layout.span));
+                }
+                for field in fields {
+                    ret.references.push((Symbol::OccurrenceField(layout.table_occurrence_name.clone(), field.text.clone()), field.span));
+                }
+                ret.layouts.insert(ret.layouts.len() + 1, layout);
+            },
+            TokenType::Data => {
+                let mut seed = FMComponentSeed::new();
+                let name = parser_iter.expect(TokenType::Identifier, "Expected table name after \"data\" keyword.")?;
+                seed.table_name = name.text.clone();
+                seed.span = name.span;
+                ret.references.push((Symbol::Table(name.text.clone()), name.span));
+                /* The first row names the fields, every row after it holds values. */
+                let mut row = vec![];
+                loop {
+                    let n = parser_iter.next_or("Expected \"end data;\"")?;
+                    match n.ttype {
+                        TokenType::Identifier if n.text == "source" => {
+                            parser_iter.expect(TokenType::Colon, "Expected colon after \"source\" in data block.")?;
+                            let source = parser_iter.expect(TokenType::String, "Expected file name for data source.")?;
+                            seed.source = Some(source.text.clone());
+                        },
+                        TokenType::Identifier if seed.columns.is_empty() => {
+                            ret.references.push((Symbol::Field(seed.table_name.clone(), n.text.clone()), n.span));
+                            row.push(n.text.clone());
+                        },
+                        TokenType::String | TokenType::NumericLiteral => {
+                            if seed.columns.is_empty() {
+                                return Err(Diagnostic::error(
+                                        std::format!("Data for {} needs a row of field names first.", seed.table_name), n.span));
                             }
-                            _ => {
-                                continue;
+                            row.push(n.text.clone());
+                        },
+                        TokenType::SemiColon if !row.is_empty() => {
+                            if seed.columns.is_empty() {
+                                seed.columns = std::mem::take(&mut row);
+                            } else if row.len() != seed.columns.len() {
+                                return Err(Diagnostic::error(std::format!("Row {} of data for {} has {} values, expected {}.",
+                                        seed.rows.len() + 1, seed.table_name, row.len(), seed.columns.len()), n.span));
+                            } else {
+                                seed.rows.push(std::mem::take(&mut row));
                             }
+                        },
+                        TokenType::End => {
+                            parse_end(&mut parser_iter, "data", TokenType::Data)?;
+                            break;
+                        }
+                        _ => {
+                            continue;
                         }
                     }
-                    ret.value_lists.insert(ret.value_lists.len() + 1, value_list);
-                },
-                TokenType::Script => {
-                    let scripts: Vec<FMComponentScript> = vec![];
-                    if parser_iter.peek().unwrap().ttype != TokenType::Colon {
-                        return Err("Expected Colon after top level declaration script keyword.".to_string());
-                    }
-                    parser_iter.next();
-                    if parser_iter.peek().unwrap().ttype != TokenType::OpenSquare {
-                        return Err("Please use \"[\" to denote entering a script block.".to_string());
+                }
+                if seed.source.is_some() && !seed.columns.is_empty() {
+                    return Err(Diagnostic::error(std::format!("Data for {} has both a source and rows.", seed.table_name), seed.span));
+                }
+                ret.seeds.push(seed);
+            },
+            TokenType::PrivilegeSet => {
+                let mut set = FMComponentPrivilegeSet::new();
+                let name = parser_iter.expect(TokenType::Identifier, "Expected identifier after \"privilege_set\" keyword.")?;
+                set.set_name = name.text.clone();
+                set.span = name.span;
+                parser_iter.expect(TokenType::Colon, std::format!("Expected colon after privilege set name {}.", set.set_name))?;
+                loop {
+                    let n = parser_iter.next_or("Expected \"end privilege_set;\"")?;
+                    match n.ttype {
+                        /* The standard set whose privileges are given: "access: read_only" */
+                        TokenType::Identifier if n.text == "access" => {
+                            parser_iter.expect(TokenType::Colon, "Expected colon after \"access\" in privilege set.")?;
+                            let access = parser_iter.next_or("Expected full, data_entry or read_only after \"access:\".")?;
+                            set.access = match access.text.as_str() {
+                                "full" => PrivilegeAccess::FullAccess,
+                                "data_entry" => PrivilegeAccess::DataEntryOnly,
+                                "read_only" => PrivilegeAccess::ReadOnlyAccess,
+                                _ => return Err(Diagnostic::error(
+                                        std::format!("Unknown access {}, expected full, data_entry or read_only.", describe(access)), access.span)),
+                            };
+                        },
+                        TokenType::Identifier if n.text == "description" => {
+                            parser_iter.expect(TokenType::Colon, "Expected colon after \"description\" in privilege set.")?;
+                            let description = parser_iter.expect(TokenType::String, "Expected string for privilege set description.")?;
+                            set.set_description = description.text.clone();
+                        },
+                        TokenType::Comma => {
+                            continue;
+                        },
+                        TokenType::End => {
+                            parse_end(&mut parser_iter, "privilege_set", TokenType::PrivilegeSet)?;
+                            break;
+                        }
+                        _ => {
+                            return Err(Diagnostic::error(
+                                    std::format!("Unexpected {} in privilege set {}.", describe(n), set.set_name), n.span));
+                        }
                     }
+                }
+                ret.privilege_sets.insert(ret.privilege_sets.len() + 1, set);
+            },
+            TokenType::Script => {
+                /* "script export:" blocks are written to the file, "script:" blocks aren't. */
+                let export = parser_iter.peek().is_some_and(|t| t.ttype == TokenType::Identifier && t.text == "export");
+                if export {
                     parser_iter.next();
-                    let block = parser_iter.next().unwrap();
-
-                    let scripts = burn_script::compiler::BurnScriptCompiler::compile_burn_script(&block.text);
-                    
-                    for s in scripts {
-                        ret.scripts.insert(ret.scripts.len() + 1, s);
-                    }
-
-                    let next = parser_iter.next().unwrap();
-                    if next.ttype != TokenType::CloseSquare {
-                        return Err("Please end BurnScript block with \"]\"".to_string());
-                    }
-                },
-                TokenType::TableOccurence => {
-                    let mut table_occurence = FMComponentTableOccurence::new();
-                    if parser_iter.peek().unwrap().ttype != TokenType::Identifier {
-                        return Err("Expected Table name after \"table\" keyword.".to_string())
-                    } else {
-                        table_occurence.table_occurence_name = parser_iter.next().unwrap().text.clone();
-                    }
-                    while let Some(mut t) = parser_iter.next() {
-                        // println!("{:?}", t.ttype);
-                        match t.ttype {
-                            TokenType::Table => {
-                                t = parser_iter.next().unwrap();
-                                if t.ttype != TokenType::Colon {
-                                    return Err("Expected colon after table specifier.".to_string());
-                                }
-                                t = parser_iter.next().unwrap();
-                                if t.ttype != TokenType::Identifier {
-                                    return Err("Expected Identifier for table reference.".to_string());
-                                }
-                                table_occurence.table_actual_name = t.text.clone();
-                            },
-                            TokenType::Comma => {
-
-                            },
-                            TokenType::End => {
-                                t = parser_iter.next().unwrap();
-                                if t.ttype == TokenType::TableOccurence {
-                                    if parser_iter.next().unwrap().ttype != TokenType::SemiColon {
-                                        return Err("Please end top level constructs with \";\"".to_string());
-                                    }
-                                    break;
-                                } else {
-                                    return Err(std::format!("Unexpected {} after \"end\"", t.text).to_string());
-                                }
-                            }
-                            _ => {
-
-                            }
+                }
+                parser_iter.expect(TokenType::Colon, "Expected Colon after top level declaration script keyword.")?;
+                parser_iter.expect(TokenType::OpenSquare, "Please use \"[\" to denote entering a script block.")?;
+                let block = parser_iter.expect(TokenType::Script, "Expected script block after \"[\".")?;
+                match burn_script::compiler::BurnScriptCompiler::compile_burn_script(&block.text) {
+                    Ok(scripts) => {
+                        for mut s in scripts {
+                            place_script(&mut s, block.span);
+                            s.export = export;
+                            ret.scripts.insert(ret.scripts.len() + 1, s);
                         }
+                    },
+                    Err(errors) => {
+                        diagnostics.extend(errors.into_iter().map(|mut e| {
+                            e.span = e.span.relative_to(block.span);
+                            e
+                        }));
                     }
-                    ret.table_occurrences.insert(ret.table_occurrences.len(), table_occurence);
-                },
-                TokenType::Test => {
-                    let mut test = FMComponentTest::new();
-                    if parser_iter.peek().unwrap().ttype != TokenType::Identifier {
-                        return Err("Expected identifier after \"test\" keyword.".to_string())
-                    } else {
-                        test.test_name = parser_iter.next().unwrap().text.clone();
+                }
+                parser_iter.expect(TokenType::CloseSquare, "Please end BurnScript block with \"]\"")?;
+            },
+            TokenType::TableOccurence => {
+                let mut table_occurence = FMComponentTableOccurence::new();
+                let name = parser_iter.expect(TokenType::Identifier, "Expected Table name after \"table\" keyword.")?;
+                table_occurence.table_occurence_name = name.text.clone();
+                table_occurence.span = name.span;
+                parser_iter.expect(TokenType::Colon,
+                    std::format!("Expected colon after table occurrence name {}.", table_occurence.table_occurence_name))?;
+                loop {
+                    let t = parser_iter.next_or("Expected \"end table_occurence;\"")?;
+                    match t.ttype {
+                        TokenType::Table => {
+                            parser_iter.expect(TokenType::Colon, "Expected colon after table specifier.")?;
+                            let table = parser_iter.expect(TokenType::Identifier, "Expected Identifier for table reference.")?;
+                            table_occurence.table_actual_name = table.text.clone();
+                            ret.references.push((Symbol::Table(table.text.clone()), table.span));
+                        },
+                        TokenType::Comma => {
+                            continue;
+                        },
+                        TokenType::End => {
+                            parse_end(&mut parser_iter, "table_occurence", TokenType::TableOccurence)?;
+                            break;
+                        }
+                        _ => {
+                            return Err(Diagnostic::error(std::format!("Unexpected {} in table occurrence {}.",
+                                    describe(t), table_occurence.table_occurence_name), t.span));
+                        }
                     }
+                }
+                ret.table_occurrences.insert(ret.table_occurrences.len() + 1, table_occurence);
+            },
+            TokenType::Test => {
+                let mut test = FMComponentTest::new();
+                let name = parser_iter.expect(TokenType::Identifier, "Expected identifier after \"test\" keyword.")?;
+                test.test_name = name.text.clone();
+                test.span = name.span;
 
-                    while let Some(mut n) = parser_iter.next() {
-                        match n.ttype {
-                            TokenType::Script => {
-                                if parser_iter.next().unwrap().ttype != TokenType::Colon {
-                                    return Err("expected ':' after object block".to_string());
-                                }
-                                if parser_iter.next().unwrap().ttype != TokenType::OpenSquare {
-                                    return Err("expected '[' to start script block".to_string());
-                                }
-                                let block = parser_iter.next();
-                                let scripts = burn_script::compiler::BurnScriptCompiler::compile_burn_script(&block.unwrap().text);
-                                if scripts.len() > 1 {
-                                    return Err("Please ensure that tests only have 1 script defined.".to_string());
+                loop {
+                    let n = parser_iter.next_or("Expected \"end test;\"")?;
+                    match n.ttype {
+                        TokenType::Script => {
+                            parser_iter.expect(TokenType::Colon, "expected ':' after object block")?;
+                            parser_iter.expect(TokenType::OpenSquare, "expected '[' to start script block")?;
+                            let block = parser_iter.expect(TokenType::Script, "Expected script block after \"[\".")?;
+                            let mut scripts = match burn_script::compiler::BurnScriptCompiler::compile_burn_script(&block.text) {
+                                Ok(scripts) => scripts,
+                                Err(errors) => {
+                                    diagnostics.extend(errors.into_iter().map(|mut e| {
+                                        e.span = e.span.relative_to(block.span);
+                                        e
+                                    }));
+                                    return Ok(());
                                 }
-                                test.script = scripts[0].clone();
-                                continue;
+                            };
+                            if scripts.len() != 1 {
+                                return Err(Diagnostic::error("Please ensure that tests only have 1 script defined.", block.span));
                             }
-                            TokenType::AssertionBlock => {
-                                if parser_iter.next().unwrap().ttype != TokenType::Colon {
-                                    return Err("Expected ':' after object block.".to_string());
-                                }
-                                while let Some(t) = parser_iter.next() {
-                                    match t.ttype {
-                                        TokenType::Assertion => {
-                                            test.assertions.push(t.text.clone());
-                                        },
-                                        TokenType::Comma => {
-                                            continue;
-                                        }
-                                        _ => {
-                                            break;
-                                        }
-
+                            test.script = scripts.remove(0);
+                            place_script(&mut test.script, block.span);
+                            continue;
+                        }
+                        TokenType::AssertionBlock => {
+                            parser_iter.expect(TokenType::Colon, "Expected ':' after object block.")?;
+                            while let Some(t) = parser_iter.next() {
+                                match t.ttype {
+                                    TokenType::Assertion => {
+                                        test.assertions.push(t.text.clone());
+                                    },
+                                    TokenType::Comma => {
+                                        continue;
                                     }
-                                }
-                            }
-                            TokenType::End => 
-                            {
-                                n = parser_iter.next().unwrap();
-                                if n.ttype == TokenType::Test {
-                                    if parser_iter.next().unwrap().ttype != TokenType::SemiColon {
-                                        return Err("Please end top level constructs with \";\"".to_string());
+                                    _ => {
+                                        break;
                                     }
-                                    break;
-                                } else {
-                                    return Err(std::format!("Unexpected {} after \"end\"", n.text).to_string());
+
                                 }
                             }
-                            _ => {
-                                continue;
-                            }
+                        }
+                        TokenType::End => {
+                            parse_end(&mut parser_iter, "test", TokenType::Test)?;
+                            break;
+                        }
+                        _ => {
+                            continue;
                         }
                     }
-                    ret.tests.push(test);
-                },
-                _ => {
-                    return Err("Unrecognized top level structure.".to_string());
                 }
+                ret.tests.push(test);
+            },
+            TokenType::Import => {
+                let path = parser_iter.expect(TokenType::String, "Expected file name in quotes after \"import\".")?;
+                parser_iter.expect(TokenType::SemiColon, "Please end imports with \";\"")?;
+                ret.imports.push((path.text.clone(), path.span));
+            },
+            _ => {
+                return Err(Diagnostic::error("Unrecognized top level structure.", t.span));
             }
         }
-        return Ok(ret);
+        Ok(())
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::{compile::{diagnostic::Span, lexer::tokenize}, component::RelationComparison};
+    use super::Parser;
+
+    #[test]
+    fn field_declaration_test() {
+        let code = "
+            table Person:
+              PrimaryKey: Unique, Required, Text, {Get ( UUID )},
+              !{not IsEmpty ( PrimaryKey )}, !\"A primary key is needed\",
+              \"Used as a primary key\",
+
+              sex: Text, foundIn[male_female],
+              age: Number,
+            end table;
+        ";
+        let file = Parser::new(tokenize(code).unwrap()).parse_program().unwrap();
+        let fields = &file.tables[&1].fields;
+        assert_eq!(fields.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let key = &fields[&1];
+        assert_eq!(key.field_name, "PrimaryKey");
+        assert_eq!(key.data_type, "Text");
+        assert_eq!(key.field_description, "Used as a primary key");
+        assert_eq!(key.auto_enter_calc.as_deref(), Some("Get ( UUID )"));
+        assert!(key.validation.unique && key.validation.required);
+        assert_eq!(key.validation.calculation.as_deref(), Some("not IsEmpty ( PrimaryKey )"));
+        assert_eq!(key.validation.message.as_deref(), Some("A primary key is needed"));
+
+        assert_eq!(fields[&2].validation.value_list.as_deref(), Some("male_female"));
+        assert!(!fields[&2].validation.unique);
+        assert_eq!(fields[&3].field_name, "age");
+        assert_eq!(fields[&3].data_type, "Number");
+
+        /* Fields given an id keep it wherever they're declared, the rest follow the highest id so far. */
+        let code = "
+            table Person:
+              age: Number, id 3,
+              name: Text,
+              PrimaryKey: id 1, Text,
+              sex: Text,
+            end table;
+        ";
+        let file = Parser::new(tokenize(code).unwrap()).parse_program().unwrap();
+        let names = file.tables[&1].fields.iter().map(|(id, f)| (*id, f.field_name.as_str())).collect::<Vec<_>>();
+        assert_eq!(names, vec![(1, "PrimaryKey"), (3, "age"), (4, "name"), (5, "sex")]);
+    }
+
+    #[test]
+    fn relationship_predicate_test() {
+        let code = "
+            relationship:
+              Person:Invoice,
+              PrimaryKey == PersonKey,
+              Amount != Total, Amount < Total, Amount <= Total,
+              Amount > Total, Amount >= Total,
+              Created * Created, Created × Created,
+            end relationship;
+        ";
+        let file = Parser::new(tokenize(code).unwrap()).parse_program().unwrap();
+        let rel = &file.relationships[&1];
+        assert_eq!((rel.table1_name.as_str(), rel.table2_name.as_str()), ("Person", "Invoice"));
+        assert_eq!((rel.criteria[0].field1_name.as_str(), rel.criteria[0].field2_name.as_str()), ("PrimaryKey", "PersonKey"));
+        assert_eq!(rel.criteria.iter().map(|c| c.comparison.clone()).collect::<Vec<_>>(), vec![
+            RelationComparison::Equal, RelationComparison::NotEqual,
+            RelationComparison::Less, RelationComparison::LessEqual,
+            RelationComparison::Greater, RelationComparison::GreaterEqual,
+            RelationComparison::Cartesian, RelationComparison::Cartesian]);
+
+        let errors = Parser::new(tokenize("relationship: A:B, x = y, end relationship;").unwrap()).parse_program().err().unwrap();
+        assert_eq!(errors[0].message, "Expected comparison after x in relationship A:B.");
+        let errors = Parser::new(tokenize("relationship: A:B, x O y, end relationship;").unwrap()).parse_program().err().unwrap();
+        assert_eq!(errors[0].message, "Expected comparison after x in relationship A:B.");
+
+        /* Relationships are numbered on their own, not after the tables. */
+        let code = "table A: x: Text, end table; relationship: A:B, x == y, end relationship; relationship: B:A, y == x, end relationship;";
+        let file = Parser::new(tokenize(code).unwrap()).parse_program().unwrap();
+        assert_eq!(file.relationships.keys().collect::<Vec<_>>(), vec![&1, &2]);
+    }
+
+    #[test]
+    fn unexpected_token_test() {
+        let code = "
+value_list Sizes:
+  \"S\", Text,
+end value_list;
+layout People:
+  table_occurence: Person, \"name\",
+end layout;
+privilege_set Sales:
+  access: full, Unique,
+end privilege_set;
+table_occurence Person:
+  table: Person, name,
+end table_occurence;
+";
+        let errors = Parser::new(tokenize(code).unwrap()).parse_program().err().unwrap();
+        let errors = errors.iter().map(|d| (d.message.as_str(), d.span)).collect::<Vec<_>>();
+        assert_eq!(errors, vec![
+            ("Unexpected Text in value list Sizes.", Span::new(3, 8, 4)),
+            ("Unexpected name in layout People.", Span::new(6, 28, 6)),
+            ("Unexpected Unique in privilege set Sales.", Span::new(9, 17, 6)),
+            ("Unexpected name in table occurrence Person.", Span::new(12, 18, 4)),
+        ]);
+    }
+
+    #[test]
+    fn script_export_test() {
+        let code = "
+            script export: [ define main() { perform_script(\"setup\"); } ]
+            script: [ define setup() { set_variable(x, 1); } ]
+        ";
+        let file = Parser::new(tokenize(code).unwrap()).parse_program().unwrap();
+        let scripts = file.scripts.values().map(|s| (s.script_name.as_str(), s.export)).collect::<Vec<_>>();
+        assert_eq!(scripts, vec![("main", true), ("setup", false)]);
+    }
+
+    #[test]
+    fn collected_errors_test() {
+        let code = "
+table Person:
+  name: Text, Indexed,
+end table;
+
+layout People:
+  name,
+end layout;
+
+script: [
+  define broken() {
+    set_variable(x, 1);
+    go_nowhere(x);
+  }
+]
+";
+        let errors = Parser::new(tokenize(code).unwrap()).parse_program().err().unwrap();
+        let errors = errors.iter().map(|d| (d.message.as_str(), d.span)).collect::<Vec<_>>();
+        assert_eq!(errors, vec![
+            ("Unknown option Indexed for field name.", Span::new(3, 15, 7)),
+            ("Layout People needs a table_occurence.", Span::new(6, 8, 6)),
+            ("Unknown script step go_nowhere.", Span::new(13, 5, 10)),
+        ]);
+    }
+
+    #[test]
+    fn field_error_test() {
+        let parse = |code: &str| Parser::new(tokenize(code).unwrap()).parse_program().err().map(|e| e[0].message.clone());
+        assert_eq!(parse("table T: name: Text, Indexed, end table;"),
+            Some(String::from("Unknown option Indexed for field name.")));
+        assert_eq!(parse("table T: name: Text, Number, end table;"),
+            Some(String::from("Field name has more than one data type.")));
+        assert_eq!(parse("table T: name: Unique, end table;"),
+            Some(String::from("Field name in table T needs a data type.")));
+        assert_eq!(parse("table T: name: Text, name: Text, end table;"),
+            Some(String::from("Table T already has a field named name.")));
+        assert_eq!(parse("table T: name: Text, label: Text, id 1, end table;"),
+            Some(String::from("Field label has id 1, which field name already has.")));
+        assert_eq!(parse("table T: name: Text, id 0, end table;"),
+            Some(String::from("Field name has id 0, field ids go from 1 to 65535.")));
+        assert_eq!(parse("table T: name: Text, id 1, id 2, end table;"),
+            Some(String::from("Field name has more than one id.")));
+        assert_eq!(parse("table T: name: Text, id, end table;"),
+            Some(String::from("Expected field id after \"id\" for field name.")));
+    }
+}
@@ -0,0 +1,900 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::chunk::{get_chunk_from_code, ChunkType};
+use crate::component::FMComponentRelationship;
+use crate::decompile::decompiler::{get_sector_chain, Decompiler};
+use crate::encoding_util::get_int;
+use crate::file::FmpFile;
+use crate::fm_script_engine::fm_script_engine_instructions::Instruction;
+use super::assembler::Assembler;
+
+const SECTOR_SIZE: usize = 4096;
+const SECTOR_HEADER_SIZE: usize = 20;
+const PAYLOAD_SIZE: usize = SECTOR_SIZE - SECTOR_HEADER_SIZE;
+
+/* Parts of a file that can be rewritten on their own when patching. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PatchTarget {
+    /* [128 + table].[3].[1] */
+    FieldIndex(usize),
+    /* [128 + table].[3].[5].[field] */
+    Field(usize, u16),
+    /* Keys 0 and 4 and directories 1, 3 and 7 of [17].[1]. */
+    ScriptIndex,
+    /* [17].[5].[script] */
+    Script(usize),
+    /* [33] */
+    ValueLists,
+    /* [128 + table].[3].[5].[field], taken out of the file. */
+    RemovedField(usize, u16),
+    /* [17].[5].[script], taken out of the file. */
+    RemovedScript(usize),
+}
+
+fn path(dirs: &[usize]) -> Vec<String> {
+    dirs.iter().map(|d| d.to_string()).collect()
+}
+
+/* Something held directly in a directory. */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Item {
+    Key(u16),
+    Directory(String),
+}
+
+impl Item {
+    /* Keys and directories are stored in the order of their numbers. */
+    fn order(&self) -> (usize, bool) {
+        match self {
+            Item::Key(key) => (*key as usize, false),
+            Item::Directory(dir) => (dir.parse().unwrap_or(usize::MAX), true),
+        }
+    }
+}
+
+/* Keys and directories of `dir` that are taken from the edited file. The
+ * rest of `dir` is kept as it is, including anything that isn't modelled. */
+struct Rewrite {
+    dir: Vec<String>,
+    keys: Vec<u16>,
+    dirs: Vec<String>,
+}
+
+impl Rewrite {
+    fn new(dir: Vec<String>, keys: &[u16], dirs: &[usize]) -> Self {
+        Self { dir, keys: keys.to_vec(), dirs: dirs.iter().map(|d| d.to_string()).collect() }
+    }
+
+    fn replaces(&self, item: &Item) -> bool {
+        match item {
+            Item::Key(key) => self.keys.contains(key),
+            Item::Directory(dir) => self.dirs.contains(dir),
+        }
+    }
+}
+
+/* A chunk with the paths it was read at. */
+#[derive(Debug, Clone)]
+struct PlacedChunk {
+    bytes: Vec<u8>,
+    before: Vec<String>,
+    after: Vec<String>,
+    ctype: ChunkType,
+    key: Option<u16>,
+    data: Vec<u8>,
+}
+
+impl PlacedChunk {
+    /* The key or directory of `dir` this chunk is part of. */
+    fn item_in(&self, dir: &[String]) -> Option<Item> {
+        if self.before.len() > dir.len() && self.before.starts_with(dir) {
+            return Some(Item::Directory(self.before[dir.len()].clone()));
+        }
+        if self.before != dir {
+            return None;
+        }
+        match self.ctype {
+            ChunkType::PathPush if self.after.len() > dir.len() => Some(Item::Directory(self.after[dir.len()].clone())),
+            ChunkType::RefSimple => self.key.map(Item::Key),
+            _ => None,
+        }
+    }
+
+    /* Chunks can pop a directory once they're read. */
+    fn delayed_pop(&self) -> bool {
+        self.bytes[0] & 0xC0 == 0xC0 && self.ctype != ChunkType::PathPop
+    }
+}
+
+/* Reads the chunks of a sector, up to the unused bytes at its end. */
+fn decode_sector(buffer: &[u8], idx: usize) -> Result<Vec<PlacedChunk>, String> {
+    let start = idx * SECTOR_SIZE;
+    let unused = get_int(&buffer[start + 12..start + 16]);
+    let end = start + SECTOR_SIZE - if unused <= PAYLOAD_SIZE { unused } else { 0 };
+    let mut offset = start + SECTOR_HEADER_SIZE;
+    let mut path = Vec::<String>::new();
+    let mut chunks = vec![];
+    while offset < end {
+        let before = path.clone();
+        let begin = offset;
+        let chunk = get_chunk_from_code(buffer, &mut offset, &mut path, start)
+            .map_err(|e| format!("Unable to decode sector {}: {}", idx, e))?;
+        if offset > end {
+            return Err(format!("Unable to decode sector {}: a chunk runs past its end.", idx));
+        }
+        chunks.push(PlacedChunk {
+            bytes: buffer[begin..offset].to_vec(),
+            before,
+            after: chunk.path,
+            key: chunk.ref_simple.filter(|_| chunk.ctype == ChunkType::RefSimple),
+            ctype: chunk.ctype,
+            data: chunk.data.unwrap_or_default().to_vec(),
+        });
+    }
+    Ok(chunks)
+}
+
+/* Chunks in the order they're read, with what each directory holds. */
+struct Tree {
+    chunks: Vec<PlacedChunk>,
+    /* Index and item of every chunk inside each directory. */
+    items: HashMap<Vec<String>, Vec<(usize, Item)>>,
+    paths: HashSet<Vec<String>>,
+}
+
+impl Tree {
+    fn new(chunks: Vec<PlacedChunk>) -> Self {
+        let mut items = HashMap::<Vec<String>, Vec<(usize, Item)>>::new();
+        let mut paths = HashSet::new();
+        for (k, chunk) in chunks.iter().enumerate() {
+            for depth in 0..=chunk.before.len() {
+                let dir = &chunk.before[..depth];
+                if let Some(item) = chunk.item_in(dir) {
+                    items.entry(dir.to_vec()).or_default().push((k, item));
+                }
+            }
+            paths.insert(chunk.after.clone());
+        }
+        Self { chunks, items, paths }
+    }
+
+    fn exists(&self, dir: &[String]) -> bool {
+        dir.is_empty() || self.paths.contains(dir)
+    }
+
+    fn items(&self, dir: &[String]) -> &[(usize, Item)] {
+        self.items.get(dir).map(|i| i.as_slice()).unwrap_or_default()
+    }
+
+    fn children(&self, dir: &[String]) -> Vec<String> {
+        let mut children = self.items(dir).iter()
+            .filter_map(|(_, item)| match item {
+                Item::Directory(d) => Some(d.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        children.sort_by_key(|d| Item::Directory(d.clone()).order());
+        children.dedup();
+        children
+    }
+
+    fn item_chunks(&self, dir: &[String], item: &Item) -> Vec<Vec<u8>> {
+        self.items(dir).iter()
+            .filter(|(_, i)| i == item)
+            .map(|(k, _)| self.chunks[*k].bytes.clone())
+            .collect()
+    }
+}
+
+/* Directories of `dir` the edited file no longer has. Replacing them with
+ * nothing takes them out. */
+fn removed_children(dir: &[String], original: &Tree, stream: &Tree) -> Vec<Rewrite> {
+    let kept = stream.children(dir);
+    original.children(dir).into_iter()
+        .filter(|id| !kept.contains(id))
+        .map(|id| Rewrite { dir: dir.to_vec(), keys: vec![], dirs: vec![id] })
+        .collect()
+}
+
+/* What each target replaces. Everything else in the directories it touches is kept. */
+fn rewrites(targets: &[PatchTarget], original: &Tree, stream: &Tree) -> Vec<Rewrite> {
+    let mut rewrites = vec![];
+    for target in targets {
+        match *target {
+            PatchTarget::FieldIndex(table) => rewrites.push(Rewrite::new(path(&[128 + table, 3, 1]), &[0, 4], &[1, 3])),
+            PatchTarget::Field(table, field) => rewrites.push(
+                Rewrite::new(path(&[128 + table, 3, 5, field as usize]), &[2, 3, 12, 13, 16], &[5])),
+            PatchTarget::ScriptIndex => {
+                rewrites.push(Rewrite::new(path(&[17, 1]), &[0, 4], &[1, 3]));
+                let index = path(&[17, 1, 7]);
+                for id in stream.children(&index) {
+                    rewrites.push(Rewrite { dir: [index.clone(), vec![id]].concat(), keys: vec![16], dirs: vec![] });
+                }
+                rewrites.extend(removed_children(&index, original, stream));
+            },
+            PatchTarget::Script(id) => {
+                rewrites.push(Rewrite::new(path(&[17, 5, id]), &[4], &[4]));
+                /* Steps keep what isn't a parameter, parameters are directories from 128 on. */
+                let steps = path(&[17, 5, id, 5]);
+                let before = original.children(&steps);
+                let after = stream.children(&steps);
+                for step in before.iter().chain(after.iter().filter(|s| !before.contains(s))) {
+                    if !before.contains(step) || !after.contains(step) {
+                        rewrites.push(Rewrite { dir: steps.clone(), keys: vec![], dirs: vec![step.clone()] });
+                        continue;
+                    }
+                    let dir = [steps.clone(), vec![step.clone()]].concat();
+                    let mut parameters = original.children(&dir);
+                    parameters.extend(stream.children(&dir));
+                    parameters.retain(|p| p.parse::<usize>().is_ok_and(|p| p >= 128));
+                    parameters.sort_by_key(|p| Item::Directory(p.clone()).order());
+                    parameters.dedup();
+                    rewrites.push(Rewrite { dir, keys: vec![], dirs: parameters });
+                }
+            },
+            PatchTarget::ValueLists => {
+                rewrites.push(Rewrite::new(path(&[33, 1]), &[0, 4], &[1, 3]));
+                let index = path(&[33, 1, 7]);
+                for id in stream.children(&index) {
+                    rewrites.push(Rewrite { dir: [index.clone(), vec![id]].concat(), keys: vec![16], dirs: vec![] });
+                }
+                rewrites.extend(removed_children(&index, original, stream));
+                let lists = path(&[33, 5]);
+                for id in stream.children(&lists) {
+                    rewrites.push(Rewrite { dir: [lists.clone(), vec![id]].concat(), keys: vec![2, 4, 7], dirs: vec![String::from("7")] });
+                }
+                rewrites.extend(removed_children(&lists, original, stream));
+            },
+            PatchTarget::RemovedField(table, field) =>
+                rewrites.push(Rewrite::new(path(&[128 + table, 3, 5]), &[], &[field as usize])),
+            PatchTarget::RemovedScript(id) => rewrites.push(Rewrite::new(path(&[17, 5]), &[], &[id])),
+        }
+    }
+    rewrites
+}
+
+/* Chunks of the edited file to write into `dir`, as one of its keys or directories. */
+struct Insert {
+    dir: Vec<String>,
+    item: Item,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl Insert {
+    fn order(&self) -> (Vec<usize>, (usize, bool)) {
+        (self.dir.iter().map(|d| d.parse().unwrap_or(usize::MAX)).collect(), self.item.order())
+    }
+}
+
+/* Works out what to insert. A directory that doesn't exist yet is written
+ * whole, inside the deepest of its parents that does. */
+fn inserts(rewrites: &[Rewrite], original: &Tree, stream: &Tree) -> Vec<Insert> {
+    let mut inserts = Vec::<Insert>::new();
+    for rewrite in rewrites {
+        if original.exists(&rewrite.dir) {
+            let items = rewrite.keys.iter().map(|k| Item::Key(*k))
+                .chain(rewrite.dirs.iter().map(|d| Item::Directory(d.clone())));
+            for item in items {
+                let chunks = stream.item_chunks(&rewrite.dir, &item);
+                if !chunks.is_empty() {
+                    inserts.push(Insert { dir: rewrite.dir.clone(), item, chunks });
+                }
+            }
+            continue;
+        }
+        let depth = (0..rewrite.dir.len()).rev().find(|d| original.exists(&rewrite.dir[..*d])).unwrap_or(0);
+        let dir = rewrite.dir[..depth].to_vec();
+        let item = Item::Directory(rewrite.dir[depth].clone());
+        if !inserts.iter().any(|i| i.dir == dir && i.item == item) {
+            let chunks = stream.item_chunks(&dir, &item);
+            inserts.push(Insert { dir, item, chunks });
+        }
+    }
+    inserts
+}
+
+/* Inside a directory that's already in the file, an item goes after the
+ * last of its keys and directories numbered below it, or else right after
+ * the directory is opened. Returns the index of the chunk it goes after. */
+fn anchor(original: &Tree, insert: &Insert) -> Result<usize, String> {
+    let order = insert.item.order();
+    let opened = || {
+        let (last, parent) = insert.dir.split_last()?;
+        let (open, _) = original.items(parent).iter().find(|(k, i)| *i == Item::Directory(last.clone())
+            && original.chunks[*k].ctype == ChunkType::PathPush)?;
+        Some(open + original.chunks[open + 1..].iter().take_while(|c| c.ctype == ChunkType::Noop).count())
+    };
+    let after = original.items(&insert.dir).iter()
+        .rev()
+        .find(|(_, i)| i.order() < order)
+        .map(|(k, _)| *k)
+        .or_else(opened);
+    match after {
+        Some(after) => Ok(after),
+        None => Err(format!("Unable to find where to write [{}] in the file.", insert.dir.join("].["))),
+    }
+}
+
+/* Chunks that close and open directories to get from one path to another. */
+fn navigate(from: &[String], to: &[String], pushes: &HashMap<Vec<String>, Vec<u8>>) -> Result<Vec<Vec<u8>>, String> {
+    let common = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+    let mut chunks = vec![vec![0x40]; from.len() - common];
+    for depth in common + 1..=to.len() {
+        let Some(push) = pushes.get(&to[..depth]) else {
+            return Err(format!("Unable to open [{}].", to[..depth].join("].[")));
+        };
+        chunks.push(push.clone());
+    }
+    Ok(chunks)
+}
+
+/* Splits chunks into sector payloads. A sector that would overflow closes
+ * the open directories, and the next one opens them again. */
+fn layout(chunks: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, String> {
+    let mut payloads = vec![Vec::<u8>::new()];
+    let mut path = Vec::<String>::new();
+    let mut pushes = Vec::<Vec<u8>>::new();
+    for chunk in chunks {
+        let mut next = path.clone();
+        get_chunk_from_code(chunk, &mut 0, &mut next, 0)
+            .map_err(|e| format!("Unable to lay out a chunk: {}", e))?;
+        let payload = payloads.last_mut().unwrap();
+        if payload.len() + chunk.len() + next.len() > PAYLOAD_SIZE {
+            payload.resize(payload.len() + path.len(), 0x40);
+            let reopened = pushes.concat();
+            if reopened.len() + chunk.len() + next.len() > PAYLOAD_SIZE {
+                return Err(format!("Chunk of {} bytes doesn't fit in a sector.", chunk.len()));
+            }
+            payloads.push(reopened);
+        }
+        payloads.last_mut().unwrap().extend(chunk);
+        if next.len() > path.len() {
+            pushes.push(chunk.clone());
+        }
+        pushes.truncate(next.len());
+        path = next;
+    }
+    Ok(payloads)
+}
+
+pub struct PatchedFile {
+    pub buffer: Vec<u8>,
+    /* Sectors of the original chain that were rewritten in place. */
+    pub rewritten: Vec<usize>,
+    /* Sectors appended to the file for what didn't fit. Each is linked into
+     * the chain right after the sector it continues. */
+    pub added: Vec<usize>,
+}
+
+fn differs<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).expect("Unable to serialize component.")
+        != serde_json::to_value(b).expect("Unable to serialize component.")
+}
+
+/* Criteria name their fields as they were resolved when decompiling. Only
+ * the ids are stored with a relationship, so renaming a field doesn't touch it. */
+fn stored_relationships(file: &FmpFile) -> Vec<FMComponentRelationship> {
+    let mut relationships = file.relationships.values().cloned().collect::<Vec<_>>();
+    for criterion in relationships.iter_mut().flat_map(|r| r.criteria.iter_mut()) {
+        criterion.field1_name.clear();
+        criterion.field2_name.clear();
+    }
+    relationships
+}
+
+/* Why a field can't be taken out: something still refers to it and would be
+ * left pointing at nothing, or records would lose the data they hold in it. */
+fn field_in_use(file: &FmpFile, table: usize, field: u16) -> Option<String> {
+    let on_table = |occurrence: usize| file.table_occurrences.get(&occurrence)
+        .is_some_and(|o| o.table_actual as usize == table);
+    let records = file.records.get(&table).map_or(0, |r| r.values().filter(|r| r.fields.contains_key(&field)).count());
+    if records > 0 {
+        return Some(format!("{} record(s) hold data in it", records));
+    }
+    if let Some(layout) = file.layouts.values().find(|l| on_table(l.table_occurrence) && l.fields.iter().any(|f| f.field == field)) {
+        return Some(format!("layout {} still shows it", layout.layout_name));
+    }
+    let relationship = file.relationships.values().find(|r| r.criteria.iter()
+        .any(|c| (on_table(r.table1 as usize) && c.field1 == field) || (on_table(r.table2 as usize) && c.field2 == field)));
+    relationship.map(|r| format!("the relationship between {} and {} still matches on it", r.table1_name, r.table2_name))
+}
+
+/* Works out what has to be rewritten to turn `original` into `edited`. Only
+ * field, script and value list edits can be patched. Fields and scripts can
+ * be removed once nothing in the edited file uses them, tables can't. */
+pub fn find_targets(original: &FmpFile, edited: &FmpFile) -> Result<Vec<PatchTarget>, String> {
+    if differs(&original.table_occurrences, &edited.table_occurrences) {
+        return Err(String::from("Patching table occurrences isn't supported."));
+    }
+    if differs(&stored_relationships(original), &stored_relationships(edited)) {
+        return Err(String::from("Patching relationships isn't supported."));
+    }
+    if differs(&original.layouts, &edited.layouts) {
+        return Err(String::from("Patching layouts isn't supported."));
+    }
+    if differs(&original.data_sources, &edited.data_sources) {
+        return Err(String::from("Patching data sources isn't supported."));
+    }
+
+    let mut targets = BTreeSet::new();
+    for (id, before) in &original.tables {
+        let Some(table) = edited.tables.get(id) else {
+            return Err(format!("Table {} was removed, which can't be patched.", before.table_name));
+        };
+        if table.table_name != before.table_name {
+            return Err(format!("Table {} was renamed, which can't be patched.", before.table_name));
+        }
+        for (field_id, field) in before.fields.iter().filter(|f| !table.fields.contains_key(f.0)) {
+            if let Some(reason) = field_in_use(edited, *id, *field_id) {
+                return Err(format!("Field {}::{} was removed, but {}.", table.table_name, field.field_name, reason));
+            }
+            targets.insert(PatchTarget::FieldIndex(*id));
+            targets.insert(PatchTarget::RemovedField(*id, *field_id));
+        }
+        for (field_id, field) in &table.fields {
+            match before.fields.get(field_id) {
+                Some(b) if !differs(b, field) => continue,
+                Some(b) if b.field_name == field.field_name => {},
+                _ => { targets.insert(PatchTarget::FieldIndex(*id)); },
+            }
+            targets.insert(PatchTarget::Field(*id, *field_id));
+        }
+    }
+    if let Some(table) = edited.tables.iter().find(|t| !original.tables.contains_key(t.0)) {
+        return Err(format!("Table {} is new, which can't be patched.", table.1.table_name));
+    }
+
+    for (id, script) in original.scripts.iter().filter(|s| !edited.scripts.contains_key(s.0)) {
+        let caller = edited.scripts.values().find(|s| s.instructions.iter()
+            .any(|step| step.opcode == Instruction::PerformScript && step.switches.first()
+                /* Compiled steps name the script with a quoted calculation, decompiled ones don't. */
+                .is_some_and(|s| s.trim().trim_matches('"') == script.script_name)));
+        if let Some(caller) = caller {
+            return Err(format!("Script {} was removed, but script {} still performs it.",
+                    script.script_name, caller.script_name));
+        }
+        targets.insert(PatchTarget::ScriptIndex);
+        targets.insert(PatchTarget::RemovedScript(*id));
+    }
+    for (id, script) in &edited.scripts {
+        match original.scripts.get(id) {
+            Some(b) if !differs(b, script) => continue,
+            Some(b) if b.script_name == script.script_name => {},
+            _ => { targets.insert(PatchTarget::ScriptIndex); },
+        }
+        targets.insert(PatchTarget::Script(*id));
+    }
+
+    for list in original.value_lists.iter().filter(|l| !edited.value_lists.contains_key(l.0)).map(|l| l.1) {
+        let user = edited.tables.values()
+            .flat_map(|t| t.fields.values().map(move |f| (t, f)))
+            .find(|(_, f)| f.validation.value_list.as_ref() == Some(&list.list_name));
+        if let Some((table, field)) = user {
+            return Err(format!("Value list {} was removed, but field {}::{} still uses it.",
+                    list.list_name, table.table_name, field.field_name));
+        }
+    }
+    if differs(&original.value_lists, &edited.value_lists) {
+        targets.insert(PatchTarget::ValueLists);
+    }
+    Ok(targets.into_iter().collect())
+}
+
+/* Rewrites `targets` in an existing file, in the sectors that hold them.
+ * Keys and directories that aren't replaced are left as they are. */
+pub fn patch_targets(original: &[u8], edited: &FmpFile, targets: &[PatchTarget]) -> Result<PatchedFile, String> {
+    let chain = get_sector_chain(original);
+    if chain.is_empty() {
+        return Err(String::from("File has no sectors to patch."));
+    }
+    let sectors = chain.iter().map(|idx| decode_sector(original, *idx)).collect::<Result<Vec<_>, _>>()?;
+    let tree = Tree::new(sectors.iter().flatten().cloned().collect());
+
+    /* Options of existing fields keep what the edit didn't change. */
+    let options = targets.iter()
+        .filter_map(|t| match *t {
+            PatchTarget::Field(table, field) => Some((table, field)),
+            _ => None,
+        })
+        .filter_map(|(table, field)| {
+            let dir = path(&[128 + table, 3, 5, field as usize]);
+            let (k, _) = tree.items(&dir).iter().find(|(_, i)| *i == Item::Key(2))?;
+            Some(((table, field), tree.chunks[*k].data.clone()))
+        })
+        .collect();
+    let stream = Assembler::new(edited).with_original_options(options).emit_patch_targets(targets)?;
+    let stream = Tree::new((0..stream.len() / SECTOR_SIZE)
+        .map(|idx| decode_sector(&stream, idx))
+        .collect::<Result<Vec<_>, _>>()?
+        .concat());
+
+    let rewrites = rewrites(targets, &tree, &stream);
+    let inserts = inserts(&rewrites, &tree, &stream);
+    let mut pushes = HashMap::new();
+    for chunk in tree.chunks.iter().chain(&stream.chunks) {
+        if chunk.ctype == ChunkType::PathPush && chunk.after.len() == chunk.before.len() + 1 {
+            pushes.entry(chunk.after.clone()).or_insert(chunk.bytes.clone());
+        }
+    }
+
+    /* Rewrites of directories already in the file, which lose what they replace. */
+    let mut existing = HashMap::<&[String], Vec<&Rewrite>>::new();
+    for rewrite in rewrites.iter().filter(|r| tree.exists(&r.dir)) {
+        existing.entry(&rewrite.dir).or_default().push(rewrite);
+    }
+    /* A directory whose push was removed, or one inside it. */
+    let removed = |dir: &[String]| (0..dir.len()).any(|d| existing.get(&dir[..d])
+        .is_some_and(|rs| rs.iter().any(|r| r.replaces(&Item::Directory(dir[d].clone())))));
+    let mut stripped = vec![false; tree.chunks.len()];
+    for (dir, rewrites) in &existing {
+        for (k, item) in tree.items(dir) {
+            stripped[*k] |= rewrites.iter().any(|r| r.replaces(item));
+        }
+    }
+
+    let mut anchored = BTreeMap::<usize, Vec<&Insert>>::new();
+    for insert in &inserts {
+        anchored.entry(anchor(&tree, insert)?).or_default().push(insert);
+    }
+    for list in anchored.values_mut() {
+        list.sort_by_key(|i| i.order());
+    }
+
+    let mut buffer = original.to_vec();
+    buffer.resize(buffer.len().div_ceil(SECTOR_SIZE) * SECTOR_SIZE, 0);
+    let mut rewritten = vec![];
+    let mut added = vec![];
+    let mut end = 0;
+    for (idx, sector) in chain.iter().zip(&sectors) {
+        let range = end..end + sector.len();
+        end = range.end;
+        let touched = stripped[range.clone()].iter().any(|s| *s)
+            || anchored.range(range.clone()).next().is_some();
+        if !touched {
+            continue;
+        }
+
+        let mut chunks = Vec::<Vec<u8>>::new();
+        for k in range {
+            let chunk = &tree.chunks[k];
+            if !stripped[k] {
+                chunks.push(chunk.bytes.clone());
+            } else if chunk.delayed_pop() && !removed(&chunk.before) {
+                chunks.push(vec![0x40]);
+            }
+            /* Where the chunks written so far leave off, which isn't inside anything removed. */
+            let at = (0..chunk.after.len())
+                .find(|d| removed(&chunk.after[..=*d]))
+                .map(|d| chunk.after[..d].to_vec())
+                .unwrap_or(chunk.after.clone());
+            for insert in anchored.get(&k).into_iter().flatten() {
+                chunks.extend(navigate(&at, &insert.dir, &pushes)?);
+                chunks.extend(insert.chunks.iter().cloned());
+                chunks.extend(navigate(&insert.dir, &at, &pushes)?);
+            }
+        }
+
+        let payloads = layout(&chunks)?;
+        let next = get_int(&original[idx * SECTOR_SIZE + 8..idx * SECTOR_SIZE + 12]);
+        let mut previous = *idx;
+        for (n, payload) in payloads.iter().enumerate() {
+            let sector = if n == 0 { *idx } else { buffer.len() / SECTOR_SIZE };
+            if n > 0 {
+                buffer.resize(buffer.len() + SECTOR_SIZE, 0);
+                buffer[sector * SECTOR_SIZE + 1] = original[idx * SECTOR_SIZE + 1];
+                buffer[sector * SECTOR_SIZE + 4..sector * SECTOR_SIZE + 8].copy_from_slice(&(previous as u32).to_be_bytes());
+                buffer[previous * SECTOR_SIZE + 8..previous * SECTOR_SIZE + 12].copy_from_slice(&(sector as u32).to_be_bytes());
+                added.push(sector);
+            }
+            let start = sector * SECTOR_SIZE;
+            buffer[start + SECTOR_HEADER_SIZE..start + SECTOR_SIZE].fill(0);
+            buffer[start + SECTOR_HEADER_SIZE..start + SECTOR_HEADER_SIZE + payload.len()].copy_from_slice(payload);
+            let unused = (PAYLOAD_SIZE - payload.len()) as u32;
+            buffer[start + 12..start + 16].copy_from_slice(&unused.to_be_bytes());
+            previous = sector;
+        }
+        buffer[previous * SECTOR_SIZE + 8..previous * SECTOR_SIZE + 12].copy_from_slice(&(next as u32).to_be_bytes());
+        if next != 0 {
+            buffer[next * SECTOR_SIZE + 4..next * SECTOR_SIZE + 8].copy_from_slice(&(previous as u32).to_be_bytes());
+        }
+        rewritten.push(*idx);
+    }
+
+    /* Sector 1 holds the index of the last sector. */
+    let last = (buffer.len() / SECTOR_SIZE - 1) as u32;
+    buffer[SECTOR_SIZE + 8..SECTOR_SIZE + 12].copy_from_slice(&last.to_be_bytes());
+    Ok(PatchedFile { buffer, rewritten, added })
+}
+
+pub fn patch_fmp12(original: &[u8], edited: &FmpFile) -> Result<PatchedFile, String> {
+    let current = Decompiler::new(Path::new("")).run_on(original);
+    let targets = find_targets(&current, edited)?;
+    patch_targets(original, edited, &targets)
+}
+
+/* Applies a compiled schema to a decompiled file. Tables, fields, exported
+ * scripts and value lists are matched by name and keep their ids, anything
+ * new is given the next free id. With `prune`, fields of the schema's tables,
+ * scripts and value lists that the schema doesn't have are removed. */
+pub fn merge_schema(file: &mut FmpFile, compiled: &FmpFile, prune: bool) -> Result<(), String> {
+    for table in compiled.tables.values() {
+        let Some(existing) = file.tables.values_mut().find(|t| t.table_name == table.table_name) else {
+            return Err(format!("Table {} doesn't exist in {}.", table.table_name, file.name));
+        };
+        for field in table.fields.values() {
+            let mut field = field.clone();
+            let id = match existing.fields.iter().find(|f| f.1.field_name == field.field_name) {
+                Some((id, f)) => {
+                    field.created_by_account = f.created_by_account.clone();
+                    field.created_by_user = f.created_by_user.clone();
+                    *id
+                },
+                None => existing.fields.keys().max().copied().unwrap_or(0) + 1,
+            };
+            existing.fields.insert(id, field);
+        }
+        if prune {
+            existing.fields.retain(|_, f| table.fields.values().any(|c| c.field_name == f.field_name));
+        }
+    }
+
+    for script in compiled.scripts.values().filter(|s| s.export) {
+        let mut script = script.clone();
+        let id = match file.scripts.iter().find(|s| s.1.script_name == script.script_name) {
+            Some((id, s)) => {
+                script.created_by_account = s.created_by_account.clone();
+                script.create_by_user = s.create_by_user.clone();
+                *id
+            },
+            None => file.scripts.keys().max().copied().unwrap_or(0) + 1,
+        };
+        file.scripts.insert(id, script);
+    }
+
+    for list in compiled.value_lists.values() {
+        let id = file.value_lists.iter()
+            .find(|l| l.1.list_name == list.list_name)
+            .map(|l| *l.0)
+            .unwrap_or(file.value_lists.keys().max().copied().unwrap_or(0) + 1);
+        file.value_lists.insert(id, list.clone());
+    }
+
+    if prune {
+        file.scripts.retain(|_, s| compiled.scripts.values().any(|c| c.export && c.script_name == s.script_name));
+        file.value_lists.retain(|_, l| compiled.value_lists.values().any(|c| c.list_name == l.list_name));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::burn_script::compiler::BurnScriptCompiler;
+    use crate::component::{FMComponentField, FMComponentRecord, FMComponentValueList, ValueListSource};
+    use crate::decompile::decompiler::Decompiler;
+    use crate::decompile::decompiler::get_sector_chain;
+    use crate::encoding_util::get_int;
+    use super::{decode_sector, field_in_use, find_targets, merge_schema, patch_fmp12, PatchTarget};
+
+    const SECTOR_SIZE: usize = 4096;
+
+    #[test]
+    fn patch_test() {
+        let path = Path::new("tests/input/blank.fmp12");
+        let buffer = std::fs::read(path).expect("Unable to read file.");
+        let original = Decompiler::new(path).run_on(&buffer);
+        let mut edited = Decompiler::new(path).run_on(&buffer);
+
+        let (table_id, table) = edited.tables.iter_mut().find(|t| !t.1.fields.is_empty()).unwrap();
+        let table_id = *table_id;
+        let renamed = *table.fields.keys().next().unwrap();
+        table.fields.get_mut(&renamed).unwrap().field_name = String::from("Renamed");
+        let mut added = FMComponentField::new();
+        added.field_name = String::from("Added");
+        added.data_type = String::from("Number");
+        added.field_type = String::from("Simple");
+        added.created_by_account = table.fields[&renamed].created_by_account.clone();
+        added.created_by_user = table.fields[&renamed].created_by_user.clone();
+        let added_id = table.fields.keys().max().unwrap() + 1;
+        table.fields.insert(added_id, added);
+
+        let script_id = *edited.scripts.keys().next().unwrap();
+        edited.scripts.get_mut(&script_id).unwrap().instructions =
+            BurnScriptCompiler::compile_burn_script("define x() { set_variable(y, 7); exit_script(y); }")
+            .unwrap().remove(0).instructions;
+        /* Steps compiled from Burn are numbered when they're assembled. */
+        for (n, step) in edited.scripts.get_mut(&script_id).unwrap().instructions.iter_mut().enumerate() {
+            step.index = n + 1;
+        }
+
+        let targets = find_targets(&original, &edited).unwrap();
+        assert_eq!(targets, vec![
+            PatchTarget::FieldIndex(table_id),
+            PatchTarget::Field(table_id, renamed),
+            PatchTarget::Field(table_id, added_id),
+            PatchTarget::Script(script_id),
+        ]);
+
+        let patched = patch_fmp12(&buffer, &edited).unwrap();
+        assert!(!patched.rewritten.is_empty());
+        assert_eq!(patched.buffer[..SECTOR_SIZE], buffer[..SECTOR_SIZE]);
+        for idx in (2..buffer.len() / SECTOR_SIZE).filter(|idx| !patched.rewritten.contains(idx)) {
+            assert_eq!(patched.buffer[idx * SECTOR_SIZE..(idx + 1) * SECTOR_SIZE],
+                buffer[idx * SECTOR_SIZE..(idx + 1) * SECTOR_SIZE], "sector {}", idx);
+        }
+        let chain = get_sector_chain(&patched.buffer);
+        for idx in patched.rewritten.iter().chain(&patched.added) {
+            let sector = &patched.buffer[idx * SECTOR_SIZE..(idx + 1) * SECTOR_SIZE];
+            let unused = get_int(&sector[12..16]);
+            assert!(sector[SECTOR_SIZE - unused..].iter().all(|b| *b == 0), "sector {}", idx);
+            assert!(chain.contains(idx));
+        }
+        /* Added sectors follow the one they continue. */
+        for idx in &patched.added {
+            let previous = get_int(&patched.buffer[idx * SECTOR_SIZE + 4..idx * SECTOR_SIZE + 8]);
+            assert!(patched.added.contains(&previous) || patched.rewritten.contains(&previous));
+        }
+
+        /* Keys that aren't modelled are kept, only the edited ones change. */
+        let keys = |buffer: &[u8]| get_sector_chain(buffer).iter()
+            .flat_map(|idx| decode_sector(buffer, *idx).unwrap())
+            .filter(|c| c.key.is_some())
+            .map(|c| (c.before, c.key.unwrap(), c.data))
+            .collect::<Vec<_>>();
+        let (before, after) = (keys(&buffer), keys(&patched.buffer));
+        let field = super::path(&[128 + table_id, 3, 5, renamed as usize]);
+        let edited_dirs = [super::path(&[128 + table_id, 3, 1]), super::path(&[17, 5, script_id])];
+        for key in &before {
+            if edited_dirs.iter().any(|d| key.0.starts_with(d)) || (key.0 == field && key.1 == 16) {
+                continue;
+            }
+            assert!(after.contains(key), "{:?} key {} is missing", key.0, key.1);
+        }
+        let order = |keys: &[(Vec<String>, u16, Vec<u8>)], dir: &[String]| keys.iter()
+            .filter(|k| k.0 == dir)
+            .map(|k| k.1)
+            .collect::<Vec<_>>();
+        assert_eq!(order(&before, &field), order(&after, &field));
+        assert!(order(&after, &super::path(&[128 + table_id, 3, 5, added_id as usize])).contains(&216));
+
+        let result = Decompiler::new(path).run_on(&patched.buffer);
+        let fields = &result.tables[&table_id].fields;
+        assert_eq!(fields[&renamed].field_name, "Renamed");
+        assert_eq!(fields[&added_id].field_name, "Added");
+        assert_eq!(fields.len(), edited.tables[&table_id].fields.len());
+        assert_eq!(result.scripts[&script_id].instructions, edited.scripts[&script_id].instructions);
+        for (id, script) in &original.scripts {
+            if *id != script_id {
+                assert_eq!(result.scripts[id].instructions, script.instructions);
+            }
+        }
+        assert_eq!(serde_json::to_value(&result.records).unwrap(), serde_json::to_value(&original.records).unwrap());
+        assert_eq!(serde_json::to_value(&result.layouts).unwrap(), serde_json::to_value(&original.layouts).unwrap());
+        assert!(find_targets(&result, &edited).unwrap().is_empty());
+    }
+
+    #[test]
+    fn patch_overflow_test() {
+        let path = Path::new("tests/input/blank.fmp12");
+        let buffer = std::fs::read(path).expect("Unable to read file.");
+        let mut edited = Decompiler::new(path).run_on(&buffer);
+
+        let script_id = *edited.scripts.keys().next().unwrap();
+        let source = (0..300).map(|n| format!("set_variable(v{}, {});", n, n % 100)).collect::<String>();
+        let script = edited.scripts.get_mut(&script_id).unwrap();
+        script.instructions = BurnScriptCompiler::compile_burn_script(&format!("define x() {{ {} }}", source))
+            .unwrap().remove(0).instructions;
+        for (n, step) in script.instructions.iter_mut().enumerate() {
+            step.index = n + 1;
+        }
+        let mut list = FMComponentValueList::new();
+        list.list_name = String::from("Sizes");
+        list.source = ValueListSource::CustomValues(vec![String::from("Small"), String::from("Large")]);
+        list.created_by_account = String::from("admin");
+        list.create_by_user = String::from("Admin");
+        edited.value_lists.insert(1, list);
+
+        let patched = patch_fmp12(&buffer, &edited).unwrap();
+        assert!(!patched.added.is_empty());
+        let chain = get_sector_chain(&patched.buffer);
+        for idx in &patched.added {
+            let at = chain.iter().position(|s| s == idx).unwrap();
+            assert!(patched.added.contains(&chain[at - 1]) || patched.rewritten.contains(&chain[at - 1]));
+        }
+        let last = get_int(&patched.buffer[SECTOR_SIZE + 8..SECTOR_SIZE + 12]);
+        assert_eq!(last, patched.buffer.len() / SECTOR_SIZE - 1);
+
+        let result = Decompiler::new(path).run_on(&patched.buffer);
+        assert_eq!(result.scripts[&script_id].instructions, edited.scripts[&script_id].instructions);
+        assert_eq!(result.value_lists[&1].list_name, "Sizes");
+        assert!(find_targets(&result, &edited).unwrap().is_empty());
+    }
+
+    #[test]
+    fn patch_removal_test() {
+        let path = Path::new("tests/input/blank.fmp12");
+        let buffer = std::fs::read(path).expect("Unable to read file.");
+        let decompile = |buffer: &[u8]| Decompiler::new(path).run_on(buffer);
+        let original = decompile(&buffer);
+
+        let mut edited = decompile(&buffer);
+        let (table_id, field_id) = original.tables.iter()
+            .flat_map(|(t, table)| table.fields.keys().map(move |f| (*t, *f)))
+            .find(|(t, f)| field_in_use(&original, *t, *f).is_none())
+            .unwrap();
+        edited.tables.get_mut(&table_id).unwrap().fields.remove(&field_id);
+        let (script_id, script) = edited.scripts.iter()
+            .find(|(_, s)| !original.scripts.values().any(|c| c.instructions.iter()
+                .any(|step| step.switches.first() == Some(&s.script_name))))
+            .map(|(id, s)| (*id, s.script_name.clone()))
+            .unwrap();
+        edited.scripts.remove(&script_id);
+
+        let targets = find_targets(&original, &edited).unwrap();
+        assert!(targets.contains(&PatchTarget::RemovedField(table_id, field_id)));
+        assert!(targets.contains(&PatchTarget::FieldIndex(table_id)));
+        assert!(targets.contains(&PatchTarget::RemovedScript(script_id)));
+        assert!(targets.contains(&PatchTarget::ScriptIndex));
+
+        let patched = patch_fmp12(&buffer, &edited).unwrap();
+        let result = Decompiler::new(path).run_on(&patched.buffer);
+        assert!(!result.tables[&table_id].fields.contains_key(&field_id));
+        assert_eq!(result.tables[&table_id].fields.len(), edited.tables[&table_id].fields.len());
+        assert!(!result.scripts.values().any(|s| s.script_name == script));
+        assert_eq!(result.scripts.len(), edited.scripts.len());
+        assert!(find_targets(&result, &edited).unwrap().is_empty());
+
+        /* A value list added by one patch can be taken out by the next. */
+        let mut listed = decompile(&patched.buffer);
+        let mut list = FMComponentValueList::new();
+        list.list_name = String::from("Sizes");
+        list.source = ValueListSource::CustomValues(vec![String::from("Small")]);
+        listed.value_lists.insert(1, list);
+        let with_list = patch_fmp12(&patched.buffer, &listed).unwrap().buffer;
+        let without_list = patch_fmp12(&with_list, &result).unwrap().buffer;
+        assert!(decompile(&without_list).value_lists.is_empty());
+
+        /* Anything still in use stays. */
+        let mut performed = decompile(&buffer);
+        let caller = *performed.scripts.keys().find(|id| **id != script_id).unwrap();
+        performed.scripts.get_mut(&caller).unwrap().instructions =
+            BurnScriptCompiler::compile_burn_script(&format!("define x() {{ perform_script(\"{}\"); }}", script))
+            .unwrap().remove(0).instructions;
+        performed.scripts.remove(&script_id);
+        assert!(find_targets(&original, &performed).unwrap_err().contains("still performs it"));
+
+        let mut held = decompile(&buffer);
+        held.records.entry(table_id).or_default().entry(1).or_insert_with(FMComponentRecord::new)
+            .fields.insert(field_id, String::from("kept"));
+        held.tables.get_mut(&table_id).unwrap().fields.remove(&field_id);
+        assert!(find_targets(&original, &held).unwrap_err().contains("record(s) hold data in it"));
+    }
+
+    #[test]
+    fn merge_schema_test() {
+        let path = Path::new("tests/input/blank.fmp12");
+        let buffer = std::fs::read(path).expect("Unable to read file.");
+        let mut file = Decompiler::new(path).run_on(&buffer);
+        let mut compiled = crate::FmpFile::new();
+        for script in BurnScriptCompiler::compile_burn_script("define testing() { exit_script(1); } define fresh() { exit_script(2); }").unwrap() {
+            compiled.scripts.insert(compiled.scripts.len() + 1, script);
+        }
+        let existing = *file.scripts.iter().find(|s| s.1.script_name == "testing").unwrap().0;
+        let next = file.scripts.keys().max().unwrap() + 1;
+        merge_schema(&mut file, &compiled, false).unwrap();
+        assert_eq!(file.scripts[&existing].instructions, compiled.scripts[&1].instructions);
+        assert_eq!(file.scripts[&next].script_name, "fresh");
+        assert!(file.scripts.len() > 2);
+        merge_schema(&mut file, &compiled, true).unwrap();
+        assert_eq!(file.scripts.keys().copied().collect::<Vec<_>>(), vec![existing, next]);
+
+        let mut unknown = crate::FmpFile::new();
+        let mut table = crate::component::FMComponentTable::new();
+        table.table_name = String::from("Missing");
+        unknown.tables.insert(1, table);
+        assert!(merge_schema(&mut file, &unknown, false).is_err());
+    }
+}
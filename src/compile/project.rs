@@ -0,0 +1,362 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::compile::compiler::{link, parse_burn};
+use crate::compile::diagnostic::{Diagnostic, Span};
+use crate::file::FmpFile;
+
+pub const MANIFEST: &str = "burn.toml";
+
+/* The manifest of a project, burn.toml:
+ *
+ * [project]
+ * name = "app"
+ * sources = ["app.burn", "tables.burn", "scripts_*.burn"]
+ * tests = ["test_*.burn"]
+ * output = "build/"
+ *
+ * Globs and the output path are relative to the manifest. */
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub name: String,
+    #[serde(default)]
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub tests: Vec<String>,
+    pub output: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    project: Manifest,
+}
+
+impl Manifest {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str::<ManifestFile>(text)
+            .map(|m| m.project)
+            .map_err(|e| e.message().to_string())
+    }
+}
+
+/* A file read into a project. */
+pub struct Source {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/* Burn files compiled into one FileMaker file. Files named by "import" are
+ * read as they're found, relative to the file importing them, and no file
+ * is read twice. Spans of everything compiled from a file hold its index
+ * in `sources`. */
+pub struct Project {
+    pub name: String,
+    pub output: Option<String>,
+    /* Files the project starts from. */
+    pub roots: Vec<PathBuf>,
+    pub sources: Vec<Source>,
+    /* Text to use instead of what's on disk, such as files being edited. */
+    documents: HashMap<PathBuf, String>,
+}
+
+/* Where a file really is, or the path given if it can't be found. */
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/* Adds components to `into`, each taking the next free id. */
+fn append<T>(into: &mut BTreeMap<usize, T>, from: BTreeMap<usize, T>) {
+    for component in from.into_values() {
+        let id = into.keys().max().copied().unwrap_or(0) + 1;
+        into.insert(id, component);
+    }
+}
+
+/* Adds everything parsed from one file to the project's file. */
+fn merge(file: &mut FmpFile, part: FmpFile) {
+    append(&mut file.tables, part.tables);
+    append(&mut file.relationships, part.relationships);
+    append(&mut file.layouts, part.layouts);
+    append(&mut file.value_lists, part.value_lists);
+    append(&mut file.scripts, part.scripts);
+    append(&mut file.table_occurrences, part.table_occurrences);
+    append(&mut file.data_sources, part.data_sources);
+    append(&mut file.privilege_sets, part.privilege_sets);
+    file.seeds.extend(part.seeds);
+    file.tests.extend(part.tests);
+    file.references.extend(part.references);
+}
+
+impl Project {
+    /* A project of the files given, named after the first. */
+    pub fn from_files(paths: &[String]) -> Self {
+        let name = paths.first()
+            .and_then(|p| Path::new(p).file_stem())
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Self {
+            name,
+            output: None,
+            roots: paths.iter().map(PathBuf::from).collect(),
+            sources: vec![],
+            documents: HashMap::new(),
+        }
+    }
+
+    /* Reads a manifest, or the manifest inside a directory. */
+    pub fn from_manifest(path: &Path) -> Result<Self, String> {
+        let path = if path.is_dir() { path.join(MANIFEST) } else { path.to_path_buf() };
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let manifest = Manifest::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut roots = vec![];
+        for pattern in manifest.sources.iter().chain(&manifest.tests) {
+            let full = dir.join(pattern).to_string_lossy().to_string();
+            let mut matches = glob::glob(&full)
+                .map_err(|e| format!("{}: Invalid pattern {}: {}", path.display(), pattern, e))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Unable to read {}: {}", e.path().display(), e.error()))?;
+            if matches.is_empty() {
+                return Err(format!("{}: No files match {}.", path.display(), pattern));
+            }
+            matches.retain(|m| !roots.contains(m));
+            roots.extend(matches);
+        }
+        Ok(Self {
+            name: manifest.name,
+            output: manifest.output.map(|o| dir.join(o).to_string_lossy().to_string()),
+            roots,
+            sources: vec![],
+            documents: HashMap::new(),
+        })
+    }
+
+    /* Reads `documents` from memory rather than disk, by path. */
+    pub fn with_documents(mut self, documents: HashMap<PathBuf, String>) -> Self {
+        self.documents = documents.into_iter().map(|(path, text)| (canonical(&path), text)).collect();
+        self
+    }
+
+    /* Index in `sources` of the file at `path`, once it's been read. */
+    pub fn source_of(&self, path: &Path) -> Option<usize> {
+        let real = canonical(path);
+        self.sources.iter().position(|s| canonical(&s.path) == real)
+    }
+
+    /* The text a diagnostic points into. */
+    pub fn text_of(&self, diagnostic: &Diagnostic) -> &str {
+        self.sources.get(diagnostic.span.source).map(|s| s.text.as_str()).unwrap_or("")
+    }
+
+    fn in_files(&self, diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+        diagnostics.into_iter().map(|d| match self.sources.get(d.span.source) {
+            Some(source) if d.file.is_empty() => d.in_file(&source.path.to_string_lossy()),
+            _ => d,
+        }).collect()
+    }
+
+    /* Reads a file, unless it's already been read. Files that can't be read
+     * are reported where they're imported. */
+    fn read(&mut self, path: &Path, imported_at: Option<Span>) -> Result<Option<usize>, Diagnostic> {
+        let unreadable = |e: std::io::Error| {
+            let message = format!("Unable to read {}: {}", path.display(), e);
+            match imported_at {
+                Some(span) => Diagnostic::error(message, span),
+                None => Diagnostic::error(message, Span::default()).in_file(&path.to_string_lossy()),
+            }
+        };
+        if self.source_of(path).is_some() {
+            return Ok(None);
+        }
+        let text = match self.documents.get(&canonical(path)) {
+            Some(text) => text.clone(),
+            None => std::fs::read_to_string(path).map_err(unreadable)?,
+        };
+        self.sources.push(Source { path: path.to_path_buf(), text });
+        Ok(Some(self.sources.len() - 1))
+    }
+
+    /* Parses every file of the project into one, with whatever could be
+     * parsed from files with mistakes. Components are numbered in the order
+     * files are read. */
+    fn parse(&mut self) -> (FmpFile, Vec<Diagnostic>) {
+        self.sources.clear();
+        let mut file = FmpFile::new();
+        file.name = self.name.clone();
+        let mut diagnostics = vec![];
+        let mut queue = self.roots.iter().map(|p| (p.clone(), None)).collect::<VecDeque<_>>();
+        while let Some((path, imported_at)) = queue.pop_front() {
+            let source = match self.read(&path, imported_at) {
+                Ok(Some(source)) => source,
+                Ok(None) => continue,
+                Err(e) => {
+                    diagnostics.push(e);
+                    continue;
+                }
+            };
+            let (mut part, errors) = parse_burn(&self.sources[source].text, source);
+            diagnostics.extend(errors);
+            let dir = path.parent().unwrap_or(Path::new(""));
+            for (import, span) in part.imports.drain(..) {
+                queue.push_back((dir.join(import), Some(span)));
+            }
+            /* Data files are named relative to the file they're named in. */
+            for seed in &mut part.seeds {
+                if let Some(data) = &mut seed.source {
+                    *data = dir.join(&*data).to_string_lossy().to_string();
+                }
+            }
+            merge(&mut file, part);
+        }
+        (file, diagnostics)
+    }
+
+    /* Compiles every file of the project into one, resolving names across
+     * files. */
+    pub fn compile(&mut self) -> Result<(FmpFile, Vec<Diagnostic>), Vec<Diagnostic>> {
+        let (mut file, diagnostics) = self.parse();
+        if !diagnostics.is_empty() {
+            return Err(self.in_files(diagnostics));
+        }
+        match link(&mut file) {
+            Ok(warnings) => Ok((file, self.in_files(warnings))),
+            Err(e) => Err(self.in_files(e)),
+        }
+    }
+
+    /* Compiles as much of the project as can be, for editors. The file is
+     * only checked once it's been parsed without mistakes, as names are
+     * missing from a file that's partly parsed. */
+    pub fn analyze(&mut self) -> (FmpFile, Vec<Diagnostic>) {
+        let (mut file, mut diagnostics) = self.parse();
+        if diagnostics.is_empty() {
+            diagnostics = link(&mut file).unwrap_or_else(|e| e);
+        }
+        (file, self.in_files(diagnostics))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use crate::compile::diagnostic::Span;
+    use super::{Manifest, Project};
+
+    /* Writes files into a fresh directory for one test. */
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("burn_{}_{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, text) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn manifest_test() {
+        let manifest = Manifest::parse("
+            [project]
+            name = \"app\"
+            sources = [\"tables/*.burn\", \"app.burn\"]
+            tests = [\"tests/*.burn\"]
+            output = \"build/\"
+        ").unwrap();
+        assert_eq!(manifest, Manifest {
+            name: String::from("app"),
+            sources: vec![String::from("tables/*.burn"), String::from("app.burn")],
+            tests: vec![String::from("tests/*.burn")],
+            output: Some(String::from("build/")),
+        });
+        assert!(Manifest::parse("[project]\nname = \"app\"\nsource = [\"app.burn\"]\n").is_err());
+
+        let dir = write_files("manifest", &[
+            ("burn.toml", "[project]\nname = \"app\"\nsources = [\"tables/*.burn\", \"app.burn\"]\ntests = [\"tests/*.burn\"]\n"),
+            ("app.burn", ""),
+            ("tables/b.burn", ""),
+            ("tables/a.burn", ""),
+            ("tests/basic.burn", ""),
+        ]);
+        let project = Project::from_manifest(&dir).unwrap();
+        let roots = project.roots.iter().map(|p| p.strip_prefix(&dir).unwrap()).collect::<Vec<_>>();
+        assert_eq!(roots, vec![Path::new("tables/a.burn"), Path::new("tables/b.burn"),
+            Path::new("app.burn"), Path::new("tests/basic.burn")]);
+        assert_eq!(project.name, "app");
+    }
+
+    #[test]
+    fn import_test() {
+        let dir = write_files("import", &[
+            ("app.burn", "import \"tables/person.burn\";\nimport \"scripts.burn\";\n"),
+            ("tables/person.burn", "\
+table Person:
+  id: Number,
+end table;
+table_occurence Person:
+  table: Person,
+end table_occurence;
+"),
+            ("tables/job.burn", "\
+import \"person.burn\";
+table Job:
+  person: Number,
+end table;
+table_occurence Job:
+  table: Job,
+end table_occurence;
+relationship:
+  Person:Job,
+  id == person,
+end relationship;
+"),
+            ("scripts.burn", "\
+import \"tables/job.burn\";
+import \"app.burn\";
+script export: [
+  define add_job() {
+    go_to_layout(\"Job\");
+    set_field(Job::person, 1);
+  }
+]
+"),
+        ]);
+        let mut project = Project::from_files(&[dir.join("app.burn").to_string_lossy().to_string()]);
+        let (file, warnings) = project.compile().unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(project.sources.len(), 4);
+        assert_eq!(file.name, "app");
+        assert_eq!(file.tables.values().map(|t| t.table_name.as_str()).collect::<Vec<_>>(), vec!["Person", "Job"]);
+        assert_eq!(file.table_occurrences.keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+        let rel = &file.relationships[&1];
+        assert_eq!((rel.table1, rel.table2), (1, 2));
+        assert_eq!(file.scripts[&1].script_name, "add_job");
+
+        std::fs::write(dir.join("scripts.burn"), "\
+import \"tables/job.burn\";
+import \"missing.burn\";
+table Person:
+  id: Text,
+end table;
+").unwrap();
+        let mut project = Project::from_files(&[dir.join("app.burn").to_string_lossy().to_string()]);
+        let errors = project.compile().err().unwrap();
+        let errors = errors.iter()
+            .map(|d| (Path::new(&d.file).strip_prefix(&dir).unwrap(), d.message.starts_with("Unable to read"), d.span))
+            .collect::<Vec<_>>();
+        assert_eq!(errors, vec![(Path::new("scripts.burn"), true, Span { source: 2, ..Span::new(2, 8, 14) })]);
+
+        std::fs::write(dir.join("scripts.burn"), "table Person:\n  id: Text,\nend table;\n").unwrap();
+        let mut project = Project::from_files(&[dir.join("app.burn").to_string_lossy().to_string()]);
+        let errors = project.compile().err().unwrap();
+        assert_eq!(errors.iter().map(|d| (d.message.as_str(), d.file.ends_with("scripts.burn"))).collect::<Vec<_>>(),
+            vec![("Table Person is defined more than once.", true)]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
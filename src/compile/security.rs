@@ -0,0 +1,52 @@
+use crate::component::PrivilegeAccess;
+use crate::encoding_util::{derived_uuid, digest};
+
+/* Accounts and privilege sets as FileMaker writes them into a new file,
+ * copied from a blank file. The password verifier at key 6 of an account
+ * hasn't been decoded, so accounts keep the verifier FileMaker stores for
+ * an empty password, next to the 16 byte account id at key 4 it was
+ * written with. */
+
+pub const GUEST_ACCOUNT_ID: usize = 1;
+pub const ADMIN_ACCOUNT_ID: usize = 2;
+
+/* "[Guest]" in the account name index. */
+pub const GUEST_INDEX_NAME: &[u8] = &[3, 1, 255, 224, 224, 2, 3, 2];
+
+pub const GUEST_KEY: &[u8] = &[100, 217, 193, 37, 93, 42, 11, 249, 142, 61, 153, 104, 113, 106, 180, 35];
+pub const GUEST_VERIFIER: &[u8] = &[1, 40, 185, 51, 108, 2, 84, 57, 194, 127, 151, 225, 46, 115, 19, 77, 55, 87,
+    202, 169, 133, 191, 56, 227, 13, 159, 78, 248, 89, 65, 202, 201, 151, 64, 127, 79, 99, 110, 8, 214, 18, 94, 22, 144, 30];
+pub const GUEST_OPTIONS: &[u8] = &[8, 144, 0, 65, 128, 0, 99, 83, 153];
+/* Keys 0 and 1 of [23].[1].[5].[1].[16], only written for the guest account. */
+pub const GUEST_SETTINGS: [&[u8]; 2] = [&[0, 0, 0, 5, 0, 0, 0, 3], &[1, 65, 50, 216, 7]];
+pub const GUEST_UUID: &[u8] = &[45, 251, 249, 31, 65, 152, 73, 77, 179, 24, 139, 190, 69, 204, 225, 166];
+
+pub const ADMIN_KEY: &[u8] = &[134, 189, 36, 47, 98, 87, 205, 80, 252, 13, 73, 153, 192, 74, 52, 13];
+pub const ADMIN_VERIFIER: &[u8] = &[1, 44, 43, 129, 38, 7, 43, 157, 120, 93, 5, 64, 5, 250, 119, 54, 120, 34,
+    190, 18, 253, 125, 98, 111, 223, 232, 230, 26, 23, 121, 97, 108, 46, 123, 81, 49];
+pub const ADMIN_OPTIONS: &[u8] = &[8, 159, 162, 15, 128, 5, 100, 83, 112];
+pub const ADMIN_UUID: &[u8] = &[219, 195, 235, 96, 50, 208, 78, 11, 170, 14, 82, 157, 148, 253, 144, 200];
+
+/* Keys 4 and 216 of the standard privilege sets. */
+pub fn standard_set_ids(access: PrivilegeAccess) -> (&'static [u8], &'static [u8]) {
+    match access {
+        PrivilegeAccess::FullAccess => (
+            &[162, 7, 63, 13, 128, 114, 52, 21, 186, 157, 146, 102, 175, 177, 49, 77],
+            &[31, 184, 222, 63, 253, 221, 68, 198, 169, 17, 48, 63, 123, 0, 125, 108]),
+        PrivilegeAccess::DataEntryOnly => (
+            &[61, 107, 201, 153, 146, 122, 255, 59, 210, 68, 148, 127, 84, 73, 248, 53],
+            &[249, 140, 155, 228, 23, 46, 66, 155, 159, 120, 175, 255, 17, 157, 67, 17]),
+        PrivilegeAccess::ReadOnlyAccess => (
+            &[86, 233, 241, 120, 172, 33, 159, 224, 220, 91, 9, 159, 231, 228, 104, 141],
+            &[144, 232, 90, 33, 241, 205, 68, 201, 148, 122, 159, 81, 182, 188, 49, 115]),
+    }
+}
+
+/* Keys 4 and 216 of a custom privilege set. FileMaker generates these at
+ * random; ours are derived from the file name, set id and set name, so they
+ * differ between sets and files but stay the same between builds. */
+pub fn custom_set_ids(file_name: &str, id: usize, set_name: &str) -> (Vec<u8>, Vec<u8>) {
+    let seed = format!("{}\0{}\0{}", file_name, id, set_name);
+    let key = digest(&format!("key\0{}", seed));
+    (key.to_vec(), derived_uuid(&format!("uuid\0{}", seed)))
+}
@@ -0,0 +1,324 @@
+use std::path::Path;
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde_json::Value;
+
+use crate::component::{FMComponentField, FMComponentRecord, FMComponentSeed};
+use crate::file::FmpFile;
+
+/* Dates, times and timestamps are stored as text in the file's locale.
+ * blank.fmp12, which our header and settings are copied from, stores
+ * "26/08/2024 11:43:04", so dates are written day first. */
+const DATE_FORMAT: &str = "%d/%m/%Y";
+const TIME_FORMAT: &str = "%H:%M:%S";
+const TIMESTAMP_FORMAT: &str = "%d/%m/%Y %H:%M:%S";
+
+/* Formats accepted in seed data, besides the stored ones. Month first
+ * dates aren't accepted, "3/6/2024" would be read as the 3rd of June. */
+const DATE_INPUTS: [&str; 2] = ["%Y-%m-%d", DATE_FORMAT];
+const TIME_INPUTS: [&str; 2] = [TIME_FORMAT, "%H:%M"];
+const TIMESTAMP_INPUTS: [&str; 4] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", TIMESTAMP_FORMAT, "%d/%m/%Y %H:%M"];
+
+/* Record values are keyed with a single byte, so longer text doesn't fit. */
+const MAX_VALUE_LEN: usize = u8::MAX as usize;
+
+/* Splits CSV text into a header and rows. Fields may be quoted, with "" for a quote. */
+fn read_csv(text: &str) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                value.push('"');
+                chars.next();
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut value)),
+            '\r' if !quoted => {},
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut value));
+                rows.push(std::mem::take(&mut row));
+            },
+            _ => value.push(c),
+        }
+    }
+    if quoted {
+        return Err(String::from("Unterminated quote in CSV."));
+    }
+    if !value.is_empty() || !row.is_empty() {
+        row.push(value);
+        rows.push(row);
+    }
+    rows.retain(|r| !(r.len() == 1 && r[0].is_empty()));
+    if rows.is_empty() {
+        return Err(String::from("CSV has no header row."));
+    }
+    let columns = rows.remove(0).into_iter().map(|c| c.trim().to_string()).collect();
+    Ok((columns, rows))
+}
+
+/* Reads an array of objects. Columns are every key, in the order they first appear. */
+fn read_json(text: &str) -> Result<(Vec<String>, Vec<Vec<String>>), String> {
+    let json = serde_json::from_str::<Value>(text).map_err(|e| e.to_string())?;
+    let Value::Array(objects) = json else {
+        return Err(String::from("Expected an array of records."));
+    };
+    let mut columns = Vec::<String>::new();
+    for object in &objects {
+        let Value::Object(object) = object else {
+            return Err(String::from("Expected every record to be an object."));
+        };
+        for key in object.keys() {
+            if !columns.contains(key) {
+                columns.push(key.clone());
+            }
+        }
+    }
+    let rows = objects.iter()
+        .map(|object| columns.iter()
+            .map(|c| match object.get(c) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Bool(b)) => (*b as u8).to_string(),
+                Some(v) => v.to_string(),
+            })
+            .collect())
+        .collect();
+    Ok((columns, rows))
+}
+
+/* Reads the rows of every seed that names a file, relative to `base`. */
+pub fn load_seed_sources(file: &mut FmpFile, base: &Path) -> Result<(), String> {
+    for seed in &mut file.seeds {
+        let Some(source) = &seed.source else {
+            continue;
+        };
+        let path = base.join(source);
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        let (columns, rows) = match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => read_csv(&text),
+            Some("json") => read_json(&text),
+            _ => Err(String::from("Seed files have to be .csv or .json.")),
+        }.map_err(|e| format!("{}: {}", path.display(), e))?;
+        seed.columns = columns;
+        seed.rows = rows;
+        seed.source = None;
+    }
+    Ok(())
+}
+
+fn parse_any<T>(value: &str, formats: &[&str], parse: impl Fn(&str, &str) -> chrono::ParseResult<T>) -> Option<T> {
+    formats.iter().find_map(|f| parse(value, f).ok())
+}
+
+/* Writes a decimal number in plain notation, without a plus sign, exponent,
+ * or leading and trailing zeros. Digits aren't rounded, as FileMaker keeps
+ * far more of them than a float does. */
+fn number_text(value: &str) -> Option<String> {
+    let (mantissa, exponent) = match value.find(['e', 'E']) {
+        Some(at) => (&value[..at], value[at + 1..].parse::<i32>().ok()?),
+        None => (value, 0),
+    };
+    let (negative, mantissa) = match mantissa.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, mantissa.strip_prefix('+').unwrap_or(mantissa)),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if whole.is_empty() && fraction.is_empty()
+        || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    /* Moving the decimal point by the exponent. */
+    let digits = format!("{}{}", whole, fraction);
+    let point = whole.len() as i64 + exponent as i64;
+    if point.abs() > 400 {
+        return None;
+    }
+    let (whole, fraction) = if point <= 0 {
+        (String::new(), "0".repeat(-point as usize) + &digits)
+    } else if point as usize >= digits.len() {
+        (digits.clone() + &"0".repeat(point as usize - digits.len()), String::new())
+    } else {
+        (digits[..point as usize].to_string(), digits[point as usize..].to_string())
+    };
+    let whole = whole.trim_start_matches('0');
+    let fraction = fraction.trim_end_matches('0');
+    let mut text = String::from(if whole.is_empty() { "0" } else { whole });
+    if !fraction.is_empty() {
+        text = format!("{}.{}", text, fraction);
+    }
+    if negative && text != "0" {
+        text.insert(0, '-');
+    }
+    Some(text)
+}
+
+/* Converts a value to the text stored for the field's data type. */
+pub fn encode_value(field: &FMComponentField, value: &str) -> Result<String, String> {
+    let value = value.trim();
+    let invalid = || format!("\"{}\" isn't a valid {} for {}.", value, field.data_type.to_lowercase(), field.field_name);
+    let encoded = match field.data_type.as_str() {
+        "Number" => number_text(value).ok_or_else(invalid)?,
+        "Date" => parse_any(value, &DATE_INPUTS, NaiveDate::parse_from_str)
+            .ok_or_else(invalid)?
+            .format(DATE_FORMAT).to_string(),
+        "Time" => parse_any(value, &TIME_INPUTS, NaiveTime::parse_from_str)
+            .ok_or_else(invalid)?
+            .format(TIME_FORMAT).to_string(),
+        "Timestamp" => parse_any(value, &TIMESTAMP_INPUTS, NaiveDateTime::parse_from_str)
+            .ok_or_else(invalid)?
+            .format(TIMESTAMP_FORMAT).to_string(),
+        "Container" => return Err(format!("Container field {} can't be seeded.", field.field_name)),
+        _ => value.to_string(),
+    };
+    if encoded.len() > MAX_VALUE_LEN {
+        return Err(format!("Value for {} is longer than {} bytes.", field.field_name, MAX_VALUE_LEN));
+    }
+    Ok(encoded)
+}
+
+fn seed_table(file: &mut FmpFile, seed: &FMComponentSeed) -> Result<(), String> {
+    let Some((table_id, table)) = file.tables.iter_mut().find(|t| t.1.table_name == seed.table_name) else {
+        return Err(format!("Data for unknown table {}.", seed.table_name));
+    };
+    let columns = seed.columns.iter()
+        .map(|name| table.fields.iter()
+            .find(|f| f.1.field_name == *name)
+            .map(|f| *f.0)
+            .ok_or(format!("Table {} has no field {}.", seed.table_name, name)))
+        .collect::<Result<Vec<u16>, String>>()?;
+
+    let records = file.records.entry(*table_id).or_default();
+    let mut next_id = records.keys().max().copied().unwrap_or(0) + 1;
+    for (n, row) in seed.rows.iter().enumerate() {
+        if row.len() != columns.len() {
+            return Err(format!("Row {} of {} has {} values, expected {}.",
+                    n + 1, seed.table_name, row.len(), columns.len()));
+        }
+        let mut record = FMComponentRecord::new();
+        for (field_id, value) in columns.iter().zip(row) {
+            if !value.trim().is_empty() {
+                record.fields.insert(*field_id, encode_value(&table.fields[field_id], value)?);
+            }
+        }
+        /* Serial numbers are entered for records that don't have one, and
+         * the next value moves past every number used. */
+        for (field_id, field) in table.fields.iter_mut() {
+            let Some(serial) = field.auto_enter_serial.as_mut() else {
+                continue;
+            };
+            match record.fields.get(field_id).and_then(|v| v.parse::<u64>().ok()) {
+                Some(used) => serial.next_value = serial.next_value.max(used + serial.increment),
+                None if !record.fields.contains_key(field_id) => {
+                    record.fields.insert(*field_id, serial.next_value.to_string());
+                    serial.next_value += serial.increment;
+                },
+                None => {},
+            }
+        }
+        records.insert(next_id, record);
+        next_id += 1;
+    }
+    Ok(())
+}
+
+/* Adds the rows of every seed to the records of its table. Seeds naming a
+ * file have to be loaded with `load_seed_sources` first. */
+pub fn seed_records(file: &mut FmpFile) -> Result<(), String> {
+    for seed in std::mem::take(&mut file.seeds) {
+        if let Some(source) = &seed.source {
+            return Err(format!("Data for {} from {} hasn't been loaded.", seed.table_name, source));
+        }
+        seed_table(file, &seed)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::component::{FMComponentField, FMComponentSeed, FMComponentTable, FMSerialNumber};
+    use crate::file::FmpFile;
+    use super::{encode_value, read_csv, read_json, seed_records};
+
+    fn field(name: &str, data_type: &str) -> FMComponentField {
+        let mut field = FMComponentField::new();
+        field.field_name = String::from(name);
+        field.data_type = String::from(data_type);
+        field
+    }
+
+    #[test]
+    fn encode_value_test() {
+        assert_eq!(encode_value(&field("n", "Number"), "42.0").unwrap(), "42");
+        assert_eq!(encode_value(&field("n", "Number"), "-1.5").unwrap(), "-1.5");
+        assert_eq!(encode_value(&field("n", "Number"), "+007.250").unwrap(), "7.25");
+        assert_eq!(encode_value(&field("n", "Number"), ".5").unwrap(), "0.5");
+        assert_eq!(encode_value(&field("n", "Number"), "-0.0").unwrap(), "0");
+        assert_eq!(encode_value(&field("n", "Number"), "1.5e3").unwrap(), "1500");
+        assert_eq!(encode_value(&field("n", "Number"), "25E-3").unwrap(), "0.025");
+        assert_eq!(encode_value(&field("n", "Number"), "12345678901234567890123").unwrap(), "12345678901234567890123");
+        assert!(encode_value(&field("n", "Number"), "lots").is_err());
+        assert!(encode_value(&field("n", "Number"), "1.2.3").is_err());
+        assert!(encode_value(&field("n", "Number"), "-").is_err());
+        assert!(encode_value(&field("n", "Number"), "1e999").is_err());
+        assert_eq!(encode_value(&field("d", "Date"), "2024-03-06").unwrap(), "06/03/2024");
+        assert_eq!(encode_value(&field("d", "Date"), "6/3/2024").unwrap(), "06/03/2024");
+        assert!(encode_value(&field("d", "Date"), "2024-02-30").is_err());
+        assert_eq!(encode_value(&field("t", "Time"), "9:05").unwrap(), "09:05:00");
+        assert!(encode_value(&field("t", "Time"), "25:00").is_err());
+        assert_eq!(encode_value(&field("ts", "Timestamp"), "2024-03-06T11:27:03").unwrap(), "06/03/2024 11:27:03");
+        /* As stored in blank.fmp12. */
+        assert_eq!(encode_value(&field("ts", "Timestamp"), "26/08/2024 11:43:04").unwrap(), "26/08/2024 11:43:04");
+        assert_eq!(encode_value(&field("s", "Text"), "New Zealand").unwrap(), "New Zealand");
+        assert!(encode_value(&field("c", "Container"), "x").is_err());
+    }
+
+    #[test]
+    fn read_source_test() {
+        let (columns, rows) = read_csv("code,name\r\nNZ,New Zealand\n\"US\",\"United States, \"\"USA\"\"\"\n").unwrap();
+        assert_eq!(columns, vec!["code", "name"]);
+        assert_eq!(rows, vec![vec!["NZ", "New Zealand"], vec!["US", "United States, \"USA\""]]);
+
+        let (columns, rows) = read_json(r#"[{"code": "NZ", "rank": 1}, {"code": "AU", "active": true}]"#).unwrap();
+        assert_eq!(columns, vec!["code", "rank", "active"]);
+        assert_eq!(rows, vec![vec!["NZ", "1", ""], vec!["AU", "", "1"]]);
+    }
+
+    #[test]
+    fn seed_records_test() {
+        let mut file = FmpFile::new();
+        let mut table = FMComponentTable::new();
+        table.table_name = String::from("Status");
+        let mut id = field("id", "Number");
+        id.auto_enter_serial = Some(FMSerialNumber::new());
+        table.fields.insert(1, id);
+        table.fields.insert(2, field("name", "Text"));
+        file.tables.insert(1, table);
+
+        let mut seed = FMComponentSeed::new();
+        seed.table_name = String::from("Status");
+        seed.columns = vec![String::from("name"), String::from("id")];
+        seed.rows = vec![
+            vec![String::from("Open"), String::new()],
+            vec![String::from("Closed"), String::from("7")],
+            vec![String::from("Held"), String::new()],
+        ];
+        file.seeds.push(seed.clone());
+        seed_records(&mut file).unwrap();
+
+        let records = &file.records[&1];
+        let ids = records.values().map(|r| r.fields[&1].as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["1", "7", "8"]);
+        assert_eq!(records[&2].fields[&2], "Closed");
+        assert_eq!(file.tables[&1].fields[&1].auto_enter_serial.as_ref().unwrap().next_value, 9);
+        assert!(file.seeds.is_empty());
+
+        seed.rows = vec![vec![String::from("Open")]];
+        file.seeds.push(seed);
+        assert!(seed_records(&mut file).is_err());
+    }
+}
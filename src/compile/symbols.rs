@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use crate::compile::diagnostic::Span;
+use crate::file::FmpFile;
+use crate::fm_script_engine::fm_script_engine_instructions::{Instruction, ScriptStep};
+
+/* Something named in Burn. */
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Symbol {
+    Table(String),
+    TableOccurrence(String),
+    /* A field, by the table it's in. */
+    Field(String, String),
+    /* A field reached through a table occurrence, which is only known to
+     * be a `Field` once every occurrence has been read. */
+    OccurrenceField(String, String),
+    Layout(String),
+    ValueList(String),
+    Script(String),
+}
+
+/* Where a symbol is defined, and what's shown when hovering over it. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub symbol: Symbol,
+    pub span: Span,
+    pub detail: String,
+    pub description: String,
+}
+
+impl Definition {
+    fn new(symbol: Symbol, span: Span, detail: String) -> Self {
+        Self { symbol, span, detail, description: String::new() }
+    }
+}
+
+/* The name passed to a script step, when it's written out rather than
+ * calculated: perform_script("helper") or perform_script(helper). */
+pub fn literal_name(param: &str) -> Option<&str> {
+    let param = param.trim();
+    match param.strip_prefix('"').and_then(|p| p.strip_suffix('"')) {
+        Some(name) if !name.contains('"') => Some(name),
+        Some(_) => None,
+        None if param.chars().all(|c| c.is_alphanumeric() || c == '_') => Some(param),
+        None => None,
+    }
+}
+
+/* Names written out in the first switch of a step. */
+fn step_references(step: &ScriptStep) -> Vec<(Symbol, Span)> {
+    let (Some(param), Some(span)) = (step.switches.first(), step.switch_spans.first()) else {
+        return vec![];
+    };
+    let name = || literal_name(param).map(String::from);
+    match step.opcode {
+        Instruction::PerformScript => name().map(|n| (Symbol::Script(n), *span)).into_iter().collect(),
+        Instruction::GoToLayout => name().map(|n| (Symbol::Layout(n), *span)).into_iter().collect(),
+        Instruction::SetField => {
+            let Some((occurrence, field)) = param.trim().split_once("::") else {
+                return vec![];
+            };
+            let field_span = Span { column: span.column + occurrence.len() + 2, len: field.trim().len(), ..*span };
+            vec![
+                (Symbol::TableOccurrence(occurrence.to_string()), Span { len: occurrence.len(), ..*span }),
+                (Symbol::OccurrenceField(occurrence.to_string(), field.trim().to_string()), field_span),
+            ]
+        },
+        _ => vec![],
+    }
+}
+
+/* Every symbol of a file, where it's defined and where it's used. Fields
+ * reached through an occurrence are resolved to the field of its table. */
+pub struct SymbolTable {
+    pub definitions: Vec<Definition>,
+    pub references: Vec<(Symbol, Span)>,
+}
+
+impl SymbolTable {
+    pub fn new(file: &FmpFile) -> Self {
+        let mut definitions = vec![];
+        for table in file.tables.values() {
+            definitions.push(Definition::new(Symbol::Table(table.table_name.clone()), table.span,
+                    format!("table {}", table.table_name)));
+            for field in table.fields.values() {
+                let mut definition = Definition::new(Symbol::Field(table.table_name.clone(), field.field_name.clone()), field.span,
+                    format!("{}::{}: {}", table.table_name, field.field_name, field.data_type));
+                definition.description = field.field_description.clone();
+                definitions.push(definition);
+            }
+        }
+        for to in file.table_occurrences.values() {
+            definitions.push(Definition::new(Symbol::TableOccurrence(to.table_occurence_name.clone()), to.span,
+                    format!("table_occurence {}: table {}", to.table_occurence_name, to.table_actual_name)));
+        }
+        for layout in file.layouts.values() {
+            definitions.push(Definition::new(Symbol::Layout(layout.layout_name.clone()), layout.span,
+                    format!("layout {}: table_occurence {}", layout.layout_name, layout.table_occurrence_name)));
+        }
+        for list in file.value_lists.values() {
+            definitions.push(Definition::new(Symbol::ValueList(list.list_name.clone()), list.span,
+                    format!("value_list {}", list.list_name)));
+        }
+        for script in file.scripts.values() {
+            definitions.push(Definition::new(Symbol::Script(script.script_name.clone()), script.span,
+                    format!("define {}({})", script.script_name, script.arguments.join(", "))));
+        }
+
+        let occurrences = file.table_occurrences.values()
+            .map(|to| (to.table_occurence_name.as_str(), to.table_actual_name.as_str()))
+            .collect::<HashMap<_, _>>();
+        let scripts = file.scripts.values().chain(file.tests.iter().map(|t| &t.script));
+        let references = file.references.iter().cloned()
+            .chain(scripts.flat_map(|s| &s.instructions).flat_map(step_references))
+            .map(|(symbol, span)| match symbol {
+                Symbol::OccurrenceField(occurrence, field) if occurrences.contains_key(occurrence.as_str()) =>
+                    (Symbol::Field(occurrences[occurrence.as_str()].to_string(), field), span),
+                _ => (symbol, span),
+            })
+            .collect();
+        Self { definitions, references }
+    }
+
+    /* The symbol defined or used at a position, counting the character
+     * just after a name as part of it. */
+    pub fn symbol_at(&self, source: usize, line: usize, column: usize) -> Option<&Symbol> {
+        let at = |span: &Span| span.source == source && span.line == line
+            && span.column <= column && column <= span.column + span.len;
+        self.references.iter().find(|(_, span)| at(span)).map(|(symbol, _)| symbol)
+            .or_else(|| self.definitions.iter().find(|d| at(&d.span)).map(|d| &d.symbol))
+    }
+
+    pub fn definitions_of(&self, symbol: &Symbol) -> impl Iterator<Item = &Definition> {
+        let symbol = symbol.clone();
+        self.definitions.iter().filter(move |d| d.symbol == symbol)
+    }
+
+    pub fn references_to(&self, symbol: &Symbol) -> impl Iterator<Item = Span> + '_ {
+        let symbol = symbol.clone();
+        self.references.iter().filter(move |(s, _)| *s == symbol).map(|(_, span)| *span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::{compiler::compile_burn, diagnostic::Span};
+    use super::{Symbol, SymbolTable};
+
+    #[test]
+    fn symbol_table_test() {
+        let file = compile_burn("
+            table Person:
+              name: Text, \"Full name\",
+            end table;
+            table_occurence People:
+              table: Person,
+            end table_occurence;
+            script: [
+              define rename(n) {
+                go_to_layout(\"People\");
+                set_field(People::name, n);
+              }
+            ]
+        ");
+        let symbols = SymbolTable::new(&file);
+        let name = Symbol::Field(String::from("Person"), String::from("name"));
+        assert_eq!(symbols.symbol_at(0, 11, 37), Some(&name));
+        assert_eq!(symbols.symbol_at(0, 11, 29), Some(&Symbol::TableOccurrence(String::from("People"))));
+        assert_eq!(symbols.symbol_at(0, 6, 22), Some(&Symbol::Table(String::from("Person"))));
+
+        let definition = symbols.definitions_of(&name).next().unwrap();
+        assert_eq!((definition.span, definition.detail.as_str(), definition.description.as_str()),
+            (Span::new(3, 15, 4), "Person::name: Text", "Full name"));
+        assert_eq!(symbols.references_to(&name).collect::<Vec<_>>(), vec![Span::new(11, 35, 4)]);
+        let layout = symbols.definitions_of(&Symbol::Layout(String::from("People"))).next().unwrap();
+        assert_eq!(layout.span, Span::new(5, 29, 6));
+        assert_eq!(symbols.references_to(&Symbol::Layout(String::from("People"))).collect::<Vec<_>>(),
+            vec![Span::new(10, 30, 8)]);
+    }
+}
@@ -1,12 +1,17 @@
+use crate::compile::diagnostic::Span;
 
 #[derive(Debug, PartialEq)]
 pub enum TokenType {
     Table,
     Relationship,
     ValueList,
+    Layout,
+    Data,
+    PrivilegeSet,
     Script,
     Test,
     TableOccurence,
+    Import,
     AssertionBlock,
     Assertion,
     Identifier,
@@ -44,17 +49,24 @@ pub enum TokenType {
     EOF,
 }
 
+#[derive(Debug)]
 pub struct Token {
     pub ttype: TokenType,
     pub text: String,
+    pub span: Span,
 }
 
 impl Token {
     pub fn new(t: TokenType) -> Self {
-        Self { ttype: t, text: String::new() }
+        Self { ttype: t, text: String::new(), span: Span::default() }
     }
 
     pub fn with_value(t: TokenType, val: String) -> Self {
-        Self { ttype: t, text: val }
+        Self { ttype: t, text: val, span: Span::default() }
+    }
+
+    pub fn at(mut self, span: Span) -> Self {
+        self.span = span;
+        self
     }
 }
@@ -3,7 +3,7 @@ use std::collections::{BTreeMap, HashMap};
 
 use serde::{Deserialize, Serialize};
 
-use crate::{encoding_util::fm_string_decrypt, fm_script_engine::fm_script_engine_instructions::ScriptStep};
+use crate::{compile::diagnostic::Span, encoding_util::{fm_string_decrypt, fm_string_encrypt}, fm_script_engine::fm_script_engine_instructions::ScriptStep};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FMComponentType {
@@ -17,6 +17,126 @@ pub enum FMComponentType {
     DataSource,
 }
 
+/* Byte offsets into the option array stored at key 2 of a field definition. */
+const FIELD_OPT_KIND: usize = 0;
+const FIELD_OPT_DATA_TYPE: usize = 1;
+const FIELD_OPT_AUTO_ENTER_PRESET: usize = 3;
+const FIELD_OPT_LANGUAGE: usize = 7;
+const FIELD_OPT_INDEX: usize = 8;
+const FIELD_OPT_STORAGE: usize = 9;
+const FIELD_OPT_ENTRY: usize = 10;
+const FIELD_OPT_AUTO_ENTER: usize = 11;
+const FIELD_OPT_VALIDATION_TYPE: usize = 14;
+const FIELD_OPT_VALIDATION: usize = 15;
+const FIELD_OPT_REPETITIONS: usize = 25;
+const FIELD_OPTIONS_LEN: usize = 26;
+/* Data types by the code stored at FIELD_OPT_DATA_TYPE. */
+const FIELD_DATA_TYPES: [(&str, u8); 6] = [
+    ("Text", 1),
+    ("Number", 2),
+    ("Date", 3),
+    ("Time", 4),
+    ("Timestamp", 5),
+    ("Container", 6),
+];
+/* Bits of the option array that `set_options` reads back, or that follow from
+ * what it reads, like the index flag of a unique field. */
+const FIELD_OPTIONS_READ: [(usize, u8); 10] = [
+    (FIELD_OPT_KIND, 0xFF),
+    (FIELD_OPT_DATA_TYPE, 0xFF),
+    (FIELD_OPT_AUTO_ENTER_PRESET, 0xFF),
+    (FIELD_OPT_INDEX, 128),
+    (FIELD_OPT_STORAGE, 1),
+    (FIELD_OPT_ENTRY, 3),
+    (FIELD_OPT_AUTO_ENTER, 139),
+    (FIELD_OPT_VALIDATION_TYPE, 5),
+    (FIELD_OPT_VALIDATION, 189),
+    (FIELD_OPT_REPETITIONS, 0xFF),
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AutoEnterPreset {
+    CreationDate,
+    CreationTime,
+    CreationTimestamp,
+    CreationName,
+    CreationAccountName,
+    ModificationDate,
+    ModificationTime,
+    ModificationTimestamp,
+    ModificationName,
+    ModificationAccountName,
+}
+
+impl AutoEnterPreset {
+    const ALL: [AutoEnterPreset; 10] = [
+        AutoEnterPreset::CreationDate,
+        AutoEnterPreset::CreationTime,
+        AutoEnterPreset::CreationTimestamp,
+        AutoEnterPreset::CreationName,
+        AutoEnterPreset::CreationAccountName,
+        AutoEnterPreset::ModificationDate,
+        AutoEnterPreset::ModificationTime,
+        AutoEnterPreset::ModificationTimestamp,
+        AutoEnterPreset::ModificationName,
+        AutoEnterPreset::ModificationAccountName,
+    ];
+
+    pub fn from_byte(b: u8) -> Option<Self> {
+        Self::ALL.get(b as usize).cloned()
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        Self::ALL.iter().position(|p| p == self).unwrap() as u8
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FMFieldValidation {
+    pub unique: bool,
+    pub required: bool,
+    pub existing: bool,
+    pub always_validate: bool,
+    pub user_override: bool,
+    pub value_list: Option<String>,
+    pub calculation: Option<String>,
+    pub message: Option<String>,
+}
+
+impl FMFieldValidation {
+    pub fn new() -> Self {
+        Self {
+            unique: false,
+            required: false,
+            existing: false,
+            always_validate: false,
+            user_override: true,
+            value_list: None,
+            calculation: None,
+            message: None,
+        }
+    }
+}
+
+/* An auto-entered serial number. The next value is stored as text at key 12
+ * of the field definition, the increment at key 13. */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FMSerialNumber {
+    pub next_value: u64,
+    pub increment: u64,
+    pub on_commit: bool,
+}
+
+impl FMSerialNumber {
+    pub fn new() -> Self {
+        Self {
+            next_value: 1,
+            increment: 1,
+            on_commit: false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FMComponentField {
     pub data_type: String,
@@ -25,7 +145,18 @@ pub struct FMComponentField {
     pub field_type: String,
     pub created_by_account: String,
     pub created_by_user: String,
+    pub global: bool,
+    pub repetitions: u8,
+    pub prohibit_modification: bool,
+    pub auto_enter_preset: Option<AutoEnterPreset>,
+    pub auto_enter_calc: Option<String>,
+    pub auto_enter_replace: bool,
+    pub auto_enter_serial: Option<FMSerialNumber>,
+    pub validation: FMFieldValidation,
+    #[serde(skip)]
+    pub span: Span,
 }
+
 impl FMComponentField {
     pub fn new() -> Self {
         Self {
@@ -35,8 +166,147 @@ impl FMComponentField {
             field_type: String::new(),
             created_by_account: String::new(),
             created_by_user: String::new(),
+            global: false,
+            repetitions: 1,
+            prohibit_modification: false,
+            auto_enter_preset: None,
+            auto_enter_calc: None,
+            auto_enter_replace: false,
+            auto_enter_serial: None,
+            validation: FMFieldValidation::new(),
+            span: Span::default(),
         }
     }
+
+    /* Reads the option array found at key 2 of a field definition. */
+    pub fn set_options(&mut self, bytes: &[u8]) {
+        let get = |i: usize| -> u8 { *bytes.get(i).unwrap_or(&0) };
+        self.field_type = match get(FIELD_OPT_KIND) {
+            2 => "Calculation",
+            3 => "Summary",
+            _ => "Simple",
+        }.to_string();
+        self.data_type = FIELD_DATA_TYPES.iter()
+            .find(|(_, code)| *code == get(FIELD_OPT_DATA_TYPE))
+            .map_or("Unknown", |(name, _)| name)
+            .to_string();
+
+        self.global = get(FIELD_OPT_STORAGE) & 1 != 0;
+        self.prohibit_modification = get(FIELD_OPT_ENTRY) & 1 != 0;
+        self.repetitions = get(FIELD_OPT_REPETITIONS).max(1);
+
+        let auto_enter = get(FIELD_OPT_AUTO_ENTER);
+        self.auto_enter_preset = if auto_enter & 1 != 0 {
+            AutoEnterPreset::from_byte(get(FIELD_OPT_AUTO_ENTER_PRESET))
+        } else {
+            None
+        };
+        self.auto_enter_replace = auto_enter & 136 == 136;
+        /* The calculation itself is stored separately and filled in once read. */
+        self.auto_enter_calc = if auto_enter & 8 != 0 { Some(String::new()) } else { None };
+        /* So are the next value and increment of a serial number. */
+        let on_commit = get(FIELD_OPT_ENTRY) & 2 != 0;
+        self.auto_enter_serial = if auto_enter & 2 != 0 || on_commit {
+            let mut serial = self.auto_enter_serial.take().unwrap_or(FMSerialNumber::new());
+            serial.on_commit = on_commit;
+            Some(serial)
+        } else {
+            None
+        };
+
+        let validation_type = get(FIELD_OPT_VALIDATION_TYPE);
+        let validation = get(FIELD_OPT_VALIDATION);
+        self.validation.always_validate = validation_type & 4 != 0;
+        self.validation.user_override = validation & 4 == 0;
+        self.validation.required = validation & 8 != 0;
+        self.validation.unique = validation & 16 != 0;
+        self.validation.existing = validation & 32 != 0;
+        /* The value list, calculation and message are stored separately and filled in once read. */
+        self.validation.value_list = if validation_type & 1 != 0 { Some(String::new()) } else { None };
+        self.validation.calculation = if validation & 1 != 0 { Some(String::new()) } else { None };
+        self.validation.message = if validation & 128 != 0 { Some(String::new()) } else { None };
+    }
+
+    /* The data type as stored in the option array. Unknown types are written as Text. */
+    pub fn data_type_code(&self) -> u8 {
+        FIELD_DATA_TYPES.iter()
+            .find(|(name, _)| *name == self.data_type)
+            .map_or(1, |(_, code)| *code)
+    }
+
+    /* Builds the option array stored at key 2 of a field definition. */
+    pub fn options_to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; FIELD_OPTIONS_LEN];
+        bytes[FIELD_OPT_KIND] = match self.field_type.as_str() {
+            "Calculation" => 2,
+            "Summary" => 3,
+            _ => 1,
+        };
+        bytes[FIELD_OPT_DATA_TYPE] = self.data_type_code();
+        /* English */
+        bytes[FIELD_OPT_LANGUAGE] = 21;
+        if self.validation.unique {
+            bytes[FIELD_OPT_INDEX] = 128;
+        }
+        if self.global {
+            bytes[FIELD_OPT_STORAGE] = 1;
+        }
+        if self.prohibit_modification {
+            bytes[FIELD_OPT_ENTRY] = 1;
+        }
+        if let Some(preset) = &self.auto_enter_preset {
+            bytes[FIELD_OPT_AUTO_ENTER_PRESET] = preset.to_byte();
+            bytes[FIELD_OPT_AUTO_ENTER] |= 1;
+        }
+        if self.auto_enter_calc.is_some() {
+            bytes[FIELD_OPT_AUTO_ENTER] |= if self.auto_enter_replace { 136 } else { 8 };
+        }
+        match &self.auto_enter_serial {
+            Some(serial) if serial.on_commit => bytes[FIELD_OPT_ENTRY] |= 2,
+            Some(_) => bytes[FIELD_OPT_AUTO_ENTER] |= 2,
+            None => {},
+        }
+
+        if self.validation.value_list.is_some() {
+            bytes[FIELD_OPT_VALIDATION_TYPE] |= 1;
+        }
+        if self.validation.always_validate {
+            bytes[FIELD_OPT_VALIDATION_TYPE] |= 4;
+        }
+        let v = &mut bytes[FIELD_OPT_VALIDATION];
+        if self.validation.calculation.is_some() { *v |= 1; }
+        if !self.validation.user_override { *v |= 4; }
+        if self.validation.required { *v |= 8; }
+        if self.validation.unique { *v |= 16; }
+        if self.validation.existing { *v |= 32; }
+        if self.validation.message.is_some() { *v |= 128; }
+
+        /* Always set in files written by FileMaker. */
+        bytes[19] = 1;
+        bytes[FIELD_OPT_REPETITIONS] = self.repetitions.max(1);
+        bytes
+    }
+
+    /* Option array for a field that already exists as `original` in a file.
+     * Options that weren't changed keep their original bits, so anything that
+     * isn't modelled yet survives being written again. */
+    pub fn merge_options(&self, original: &[u8]) -> Vec<u8> {
+        let mut before = self.clone();
+        before.set_options(original);
+        let before = before.options_to_bytes();
+        let ours = self.options_to_bytes();
+
+        let mut bytes = original.to_vec();
+        if bytes.len() < FIELD_OPTIONS_LEN {
+            bytes.resize(FIELD_OPTIONS_LEN, 0);
+        }
+        for (i, mask) in FIELD_OPTIONS_READ {
+            if before[i] & mask != ours[i] & mask {
+                bytes[i] = (bytes[i] & !mask) | (ours[i] & mask);
+            }
+        }
+        bytes
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -46,6 +316,8 @@ pub struct FMComponentTest {
     pub created_by_account: String,
     pub create_by_user: String,
     pub assertions: Vec<String>,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 impl FMComponentTest {
@@ -56,6 +328,7 @@ impl FMComponentTest {
             created_by_account: String::new(),
             create_by_user: String::new(),
             assertions: vec![],
+            span: Span::default(),
         }
     }
 }
@@ -67,6 +340,8 @@ pub struct FMComponentTable {
     pub create_by_user: String,
     pub fields: BTreeMap<u16, FMComponentField>,
     pub init: bool,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 impl FMComponentTable {
@@ -76,7 +351,8 @@ impl FMComponentTable {
             created_by_account: String::new(),
             create_by_user: String::new(),
             fields: BTreeMap::new(),
-            init: false
+            init: false,
+            span: Span::default(),
         }
     }
 
@@ -86,7 +362,8 @@ impl FMComponentTable {
             created_by_account: String::new(),
             create_by_user: String::new(),
             fields: BTreeMap::new(),
-            init: true
+            init: true,
+            span: Span::default(),
         }
 
     }
@@ -99,8 +376,18 @@ pub struct FMComponentScript {
     pub create_by_user: String,
     pub arguments: Vec<String>,
     pub instructions: Vec<ScriptStep>,
+    /* Whether the script is written to the file. Scripts from a "script:"
+     * block are only there for tests and other scripts to run. */
+    #[serde(default = "script_export_default")]
+    pub export: bool,
+    #[serde(skip)]
+    pub span: Span,
 } 
 
+fn script_export_default() -> bool {
+    true
+}
+
 impl FMComponentScript {
     pub fn new() -> Self {
         Self {
@@ -109,6 +396,8 @@ impl FMComponentScript {
             create_by_user: String::new(),
             arguments: vec![],
             instructions: vec![],
+            export: true,
+            span: Span::default(),
         }
     }
 }
@@ -120,6 +409,8 @@ pub struct FMComponentTableOccurence {
     pub table_actual_name: String,
     pub created_by_account: String,
     pub create_by_user: String,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 impl FMComponentTableOccurence {
@@ -129,12 +420,13 @@ impl FMComponentTableOccurence {
             table_actual: 0,
             table_actual_name: String::new(),
             created_by_account: String::new(),
-            create_by_user: String::new()
+            create_by_user: String::new(),
+            span: Span::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum RelationComparison {
     Equal,
     NotEqual,
@@ -145,15 +437,86 @@ pub enum RelationComparison {
     Cartesian
 }
 
+impl RelationComparison {
+    const ALL: [RelationComparison; 7] = [
+        RelationComparison::Equal,
+        RelationComparison::NotEqual,
+        RelationComparison::Less,
+        RelationComparison::LessEqual,
+        RelationComparison::Greater,
+        RelationComparison::GreaterEqual,
+        RelationComparison::Cartesian,
+    ];
+
+    pub fn from_byte(b: u8) -> Self {
+        Self::ALL.get(b as usize).cloned().unwrap_or(RelationComparison::Equal)
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        Self::ALL.iter().position(|c| c == self).unwrap() as u8
+    }
+}
+
+/* A single predicate of a relationship, comparing a field on each side. */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FMRelationCriterion {
+    pub field1: u16,
+    pub field1_name: String,
+    pub field2: u16,
+    pub field2_name: String,
+    pub comparison: RelationComparison,
+}
+
+impl FMRelationCriterion {
+    pub fn new() -> Self {
+        Self {
+            field1: 0,
+            field1_name: String::new(),
+            field2: 0,
+            field2_name: String::new(),
+            comparison: RelationComparison::Equal,
+        }
+    }
+}
+
+/* Options set on one side of a relationship. */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FMRelationSideOptions {
+    pub allow_creation: bool,
+    pub delete_related: bool,
+}
+
+impl FMRelationSideOptions {
+    pub fn new() -> Self {
+        Self {
+            allow_creation: false,
+            delete_related: false,
+        }
+    }
+
+    pub fn from_byte(b: u8) -> Self {
+        Self {
+            allow_creation: b & 1 != 0,
+            delete_related: b & 2 != 0,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        self.allow_creation as u8 | (self.delete_related as u8) << 1
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FMComponentRelationship {
     pub table1: u16,
     pub table1_name: String,
-    pub field1: u16,
+    pub table1_options: FMRelationSideOptions,
     pub table2: u16,
     pub table2_name: String,
-    pub field2: u16,
-    pub comparison: RelationComparison,
+    pub table2_options: FMRelationSideOptions,
+    pub criteria: Vec<FMRelationCriterion>,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 impl FMComponentRelationship {
@@ -161,11 +524,37 @@ impl FMComponentRelationship {
         Self {
             table1: 0,
             table1_name: String::new(),
-            field1: 0,
+            table1_options: FMRelationSideOptions::new(),
             table2: 0,
             table2_name: String::new(),
-            field2: 0,
-            comparison: RelationComparison::Equal,
+            table2_options: FMRelationSideOptions::new(),
+            criteria: vec![],
+            span: Span::default(),
+        }
+    }
+}
+
+/* A field placed on a layout, from the layout's own table occurrence.
+ * Positions are in points from the top left of the layout. */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FMLayoutField {
+    pub field: u16,
+    pub field_name: String,
+    pub top: u16,
+    pub left: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl FMLayoutField {
+    pub fn new() -> Self {
+        Self {
+            field: 0,
+            field_name: String::new(),
+            top: 0,
+            left: 0,
+            width: 0,
+            height: 0,
         }
     }
 }
@@ -174,17 +563,66 @@ impl FMComponentRelationship {
 pub struct FMComponentLayout {
     pub layout_name: String,
     pub table_occurrence: usize,
+    pub table_occurrence_name: String,
+    pub fields: Vec<FMLayoutField>,
     pub created_by_account: String,
     pub create_by_user: String,
+    #[serde(skip)]
+    pub span: Span,
 }
 
+/* Where fields listed in Burn are placed: one per row, below the layout header. */
+const LAYOUT_FIELD_TOP: u16 = 100;
+const LAYOUT_FIELD_LEFT: u16 = 120;
+const LAYOUT_FIELD_WIDTH: u16 = 240;
+const LAYOUT_FIELD_HEIGHT: u16 = 20;
+const LAYOUT_FIELD_SPACING: u16 = 10;
+
 impl FMComponentLayout {
     pub fn new() -> Self {
         Self {
             layout_name: String::new(),
             table_occurrence: 0,
+            table_occurrence_name: String::new(),
+            fields: vec![],
             created_by_account: String::new(),
-            create_by_user: String::new()
+            create_by_user: String::new(),
+            span: Span::default(),
+        }
+    }
+
+    /* Places a field in the row below the last one. */
+    pub fn place_field(&mut self, name: &str) {
+        let mut field = FMLayoutField::new();
+        field.field_name = name.to_string();
+        field.top = self.fields.last()
+            .map(|f| f.top + f.height + LAYOUT_FIELD_SPACING)
+            .unwrap_or(LAYOUT_FIELD_TOP);
+        field.left = LAYOUT_FIELD_LEFT;
+        field.width = LAYOUT_FIELD_WIDTH;
+        field.height = LAYOUT_FIELD_HEIGHT;
+        self.fields.push(field);
+    }
+}
+
+/* Where the values of a value list come from. */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ValueListSource {
+    CustomValues(Vec<String>),
+    /* Values of a field, looked up through a table occurrence. */
+    Field {
+        table_occurrence: u16,
+        table_occurrence_name: String,
+        field: u16,
+        field_name: String,
+    },
+}
+
+impl ValueListSource {
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            ValueListSource::CustomValues(_) => 1,
+            ValueListSource::Field { .. } => 2,
         }
     }
 }
@@ -192,16 +630,91 @@ impl FMComponentLayout {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FMComponentValueList {
     pub list_name: String,
+    pub source: ValueListSource,
     pub created_by_account: String,
     pub create_by_user: String,
+    #[serde(skip)]
+    pub span: Span,
 }
 
 impl FMComponentValueList {
     pub fn new() -> Self {
         Self {
             list_name: String::new(),
+            source: ValueListSource::CustomValues(vec![]),
             created_by_account: String::new(),
             create_by_user: String::new(),
+            span: Span::default(),
+        }
+    }
+}
+
+/* The privilege sets FileMaker creates in every file, in the order it numbers them. */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PrivilegeAccess {
+    FullAccess,
+    DataEntryOnly,
+    ReadOnlyAccess,
+}
+
+impl PrivilegeAccess {
+    pub const STANDARD: [PrivilegeAccess; 3] = [
+        PrivilegeAccess::FullAccess,
+        PrivilegeAccess::DataEntryOnly,
+        PrivilegeAccess::ReadOnlyAccess,
+    ];
+
+    pub fn set_name(&self) -> &'static str {
+        match self {
+            PrivilegeAccess::FullAccess => "[Full Access]",
+            PrivilegeAccess::DataEntryOnly => "[Data Entry Only]",
+            PrivilegeAccess::ReadOnlyAccess => "[Read-Only Access]",
+        }
+    }
+
+    /* Privilege bits of a set: key 2, keys 4, 17 and 33 of its [9] directory, and key 10. */
+    pub fn to_flags(self) -> ([u8; 2], [u8; 3], [u8; 2]) {
+        match self {
+            PrivilegeAccess::FullAccess => ([85, 3], [223, 215, 215], [159, 255]),
+            PrivilegeAccess::DataEntryOnly => ([85, 3], [222, 214, 214], [3, 4]),
+            PrivilegeAccess::ReadOnlyAccess => ([16, 3], [222, 214, 214], [3, 4]),
+        }
+    }
+
+    /* Only full access sets the high bit of key 10, and read-only access
+     * clears bit 0x40 of key 2. */
+    pub fn from_flags(key2: u8, key10: u8) -> Self {
+        if key10 & 0x80 != 0 {
+            PrivilegeAccess::FullAccess
+        } else if key2 & 0x40 == 0 {
+            PrivilegeAccess::ReadOnlyAccess
+        } else {
+            PrivilegeAccess::DataEntryOnly
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FMComponentPrivilegeSet {
+    pub set_name: String,
+    pub set_description: String,
+    /* Custom sets are given the privileges of one of the standard sets. */
+    pub access: PrivilegeAccess,
+    pub created_by_account: String,
+    pub create_by_user: String,
+    #[serde(skip)]
+    pub span: Span,
+}
+
+impl FMComponentPrivilegeSet {
+    pub fn new() -> Self {
+        Self {
+            set_name: String::new(),
+            set_description: String::new(),
+            access: PrivilegeAccess::DataEntryOnly,
+            created_by_account: String::new(),
+            create_by_user: String::new(),
+            span: Span::default(),
         }
     }
 }
@@ -236,7 +749,6 @@ impl SourceFileLocation {
         let path : Vec<_> = path_bytes.into_iter()
             .map(|b| vec![b])
             .map(|b| match b[..] {
-                [50] => { vec![] }
                 [65] => { vec![96 as u8]},
                 [218] => { vec![(117 as u8)] },
                 [219] => { vec![(116 as u8), (116 as u8)] },
@@ -256,6 +768,40 @@ impl SourceFileLocation {
         res
 
     }
+
+    /* The encoding `from_bytes` reads: a count of 1, the type, the path with
+     * ".." and '/' written as 219 and 218 after the [65, 50] marker, then the
+     * file name. Each is preceded by its length. */
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut path = vec![];
+        let mut rest = self.source_path.as_str();
+        while let Some(c) = rest.chars().next() {
+            if let Some(after) = rest.strip_prefix("..") {
+                path.push(219);
+                rest = after;
+                continue;
+            }
+            match c {
+                '/' => path.push(218),
+                ':' => path.push(65),
+                _ => path.extend(fm_string_encrypt(c.to_string())),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+
+        let mut bytes = vec![0, 0, 0, 1];
+        let parts = [fm_string_encrypt(self.source_type.clone()), path, fm_string_encrypt(self.source_filename.clone())];
+        for (n, part) in parts.into_iter().enumerate() {
+            let len = u8::try_from(part.len())
+                .map_err(|_| format!("Data source location {}{} is too long to be written.", self.source_path, self.source_filename))?;
+            bytes.push(len);
+            if n == 1 {
+                bytes.extend([65, 50]);
+            }
+            bytes.extend(part);
+        }
+        Ok(bytes)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -276,3 +822,66 @@ impl FMComponentDataSource {
         }
     }
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FMComponentContainer {
+    pub file_name: String,
+    pub size: usize,
+    pub dimensions: Option<(u16, u16)>,
+    pub external_path: Option<String>,
+    /* Raw streams keyed by their 4 character tag, i.e. "FNAM", "FILE", "JPEG". */
+    #[serde(skip)]
+    pub streams: BTreeMap<String, Vec<u8>>,
+}
+
+impl FMComponentContainer {
+    pub fn new() -> Self {
+        Self {
+            file_name: String::new(),
+            size: 0,
+            dimensions: None,
+            external_path: None,
+            streams: BTreeMap::new(),
+        }
+    }
+}
+
+/* Initial records for a table, given in Burn or read from a CSV or JSON file.
+ * Values are kept as written until they're checked against the table's fields. */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FMComponentSeed {
+    pub table_name: String,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /* File the rows are read from, relative to the Burn source. */
+    pub source: Option<String>,
+    #[serde(skip)]
+    pub span: Span,
+}
+
+impl FMComponentSeed {
+    pub fn new() -> Self {
+        Self {
+            table_name: String::new(),
+            columns: vec![],
+            rows: vec![],
+            source: None,
+            span: Span::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FMComponentRecord {
+    pub fields: BTreeMap<u16, String>,
+    pub containers: BTreeMap<u16, FMComponentContainer>,
+}
+
+impl FMComponentRecord {
+    pub fn new() -> Self {
+        Self {
+            fields: BTreeMap::new(),
+            containers: BTreeMap::new(),
+        }
+    }
+}
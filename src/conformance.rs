@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::chunk::ChunkType;
+use crate::compile::assembler::Assembler;
+use crate::decompile::components::Components;
+use crate::decompile::decompiler::{decode_sector, get_sector_chain, Decompiler};
+use crate::file::FmpFile;
+
+/* Keys and values of every directory in a file, independent of how the
+ * chunks were laid out across sectors. Keyed values use their key, with a
+ * ' for each time it was already used in the directory, data segments are
+ * joined into one value, and values without a key are sorted and numbered. */
+#[derive(Debug, Default, PartialEq)]
+pub struct FmpTree {
+    pub directories: BTreeMap<Vec<String>, BTreeMap<String, Vec<u8>>>,
+}
+
+impl FmpTree {
+    pub fn from_buffer(buffer: &[u8]) -> Self {
+        let mut directories = BTreeMap::<Vec<String>, BTreeMap<String, Vec<u8>>>::new();
+        let mut segments = BTreeMap::<Vec<String>, BTreeMap<u8, Vec<u8>>>::new();
+        let mut unkeyed = BTreeMap::<Vec<String>, Vec<Vec<u8>>>::new();
+
+        for idx in get_sector_chain(buffer) {
+            /* Occurrence directories are pushed with a 3 byte reference, which
+             * the chunk path doesn't keep, so each directory of the path is
+             * paired with the id it was pushed with. */
+            let mut labels = Vec::<(String, String)>::new();
+            for chunk in decode_sector(buffer, idx, Components::ALL).chunks {
+                let kept = labels.iter().zip(&chunk.path).take_while(|(l, p)| l.0 == **p).count();
+                labels.truncate(kept);
+                labels.extend(chunk.path[kept..].iter().map(|p| (p.clone(), p.clone())));
+                if chunk.ctype == ChunkType::PathPush {
+                    if let (0x30, Some(data), Some(last)) = (chunk.code, chunk.data, labels.last_mut()) {
+                        last.1 = u16::from_be_bytes([data[1], data[2]]).to_string();
+                    }
+                    directories.entry(labels.iter().map(|l| l.1.clone()).collect()).or_default();
+                    continue;
+                }
+
+                let path = labels.iter().map(|l| l.1.clone()).collect::<Vec<_>>();
+                let data = chunk.data.unwrap_or(&[]).to_vec();
+                match chunk.ctype {
+                    /* A key can be written once with one byte and once with two, data
+                     * sources keep their location and their creator at key 130. */
+                    ChunkType::RefSimple => {
+                        let directory = directories.entry(path).or_default();
+                        let mut key = chunk.ref_simple.unwrap().to_string();
+                        while directory.contains_key(&key) {
+                            key.push('\'');
+                        }
+                        directory.insert(key, data);
+                    },
+                    ChunkType::RefLong => {
+                        directories.entry(path).or_default()
+                            .insert(format!("{:?}", chunk.ref_data.unwrap_or(&[])), data);
+                    },
+                    ChunkType::DataSegment => {
+                        segments.entry(path).or_default()
+                            .insert(chunk.segment_idx.unwrap_or(0), data);
+                    },
+                    /* Zero filled sector padding decodes as empty data. */
+                    ChunkType::DataSimple if !data.iter().all(|b| *b == 0) => {
+                        unkeyed.entry(path).or_default().push(data);
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        for (path, segments) in segments {
+            directories.entry(path).or_default()
+                .insert(String::from("segments"), segments.into_values().flatten().collect());
+        }
+        for (path, mut values) in unkeyed {
+            values.sort();
+            let directory = directories.entry(path).or_default();
+            for (n, value) in values.into_iter().enumerate() {
+                directory.insert(format!("#{}", n), value);
+            }
+        }
+
+        Self { directories }
+    }
+}
+
+fn format_path(path: &[String]) -> String {
+    path.iter().map(|p| format!("[{}]", p)).collect::<Vec<_>>().join(".")
+}
+
+/* Keys whose values FileMaker makes up each time a component is created or
+ * changed: a random id, the time of the change and how often it changed. A
+ * rebuilt file can only have them, not the same values. */
+const GENERATED_KEYS: [&str; 3] = ["216", "131", "252"];
+
+/* Lists every directory and key that is missing from, added to, or changed in `rebuilt`. */
+pub fn diff_trees(original: &FmpTree, rebuilt: &FmpTree) -> Vec<String> {
+    let mut differences = vec![];
+    for (path, keys) in &original.directories {
+        let Some(rebuilt_keys) = rebuilt.directories.get(path) else {
+            differences.push(format!("{}: directory missing", format_path(path)));
+            continue;
+        };
+        for (key, value) in keys {
+            match rebuilt_keys.get(key) {
+                None => differences.push(format!("{} key {}: missing", format_path(path), key)),
+                Some(v) if v != value && !GENERATED_KEYS.contains(&key.as_str()) => differences.push(format!("{} key {}: {:?} != {:?}",
+                        format_path(path), key, value, v)),
+                _ => {}
+            }
+        }
+        for key in rebuilt_keys.keys().filter(|k| !keys.contains_key(*k)) {
+            differences.push(format!("{} key {}: added", format_path(path), key));
+        }
+    }
+    for path in rebuilt.directories.keys().filter(|p| !original.directories.contains_key(*p)) {
+        differences.push(format!("{}: directory added", format_path(path)));
+    }
+    differences
+}
+
+fn diff_values(path: &str, original: &Value, rebuilt: &Value, differences: &mut Vec<String>) {
+    match (original, rebuilt) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, value) in a {
+                let child = format!("{}.{}", path, key);
+                match b.get(key) {
+                    Some(v) => diff_values(&child, value, v, differences),
+                    None => differences.push(format!("{}: missing", child)),
+                }
+            }
+            for key in b.keys().filter(|k| !a.contains_key(*k)) {
+                differences.push(format!("{}.{}: added", path, key));
+            }
+        },
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (n, (x, y)) in a.iter().zip(b).enumerate() {
+                diff_values(&format!("{}.{}", path, n), x, y, differences);
+            }
+        },
+        _ if original != rebuilt => differences.push(format!("{}: {} != {}", path, original, rebuilt)),
+        _ => {}
+    }
+}
+
+/* Lists every component attribute that differs between two files, by its path in the JSON output. */
+pub fn diff_files(original: &FmpFile, rebuilt: &FmpFile) -> Vec<String> {
+    let original = serde_json::to_value(original).expect("Unable to serialize file.");
+    let rebuilt = serde_json::to_value(rebuilt).expect("Unable to serialize file.");
+    let mut differences = vec![];
+    diff_values("file", &original, &rebuilt, &mut differences);
+    differences
+}
+
+pub struct ConformanceReport {
+    /* Differences between the decompiled original and the decompiled rebuild. */
+    pub file: Vec<String>,
+    /* Differences between the raw directory trees of both files. */
+    pub tree: Vec<String>,
+}
+
+impl ConformanceReport {
+    /* Both the decompiled files and the raw trees have to match. */
+    pub fn passed(&self) -> bool {
+        self.file.is_empty() && self.tree.is_empty()
+    }
+
+    pub fn print(&self, name: &str) {
+        println!("{}: {}", name, if self.passed() { "ok" } else { "FAILED" });
+        for difference in &self.file {
+            println!("  {}", difference);
+        }
+        if !self.tree.is_empty() {
+            println!("  {} directory tree difference(s):", self.tree.len());
+            for difference in &self.tree {
+                println!("    {}", difference);
+            }
+        }
+    }
+}
+
+/* Decompiles `buffer`, assembles the result and decompiles that again,
+ * reporting anything that didn't survive. */
+pub fn check_round_trip(path: &Path, buffer: &[u8]) -> ConformanceReport {
+    if get_sector_chain(buffer).is_empty() {
+        return ConformanceReport { file: vec![String::from("The file has no sectors after its header, so there is nothing to compare.")], tree: vec![] };
+    }
+    let decompiler = Decompiler::new(path);
+    let original = decompiler.run_on(buffer);
+    let mut assembler = Assembler::new(&original);
+    if let Err(e) = assembler.assemble_fmp12(&original) {
+        return ConformanceReport { file: vec![format!("Unable to assemble the file. {}", e)], tree: vec![] };
+    }
+    let rebuilt = decompiler.run_on(assembler.buffer());
+
+    ConformanceReport {
+        file: diff_files(&original, &rebuilt),
+        tree: diff_trees(&FmpTree::from_buffer(buffer), &FmpTree::from_buffer(assembler.buffer())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use crate::compile::assembler::Assembler;
+    use crate::decompile::decompiler::{get_sector_chain, Decompiler};
+    use super::{check_round_trip, diff_trees, FmpTree};
+
+    fn fixtures(dir: &Path, found: &mut Vec<PathBuf>) {
+        for entry in std::fs::read_dir(dir).expect("Unable to read directory.") {
+            let path = entry.expect("Unable to read directory.").path();
+            if path.is_dir() {
+                fixtures(&path, found);
+            } else if path.extension().is_some_and(|e| e == "fmp12") {
+                found.push(path);
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut paths = vec![];
+        fixtures(Path::new("tests"), &mut paths);
+        fixtures(Path::new("doc"), &mut paths);
+        assert!(!paths.is_empty());
+        for path in &paths {
+            let path = path.as_path();
+            let buffer = std::fs::read(path).expect("Unable to read file.");
+            let report = check_round_trip(path, &buffer);
+            /* doc/example.fmp12 is only a file header. */
+            if get_sector_chain(&buffer).is_empty() {
+                assert!(!report.passed(), "{} has nothing to compare but passed.", path.display());
+                continue;
+            }
+            /* Every component, records, data sources and security included, has to survive. */
+            assert!(report.file.is_empty(), "{}:\n{}", path.display(), report.file.join("\n"));
+
+            /* A rebuilt file is written by us, so rebuilding it again must not change a single key. */
+            let decompiler = Decompiler::new(path);
+            let original = decompiler.run_on(&buffer);
+            let mut first = Assembler::new(&original);
+            first.assemble_fmp12(&original).unwrap();
+            let rebuilt = decompiler.run_on(first.buffer());
+            let mut second = Assembler::new(&rebuilt);
+            second.assemble_fmp12(&rebuilt).unwrap();
+            let differences = diff_trees(&FmpTree::from_buffer(first.buffer()), &FmpTree::from_buffer(second.buffer()));
+            assert!(differences.is_empty(), "{}:\n{}", path.display(), differences.join("\n"));
+        }
+    }
+
+    /* The rest of a rebuilt tree still differs from what FileMaker writes, and
+     * check_round_trip reports it. These parts don't. */
+    #[test]
+    fn rebuilt_tree_test() {
+        let path = Path::new("tests/input/blank.fmp12");
+        let buffer = std::fs::read(path).expect("Unable to read file.");
+        let file = Decompiler::new(path).run_on(&buffer);
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let original = FmpTree::from_buffer(&buffer);
+        let rebuilt = FmpTree::from_buffer(assembler.buffer());
+        let dir = |tree: &FmpTree, path: &[&str]| tree.directories[&path.iter().map(|p| p.to_string()).collect::<Vec<_>>()].clone();
+
+        for id in file.tables.keys() {
+            let table = (128 + id).to_string();
+            /* Names are indexed in the same order, by type at [6]. */
+            for index in ["1", "3", "6"] {
+                assert_eq!(dir(&original, &[&table, "3", "1", index]), dir(&rebuilt, &[&table, "3", "1", index]), "[{}].[3].[1].[{}]", table, index);
+            }
+            /* Only the time it was changed is missing from the catalog. */
+            let catalog = dir(&rebuilt, &[&table, "3", "1"]);
+            for key in dir(&original, &[&table, "3", "1"]).keys().filter(|k| *k != "131") {
+                assert!(catalog.contains_key(key), "[{}].[3].[1] key {} is missing", table, key);
+            }
+            /* Whatever the options say about a field comes back as it was. */
+            for (field_id, field) in &file.tables[id].fields {
+                let options = |tree: &FmpTree| {
+                    let mut read = field.clone();
+                    read.set_options(&dir(tree, &[&table, "3", "5", &field_id.to_string()])["2"]);
+                    read.options_to_bytes()
+                };
+                assert_eq!(options(&original), options(&rebuilt), "[{}].[3].[5].[{}] options", table, field_id);
+            }
+        }
+
+        for id in file.data_sources.keys() {
+            let (before, after) = (dir(&original, &["32", "5", &id.to_string()]), dir(&rebuilt, &["32", "5", &id.to_string()]));
+            for key in ["16", "130", "129", "130'"] {
+                assert_eq!(before[key], after[key], "[32].[5].[{}] key {}", id, key);
+            }
+        }
+        assert!(!check_round_trip(path, &buffer).tree.is_empty());
+    }
+
+    #[test]
+    fn tree_difference_test() {
+        let mut original = FmpTree::default();
+        let path = vec![String::from("17"), String::from("1")];
+        original.directories.entry(path.clone()).or_default().insert(String::from("4"), vec![1]);
+        original.directories.entry(path.clone()).or_default().insert(String::from("16"), vec![2]);
+        let mut rebuilt = FmpTree::default();
+        rebuilt.directories.entry(path.clone()).or_default().insert(String::from("4"), vec![3]);
+        rebuilt.directories.entry(vec![String::from("6")]).or_default();
+
+        assert_eq!(diff_trees(&original, &rebuilt), vec![
+            "[17].[1] key 16: missing",
+            "[17].[1] key 4: [1] != [3]",
+            "[6]: directory added",
+        ]);
+    }
+}
@@ -1,8 +1,9 @@
 /* Double Byte Encoding/Decoding for scheme found in FileMaker File.
  * For now the encoding is a hardcoded lookup. */ 
 
-pub const ENCODING_MAPPING: [(u8, u8, char); 29] = [
-    (0x0, 0x0, '\0'), (0x2, 0xa, ' '), (0x2, 0x1d, '_'),
+pub const ENCODING_MAPPING: [(u8, u8, char); 32] = [
+    (0x0, 0x0, '\0'), (0x2, 0xa, ' '), (0x2, 0x1d, '_'), (0x2, 0x23, '-'),
+    (0x3, 0x1, '['), (0x3, 0x2, ']'),
     (0x12, 0xf, 'a'), (0x12, 0x25, 'b'), (0x12, 0x3d, 'c'),
     (0x12, 0x50, 'd'), (0x12, 0x6b, 'e'), (0x12, 0xa3, 'f'),
     (0x12, 0xb0, 'g'), (0x12, 0xd3, 'h'), (0x12, 0xec, 'i'),
@@ -35,15 +36,10 @@ pub fn encode_char(ch: char) -> (u8, u8) {
 }
 
 pub fn encode_text(text: &str) -> Vec<u8> {
-    let mut res = text.chars()
-        .map(|c| encode_char(c))
-        .collect::<Vec<(u8, u8)>>();
-
-    unsafe {
-        res.set_len(res.len() * 2);
-        std::mem::transmute(res)
-    }
-
+    text.chars()
+        .map(encode_char)
+        .flat_map(|(h, l)| [h, l])
+        .collect::<Vec<u8>>()
 }
 
 #[cfg(test)]
@@ -0,0 +1,613 @@
+use std::collections::BTreeSet;
+
+use crate::calc_bytecode::quoted_text;
+use crate::compile::{calc::canonical_calculation, lexer::KEYWORDS};
+use crate::component::{FMComponentField, FMComponentLayout, FMComponentRelationship, FMComponentScript, FMComponentTable, FMComponentValueList, PrivilegeAccess, RelationComparison, ValueListSource};
+use crate::file::FmpFile;
+use crate::fm_script_engine::fm_script_engine_instructions::{active_steps, Instruction, ScriptStep, STEP_NAMES};
+
+const INDENT: &str = "  ";
+const DATA_TYPES: [&str; 6] = ["Text", "Number", "Date", "Time", "Timestamp", "Container"];
+/* Words with a meaning of their own inside a script block. */
+const SCRIPT_KEYWORDS: [&str; 6] = ["define", "let", "loop", "if", "elif", "else"];
+
+/* Names Burn can't read are written with every other character replaced by
+ * an underscore. An underscore is also added before a leading digit, and
+ * after a keyword. */
+fn identifier(name: &str) -> String {
+    let mut id = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' })
+        .collect::<String>();
+    if id.is_empty() || id.starts_with(|c: char| c.is_ascii_digit()) {
+        id.insert(0, '_');
+    }
+    if KEYWORDS.contains(&id.as_str()) || SCRIPT_KEYWORDS.contains(&id.as_str()) {
+        id.push('_');
+    }
+    id
+}
+
+fn comparison(comparison: &RelationComparison) -> &'static str {
+    match comparison {
+        RelationComparison::Equal => "==",
+        RelationComparison::NotEqual => "!=",
+        RelationComparison::Less => "<",
+        RelationComparison::LessEqual => "<=",
+        RelationComparison::Greater => ">",
+        RelationComparison::GreaterEqual => ">=",
+        RelationComparison::Cartesian => "*",
+    }
+}
+
+fn access(access: PrivilegeAccess) -> &'static str {
+    match access {
+        PrivilegeAccess::FullAccess => "full",
+        PrivilegeAccess::DataEntryOnly => "data_entry",
+        PrivilegeAccess::ReadOnlyAccess => "read_only",
+    }
+}
+
+/* Writes Burn laid out the way `burn_fm fmt` would. */
+struct Printer<'a> {
+    file: &'a FmpFile,
+    out: String,
+    depth: usize,
+    warnings: BTreeSet<String>,
+}
+
+impl<'a> Printer<'a> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn blank(&mut self) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    /* Adds to the last line written. */
+    fn append(&mut self, text: &str) {
+        self.out.pop();
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn warn(&mut self, warning: String) {
+        self.warnings.insert(warning);
+    }
+
+    /* The name as it's written, noting when it had to be changed. */
+    fn name(&mut self, name: &str) -> String {
+        let id = identifier(name);
+        if id != name {
+            self.warn(format!("\"{}\" is written as {}.", name, id));
+        }
+        id
+    }
+
+    fn construct(&mut self, header: &str, items: &[String], keyword: &str) {
+        self.blank();
+        self.line(header);
+        self.depth += 1;
+        for item in items {
+            self.line(item);
+        }
+        self.depth -= 1;
+        self.line(&format!("end {};", keyword));
+    }
+
+    /* Options of a field in the order `burn_fm fmt` sorts them. */
+    fn field_options(&mut self, table: &str, field: &FMComponentField) -> String {
+        let mut options = vec![];
+        if DATA_TYPES.contains(&field.data_type.as_str()) {
+            options.push(field.data_type.clone());
+        } else {
+            self.warn(format!("{}::{} has no data type Burn knows of, it's written as Text.", table, field.field_name));
+            options.push(String::from("Text"));
+        }
+        if !field.field_description.is_empty() {
+            options.push(quoted_text(&field.field_description));
+        }
+        let validation = &field.validation;
+        for (set, option) in [(validation.unique, "Unique"), (validation.required, "Required"), (validation.existing, "Existing")] {
+            if set {
+                options.push(String::from(option));
+            }
+        }
+        /* Validation switched on in a file whose value list, calculation or message wasn't found is left out. */
+        let missing = |option: &Option<String>| option.as_ref().is_some_and(|s| s.is_empty());
+        if let Some(list) = validation.value_list.as_ref().filter(|l| !l.is_empty()) {
+            options.push(format!("foundIn[{}]", self.name(list)));
+        }
+        if let Some(calc) = &field.auto_enter_calc {
+            options.push(format!("{{{}}}", canonical_calculation(calc)));
+        }
+        if let Some(calc) = validation.calculation.as_ref().filter(|c| !c.is_empty()) {
+            options.push(format!("!{{{}}}", canonical_calculation(calc)));
+        }
+        if let Some(message) = validation.message.as_ref().filter(|m| !m.is_empty()) {
+            options.push(format!("!{}", quoted_text(message)));
+        }
+
+        /* Settings only FileMaker can make. */
+        let unwritten = [
+            (field.field_type == "Calculation", "calculated value"),
+            (field.field_type == "Summary", "summary"),
+            (field.global, "global storage"),
+            (field.repetitions > 1, "repetitions"),
+            (field.prohibit_modification, "prohibit modification"),
+            (field.auto_enter_preset.is_some(), "auto-enter preset"),
+            (field.auto_enter_replace, "replaced auto-enter value"),
+            (field.auto_enter_serial.is_some(), "serial number"),
+            (validation.always_validate || !validation.user_override, "validation settings"),
+            (missing(&validation.value_list), "validation value list"),
+            (missing(&validation.calculation), "validation calculation"),
+            (missing(&validation.message), "validation message"),
+        ].iter().filter(|(set, _)| *set).map(|(_, what)| *what).collect::<Vec<_>>();
+        if !unwritten.is_empty() {
+            self.warn(format!("{}::{} is written without its {}.", table, field.field_name, unwritten.join(", ")));
+        }
+        options.join(", ")
+    }
+
+    fn table(&mut self, table: &FMComponentTable) {
+        /* Ids that don't follow the field before are written out, so fields keep theirs. */
+        let mut previous = 0;
+        let mut fields = vec![];
+        for (id, f) in &table.fields {
+            let mut options = self.field_options(&table.table_name, f);
+            if *id as u32 != previous + 1 {
+                options.push_str(&format!(", id {}", id));
+            }
+            previous = *id as u32;
+            fields.push((format!("{}:", self.name(&f.field_name)), options));
+        }
+        /* Options of every field start in the same column. */
+        let width = fields.iter().map(|(name, _)| name.chars().count()).max().unwrap_or(0);
+        let items = fields.iter()
+            .map(|(name, options)| format!("{:width$} {},", name, options, width = width))
+            .collect::<Vec<_>>();
+        let header = format!("table {}:", self.name(&table.table_name));
+        self.construct(&header, &items, "table");
+    }
+
+    fn relationship(&mut self, relationship: &FMComponentRelationship) {
+        let mut items = vec![format!("{}:{},", self.name(&relationship.table1_name), self.name(&relationship.table2_name))];
+        for criterion in &relationship.criteria {
+            items.push(format!("{} {} {},", self.name(&criterion.field1_name), comparison(&criterion.comparison),
+                self.name(&criterion.field2_name)));
+        }
+        if relationship.table1_options.to_byte() != 0 || relationship.table2_options.to_byte() != 0 {
+            self.warn(format!("Relationship {}:{} is written without its options.", relationship.table1_name, relationship.table2_name));
+        }
+        self.construct("relationship:", &items, "relationship");
+    }
+
+    fn value_list(&mut self, list: &FMComponentValueList) {
+        let items = match &list.source {
+            ValueListSource::CustomValues(values) => values.iter().map(|v| format!("{},", quoted_text(v))).collect(),
+            ValueListSource::Field { table_occurrence_name, field_name, .. } =>
+                vec![format!("field: {}::{},", self.name(table_occurrence_name), self.name(field_name))],
+        };
+        let header = format!("value_list {}:", self.name(&list.list_name));
+        self.construct(&header, &items, "value_list");
+    }
+
+    fn layout(&mut self, layout: &FMComponentLayout) {
+        let mut items = vec![format!("table_occurence: {},", self.name(&layout.table_occurrence_name))];
+        for field in &layout.fields {
+            items.push(format!("{},", self.name(&field.field_name)));
+        }
+        let header = format!("layout {}:", self.name(&layout.layout_name));
+        self.construct(&header, &items, "layout");
+    }
+
+    /* A switch naming a script or layout keeps its quotes, if it had any. */
+    fn name_switch(&mut self, switch: &str, names: &[&str]) -> String {
+        let switch = switch.trim();
+        let quoted = switch.strip_prefix('"').and_then(|s| s.strip_suffix('"'));
+        match quoted.unwrap_or(switch) {
+            name if names.contains(&name) && identifier(name) != name => self.name(name),
+            _ => switch.to_string(),
+        }
+    }
+
+    fn step_args(&mut self, step: &ScriptStep) -> String {
+        let mut args = vec![];
+        for (n, switch) in step.switches.iter().enumerate() {
+            let arg = match (&step.opcode, n) {
+                (Instruction::SetVariable, 0) => switch.trim().to_string(),
+                (Instruction::PerformScript, 0) => {
+                    let scripts = self.file.scripts.values().map(|s| s.script_name.as_str()).collect::<Vec<_>>();
+                    self.name_switch(switch, &scripts)
+                },
+                (Instruction::GoToLayout, 0) => {
+                    let layouts = self.file.layouts.values().map(|l| l.layout_name.as_str()).collect::<Vec<_>>();
+                    self.name_switch(switch, &layouts)
+                },
+                (Instruction::SetField, 0) => {
+                    let (occurrence, field) = switch.trim().split_once("::").unwrap();
+                    format!("{}::{}", self.name(occurrence), self.name(field))
+                },
+                _ => canonical_calculation(switch),
+            };
+            args.push(arg);
+        }
+        format!("({})", args.join(", "))
+    }
+
+    /* Comments of more than one line are written as a block, unless they hold
+     * the marker that ends one. Those are written one `//` comment per line. */
+    fn comment(&mut self, text: &str) {
+        let lines = text.lines().collect::<Vec<_>>();
+        match lines.as_slice() {
+            [] => self.line("//"),
+            [line] => self.line(&format!("// {}", line)),
+            lines if text.contains("*/") => {
+                for line in lines {
+                    self.line(format!("// {}", line).trim_end());
+                }
+            },
+            [first, rest @ ..] => {
+                self.line(&format!("/* {}", first));
+                for line in rest {
+                    self.line(format!(" * {}", line).trim_end());
+                }
+                self.append(" */");
+            },
+        }
+    }
+
+    /* Steps are nested back into the blocks their If and Loop steps open. */
+    fn script(&mut self, script: &FMComponentScript) {
+        let name = self.name(&script.script_name);
+        /* "()" is read as one empty argument. */
+        let arguments = script.arguments.iter().filter(|a| !a.is_empty()).map(|a| identifier(a)).collect::<Vec<_>>();
+        self.line(&format!("define {}({}) {{", name, arguments.join(", ")));
+        self.depth += 1;
+        let mut blocks = vec![];
+        let active = active_steps(&script.instructions);
+        for (n, step) in script.instructions.iter().enumerate() {
+            let left_out = |reason: &str| format!("Step {} of {} ({:?}) is left out, {}.", n + 1, script.script_name, step.opcode, reason);
+            if !step.enabled {
+                self.warn(left_out("it's disabled"));
+                continue;
+            }
+            if !active[n] {
+                self.warn(left_out("the block it marks has a disabled step"));
+                continue;
+            }
+            match (&step.opcode, blocks.last()) {
+                (Instruction::If, _) => {
+                    let args = self.step_args(step);
+                    self.line(&format!("if{} {{", args));
+                    blocks.push(Instruction::If);
+                    self.depth += 1;
+                },
+                (Instruction::Loop, _) => {
+                    self.line("loop {");
+                    blocks.push(Instruction::Loop);
+                    self.depth += 1;
+                },
+                (Instruction::ElseIf, Some(Instruction::If)) => {
+                    let args = self.step_args(step);
+                    self.depth -= 1;
+                    self.line(&format!("}} elif{} {{", args));
+                    self.depth += 1;
+                },
+                (Instruction::Else, Some(Instruction::If)) => {
+                    self.depth -= 1;
+                    self.line("} else {");
+                    self.depth += 1;
+                },
+                (Instruction::EndIf, Some(Instruction::If)) | (Instruction::EndLoop, Some(Instruction::Loop)) => {
+                    blocks.pop();
+                    self.depth -= 1;
+                    self.line("}");
+                },
+                (Instruction::ElseIf | Instruction::Else | Instruction::EndIf | Instruction::EndLoop, _) => {
+                    self.warn(left_out("it's outside of the block it belongs to"));
+                },
+                (Instruction::BlankLineComment, _) => {
+                    let text = step.comment.as_deref().unwrap_or_default();
+                    if text.contains("*/") && text.lines().count() > 1 {
+                        self.warn(format!("Step {} of {} is a comment holding \"*/\", it's written as one comment per line.",
+                                n + 1, script.script_name));
+                    }
+                    self.comment(text);
+                },
+                (Instruction::SetField, _) if !step.switches.first().is_some_and(|s| s.contains("::")) => {
+                    self.warn(left_out("its field isn't known"));
+                },
+                (opcode, _) => match STEP_NAMES.iter().find(|(_, op)| op == opcode) {
+                    Some((name, _)) => {
+                        let args = self.step_args(step);
+                        self.line(&format!("{}{};", name, args));
+                    },
+                    None => self.warn(left_out("Burn has no name for it")),
+                },
+            }
+        }
+        /* Blocks the script never closed. */
+        for _ in blocks {
+            self.depth -= 1;
+            self.line("}");
+        }
+        self.depth -= 1;
+        self.line("}");
+    }
+}
+
+/* Writes the tables, occurrences, relationships, value lists, layouts,
+ * privilege sets and scripts of a file as Burn, which compiles back to the
+ * same file. Calculations are printed canonically. Returns warnings for
+ * whatever Burn can't express, which is left out, and for names that had
+ * to be changed. */
+pub fn generate_burn(file: &FmpFile) -> (String, Vec<String>) {
+    let mut printer = Printer { file, out: String::new(), depth: 0, warnings: BTreeSet::new() };
+    for table in file.tables.values() {
+        printer.table(table);
+    }
+    for occurrence in file.table_occurrences.values() {
+        let table = file.tables.get(&(occurrence.table_actual as usize))
+            .map(|t| t.table_name.as_str())
+            .filter(|_| occurrence.table_actual_name.is_empty())
+            .unwrap_or(&occurrence.table_actual_name);
+        if table.is_empty() {
+            printer.warn(format!("Table occurrence {} is left out, its table isn't known.", occurrence.table_occurence_name));
+            continue;
+        }
+        if !file.layouts.values().any(|l| l.table_occurrence_name == occurrence.table_occurence_name) {
+            printer.warn(format!("Table occurrence {} has no layout, compiling gives it one.", occurrence.table_occurence_name));
+        }
+        let items = [format!("table: {},", printer.name(table))];
+        let header = format!("table_occurence {}:", printer.name(&occurrence.table_occurence_name));
+        printer.construct(&header, &items, "table_occurence");
+    }
+    for relationship in file.relationships.values() {
+        printer.relationship(relationship);
+    }
+    for list in file.value_lists.values() {
+        printer.value_list(list);
+    }
+    for layout in file.layouts.values() {
+        printer.layout(layout);
+    }
+    /* Every file has the standard sets. */
+    for set in file.privilege_sets.values().filter(|s| !PrivilegeAccess::STANDARD.iter().any(|a| a.set_name() == s.set_name)) {
+        let mut items = vec![format!("access: {},", access(set.access))];
+        if !set.set_description.is_empty() {
+            items.push(format!("description: {},", quoted_text(&set.set_description)));
+        }
+        let header = format!("privilege_set {}:", printer.name(&set.set_name));
+        printer.construct(&header, &items, "privilege_set");
+    }
+    /* Exported scripts come first, the ones only tests run after them. */
+    for (export, header) in [(true, "script export: ["), (false, "script: [")] {
+        let scripts = file.scripts.values().filter(|s| s.export == export).collect::<Vec<_>>();
+        if scripts.is_empty() {
+            continue;
+        }
+        printer.blank();
+        printer.line(header);
+        printer.depth += 1;
+        for (n, script) in scripts.into_iter().enumerate() {
+            if n > 0 {
+                printer.blank();
+            }
+            printer.script(script);
+        }
+        printer.depth -= 1;
+        printer.line("]");
+    }
+    (printer.out, printer.warnings.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use crate::compile::compiler::compile_burn;
+    use crate::decompile::decompiler::Decompiler;
+    use crate::formatter::format_burn;
+    use super::generate_burn;
+
+    #[test]
+    fn burn_test() {
+        let code = "\
+table Person:
+  name:   Text, \"Full name\", Required, !{name != \"\"}, !\"Needs a name\",
+  id:     Number, Unique, {Get(UUID)},
+  salary: Number, foundIn[Salaries],
+end table;
+
+table Job:
+  person:  Number,
+  started: Date, id 4,
+end table;
+
+table_occurence Person:
+  table: Person,
+end table_occurence;
+
+table_occurence Job:
+  table: Job,
+end table_occurence;
+
+relationship:
+  Person:Job,
+  id == person,
+  salary * person,
+end relationship;
+
+value_list Salaries:
+  \"1\",
+  \"2\",
+end value_list;
+
+value_list People:
+  field: Person::name,
+end value_list;
+
+layout People:
+  table_occurence: Person,
+  name,
+  id,
+end layout;
+
+layout Job:
+  table_occurence: Job,
+  person,
+end layout;
+
+privilege_set Clerks:
+  access: data_entry,
+  description: \"Data entry\",
+end privilege_set;
+
+script export: [
+  define fire() {
+    /* Back to
+     * the list. */
+    go_to_layout(\"People\");
+    //
+  }
+]
+
+script: [
+  define hire(x, y) {
+    // Only some.
+    if(x > y) {
+      exit_script(x);
+    } elif(x == y) {
+      set_field(Person::name, \"a, b\");
+    } else {
+      loop {
+        exit_loop_if(x);
+      }
+    }
+    perform_script(\"fire\");
+  }
+]
+";
+        let file = compile_burn(code);
+        let (generated, warnings) = generate_burn(&file);
+        assert_eq!(generated, code);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(format_burn(&generated).unwrap(), generated);
+        assert_eq!(serde_json::to_string(&compile_burn(&generated)).unwrap(), serde_json::to_string(&file).unwrap());
+
+        /* Calculations are spaced the same way whatever they were written with. */
+        let file = compile_burn("table T: a: Number, {3^2+(- 1*a)}, end table; script: [ define s() { if( a>1){ set_variable(b, a*2 ); } } ]");
+        let (generated, _) = generate_burn(&file);
+        assert!(generated.contains("a: Number, {3 ^ 2 + (-1 * a)},"));
+        assert!(generated.contains("if(a > 1) {\n      set_variable(b, a * 2);\n    }\n"));
+    }
+
+    #[test]
+    fn comment_end_test() {
+        let code = "\
+script: [
+  define notes() {
+    // Ends with */ here.
+    /* Two
+     * lines. */
+  }
+]
+";
+        let mut file = compile_burn(code);
+        let script = file.scripts.values_mut().next().unwrap();
+        script.instructions[1].comment = Some(String::from("Two\nlines, */ inside."));
+        let (generated, warnings) = generate_burn(&file);
+        assert!(generated.contains("    // Ends with */ here.\n    // Two\n    // lines, */ inside.\n"), "{}", generated);
+        assert_eq!(warnings, vec![String::from("Step 2 of notes is a comment holding \"*/\", it's written as one comment per line.")]);
+
+        let comments = compile_burn(&generated).scripts.values().next().unwrap().instructions.iter()
+            .map(|s| s.comment.clone().unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(comments, vec!["Ends with */ here.", "Two", "lines, */ inside."]);
+    }
+
+    #[test]
+    fn string_escape_test() {
+        let code = r#"
+table Part:
+  size: Text, "Width in \"inches\"", !{size != ""}, !"Use C:\\parts\\sizes.txt",
+end table;
+table_occurence Part:
+  table: Part,
+end table_occurence;
+layout Parts:
+  table_occurence: Part,
+  size,
+end layout;
+value_list Sizes:
+  "6\"",
+  "\\",
+end value_list;
+"#;
+        let file = compile_burn(code);
+        let field = &file.tables[&1].fields[&1];
+        assert_eq!(field.field_description, "Width in \"inches\"");
+        assert_eq!(field.validation.message.as_deref(), Some("Use C:\\parts\\sizes.txt"));
+        let (generated, warnings) = generate_burn(&file);
+        assert!(generated.contains(r#""Width in \"inches\"""#), "{}", generated);
+        assert!(generated.contains("  \"6\\\"\",\n  \"\\\\\",\n"), "{}", generated);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+
+        let recompiled = compile_burn(&generated);
+        assert_eq!(recompiled.tables[&1].fields[&1].field_description, field.field_description);
+        assert_eq!(recompiled.tables[&1].fields[&1].validation.message, field.validation.message);
+        assert_eq!(generate_burn(&recompiled).0, generated);
+    }
+
+    #[test]
+    fn disabled_block_test() {
+        let code = "\
+script: [
+  define check(a) {
+    if(a > 1) {
+      set_variable(b, 1);
+    } else {
+      set_variable(b, 2);
+    }
+  }
+]
+";
+        let mut file = compile_burn(code);
+        let script = file.scripts.values_mut().next().unwrap();
+        script.instructions[0].enabled = false;
+        let (generated, warnings) = generate_burn(&file);
+        assert!(generated.contains("define check(a) {\n    set_variable(b, 1);\n    set_variable(b, 2);\n  }"), "{}", generated);
+        assert_eq!(warnings, vec![
+            String::from("Step 1 of check (If) is left out, it's disabled."),
+            String::from("Step 3 of check (Else) is left out, the block it marks has a disabled step."),
+            String::from("Step 5 of check (EndIf) is left out, the block it marks has a disabled step."),
+        ]);
+    }
+
+    #[test]
+    fn decompiled_burn_test() {
+        let path = Path::new("tests/input/blank.fmp12");
+        let buffer = std::fs::read(path).expect("Unable to read file.");
+        let file = Decompiler::new(path).run_on(&buffer);
+        let (generated, warnings) = generate_burn(&file);
+        assert!(warnings.contains(&String::from("\"third table\" is written as third_table.")), "{:?}", warnings);
+        assert!(generated.contains("table_occurence third_table:\n  table: third_table,\nend table_occurence;"));
+        assert!(generated.contains("perform_script(New_Script, \"testing\");"));
+        assert!(generated.contains("define find() {\n    //\n    enter_find_mode();"));
+        assert!(generated.contains("\nscript export: [\n") && !generated.contains("\nscript: [\n"));
+
+        assert!(warnings.contains(&String::from("Table occurrence currencydata has no layout, compiling gives it one.")));
+
+        /* Once the names are ones Burn can read, and every occurrence has a layout, it comes back the same. */
+        let (generated, _) = generate_burn(&compile_burn(&generated));
+        let (regenerated, warnings) = generate_burn(&compile_burn(&generated));
+        assert_eq!(regenerated, generated);
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+}
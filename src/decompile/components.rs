@@ -0,0 +1,106 @@
+use std::ops::{BitOr, BitOrAssign};
+
+/* Set of component kinds to extract when decompiling. Directories that don't
+ * belong to a selected component are skipped while decoding sectors. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Components(u32);
+
+impl Components {
+    pub const NONE: Components = Components(0);
+    /* Tables and their field definitions. */
+    pub const TABLES: Components = Components(1 << 0);
+    /* Table occurrences and the relationships between them. */
+    pub const RELATIONSHIPS: Components = Components(1 << 1);
+    pub const LAYOUTS: Components = Components(1 << 2);
+    pub const SCRIPTS: Components = Components(1 << 3);
+    pub const DATA_SOURCES: Components = Components(1 << 4);
+    /* Record data, including container fields. */
+    pub const RECORDS: Components = Components(1 << 5);
+    pub const VALUE_LISTS: Components = Components(1 << 6);
+    /* Accounts and privilege sets. */
+    pub const SECURITY: Components = Components(1 << 7);
+    pub const ALL: Components = Components((1 << 8) - 1);
+
+    pub fn contains(&self, other: Components) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(&self, other: Components) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /* Returns the component a directory path belongs to, or NONE if the
+     * decompiler doesn't extract anything from it. */
+    pub fn from_path(path: &[String]) -> Components {
+        match path.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+            ["3", "16", ..] => Components::TABLES,
+            ["3", "17", ..] | ["3", "251", ..] => Components::RELATIONSHIPS,
+            ["4", ..] => Components::LAYOUTS,
+            ["17", ..] => Components::SCRIPTS,
+            ["32", ..] => Components::DATA_SOURCES,
+            ["33", ..] => Components::VALUE_LISTS,
+            ["23", ..] => Components::SECURITY,
+            [x, "3", ..] if x.parse::<usize>().unwrap_or(0) >= 128 => Components::TABLES,
+            [x, "5", ..] if x.parse::<usize>().unwrap_or(0) >= 128 => Components::RECORDS,
+            _ => Components::NONE,
+        }
+    }
+
+    /* Returns every component the directory or any directory under it can
+     * belong to, so whole directories can be skipped while decoding. */
+    pub fn under_path(path: &[String]) -> Components {
+        match path.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+            [] => Components::ALL,
+            ["3"] => Components::TABLES | Components::RELATIONSHIPS,
+            [x] if x.parse::<usize>().unwrap_or(0) >= 128 => Components::TABLES | Components::RECORDS,
+            _ => Components::from_path(path),
+        }
+    }
+}
+
+impl BitOr for Components {
+    type Output = Components;
+
+    fn bitor(self, rhs: Components) -> Components {
+        Components(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Components {
+    fn bitor_assign(&mut self, rhs: Components) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Components;
+
+    fn path(p: &[&str]) -> Vec<String> {
+        p.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn selection_test() {
+        let selected = Components::SCRIPTS | Components::TABLES;
+        assert!(selected.contains(Components::SCRIPTS));
+        assert!(!selected.contains(Components::LAYOUTS));
+        assert!(Components::ALL.contains(selected));
+
+        assert_eq!(Components::from_path(&path(&["17", "5", "2"])), Components::SCRIPTS);
+        assert_eq!(Components::from_path(&path(&["130", "3", "5", "1"])), Components::TABLES);
+        assert_eq!(Components::from_path(&path(&["130", "5", "1"])), Components::RECORDS);
+        assert_eq!(Components::from_path(&path(&["3", "17", "5", "0"])), Components::RELATIONSHIPS);
+        assert_eq!(Components::from_path(&path(&["33", "5", "1"])), Components::VALUE_LISTS);
+        assert_eq!(Components::from_path(&path(&["23", "2", "5", "1"])), Components::SECURITY);
+        assert_eq!(Components::from_path(&path(&["6", "5"])), Components::NONE);
+        assert!(!selected.intersects(Components::from_path(&path(&["4", "1", "7"]))));
+
+        /* Directories above a component can't be skipped. */
+        assert_eq!(Components::under_path(&path(&[])), Components::ALL);
+        assert_eq!(Components::under_path(&path(&["3"])), Components::TABLES | Components::RELATIONSHIPS);
+        assert_eq!(Components::under_path(&path(&["130"])), Components::TABLES | Components::RECORDS);
+        assert_eq!(Components::under_path(&path(&["17", "5"])), Components::SCRIPTS);
+        assert_eq!(Components::under_path(&path(&["6"])), Components::NONE);
+    }
+}
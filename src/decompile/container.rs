@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, write};
+use std::path::Path;
+
+use crate::component::FMComponentContainer;
+use crate::file::FmpFile;
+
+/* Stream tags found inside a container directory. None of this layout, the
+ * FORK tag included, has been checked against a file FileMaker wrote, as no
+ * fixture holds a container. See "Container Fields" in doc/fmp_format.md. */
+pub const STREAM_FILE_NAME: &str = "FNAM";
+pub const STREAM_SIZE: &str = "SIZE";
+pub const STREAM_FILE: &str = "FILE";
+/* Holds the path of a container stored by reference. */
+pub const STREAM_REFERENCE: &str = "FORK";
+
+/* Image and document streams. When a FILE stream is present these hold the
+ * preview thumbnail, otherwise the first one found is the main data. */
+const PREVIEW_STREAMS: [&str; 6] = ["JPEG", "PNGf", "GIFf", "BMPf", "TIFF", "PDF "];
+
+/* Prefixes used by FileMaker for containers stored by reference. */
+const REFERENCE_PREFIXES: [&str; 10] = [
+    "file:", "filemac:", "filewin:",
+    "image:", "imagemac:", "imagewin:",
+    "movie:", "moviemac:", "moviewin:",
+    "remote:",
+];
+
+/* A single stream as it is being read from the sector chain. Small streams
+ * are stored inline at key 1, larger ones are split into data segments. */
+#[derive(Debug, Clone)]
+pub struct ContainerStream {
+    pub tag: String,
+    pub segments: BTreeMap<usize, Vec<u8>>,
+}
+
+impl ContainerStream {
+    pub fn new() -> Self {
+        Self {
+            tag: String::new(),
+            segments: BTreeMap::new(),
+        }
+    }
+}
+
+pub fn main_stream(container: &FMComponentContainer) -> Option<&Vec<u8>> {
+    if let Some(file) = container.streams.get(STREAM_FILE) {
+        return Some(file);
+    }
+    PREVIEW_STREAMS.iter()
+        .find_map(|tag| container.streams.get(*tag))
+}
+
+pub fn thumbnail_stream(container: &FMComponentContainer) -> Option<&Vec<u8>> {
+    if !container.streams.contains_key(STREAM_FILE) {
+        return None;
+    }
+    PREVIEW_STREAMS.iter()
+        .find_map(|tag| container.streams.get(*tag))
+}
+
+fn external_reference(bytes: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(bytes);
+    text.lines()
+        .map(|l| l.trim())
+        .find(|l| REFERENCE_PREFIXES.iter().any(|p| l.starts_with(p)))
+        .map(|l| l.to_string())
+}
+
+/* Assembles the collected streams and fills in the summary fields of the container. */
+pub fn finalize_container(container: &mut FMComponentContainer, streams: Vec<ContainerStream>) {
+    for stream in streams {
+        let data = stream.segments.into_values().flatten().collect::<Vec<u8>>();
+        container.streams.insert(stream.tag, data);
+    }
+
+    if let Some(name) = container.streams.get(STREAM_FILE_NAME) {
+        container.file_name = String::from_utf8_lossy(name)
+            .trim_end_matches('\0')
+            .to_string();
+    }
+
+    if let Some(size) = container.streams.get(STREAM_SIZE) {
+        if size.len() >= 4 {
+            let height = u16::from_be_bytes([size[0], size[1]]);
+            let width = u16::from_be_bytes([size[2], size[3]]);
+            container.dimensions = Some((width, height));
+        }
+    }
+
+    /* Only the reference stream is read, embedded text files can hold lines
+     * that look like paths. */
+    container.external_path = container.streams.get(STREAM_REFERENCE)
+        .and_then(|s| external_reference(s));
+
+    container.size = main_stream(container).map(|s| s.len()).unwrap_or(0);
+    if container.size == 0 {
+        if let Some(path) = &container.external_path {
+            if container.file_name.is_empty() {
+                container.file_name = path.rsplit('/').next().unwrap_or("").to_string();
+            }
+        }
+    }
+}
+
+/* Turns a name from the file into a single path component, so that no
+ * table, field or file name can lead outside the export directory. */
+fn path_component(name: &str, fallback: &str) -> String {
+    let name = name.replace(['/', '\\', '\0'], "_");
+    match name.as_str() {
+        "" => fallback.to_string(),
+        "." | ".." => name.replace('.', "_"),
+        _ => name,
+    }
+}
+
+/* Writes every embedded container to dir/table/field/recordid/filename.
+ * Externally stored containers have no data in the file and are skipped.
+ * Returns the number of files written. */
+pub fn export_containers(file: &FmpFile, dir: &Path) -> Result<usize, String> {
+    let mut written = 0;
+    for (table_id, records) in &file.records {
+        let table = file.tables.get(table_id);
+        let table_name = path_component(table.map(|t| t.table_name.as_str()).unwrap_or(""), &table_id.to_string());
+
+        for (record_id, record) in records {
+            for (field_id, container) in &record.containers {
+                let data = match main_stream(container) {
+                    Some(d) if container.external_path.is_none() => d,
+                    _ => continue,
+                };
+                let field_name = table
+                    .and_then(|t| t.fields.get(field_id))
+                    .map(|f| f.field_name.as_str())
+                    .unwrap_or("");
+                let field_name = path_component(field_name, &field_id.to_string());
+                let file_name = path_component(&container.file_name, "container.bin");
+
+                let out_dir = dir.join(&table_name)
+                    .join(&field_name)
+                    .join(record_id.to_string());
+                let path = out_dir.join(file_name);
+                create_dir_all(&out_dir)
+                    .and_then(|_| write(&path, data))
+                    .map_err(|e| format!("Unable to write {}. {}", path.display(), e))?;
+                written += 1;
+            }
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(tag: &str, data: &[u8]) -> ContainerStream {
+        let mut s = ContainerStream::new();
+        s.tag = tag.to_string();
+        s.segments.insert(0, data.to_vec());
+        s
+    }
+
+    #[test]
+    fn embedded_container_test() {
+        let mut container = FMComponentContainer::new();
+        let mut file = stream(STREAM_FILE, &[1, 2, 3]);
+        file.segments.insert(1, vec![4, 5]);
+        finalize_container(&mut container, vec![
+            stream(STREAM_FILE_NAME, b"photo.jpg"),
+            stream(STREAM_SIZE, &[0, 10, 0, 20]),
+            file,
+            stream("JPEG", &[9, 9]),
+        ]);
+        assert_eq!(container.file_name, "photo.jpg");
+        assert_eq!(container.size, 5);
+        assert_eq!(container.dimensions, Some((20, 10)));
+        assert_eq!(container.external_path, None);
+        assert_eq!(main_stream(&container), Some(&vec![1, 2, 3, 4, 5]));
+        assert_eq!(thumbnail_stream(&container), Some(&vec![9, 9]));
+    }
+
+    #[test]
+    fn external_container_test() {
+        let mut container = FMComponentContainer::new();
+        finalize_container(&mut container, vec![
+            stream("FORK", b"imagewin:/C:/images/logo.png\nimagemac:/HD/images/logo.png"),
+        ]);
+        assert_eq!(container.external_path, Some("imagewin:/C:/images/logo.png".to_string()));
+        assert_eq!(container.file_name, "logo.png");
+        assert_eq!(container.size, 0);
+
+        /* Paths in embedded files aren't references. */
+        let mut container = FMComponentContainer::new();
+        finalize_container(&mut container, vec![
+            stream(STREAM_FILE_NAME, b"notes.txt"),
+            stream(STREAM_FILE, b"see\nfile:/etc/passwd"),
+        ]);
+        assert_eq!(container.external_path, None);
+        assert_eq!(container.size, 20);
+    }
+
+    #[test]
+    fn path_component_test() {
+        assert_eq!(path_component("../../etc", "1"), ".._.._etc");
+        assert_eq!(path_component("..", "1"), "__");
+        assert_eq!(path_component("a\\b/c", "1"), "a_b_c");
+        assert_eq!(path_component("", "container.bin"), "container.bin");
+        assert_eq!(path_component("photo.jpg", "1"), "photo.jpg");
+    }
+}
@@ -1,141 +1,125 @@
 use std::char::decode_utf16;
 use std::fs::{File, write};
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::iter::Peekable;
+use std::slice::Iter;
+
+use crate::calc_bytecode::*;
 
 use crate::component::{FMComponentDataSource, RelationComparison, SourceFileLocation};
-use crate::fm_script_engine::fm_script_engine_instructions::{ScriptStep, INSTRUCTIONMAP, Instruction};
+use crate::fm_script_engine::fm_script_engine_instructions::{ScriptStep, INSTRUCTIONMAP, Instruction, STEP_DISABLED_FLAG, STEP_FLAGS_OFFSET, STEP_OPCODE_OFFSET, STEP_RECORD_SIZE};
 use crate::{chunk, component, dbcharconv, decompile, metadata_constants};
 use crate::file::FmpFile;
 use crate::decompile::sector;
+use crate::decompile::components::Components;
+use crate::compile::diagnostic::Span;
+use crate::decompile::container::{finalize_container, ContainerStream};
 
-use crate::chunk::{get_chunk_from_code, ChunkType};
+use crate::chunk::{get_chunk_from_code, skip_chunk, ChunkType};
 use crate::encoding_util::{fm_string_decrypt, get_int, get_path_int};
 
 const SECTOR_SIZE : usize = 4096;
 
-fn decompile_calculation(bytecode: &[u8]) -> String {
+fn read_calc_text(it: &mut Peekable<Iter<u8>>) -> String {
+    let n = it.next().copied().unwrap_or(0) as usize;
+    let bytes = it.by_ref().take(n).copied().collect::<Vec<u8>>();
+    /* Strings are terminated by a zero byte. */
+    it.next_if_eq(&&0);
+    fm_string_decrypt(&bytes)
+}
+
+fn decompile_field_reference(bytes: &[u8], file: &FmpFile) -> String {
+    if bytes.len() < 6 {
+        return String::new();
+    }
+    let occurrence = get_int(&bytes[0..2]);
+    let table = get_int(&bytes[2..4]);
+    let field = get_int(&bytes[4..6]) as u16;
+    let name = file.tables.get(&table)
+        .and_then(|t| t.fields.get(&field))
+        .map(|f| f.field_name.clone())
+        .unwrap_or(field.to_string());
+    match file.table_occurrences.get(&occurrence) {
+        Some(to) => format!("{}::{}", to.table_occurence_name, name),
+        None => name,
+    }
+}
+
+/* Decodes the infix form of a calculation, see `calc_bytecode`. */
+fn decompile_calculation(bytecode: &[u8], file: &FmpFile) -> String {
     let mut it = bytecode.iter().peekable();
     let mut result = String::new();
-    let mut in_get = false;
 
     while let Some(c) = it.next() {
-        match c {
-            0x4 => {
-                result.push('(');
-            }
-            0x5 => {
-                result.push(')');
-            }
-            0x2d => {
-                result.push_str("Abs");
-            }
-            0x9b => {
-                result.push_str("Get");
-            }
-            0x9c => {
-                match it.next().unwrap() {
-                    0x1d => {
-                        result.push_str("CurrentTime");
-                    }
-                    0x20 => {
-                        result.push_str("AccountName");
-                    }
-                    0x49 => {
-                        result.push_str("DocumentsPath");
-                    }
-                    0x5d => {
-                        result.push_str("DocumentsPathListing");
-                    }
-                    _ => {}
-                }
-            }
-            0x9d => {
-                result.push_str("Acos");
-            }
-            0xfb => {
-                match it.next().unwrap() {
-                    0x3 => { result.push_str("Char")}
-                    _ => eprintln!("unrecognized intrinsic.")
-                }
-            }
-            0x10 => {
-                /* decode number */
-                for i in 0..19 {
-                    let cur = it.next();
-                    if i == 8 {
-                        result.push_str(&cur.unwrap().to_string());
-                    }
+        match *c {
+            CALC_OPEN_PAREN => result.push('('),
+            CALC_CLOSE_PAREN => result.push(')'),
+            CALC_SEPARATOR => result.push(';'),
+            CALC_NEGATE => result.push('-'),
+            CALC_WHITESPACE => {
+                if it.next_if_eq(&&CALC_STRING).is_some() {
+                    result.push_str(&read_calc_text(&mut it));
+                } else {
+                    result.push(' ');
                 }
             },
-            0x13 => {
-                /* Processing String */
-                let n = it.next();
-                let mut s = String::new();
-                for i in 1..=*n.unwrap() as usize {
-                    s.push(*it.next().unwrap() as char);
+            CALC_NUMBER => {
+                let block = it.by_ref().take(CALC_NUMBER_LEN).copied().collect::<Vec<u8>>();
+                result.push_str(&number_text(&block));
+                if it.next_if_eq(&&CALC_NUMBER_END[0]).is_some() {
+                    it.next_if_eq(&&CALC_NUMBER_END[1]);
                 }
-                let mut text = String::new();
-                text.push('"');
-                text.push_str(&fm_string_decrypt(s.as_bytes()));
-                text.push('"');
-
-                result.push_str(&text);
-            }
-            0x1a => {
-                /* decode variable */
-                let n = it.next();
-                let mut name_arr = String::new();
-                for i in 1..=*n.unwrap() as usize {
-                    name_arr.push(*it.next().unwrap() as char);
+            },
+            CALC_STRING => result.push_str(&quoted_text(&read_calc_text(&mut it))),
+            CALC_VARIABLE => {
+                let n = it.next().copied().unwrap_or(0) as usize;
+                let name = it.by_ref().take(n).copied().collect::<Vec<u8>>();
+                result.push_str(&fm_string_decrypt(&name));
+            },
+            CALC_FIELD => {
+                let bytes = it.by_ref().take(6).copied().collect::<Vec<u8>>();
+                result.push_str(&decompile_field_reference(&bytes, file));
+            },
+            CALC_GET_SELECTOR => {
+                let code = it.next().copied().unwrap_or(0);
+                match get_function_name(code) {
+                    Some(name) => result.push_str(name),
+                    None => eprintln!("unrecognized Get() option: {:x}", code),
                 }
-                let name = fm_string_decrypt(name_arr.as_bytes());
-                result.push_str(&name);
             },
-            0x25 => {
-                result.push('+');
-            }
-            0x26 => {
-                result.push('-');
-            }
-            0x27 => {
-                result.push('*');
-            }
-            0x28 => {
-                result.push('/');
+            CALC_EXTENDED_FUNCTION => {
+                let code = [*c, it.next().copied().unwrap_or(0)];
+                match function_name(&code) {
+                    Some(name) => result.push_str(name),
+                    None => eprintln!("unrecognized intrinsic."),
+                }
             },
-            0x41 => {
-                result.push('<');
-            }
-            0x43 => {
-                result.push_str("<=");
-            }
-            0x44 => {
-                result.push_str("==");
-            }
-            0x46 => {
-                result.push_str("!=");
-            }
-            0x47 => {
-                result.push_str(">=");
-            }
-            0x49 => {
-                result.push('>');
-            }
-            0x50 => {
-                result.push('&');
-            }
-            0xC => {
-                result.push(' ');
-            }
-            _ => {
-
+            c => {
+                if let Some(op) = operator_name(c) {
+                    result.push_str(op);
+                } else if let Some(name) = function_name(&[c]) {
+                    result.push_str(name);
+                }
             }
         }
+    }
+    result
+}
 
+/* Reads the kind and the top, left, bottom and right edges of a layout object. */
+fn decode_layout_geometry(data: &[u8]) -> Option<(u8, [u16; 4])> {
+    if data.len() < 42 || data[0] != 8 {
+        return None;
+    }
+    let mut edges = [0; 4];
+    for (n, edge) in edges.iter_mut().enumerate() {
+        let start = 6 + n * 9;
+        let value = f64::from_le_bytes(data[start + 1..start + 9].try_into().ok()?);
+        *edge = value as u16;
     }
-    return result;
+    Some((data[1], edges))
 }
 
 fn print_chunk(chunk: &chunk::Chunk, path: &Vec<String>) {
@@ -211,577 +195,883 @@ fn print_chunk(chunk: &chunk::Chunk, path: &Vec<String>) {
     }
 }
 
-pub fn decompile_fmp12_file_with_header(path: &Path) -> FmpFile {
+pub fn decompile_fmp12_file_with_header(path: &Path, components: Components) -> FmpFile {
     let mut file = File::open(path).expect("unable to open file.");
     let mut buffer = Vec::<u8>::new();
     file.read_to_end(&mut buffer).expect("Unable to read file.");
 
-    write("header.log", buffer);
-    decompile_fmp12_file(path)
+    write("header.log", &buffer);
+    Decompiler::new(path).with(components).run_on(&buffer)
 }
 
 pub fn decompile_fmp12_file(path: &Path) -> FmpFile {
-    let mut file = File::open(path).expect("unable to open file.");
-    let mut fmp_file = FmpFile::new();
-    let mut buffer = Vec::<u8>::new();
-    file.read_to_end(&mut buffer).expect("Unable to read file.");
+    Decompiler::new(path).with(Components::ALL).run()
+}
 
-    let mut offset = SECTOR_SIZE;
-    let mut sectors = Vec::<sector::Sector>::new();
+/* Walks the sector chain starting at sector 2 and returns the sector indexes in order. */
+pub(crate) fn get_sector_chain(buffer: &[u8]) -> Vec<usize> {
+    let mut chain = Vec::<usize>::new();
+    let n_sectors = buffer.len() / SECTOR_SIZE;
+    let mut visited = vec![false; n_sectors];
+    let mut idx = 2;
+    while idx != 0 && idx < n_sectors && !visited[idx] {
+        visited[idx] = true;
+        chain.push(idx);
+        idx = sector::get_sector(&buffer[idx * SECTOR_SIZE..]).next;
+    }
+    chain
+}
 
-    let first = sector::get_sector(&buffer[offset..]);
-    // println!("{:?} == {}", &buffer[offset+8..offset+12], first.next);
-    let n_blocks = first.next;
+/* Decodes the fixed size step records of a script. */
+fn decode_script_steps(fmp_file: &FmpFile, bytes: &[u8]) -> Vec<ScriptStep> {
+    let mut steps = vec![];
+    for ins in bytes.chunks(STEP_RECORD_SIZE) {
+        if ins.len() < STEP_RECORD_SIZE {
+            continue;
+        }
+        let op = match &INSTRUCTIONMAP[ins[STEP_OPCODE_OFFSET] as usize] {
+            Some(op) => op.clone(),
+            None => continue,
+        };
+        let mut switches = vec![];
+        if op == Instruction::PerformScript {
+            if let Some(script) = fmp_file.scripts.get(&(ins[8] as usize)) {
+                switches.push(script.script_name.clone());
+            }
+        }
+        steps.push(ScriptStep {
+            opcode: op,
+            index: get_path_int(&ins[2..ins[0] as usize + 1]),
+            enabled: ins[STEP_FLAGS_OFFSET] & STEP_DISABLED_FLAG == 0,
+            comment: None,
+            switches,
+            span: Span::default(),
+            switch_spans: vec![],
+        });
+    }
+    steps
+}
 
-    sectors.resize(n_blocks + 1, sector::Sector { 
-        deleted: false, 
-        level: 0,
-        previous: 0,
-        next: 0,
-        payload: &[0],
-        chunks: vec![] 
-    });
+fn find_step<'a>(fmp_file: &'a mut FmpFile, script: &str, step: &str) -> Option<&'a mut ScriptStep> {
+    let step = step.parse::<usize>().ok()?;
+    fmp_file.scripts.get_mut(&script.parse().ok()?)?
+        .instructions.iter_mut()
+        .find(|s| s.index == step)
+}
 
-    sectors[0] = first;
-    let mut idx = 2;
-    let mut script_segments: HashMap<usize, BTreeMap<usize, Vec<u8>>> = HashMap::new();
-
-    let mut data_source_names = VecDeque::<String>::new();
-
-
-    while idx != 0 {
-        let start = idx * SECTOR_SIZE;
-        let bound = start + SECTOR_SIZE;
-        offset = start;
-
-        sectors[idx] = sector::get_sector(&buffer[offset..]);
-        // println!("sector: {}, next: {}", idx, sectors[idx].next);
-        // println!("{:?} == {}", &buffer[offset+8..offset+12], sectors[idx].next);
-        let mut path = Vec::<String>::new();
-        offset += 20;
-        while offset < bound {
-            let chunk = get_chunk_from_code(&buffer, 
-                                            &mut offset, 
-                                            &mut path, 
-                                            start).expect("Unable to decode chunk.");
-
-            // print_chunk(&chunk, &path);
-            match &path.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
-                /* Examining relatinoships of table occurences */
-                ["3", "17", "5", "0", "251"] => {
-                    if chunk.ctype == ChunkType::DataSimple {
-                        let mut tmp = component::FMComponentRelationship::new();
-                        tmp.table1 = fmp_file.table_occurrences.len() as u16;
-                        tmp.table2 = chunk.data.unwrap()[2] as u16;
-                        if fmp_file.relationships.iter()
-                            .filter(|x| x.1.table1 == tmp.table2 && x.1.table2 == tmp.table1)
-                            .collect::<Vec<_>>().len() == 0 {
-                            fmp_file.relationships.insert(fmp_file.relationships.len() + 1, tmp);
-                        }
-                    }
-                },
-                /* Examining table occurences */
-                ["3", "17", "5", "0", ..] => {
-                    let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                    match chunk.ref_simple {
-                        Some(2) => {
-                            let tmp = component::FMComponentTableOccurence {
-                                table_occurence_name: String::new(),
-                                create_by_user: String::new(),
-                                created_by_account: String::new(),
-                                table_actual: chunk.data.unwrap()[6] as u16,
-                                table_actual_name: String::new(),
-                            };
-
-                            let source = chunk.data.unwrap()[2];
-                            println!("{:?}", source);
-                            fmp_file.table_occurrences.insert(fmp_file.table_occurrences.len() + 1, tmp);
-                        }
-                        Some(16) => {
-                            fmp_file.table_occurrences
-                                .get_mut(&(fmp_file.table_occurrences.len())).unwrap()
-                                .table_occurence_name = s;
-                        },
-                        Some(129) => {
-                        },
-                        Some(130) => {
-                        },
-                        Some(131) => {
-                        },
-                        _ => {
-                            let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                            if s.is_empty() {
-                                continue;
-                            }
-                            if chunk.ctype == ChunkType::PathPush 
-                                || chunk.ctype == ChunkType::PathPop 
-                                || chunk.ctype == ChunkType::Noop {
+/* Decodes the chunks of a single sector. The directory path starts out empty
+ * for each sector, so sectors can be decoded independently of each other. */
+pub(crate) fn decode_sector(buffer: &[u8], idx: usize, components: Components) -> sector::Sector<'_> {
+    let start = idx * SECTOR_SIZE;
+    let bound = start + SECTOR_SIZE;
+    let mut sector = sector::get_sector(&buffer[start..]);
+    let mut path = Vec::<String>::new();
+    let mut offset = start + 20;
+    while offset < bound {
+        /* Chunks in directories holding nothing selected are only stepped over. */
+        if !components.intersects(Components::under_path(&path)) {
+            skip_chunk(buffer, &mut offset, &mut path, start).expect("Unable to decode chunk.");
+            continue;
+        }
+        let chunk = get_chunk_from_code(buffer,
+                                        &mut offset,
+                                        &mut path,
+                                        start).expect("Unable to decode chunk.");
+        if components.intersects(Components::from_path(&chunk.path)) {
+            sector.chunks.push(chunk);
+        }
+    }
+    sector
+}
 
-                            } else {
-                            }
-                        }
-                    }
-                },
-                ["3", "251", "5", x, "3"] => {
-                    match chunk.ref_simple {
-                        Some(1) => {
-                            let comp = match chunk.data.unwrap()[0] {
-                                0x0 => RelationComparison::Equal,
-                                0x1 => RelationComparison::NotEqual,
-                                0x2 => RelationComparison::Less,
-                                0x3 => RelationComparison::LessEqual,
-                                0x4 => RelationComparison::Greater,
-                                0x5 => RelationComparison::GreaterEqual,
-                                0x6 => RelationComparison::Cartesian,
-                                _ => RelationComparison::Equal
-                            };
-                            let field1 = get_path_int(&chunk.data.unwrap()[2..=3]) - 128;
-                            let field2 = get_path_int(&chunk.data.unwrap()[5..=6]) - 128;
-                            let handle = fmp_file.relationships.get_mut(&x.parse().unwrap());
-                            if handle.is_some() {
-                                let handle_uw = handle.unwrap();
-                                handle_uw.comparison = comp;
-                                handle_uw.field1 = field1 as u16;
-                                handle_uw.field2 = field2 as u16;
-                            }
+pub struct Decompiler {
+    path: PathBuf,
+    components: Components,
+}
+
+impl Decompiler {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            components: Components::ALL,
+        }
+    }
+
+    /* Restricts decompilation to the given components. */
+    pub fn with(mut self, components: Components) -> Self {
+        self.components = components;
+        self
+    }
+
+    /* Decodes the selected sectors across threads, then interprets the chunks in chain order. */
+    fn decode_sectors<'a>(&self, buffer: &'a [u8]) -> Vec<sector::Sector<'a>> {
+        let chain = get_sector_chain(buffer);
+        let n_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let per_thread = chain.len().div_ceil(n_threads).max(1);
+        let components = self.components;
+
+        std::thread::scope(|scope| {
+            let handles = chain.chunks(per_thread)
+                .map(|ids| scope.spawn(move || {
+                    ids.iter()
+                        .map(|idx| decode_sector(buffer, *idx, components))
+                        .collect::<Vec<_>>()
+                }))
+                .collect::<Vec<_>>();
+            handles.into_iter()
+                .flat_map(|h| h.join().expect("Sector decoding thread panicked."))
+                .collect()
+        })
+    }
+
+    pub fn run(&self) -> FmpFile {
+        let mut file = File::open(&self.path).expect("unable to open file.");
+        let mut buffer = Vec::<u8>::new();
+        file.read_to_end(&mut buffer).expect("Unable to read file.");
+        self.run_on(&buffer)
+    }
+
+    /* Decompiles a file already read into memory. */
+    pub fn run_on(&self, buffer: &[u8]) -> FmpFile {
+        let mut fmp_file = FmpFile::new();
+        let sectors = self.decode_sectors(buffer);
+        let mut script_segments: HashMap<usize, BTreeMap<usize, Vec<u8>>> = HashMap::new();
+
+        let mut container_streams = BTreeMap::<(usize, usize, u16, usize), ContainerStream>::new();
+        let mut current_occurrence = 0;
+        let mut field_calculations = Vec::<(usize, u16, &str, &[u8])>::new();
+        let mut field_value_lists = Vec::<(usize, u16, usize)>::new();
+        let mut layout_objects = BTreeMap::<(usize, usize), (Option<(u8, [u16; 4])>, Option<u16>)>::new();
+        let mut value_list_sources = BTreeMap::<usize, (Vec<u8>, Option<(u16, u16)>)>::new();
+
+        for sector in &sectors {
+            for chunk in &sector.chunks {
+                let path = &chunk.path;
+                // print_chunk(chunk, path);
+                match &path.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+                    /* Examining table occurences. Each one is pushed as [208, hi, lo],
+                     * which doesn't map onto a path integer, so the id is taken from the
+                     * push data itself. */
+                    ["3", "17", "5", "0"] if chunk.ctype == ChunkType::PathPush => {
+                        let data = chunk.data.unwrap();
+                        if data.len() == 3 {
+                            current_occurrence = get_int(&data[1..]);
+                            fmp_file.table_occurrences.entry(current_occurrence)
+                                .or_insert(component::FMComponentTableOccurence::new());
                         }
-                        _ => {}
-                    }
-                },
-                ["4", "5", ..] => {
-                    let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                },
-                /* Examing layouts */
-                ["4", "1", "7", x, ..] => {
-                    if chunk.ctype == ChunkType::PathPush {
-                        fmp_file.layouts.insert(
-                            x.parse::<usize>().unwrap(),
-                            component::FMComponentLayout::new()
-                            );
-                        continue;
-                    } else {
+                    },
+                    ["3", "17", "5", "0"] => {
+                        let handle = match fmp_file.table_occurrences.get_mut(&current_occurrence) {
+                            Some(h) => h,
+                            None => continue,
+                        };
                         match chunk.ref_simple {
                             Some(2) => {
-                                /* Byte 2 refers to table occurrence */
-                                let layout_handle = fmp_file.layouts.get_mut(&x.parse().unwrap());
-                                if layout_handle.is_none() {
-                                } else {
-                                    let occurrence = chunk.data.unwrap()[1] as usize - 128;
-                                    fmp_file.layouts.get_mut(&x.parse().unwrap())
-                                        .unwrap().table_occurrence = occurrence;
-                                }
+                                handle.table_actual = chunk.data.unwrap()[6] as u16;
                             }
                             Some(16) => {
-                                let layout_handle = fmp_file.layouts.get_mut(&x.parse().unwrap());
-                                if layout_handle.is_none() {
-                                } else {
-                                    let s = fm_string_decrypt(chunk.data.unwrap());
-                                    fmp_file.layouts.get_mut(&x.parse().unwrap())
-                                        .unwrap().layout_name = s;
-                                }
-
+                                handle.table_occurence_name = fm_string_decrypt(chunk.data.unwrap());
                             },
-                            _ => {
-
-                            }
+                            Some(129) => {
+                                handle.created_by_account = fm_string_decrypt(chunk.data.unwrap());
+                            },
+                            Some(130) => {
+                                handle.create_by_user = fm_string_decrypt(chunk.data.unwrap());
+                            },
+                            _ => {}
                         }
-                    }
-                }
-                [x, "3", "5", y] => {
-                    if x.parse::<usize>().unwrap() >= 128 {
+                    },
+                    /* Examining relationships. Key 2 holds a reference to the occurrence
+                     * on each side followed by the options for each side. */
+                    ["3", "251", "5", x] => {
                         if chunk.ctype == ChunkType::PathPush {
-                            if !fmp_file.tables.contains_key(&(x.parse::<usize>().unwrap() - 128)) {
-                                fmp_file.tables.insert(x.parse::<usize>().unwrap() - 128,
-                            component::FMComponentTable::new());
+                            fmp_file.relationships.entry(x.parse().unwrap())
+                                .or_insert(component::FMComponentRelationship::new());
+                            continue;
+                        }
+                        let handle = match fmp_file.relationships.get_mut(&x.parse().unwrap()) {
+                            Some(h) => h,
+                            None => continue,
+                        };
+                        if chunk.ref_simple == Some(2) {
+                            let data = chunk.data.unwrap();
+                            if data.len() >= 12 {
+                                handle.table1 = get_int(&data[3..5]) as u16;
+                                handle.table2 = get_int(&data[8..10]) as u16;
+                                handle.table1_options = component::FMRelationSideOptions::from_byte(data[10]);
+                                handle.table2_options = component::FMRelationSideOptions::from_byte(data[11]);
                             }
-                            fmp_file.tables.get_mut(&(x.parse::<usize>().unwrap() - 128))
-                                .unwrap().fields
-                                    .insert(y.parse::<usize>().unwrap() as u16, 
-                                            component::FMComponentField::new());
-                        } else {
-                            let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                            // match chunk.ref_simple.unwrap_or(0) {
-                            //     0 => {},
-                            //     2 => { println!("Data type: {:?}", match chunk.data.unwrap_or(&[0])[1] {
-                            //         1 => "Text",
-                            //         2 => "Number",
-                            //         3 => "Date",
-                            //         4 => "Time",
-                            //         5 => "Timestamp",
-                            //         6 => "Container",
-                            //         _ => "Unknown"
-                            //
-                            //     }); },
-                            //     3 => { println!("Description: {:?}", s); },
-                            //     16 => { println!("Field Name: {}", s); }
-                            //     129 => { println!("created by user: {}", s); }
-                            //     130 => { println!("created by user Account: {}", s); }
-                            //     _   => { println!("instr: {:x}. ref: {:?}, data: {:?}", chunk.code, chunk.ref_simple, chunk.data.unwrap()); }
-                            // };
-                            let tidx = x.parse::<usize>().unwrap() - 128;
-                            match chunk.ref_simple.unwrap_or(0) {
-                                metadata_constants::FIELD_TYPE => {
-                                    fmp_file.tables.get_mut(&tidx)
-                                        .unwrap().fields
-                                        .get_mut(&(y.parse::<usize>().unwrap() as u16))
-                                        .unwrap()
-                                        .field_type = s
-                                },
-                                metadata_constants::COMPONENT_DESC => {
-                                    fmp_file.tables.get_mut(&tidx)
-                                        .unwrap().fields
-                                        .get_mut(&(y.parse::<usize>().unwrap() as u16))
-                                        .unwrap()
-                                        .field_description = s
-                                },
-                                metadata_constants::COMPONENT_NAME => {
-                                    fmp_file.tables.get_mut(&tidx).unwrap()
-                                        .fields
-                                        .get_mut(&(y.parse::<usize>().unwrap() as u16))
-                                        .unwrap()
-                                        .field_name = s
-                                },
-                                metadata_constants::CREATOR_ACCOUNT_NAME => { 
-                                    fmp_file.tables.get_mut(&tidx).unwrap()
-                                        .fields
-                                        .get_mut(&(y.parse::<usize>().unwrap() as u16))
-                                        .unwrap()
-                                        .created_by_account = s 
-                                },
-                                metadata_constants::CREATOR_USER_NAME => {
-                                    fmp_file.tables.get_mut(&tidx).unwrap()
-                                        .fields
-                                        .get_mut(&(y.parse::<usize>().unwrap() as u16))
-                                        .unwrap()
-                                        .created_by_user = s 
-                                },
-                                _ => {},
-                            };
                         }
-                    }
-
-                },
-                ["3", "16", "1", "1"] => {
-                    if chunk.ref_data.is_some() {
-                        let s = chunk.ref_data;
-                    }
-                }
-                /* Examining metadata for table */
-                ["3", "16", "5", x] => {
-                    let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                    if chunk.ctype == ChunkType::PathPush {
-                        if !fmp_file.tables.contains_key(&x.parse().unwrap()) {
-                            fmp_file.tables.insert(x.parse::<usize>().unwrap() - 128, component::FMComponentTable::new());
-                        } 
-                    } else {
-                        match chunk.ref_simple.unwrap_or(0) {
-                            metadata_constants::COMPONENT_NAME => { 
-                                fmp_file.tables.get_mut(&(x.parse::<usize>().unwrap() - 128)).unwrap().table_name = s },
+                    },
+                    /* Each key in this directory is one criterion of the relationship. */
+                    ["3", "251", "5", x, "3"] if chunk.ref_simple.is_some() => {
+                        let data = chunk.data.unwrap();
+                        if data.len() < 7 {
+                            continue;
+                        }
+                        let mut criterion = component::FMRelationCriterion::new();
+                        criterion.comparison = RelationComparison::from_byte(data[0]);
+                        criterion.field1 = (get_path_int(&data[2..=3]) - 128) as u16;
+                        criterion.field2 = (get_path_int(&data[5..=6]) - 128) as u16;
+                        if let Some(handle) = fmp_file.relationships.get_mut(&x.parse().unwrap()) {
+                            handle.criteria.push(criterion);
+                        }
+                    },
+                    /* Fields placed on a layout, key 2 holds their position and key 4 the field. */
+                    ["4", "5", x, "13", "3", n] if chunk.ref_simple.is_some() => {
+                        let object = layout_objects.entry((x.parse().unwrap(), n.parse().unwrap())).or_default();
+                        let data = chunk.data.unwrap_or(&[]);
+                        match chunk.ref_simple {
+                            Some(2) => object.0 = decode_layout_geometry(data),
+                            Some(4) if data.len() == 2 => object.1 = Some(get_int(data) as u16),
                             _ => {}
                         }
-                    }
-                },
-                /* Examining script code */
-                ["17", "5", x, "4"] => {
-                    if chunk.ctype == ChunkType::PathPush {
-                        let script = script_segments.get(&x.parse().unwrap());
-                        if script.is_none() {
-                            script_segments.insert(x.parse().unwrap(), BTreeMap::new());
+                    },
+                    /* Examining value lists */
+                    ["33", "1", "7", x] if chunk.ctype != ChunkType::PathPush => {
+                        let handle = fmp_file.value_lists.entry(x.parse().unwrap())
+                            .or_insert(component::FMComponentValueList::new());
+                        let s = fm_string_decrypt(chunk.data.unwrap_or(&[]));
+                        match chunk.ref_simple {
+                            Some(16) => handle.list_name = s,
+                            Some(129) => handle.created_by_account = s,
+                            Some(130) => handle.create_by_user = s,
+                            _ => {}
                         }
-                        continue;
-                    } else if chunk.ctype == ChunkType::DataSegment {
-                        let n = chunk.segment_idx.unwrap() as usize;
-                        script_segments.get_mut(&x.parse().unwrap())
-                            .unwrap()
-                            .insert(n, chunk.data.unwrap().to_vec());
-                    }
-                },
-                ["17", "5", script, "5", step, "128", "5"] => {
-                    let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                    match chunk.ref_simple.unwrap_or(0).to_string().as_str() {
-                        "5" => {
-                            let instrs = &mut fmp_file.scripts.get_mut(&script.parse().unwrap()).unwrap().instructions;
-                            let instruction_idx = instrs
-                                .iter()
-                                .enumerate()
-                                .filter(|x| x.1.index == step.parse::<usize>().unwrap())
-                                .collect::<Vec<_>>()[0].0;
-
-                            // println!("Searching for {step}. instructions for script {script} == {}", instrs.len());
-
-                            match instrs.get(instruction_idx).unwrap().opcode {
-                                Instruction::SetVariable => {
-                                    instrs.get_mut(instruction_idx).unwrap().switches.push(s);
-                                },
-                                Instruction::ExitScript => {
-                                    let bytecode = decompile_calculation(chunk.data.unwrap());
-                                    instrs.get_mut(instruction_idx).unwrap().switches.push(bytecode);
+                    },
+                    ["33", "5", x] if chunk.ctype != ChunkType::PathPush => {
+                        let values = value_list_sources.entry(x.parse().unwrap()).or_default();
+                        let data = chunk.data.unwrap_or(&[]);
+                        match chunk.ref_simple {
+                            Some(4) if data.len() == 7 => {
+                                values.1 = Some((get_int(&data[3..5]) as u16, get_int(&data[5..7]) as u16));
+                            },
+                            Some(7) => values.0.extend(data),
+                            _ => {}
+                        }
+                    },
+                    ["33", "5", x, "7"] if chunk.ctype == ChunkType::DataSegment => {
+                        value_list_sources.entry(x.parse().unwrap()).or_default()
+                            .0.extend(chunk.data.unwrap_or(&[]));
+                    },
+                    ["23", "2", "5", x] => {
+                        let handle = fmp_file.privilege_sets.entry(x.parse().unwrap())
+                            .or_insert(component::FMComponentPrivilegeSet::new());
+                        let data = chunk.data.unwrap_or(&[0]);
+                        let s = fm_string_decrypt(data);
+                        match chunk.ref_simple {
+                            /* Key 10 follows key 2 and only marks full access. */
+                            Some(2) => handle.access = component::PrivilegeAccess::from_flags(data[0], 0),
+                            Some(10) if component::PrivilegeAccess::from_flags(0, data[0]) == component::PrivilegeAccess::FullAccess => {
+                                handle.access = component::PrivilegeAccess::FullAccess;
+                            },
+                            Some(3) => handle.set_description = s,
+                            Some(16) => handle.set_name = s,
+                            Some(129) => handle.created_by_account = s,
+                            Some(130) => handle.create_by_user = s,
+                            _ => {}
+                        }
+                    },
+                    ["4", "5", ..] => {
+                        let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
+                    },
+                    /* Examing layouts */
+                    ["4", "1", "7", x, ..] => {
+                        if chunk.ctype == ChunkType::PathPush {
+                            fmp_file.layouts.insert(
+                                x.parse::<usize>().unwrap(),
+                                component::FMComponentLayout::new()
+                                );
+                            continue;
+                        } else {
+                            match chunk.ref_simple {
+                                Some(2) => {
+                                    /* Byte 2 refers to table occurrence */
+                                    let layout_handle = fmp_file.layouts.get_mut(&x.parse().unwrap());
+                                    if layout_handle.is_none() {
+                                    } else {
+                                        let occurrence = chunk.data.unwrap()[1] as usize - 128;
+                                        fmp_file.layouts.get_mut(&x.parse().unwrap())
+                                            .unwrap().table_occurrence = occurrence;
+                                    }
+                                }
+                                Some(16) => {
+                                    let layout_handle = fmp_file.layouts.get_mut(&x.parse().unwrap());
+                                    if layout_handle.is_none() {
+                                    } else {
+                                        let s = fm_string_decrypt(chunk.data.unwrap());
+                                        fmp_file.layouts.get_mut(&x.parse().unwrap())
+                                            .unwrap().layout_name = s;
+                                    }
+
                                 },
                                 _ => {
 
                                 }
                             }
-
-                            for i in &mut *instrs {
-                                // println!("{:?}", i);
-                            }
-                        },
-                        _ => {
                         }
                     }
-                },
-                ["17", "5", script, "5", step, "128"] => {
-                    let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                    // println!("step type: {:?}", fmp_file.scripts.get(&script.parse().unwrap()).unwrap());
-                    match chunk.ref_simple.unwrap_or(0).to_string().as_str() {
-                        "1" => {
-                            // println!("Found variable: {}", s);
-                            // let script = &mut fmp_file.scripts.get_mut(&x.parse().unwrap()).unwrap().clone();
-                            // let instrs = &mut fmp_file.scripts.get_mut(&script.parse().unwrap()).unwrap().instructions;
-                            // let mut step = &mut fmp_file.scripts.get_mut(&x.parse().unwrap()).unwrap()
-                            //     .instructions.get_mut(&y.parse().unwrap());
-
-                            let instr = &mut fmp_file.scripts
-                                .get_mut(&script.parse().unwrap()).unwrap()
-                                .instructions.iter_mut()
-                                .filter(|x| x.index == step.parse::<usize>().unwrap())
-                                .collect::<Vec<_>>()[0];
-
-                            // println!("Searching for {step}. instructions for script {script}");
-
-                            match instr.opcode {
-                                Instruction::SetVariable => {
-                                    instr.switches.push(s);
-                                },
-                                _ => {}
+                    /* Auto-enter calculation of a field, or its validation calculation. */
+                    [x, "3", "5", y, dir @ ("5" | "6")] if chunk.ref_simple == Some(5) => {
+                        let tidx = match x.parse::<usize>() {
+                            Ok(t) if t >= 128 => t - 128,
+                            _ => continue,
+                        };
+                        /* Decoded once all field names are known. */
+                        field_calculations.push((tidx, y.parse::<u16>().unwrap(), dir, chunk.data.unwrap()));
+                    },
+                    [x, "3", "5", y] => {
+                        if x.parse::<usize>().unwrap() >= 128 {
+                            if chunk.ctype == ChunkType::PathPush {
+                                if !fmp_file.tables.contains_key(&(x.parse::<usize>().unwrap() - 128)) {
+                                    fmp_file.tables.insert(x.parse::<usize>().unwrap() - 128,
+                                component::FMComponentTable::new());
+                                }
+                                /* The directory is pushed again when a field continues in the next sector. */
+                                fmp_file.tables.get_mut(&(x.parse::<usize>().unwrap() - 128))
+                                    .unwrap().fields
+                                        .entry(y.parse::<usize>().unwrap() as u16)
+                                        .or_insert(component::FMComponentField::new());
+                            } else {
+                                let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
+                                // match chunk.ref_simple.unwrap_or(0) {
+                                //     0 => {},
+                                //     2 => { println!("Data type: {:?}", match chunk.data.unwrap_or(&[0])[1] {
+                                //         1 => "Text",
+                                //         2 => "Number",
+                                //         3 => "Date",
+                                //         4 => "Time",
+                                //         5 => "Timestamp",
+                                //         6 => "Container",
+                                //         _ => "Unknown"
+                                //
+                                //     }); },
+                                //     3 => { println!("Description: {:?}", s); },
+                                //     16 => { println!("Field Name: {}", s); }
+                                //     129 => { println!("created by user: {}", s); }
+                                //     130 => { println!("created by user Account: {}", s); }
+                                //     _   => { println!("instr: {:x}. ref: {:?}, data: {:?}", chunk.code, chunk.ref_simple, chunk.data.unwrap()); }
+                                // };
+                                let tidx = x.parse::<usize>().unwrap() - 128;
+                                match chunk.ref_simple.unwrap_or(0) {
+                                    metadata_constants::FIELD_TYPE => {
+                                        fmp_file.tables.get_mut(&tidx)
+                                            .unwrap().fields
+                                            .get_mut(&(y.parse::<usize>().unwrap() as u16))
+                                            .unwrap()
+                                            .set_options(chunk.data.unwrap_or(&[]));
+                                    },
+                                    metadata_constants::COMPONENT_DESC => {
+                                        fmp_file.tables.get_mut(&tidx)
+                                            .unwrap().fields
+                                            .get_mut(&(y.parse::<usize>().unwrap() as u16))
+                                            .unwrap()
+                                            .field_description = s
+                                    },
+                                    /* Only meaningful once the options have switched on a serial number. */
+                                    metadata_constants::FIELD_SERIAL_NEXT | metadata_constants::FIELD_SERIAL_INCREMENT => {
+                                        let serial = fmp_file.tables.get_mut(&tidx).unwrap()
+                                            .fields
+                                            .get_mut(&(y.parse::<usize>().unwrap() as u16))
+                                            .unwrap()
+                                            .auto_enter_serial.as_mut();
+                                        if let (Some(serial), Ok(n)) = (serial, s.parse::<u64>()) {
+                                            if chunk.ref_simple == Some(metadata_constants::FIELD_SERIAL_NEXT) {
+                                                serial.next_value = n;
+                                            } else {
+                                                serial.increment = n;
+                                            }
+                                        }
+                                    },
+                                    /* Resolved to a name once the value lists are read. */
+                                    metadata_constants::FIELD_VALUE_LIST => {
+                                        if let [hi, lo] = chunk.data.unwrap_or(&[]) {
+                                            field_value_lists.push((tidx, y.parse::<u16>().unwrap(),
+                                                u16::from_be_bytes([*hi, *lo]) as usize));
+                                        }
+                                    },
+                                    metadata_constants::FIELD_VALIDATION_MESSAGE => {
+                                        let validation = &mut fmp_file.tables.get_mut(&tidx).unwrap()
+                                            .fields
+                                            .get_mut(&(y.parse::<usize>().unwrap() as u16))
+                                            .unwrap()
+                                            .validation;
+                                        if validation.message.is_some() {
+                                            validation.message = Some(s);
+                                        }
+                                    },
+                                    metadata_constants::COMPONENT_NAME => {
+                                        fmp_file.tables.get_mut(&tidx).unwrap()
+                                            .fields
+                                            .get_mut(&(y.parse::<usize>().unwrap() as u16))
+                                            .unwrap()
+                                            .field_name = s
+                                    },
+                                    metadata_constants::CREATOR_ACCOUNT_NAME => { 
+                                        fmp_file.tables.get_mut(&tidx).unwrap()
+                                            .fields
+                                            .get_mut(&(y.parse::<usize>().unwrap() as u16))
+                                            .unwrap()
+                                            .created_by_account = s 
+                                    },
+                                    metadata_constants::CREATOR_USER_NAME => {
+                                        fmp_file.tables.get_mut(&tidx).unwrap()
+                                            .fields
+                                            .get_mut(&(y.parse::<usize>().unwrap() as u16))
+                                            .unwrap()
+                                            .created_by_user = s 
+                                    },
+                                    _ => {},
+                                };
                             }
-                        },
-                        _ => {
                         }
-                    }
-                },
-                /* Examining script data */
-                ["17", "5", script, "5", step, "129", "5"] => {
-                    let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                    match chunk.ref_simple.unwrap_or(0).to_string().as_str() {
-                        "5" => {
-                            let calc = decompile_calculation(chunk.data.unwrap());
-                            let instr = &mut fmp_file.scripts.get_mut(&script.parse().unwrap()).unwrap()
-                                .instructions.iter_mut().filter(|x| x.index == step.parse::<usize>().unwrap())
-                                .collect::<Vec<_>>()[0];
-                                instr.switches.push(calc);
-                        },
-                        _ => {
 
+                    },
+                    ["3", "16", "1", "1"] => {
+                        if chunk.ref_data.is_some() {
+                            let s = chunk.ref_data;
                         }
                     }
-                    let instrs = &mut fmp_file.scripts.get_mut(&script.parse().unwrap()).unwrap().instructions;
-                    // for i in instrs {
-                    //     println!("{:?}", i);
-                    // }
-                },
-                ["17", "5", x, ..] => {
-                    if chunk.ctype == ChunkType::PathPop 
-                        || chunk.ctype == ChunkType::PathPush {
-                        continue;
-                    }
-
-                    if chunk.segment_idx == Some(4) {
-                            let instrs = chunk.data.unwrap().chunks(28);
-                            for (i, ins) in instrs.enumerate() {
-                                if ins.len() >= 21 {
-                                let oc = &INSTRUCTIONMAP[ins[21] as usize];
-                                if oc.is_some() {
-                                    let mut switch: Vec<String> = vec![];
-                                    match oc {
-                                        Some(Instruction::PerformScript) => {
-                                            switch.push(
-                                                fmp_file.scripts.get(&(ins[8] as usize)).unwrap().script_name.clone()
-                                            );
-                                        },
-                                        _ => {}
-                                    }
-                                    let n = crate::encoding_util::get_path_int(&ins[2..ins[0] as usize + 1]);
-                                    let tmp = ScriptStep {
-                                        opcode: oc.clone().unwrap(),
-                                        index: n,
-                                        switches: switch,
-                                    };
-                                    let handle = &mut fmp_file.scripts
-                                        .get_mut(&x.parse().unwrap()).unwrap().instructions;
-                                        handle.insert(n, tmp);
-                                    }
-                                }
-                            }
-                    } else {
+                    /* Examining metadata for table */
+                    ["3", "16", "5", x] => {
                         let s = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                        match &chunk.ref_simple {
-                            Some(4) => {
-                                let instrs = chunk.data.unwrap().chunks(28);
-                                for ins in instrs {
-                                    if ins.len() >= 21 {
-                                    let oc = &INSTRUCTIONMAP[ins[21] as usize];
-                                    if oc.is_some() {
-                                        let mut switch: Vec<String> = vec![];
-                                        match oc {
-                                            Some(Instruction::PerformScript) => {
-                                                switch.push(
-                                                    fmp_file.scripts.get(&(ins[8] as usize)).unwrap().script_name.clone()
-                                                );
-                                            },
-                                            _ => {}
-                                        }
-                                        let n = crate::encoding_util::get_path_int(&ins[2..ins[0] as usize + 1]);
-                                        let tmp = ScriptStep {
-                                            opcode: oc.clone().unwrap(),
-                                            index: n,
-                                            switches: switch,
-                                        };
-                                        let handle = &mut fmp_file.scripts
-                                            .get_mut(&x.parse().unwrap()).unwrap().instructions;
-                                            handle.push(tmp);
-                                        }
-                                    }
-                                }
-                            },
-                            None => {
-                            },
-                            _ => {
-                            },
+                        if chunk.ctype == ChunkType::PathPush {
+                            if !fmp_file.tables.contains_key(&x.parse().unwrap()) {
+                                fmp_file.tables.insert(x.parse::<usize>().unwrap() - 128, component::FMComponentTable::new());
+                            } 
+                        } else {
+                            match chunk.ref_simple.unwrap_or(0) {
+                                metadata_constants::COMPONENT_NAME => { 
+                                    fmp_file.tables.get_mut(&(x.parse::<usize>().unwrap() - 128)).unwrap().table_name = s },
+                                _ => {}
+                            }
                         }
-                    }
-
-                },
-                /* Examining script metadata */
-                ["17", "1", x, y, ..] => {
-                    if chunk.ctype == ChunkType::PathPush 
-                        || chunk.ctype == ChunkType::PathPop {
-                        continue;
-                    }
-
-                    if chunk.ctype == ChunkType::RefSimple {
-                        match chunk.ref_simple {
-                            Some(16) => {
-                                let handle = fmp_file.scripts.get_mut(&y.parse().unwrap());
-                                if handle.is_none() {
-                                    let tmp = component::FMComponentScript {
-                                        script_name: fm_string_decrypt(chunk.data.unwrap()),
-                                        instructions: vec![],
-                                        create_by_user: String::new(),
-                                        arguments: Vec::new(),
-                                        created_by_account: String::new(),
-                                    };
-                                    let res = fmp_file.scripts.insert(y.parse().unwrap(), tmp);
-                                } else {
-                                    handle.unwrap().script_name = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
-                                }
+                    },
+                    /* Examining script code */
+                    ["17", "5", x, "4"] => {
+                        if chunk.ctype == ChunkType::PathPush {
+                            script_segments.entry(x.parse().unwrap()).or_default();
+                        } else if chunk.ctype == ChunkType::DataSegment {
+                            let n = chunk.segment_idx.unwrap() as usize;
+                            script_segments.get_mut(&x.parse().unwrap())
+                                .unwrap()
+                                .insert(n, chunk.data.unwrap().to_vec());
+                        }
+                    },
+                    /* Step parameters follow the step records, so segmented records
+                     * have to be decoded before the first parameter is read. */
+                    ["17", "5", x, "5"] if chunk.ctype == ChunkType::PathPush => {
+                        if let Some(segments) = script_segments.remove(&x.parse().unwrap()) {
+                            let bytes = segments.into_values().flatten().collect::<Vec<u8>>();
+                            let mut steps = decode_script_steps(&fmp_file, &bytes);
+                            if let Some(script) = fmp_file.scripts.get_mut(&x.parse().unwrap()) {
+                                script.instructions.append(&mut steps);
+                            }
+                        }
+                    },
+                    ["17", "5", script, "5", step, "128", "5"] => {
+                        if chunk.ref_simple != Some(5) {
+                            continue;
+                        }
+                        let calc = decompile_calculation(chunk.data.unwrap(), &fmp_file);
+                        let instr = match find_step(&mut fmp_file, script, step) {
+                            Some(i) => i,
+                            None => continue,
+                        };
+                        match instr.opcode {
+                            Instruction::SetVariable => {
+                                instr.switches.push(fm_string_decrypt(chunk.data.unwrap()));
                             },
                             _ => {
-                                // println!("{}", fm_string_decrypt(chunk.data.unwrap()));
+                                instr.switches.push(calc);
                             }
                         }
-                    }
-                    // if chunk.ref_simple == Some(16) {
-                    // } else {
-                    // }
-                },
-                /* Variable length encoded names of data sources. */
-                ["32", "1", "1"] => {
-                    match chunk.ctype {
-                        ChunkType::DataSimple => {
-                            let s = dbcharconv::decode_bytes(chunk.data.unwrap());
-                            data_source_names.push_back(s);
+                    },
+                    ["17", "5", script, "5", step, "128"] => {
+                        if chunk.ref_simple != Some(1) {
+                            continue;
                         }
-                        _ => {}
-                    }
-                },
-                /* Identifiers for data sources. */
-                ["32", "1", "3"] => {
-                    match chunk.ctype {
-                        ChunkType::DataSimple => {
-                        },
-                        _ => {}
-                    }
-                }
-                /* Storage for data sources, including path */
-                ["32", "5", ds, ..] => {
-                    match chunk.ctype {
-                        ChunkType::PathPush => {
-                            let idx = get_int(chunk.data.unwrap());
-                            let mut tmp = FMComponentDataSource::new();
-                            let front = data_source_names.pop_front();
-                            if front.is_some() {
-                                tmp.source_name = front.unwrap();
-                                fmp_file.data_sources.insert(idx, tmp);
+                        let s = fm_string_decrypt(chunk.data.unwrap());
+                        let instr = match find_step(&mut fmp_file, script, step) {
+                            Some(i) => i,
+                            None => continue,
+                        };
+                        match instr.opcode {
+                            Instruction::SetVariable => {
+                                instr.switches.push(s);
+                            },
+                            Instruction::BlankLineComment | Instruction::CommentedOut => {
+                                /* Comments use carriage returns between lines. */
+                                instr.comment = Some(s.replace('\r', "\n"));
+                            },
+                            _ => {}
+                        }
+                    },
+                    /* Examining script data */
+                    ["17", "5", script, "5", step, param, "5"] => {
+                        if chunk.ref_simple != Some(5) {
+                            continue;
+                        }
+                        let calc = decompile_calculation(chunk.data.unwrap(), &fmp_file);
+                        let instr = match find_step(&mut fmp_file, script, step) {
+                            Some(i) => i,
+                            None => continue,
+                        };
+                        /* The third parameter of Set Variable is its repetition. */
+                        if instr.opcode == Instruction::SetVariable && *param != "129" {
+                            continue;
+                        }
+                        instr.switches.push(calc);
+                    },
+                    ["17", "5", x, ..] => {
+                        if chunk.ctype == ChunkType::PathPop 
+                            || chunk.ctype == ChunkType::PathPush {
+                            continue;
+                        }
+
+                        if chunk.segment_idx == Some(4) || chunk.ref_simple == Some(4) {
+                            let mut steps = decode_script_steps(&fmp_file, chunk.data.unwrap());
+                            if let Some(script) = fmp_file.scripts.get_mut(&x.parse().unwrap()) {
+                                script.instructions.append(&mut steps);
                             }
                         }
-                        ChunkType::RefSimple => {
-                            match chunk.ref_simple.unwrap() {
-                                130 => {
-                                    if chunk.code == 0x6 {
-                                        /* Path is stored here */
-                                        let tmp = SourceFileLocation::from_bytes(chunk.data.unwrap());
-                                        let handle = fmp_file.data_sources.get_mut(&ds.parse::<usize>().unwrap());
-                                        if handle.is_some() {
-                                            handle.unwrap().source_location = tmp;
-                                        }
+                    },
+                    /* Examining script metadata */
+                    ["17", "1", x, y, ..] => {
+                        if chunk.ctype == ChunkType::PathPush 
+                            || chunk.ctype == ChunkType::PathPop {
+                            continue;
+                        }
 
+                        if chunk.ctype == ChunkType::RefSimple {
+                            match chunk.ref_simple {
+                                Some(16) => {
+                                    let handle = fmp_file.scripts.get_mut(&y.parse().unwrap());
+                                    if handle.is_none() {
+                                        let tmp = component::FMComponentScript {
+                                            script_name: fm_string_decrypt(chunk.data.unwrap()),
+                                            instructions: vec![],
+                                            create_by_user: String::new(),
+                                            arguments: Vec::new(),
+                                            created_by_account: String::new(),
+                                            export: true,
+                                            span: Span::default(),
+                                        };
+                                        let res = fmp_file.scripts.insert(y.parse().unwrap(), tmp);
                                     } else {
-                                        let s = fm_string_decrypt(chunk.data.unwrap());
-                                        let handle = fmp_file.data_sources.get_mut(&ds.parse::<usize>().unwrap());
-                                        if handle.is_some() {
-                                            handle.unwrap().created_by_user = s;
-                                        }
+                                        handle.unwrap().script_name = fm_string_decrypt(chunk.data.unwrap_or(&[0]));
                                     }
                                 },
-                                _ => {}
+                                _ => {
+                                    // println!("{}", fm_string_decrypt(chunk.data.unwrap()));
+                                }
                             }
                         }
-                        _ => {}
+                        // if chunk.ref_simple == Some(16) {
+                        // } else {
+                        // }
+                    },
+                    /* Data sources, with the location of the file at key 130. The creator's
+                     * user name is also key 130, written with a two byte key. */
+                    ["32", "5", ds] if chunk.ctype != ChunkType::PathPush => {
+                        let handle = fmp_file.data_sources.entry(ds.parse().unwrap())
+                            .or_insert(FMComponentDataSource::new());
+                        let data = chunk.data.unwrap_or(&[]);
+                        match (chunk.ref_simple, chunk.code) {
+                            (Some(16), _) => handle.source_name = fm_string_decrypt(data),
+                            (Some(130), 0x6) => handle.source_location = SourceFileLocation::from_bytes(data),
+                            (Some(129), _) => handle.created_by_account = fm_string_decrypt(data),
+                            (Some(130), _) => handle.created_by_user = fm_string_decrypt(data),
+                            _ => {}
+                        }
+                    },
+                    /* Examining record data. Container fields are stored as a directory per field. */
+                    [x, "5", record, field, stream, ..] if x.parse::<usize>().unwrap_or(0) >= 128 => {
+                        let key = (x.parse::<usize>().unwrap() - 128,
+                            record.parse::<usize>().unwrap(),
+                            field.parse::<u16>().unwrap(),
+                            stream.parse::<usize>().unwrap());
+                        let handle = container_streams.entry(key).or_insert(ContainerStream::new());
+                        match chunk.ctype {
+                            ChunkType::RefSimple => {
+                                match chunk.ref_simple {
+                                    Some(0) => {
+                                        handle.tag = String::from_utf8_lossy(chunk.data.unwrap()).to_string();
+                                    },
+                                    Some(1) => {
+                                        handle.segments.insert(0, chunk.data.unwrap().to_vec());
+                                    },
+                                    _ => {}
+                                }
+                            },
+                            ChunkType::DataSegment => {
+                                handle.segments.insert(chunk.segment_idx.unwrap() as usize, chunk.data.unwrap().to_vec());
+                            },
+                            _ => {}
+                        }
+                    },
+                    [x, "5", record, field] if x.parse::<usize>().unwrap_or(0) >= 128 => {
+                        if chunk.ctype == ChunkType::PathPush {
+                            fmp_file.records.entry(x.parse::<usize>().unwrap() - 128).or_default()
+                                .entry(record.parse().unwrap()).or_insert(component::FMComponentRecord::new())
+                                .containers.insert(field.parse().unwrap(), component::FMComponentContainer::new());
+                        }
+                    },
+                    [x, "5", record] if x.parse::<usize>().unwrap_or(0) >= 128 => {
+                        let handle = fmp_file.records.entry(x.parse::<usize>().unwrap() - 128).or_default()
+                            .entry(record.parse().unwrap()).or_insert(component::FMComponentRecord::new());
+                        if chunk.ctype == ChunkType::RefSimple {
+                            match chunk.ref_simple {
+                                /* Record metadata rather than field data. */
+                                Some(252) | None => {},
+                                Some(field) => {
+                                    handle.fields.insert(field, fm_string_decrypt(chunk.data.unwrap_or(&[0])));
+                                }
+                            }
+                        }
+                    },
+                    _ => { 
                     }
                 }
-                _ => { 
+            }
+        }
+        /* Assemble container streams */
+        let mut grouped = BTreeMap::<(usize, usize, u16), Vec<ContainerStream>>::new();
+        for ((table, record, field, _), stream) in container_streams {
+            grouped.entry((table, record, field)).or_default().push(stream);
+        }
+        for ((table, record, field), streams) in grouped {
+            let handle = fmp_file.records.get_mut(&table)
+                .and_then(|t| t.get_mut(&record))
+                .and_then(|r| r.containers.get_mut(&field));
+            if let Some(container) = handle {
+                finalize_container(container, streams);
+            }
+        }
+
+        for (table, field, dir, bytecode) in field_calculations {
+            let calc = decompile_calculation(bytecode, &fmp_file);
+            let field = fmp_file.tables.get_mut(&table)
+                .and_then(|t| t.fields.get_mut(&field));
+            if let Some(field) = field {
+                let handle = match dir {
+                    "5" => &mut field.auto_enter_calc,
+                    _ => &mut field.validation.calculation,
+                };
+                /* Only set when the options enable the calculation. */
+                if handle.is_some() {
+                    *handle = Some(calc);
                 }
             }
         }
-        idx = sectors[idx].next;
-    }
-    /* Assemble scripts */
-    for (script, segments) in &mut script_segments {
-        let mut instructions = Vec::<u8>::new();
-        for s in segments {
-            instructions.append(s.1);
+        for (table, field, list) in field_value_lists {
+            let name = fmp_file.value_lists.get(&list).map(|vl| vl.list_name.clone());
+            let field = fmp_file.tables.get_mut(&table)
+                .and_then(|t| t.fields.get_mut(&field));
+            if let (Some(field), Some(name)) = (field, name) {
+                if field.validation.value_list.is_some() {
+                    field.validation.value_list = Some(name);
+                }
+            }
+        }
+
+        /* Resolve names referenced by relationships */
+        for rel in fmp_file.relationships.values_mut() {
+            let occurrence1 = fmp_file.table_occurrences.get(&(rel.table1 as usize));
+            let occurrence2 = fmp_file.table_occurrences.get(&(rel.table2 as usize));
+            rel.table1_name = occurrence1.map(|o| o.table_occurence_name.clone()).unwrap_or_default();
+            rel.table2_name = occurrence2.map(|o| o.table_occurence_name.clone()).unwrap_or_default();
+            let table1 = occurrence1.and_then(|o| fmp_file.tables.get(&(o.table_actual as usize)));
+            let table2 = occurrence2.and_then(|o| fmp_file.tables.get(&(o.table_actual as usize)));
+            for criterion in &mut rel.criteria {
+                criterion.field1_name = table1.and_then(|t| t.fields.get(&criterion.field1))
+                    .map(|f| f.field_name.clone()).unwrap_or_default();
+                criterion.field2_name = table2.and_then(|t| t.fields.get(&criterion.field2))
+                    .map(|f| f.field_name.clone()).unwrap_or_default();
+            }
         }
 
-        for instr in instructions.chunks(28) {
-            if instr.len() < 28 {
+        /* Resolve the fields placed on layouts, and the fields value lists take their values from. */
+        let occurrence_table = |occurrence: usize| fmp_file.table_occurrences.get(&occurrence)
+            .and_then(|o| fmp_file.tables.get(&(o.table_actual as usize)));
+        let field_name = |occurrence: usize, field: u16| occurrence_table(occurrence)
+            .and_then(|t| t.fields.get(&field))
+            .map(|f| f.field_name.clone())
+            .unwrap_or_default();
+        for ((layout, _), object) in layout_objects {
+            let (Some((_, [top, left, bottom, right])), Some(field)) = object else {
                 continue;
+            };
+            if let Some(handle) = fmp_file.layouts.get_mut(&layout) {
+                let mut placed = component::FMLayoutField::new();
+                placed.field = field;
+                placed.field_name = field_name(handle.table_occurrence, field);
+                placed.top = top;
+                placed.left = left;
+                placed.height = bottom.saturating_sub(top);
+                placed.width = right.saturating_sub(left);
+                handle.fields.push(placed);
             }
-            let op = &INSTRUCTIONMAP[instr[21] as usize];
-            if op.is_some() {
-                let mut switch: Vec<String> = vec![];
-                match op {
-                    Some(Instruction::PerformScript) => {
-                        switch.push(fmp_file.scripts.get(&(instr[8] as usize)).unwrap().script_name.clone());
-                    },
+        }
+        for layout in fmp_file.layouts.values_mut() {
+            layout.table_occurrence_name = fmp_file.table_occurrences.get(&layout.table_occurrence)
+                .map(|o| o.table_occurence_name.clone())
+                .unwrap_or_default();
+        }
+        for (id, (values, field)) in value_list_sources {
+            let source = match field {
+                Some((occurrence, field)) => component::ValueListSource::Field {
+                    table_occurrence: occurrence,
+                    table_occurrence_name: fmp_file.table_occurrences.get(&(occurrence as usize))
+                        .map(|o| o.table_occurence_name.clone())
+                        .unwrap_or_default(),
+                    field,
+                    field_name: field_name(occurrence as usize, field),
+                },
+                None if values.is_empty() => component::ValueListSource::CustomValues(vec![]),
+                None => component::ValueListSource::CustomValues(
+                    fm_string_decrypt(&values).split('\r').map(String::from).collect()),
+            };
+            if let Some(handle) = fmp_file.value_lists.get_mut(&id) {
+                handle.source = source;
+            }
+        }
+
+        /* Assemble scripts that had no step parameters */
+        for (script, segments) in script_segments {
+            let bytes = segments.into_values().flatten().collect::<Vec<u8>>();
+            let mut steps = decode_script_steps(&fmp_file, &bytes);
+            if let Some(handle) = fmp_file.scripts.get_mut(&script) {
+                handle.instructions.append(&mut steps);
+            }
+        }
+        // for script in &fmp_file.scripts {
+        //     println!("{:?}", script.1.script_name);
+        //     for step in &script.1.instructions {
+        //         println!("{:?}", step);
+        //     }
+        // }
+        return fmp_file;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use crate::compile::assembler::Assembler;
+    use crate::compile::calc::compile_calculation;
+    use crate::compile::compiler::compile_burn;
+    use crate::encoding_util::fm_string_decrypt;
+    use crate::fm_script_engine::fm_script_engine_instructions::Instruction;
+    use super::{decode_sector, decompile_calculation, decompile_fmp12_file, get_sector_chain, Components, Decompiler};
+
+    #[test]
+    fn selective_decompile_test() {
+        let path = Path::new("tests/input/blank.fmp12");
+        let full = decompile_fmp12_file(path);
+        let partial = Decompiler::new(path)
+            .with(Components::SCRIPTS | Components::TABLES)
+            .run();
+
+        assert!(partial.layouts.is_empty());
+        assert!(partial.table_occurrences.is_empty());
+        assert!(partial.records.is_empty());
+        assert_eq!(partial.scripts.len(), full.scripts.len());
+        assert_eq!(partial.tables.len(), full.tables.len());
+        for (id, script) in &full.scripts {
+            assert_eq!(partial.scripts[id].instructions, script.instructions);
+        }
+
+        /* Skipping the other directories keeps the same chunks as filtering them afterwards. */
+        let buffer = std::fs::read(path).expect("Unable to read file.");
+        let selected = Components::SCRIPTS | Components::TABLES;
+        let summary = |c: &crate::chunk::Chunk| (c.path.clone(), c.code, c.data.map(|d| d.to_vec()), c.ref_simple);
+        for idx in get_sector_chain(&buffer) {
+            let expected = decode_sector(&buffer, idx, Components::ALL).chunks.iter()
+                .filter(|c| selected.intersects(Components::from_path(&c.path)))
+                .map(summary)
+                .collect::<Vec<_>>();
+            let actual = decode_sector(&buffer, idx, selected).chunks.iter().map(summary).collect::<Vec<_>>();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn comment_step_test() {
+        /* FileMaker stores no text for an empty comment line. */
+        let blank = decompile_fmp12_file(Path::new("tests/input/blank.fmp12"));
+        let find = blank.scripts.values().find(|s| s.script_name == "find").unwrap();
+        assert_eq!(find.instructions[0].opcode, Instruction::BlankLineComment);
+        assert_eq!(find.instructions[0].comment, None);
+        assert!(blank.scripts.values().flat_map(|s| &s.instructions).all(|s| s.enabled));
+
+        /* Comment text is read from [step].[128] key 1, with carriage returns between lines. */
+        let code = "script export: [\n  define notes() {\n    // One line.\n    /* First\n     * second. */\n  }\n]\n";
+        let mut file = compile_burn(code);
+        file.name = String::from("comment_step_test");
+        let mut assembler = Assembler::new(&file);
+        assembler.assemble_fmp12(&file).unwrap();
+        let buffer = assembler.buffer();
+        let stored = get_sector_chain(buffer).into_iter()
+            .flat_map(|idx| decode_sector(buffer, idx, Components::SCRIPTS).chunks)
+            .filter(|c| c.path == ["17", "5", "1", "5", "2", "128"] && c.ref_simple == Some(1))
+            .map(|c| fm_string_decrypt(c.data.unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(stored, ["First\rsecond."]);
+
+        let result = Decompiler::new(Path::new("burn_comment_step_test.fmp12")).with(Components::SCRIPTS).run_on(buffer);
+        let comments = result.scripts[&1].instructions.iter()
+            .map(|s| (s.opcode.clone(), s.comment.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(comments, [
+            (Instruction::BlankLineComment, Some("One line.")),
+            (Instruction::BlankLineComment, Some("First\nsecond.")),
+        ]);
+    }
+
+    #[test]
+    fn calc_round_trip_test() {
+        /* Every calculation FileMaker wrote into the file compiles back to the same bytes. */
+        let path = Path::new("tests/input/blank.fmp12");
+        let file = decompile_fmp12_file(path);
+        let buffer = std::fs::read(path).expect("Unable to read file.");
+
+        /* Calculations keep the infix form in key 5 and the prefix form in key 6. */
+        let mut calcs = BTreeMap::<Vec<String>, (Option<&[u8]>, Option<&[u8]>)>::new();
+        for idx in get_sector_chain(&buffer) {
+            for chunk in decode_sector(&buffer, idx, Components::ALL).chunks {
+                if chunk.path.len() < 5 || chunk.path.last().unwrap() != "5" || chunk.data.is_none() {
+                    continue;
+                }
+                let entry = calcs.entry(chunk.path.clone()).or_default();
+                match chunk.ref_simple {
+                    Some(5) => entry.0 = chunk.data,
+                    Some(6) => entry.1 = chunk.data,
                     _ => {}
                 }
-                let tmp = ScriptStep {
-                    opcode: op.clone().unwrap(),
-                    index: crate::encoding_util::get_path_int(&instr[2..instr[0] as usize + 1]),
-                    switches: switch,
-                };
-                let handle = fmp_file.scripts.get_mut(&script).unwrap();
-                handle.instructions.insert(handle.instructions.len(), tmp);
             }
+        }
+
+        let mut texts = vec![];
+        for (path, (infix, prefix)) in calcs {
+            let (Some(infix), Some(prefix)) = (infix, prefix) else { continue };
+            let text = decompile_calculation(infix, &file);
+            let compiled = compile_calculation(&text, &file, None)
+                .unwrap_or_else(|e| panic!("{:?}: {}: {}", path, text, e));
+            assert_eq!(compiled.infix, infix, "{:?}: {}", path, text);
+            assert_eq!(compiled.prefix, prefix, "{:?}: {}", path, text);
+            texts.push(text);
+        }
+        texts.sort();
+        texts.dedup();
+        assert_eq!(texts, ["\"Cancel\"", "\"OK\"", "\"testing\"", "$a", "$b", "$c", "1", "20 + 5", "3 ^ 2 +  (- 0 * 4)",
+            "Abs(20)", "Acos(40)", "Char(88)&\"c\"", "Get ( AccountName )", "Get ( CurrentTime )", "Get( UUID )"]);
 
+        /* What FileMaker's files don't have reads back as written. */
+        for text in ["\"say \\\"hi\\\" \\\\\"", "12.50 + 300 / 0.05", "not $a == 2 and Abs(-3) != 4 xor $b"] {
+            let compiled = compile_calculation(text, &file, None).unwrap();
+            assert_eq!(decompile_calculation(&compiled.infix, &file), text);
         }
     }
-    // for script in &fmp_file.scripts {
-    //     println!("{:?}", script.1.script_name);
-    //     for step in &script.1.instructions {
-    //         println!("{:?}", step);
-    //     }
-    // }
-    return fmp_file;
 }
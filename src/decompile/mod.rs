@@ -1,3 +1,6 @@
 pub mod decompiler;
+pub mod container;
+pub mod components;
+pub mod burn;
 mod sector;
 mod format;
@@ -59,6 +59,27 @@ pub fn fm_string_decrypt(bytes: &[u8]) -> String {
     }
 }
 
+/* 16 bytes from two 64 bit FNV-1a hashes of the text, the second one over
+ * the text in reverse. Not cryptographic, only used to tell ids apart. */
+pub fn digest(text: &str) -> [u8; 16] {
+    let fnv = |bytes: &mut dyn Iterator<Item = &u8>| bytes.fold(0xcbf29ce484222325_u64, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    });
+    let mut out = [0; 16];
+    out[..8].copy_from_slice(&fnv(&mut text.as_bytes().iter()).to_be_bytes());
+    out[8..].copy_from_slice(&fnv(&mut text.as_bytes().iter().rev()).to_be_bytes());
+    out
+}
+
+/* A UUID derived from `seed`, marked as a random (version 4) UUID like the
+ * ones FileMaker writes, so builds of the same source are identical. */
+pub fn derived_uuid(seed: &str) -> Vec<u8> {
+    let mut uuid = digest(seed);
+    uuid[6] = (uuid[6] & 0x0f) | 0x40;
+    uuid[8] = (uuid[8] & 0x3f) | 0x80;
+    uuid.to_vec()
+}
+
 #[cfg(test)]
 mod tests {
     use crate::encoding_util::*;
@@ -1,34 +1,51 @@
-use crate::component;
+use crate::{compile::{diagnostic::Span, symbols::Symbol}, component};
 
 use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+/* Components are kept in id order, so anything written from a file comes out the same every time. */
 #[derive(Serialize, Deserialize)]
 pub struct FmpFile {
     pub name: String,
-    pub tables: HashMap<usize, component::FMComponentTable>,
-    pub relationships: HashMap<usize, component::FMComponentRelationship>,
-    pub layouts: HashMap<usize, component::FMComponentLayout>,
-    pub value_lists: HashMap<usize, component::FMComponentValueList>,
-    pub scripts: HashMap<usize, component::FMComponentScript>,
-    pub table_occurrences: HashMap<usize, component::FMComponentTableOccurence>,
-    pub data_sources: HashMap<usize, component::FMComponentDataSource>,
+    pub tables: BTreeMap<usize, component::FMComponentTable>,
+    pub relationships: BTreeMap<usize, component::FMComponentRelationship>,
+    pub layouts: BTreeMap<usize, component::FMComponentLayout>,
+    pub value_lists: BTreeMap<usize, component::FMComponentValueList>,
+    pub scripts: BTreeMap<usize, component::FMComponentScript>,
+    pub table_occurrences: BTreeMap<usize, component::FMComponentTableOccurence>,
+    pub data_sources: BTreeMap<usize, component::FMComponentDataSource>,
+    pub privilege_sets: BTreeMap<usize, component::FMComponentPrivilegeSet>,
+    /* Records are keyed by table id, then record id. */
+    pub records: BTreeMap<usize, BTreeMap<usize, component::FMComponentRecord>>,
+    /* Records still to be added to `records`, see `compile::seed`. */
+    pub seeds: Vec<component::FMComponentSeed>,
     pub tests: Vec<component::FMComponentTest>,
+    /* Files named by "import", with where they're named. See `compile::project`. */
+    #[serde(skip)]
+    pub imports: Vec<(String, Span)>,
+    /* Names used outside of scripts, with where they're used. See `compile::symbols`. */
+    #[serde(skip)]
+    pub references: Vec<(Symbol, Span)>,
 }
 
 impl FmpFile {
     pub fn new() -> Self {
         Self {
             name: String::new(),
-            tables: HashMap::new(),
-            relationships: HashMap::new(),
-            layouts: HashMap::new(),
-            value_lists: HashMap::new(),
-            scripts: HashMap::new(),
-            table_occurrences: HashMap::new(),
-            data_sources: HashMap::new(),
+            tables: BTreeMap::new(),
+            relationships: BTreeMap::new(),
+            layouts: BTreeMap::new(),
+            value_lists: BTreeMap::new(),
+            scripts: BTreeMap::new(),
+            table_occurrences: BTreeMap::new(),
+            data_sources: BTreeMap::new(),
+            privilege_sets: BTreeMap::new(),
+            records: BTreeMap::new(),
+            seeds: vec![],
             tests: vec![],
+            imports: vec![],
+            references: vec![],
         }
     }
 }
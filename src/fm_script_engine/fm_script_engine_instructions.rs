@@ -2,6 +2,8 @@ use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
+use crate::compile::diagnostic::Span;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[repr(u8)] pub enum Instruction {
 	PerformScript = 1,
@@ -198,29 +200,35 @@ use serde::{Deserialize, Serialize};
     Assert = 255,
 }
 
+/* Script steps by the names they're written with in Burn. */
+pub const STEP_NAMES: [(&str, Instruction); 18] = [
+    ("perform_script", Instruction::PerformScript),
+    ("go_to_layout", Instruction::GoToLayout),
+    ("go_to_record", Instruction::GoToRecordRequestPage),
+    ("go_to_request", Instruction::GoToRecordRequestPage),
+    ("enter_find_mode", Instruction::EnterFindMode),
+    ("perform_find", Instruction::PerformFind),
+    ("show_all_records", Instruction::ShowAllRecords),
+    ("set_field", Instruction::SetField),
+    ("set_variable", Instruction::SetVariable),
+    ("loop", Instruction::Loop),
+    ("if", Instruction::If),
+    ("elif", Instruction::ElseIf),
+    ("else", Instruction::Else),
+    ("exit_loop_if", Instruction::ExitLoopIf),
+    ("new_record_request", Instruction::NewRecordRequest),
+    ("exit_script", Instruction::ExitScript),
+    ("show_custom_dialog", Instruction::ShowCustomDialog),
+    ("assert", Instruction::Assert),
+];
+
 impl FromStr for Instruction {
     type Err = ();
     fn from_str(input: &str) -> Result<Instruction, Self::Err> {
-        match input {
-            "perform_script" => Ok(Instruction::PerformScript),
-            "go_to_layout" => Ok(Instruction::GoToLayout),
-            "go_to_record" | "go_to_request" => Ok(Instruction::GoToRecordRequestPage),
-            "enter_find_mode" => Ok(Instruction::EnterFindMode),
-            "perform_find" => Ok(Instruction::PerformFind),
-            "show_all_records" => Ok(Instruction::ShowAllRecords),
-            "set_field" => Ok(Instruction::SetField),
-            "set_variable" => Ok(Instruction::SetVariable),
-            "loop" => Ok(Instruction::Loop),
-            "if" => Ok(Instruction::If),
-            "elif" => Ok(Instruction::ElseIf),
-            "else" => Ok(Instruction::Else),
-            "exit_loop_if" => Ok(Instruction::ExitLoopIf),
-            "new_record_request" => Ok(Instruction::NewRecordRequest),
-            "exit_script" => Ok(Instruction::ExitScript),
-            "show_custom_dialog" => Ok(Instruction::ShowCustomDialog),
-            "assert" => Ok(Instruction::Assert),
-            _ => Err(()),
-        }
+        STEP_NAMES.iter()
+            .find(|(name, _)| *name == input)
+            .map(|(_, step)| step.clone())
+            .ok_or(())
     }
 }
 
@@ -482,11 +490,77 @@ pub static INSTRUCTIONMAP : [Option<Instruction>; 255] = [
     Some(Instruction::Assert),
 ];
 
-#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+/* Steps are stored as fixed size records at [17].[5].[script] key 4. */
+pub const STEP_RECORD_SIZE: usize = 28;
+pub const STEP_OPCODE_OFFSET: usize = 21;
+/* Byte 27 of a step record holds its flags. */
+pub const STEP_FLAGS_OFFSET: usize = 26;
+pub const STEP_DISABLED_FLAG: u8 = 0x80;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ScriptStep {
     pub opcode: Instruction,
     pub index: usize,
     pub switches: Vec<String>,
+    /* Disabled steps are kept in the script but never executed. */
+    #[serde(default = "step_enabled_default")]
+    pub enabled: bool,
+    /* Text of comment steps. */
+    #[serde(default)]
+    pub comment: Option<String>,
+    /* Where the step and each of its switches were written, for steps
+     * compiled from Burn. */
+    #[serde(skip)]
+    pub span: Span,
+    #[serde(skip)]
+    pub switch_spans: Vec<Span>,
+}
+
+/* Steps are equal wherever they were written. */
+impl PartialEq for ScriptStep {
+    fn eq(&self, other: &Self) -> bool {
+        self.opcode == other.opcode
+            && self.index == other.index
+            && self.switches == other.switches
+            && self.enabled == other.enabled
+            && self.comment == other.comment
+    }
+}
+
+fn step_enabled_default() -> bool {
+    true
+}
+
+/* Whether each step of a script runs. Disabled steps don't, and neither do
+ * the markers of an If or Loop block whose first or last step is disabled,
+ * so the steps inside it run as if the block wasn't there and the blocks
+ * around it still pair up. */
+pub fn active_steps(steps: &[ScriptStep]) -> Vec<bool> {
+    let mut active = steps.iter().map(|s| s.enabled).collect::<Vec<_>>();
+    let mut blocks: Vec<Vec<usize>> = vec![];
+    for (i, step) in steps.iter().enumerate() {
+        match step.opcode {
+            Instruction::If | Instruction::Loop => blocks.push(vec![i]),
+            Instruction::Else | Instruction::ElseIf => {
+                if let Some(block) = blocks.last_mut() {
+                    block.push(i);
+                }
+            },
+            Instruction::EndIf | Instruction::EndLoop => {
+                let Some(mut block) = blocks.pop() else {
+                    continue;
+                };
+                block.push(i);
+                if !steps[block[0]].enabled || !step.enabled {
+                    for marker in block {
+                        active[marker] = false;
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+    active
 }
 
 pub struct Script {
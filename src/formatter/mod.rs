@@ -0,0 +1,554 @@
+use std::path::{Path, PathBuf};
+
+use crate::compile::diagnostic::Diagnostic;
+use syntax::{SyntaxElement, SyntaxKind, SyntaxNode, SyntaxToken};
+
+pub mod syntax;
+
+const INDENT: &str = "  ";
+const DATA_TYPES: [&str; 6] = ["Text", "Number", "Date", "Time", "Timestamp", "Container"];
+
+/* A node with the comments around it. Comments on lines of their own come
+ * before it, comments after it on the same line trail it. */
+struct Entry<'a> {
+    leading: Vec<(bool, &'a str)>,
+    /* Whether a blank line was left before the entry. */
+    blank: bool,
+    node: &'a SyntaxNode,
+    trailing: Vec<&'a str>,
+}
+
+/* Comments before anything else on the first line, the nodes among
+ * `children` with their comments, and comments after the last node. */
+struct Entries<'a> {
+    head: Vec<&'a str>,
+    list: Vec<Entry<'a>>,
+    rest: Vec<(bool, &'a str)>,
+}
+
+fn entries(children: &[SyntaxElement]) -> Entries<'_> {
+    let mut head = vec![];
+    let mut entries: Vec<Entry> = vec![];
+    let mut leading = vec![];
+    let mut newlines = 0;
+    for child in children {
+        match child {
+            SyntaxElement::Token(t) if t.kind == SyntaxKind::Newline => newlines += 1,
+            SyntaxElement::Token(t) if t.kind == SyntaxKind::Comment => {
+                match entries.last_mut() {
+                    Some(last) if newlines == 0 && leading.is_empty() => last.trailing.push(t.text.trim_end()),
+                    None if newlines == 0 && leading.is_empty() => head.push(t.text.trim_end()),
+                    _ => {
+                        leading.push((newlines > 1, t.text.trim_end()));
+                        newlines = 0;
+                    },
+                }
+            },
+            SyntaxElement::Token(_) => {},
+            SyntaxElement::Node(node) => {
+                entries.push(Entry { leading: std::mem::take(&mut leading), blank: newlines > 1, node, trailing: vec![] });
+                newlines = 0;
+            },
+        }
+    }
+    Entries { head, list: entries, rest: leading }
+}
+
+fn comment_text<'a>(tokens: &[&'a SyntaxToken]) -> Vec<&'a str> {
+    tokens.iter().filter(|t| t.kind == SyntaxKind::Comment).map(|t| t.text.trim_end()).collect()
+}
+
+/* Joins tokens with the spacing Burn is written with: "name: Text",
+ * "Person::id", "foundIn[List]", "!{calculation}", "if(x) {". */
+fn join(tokens: &[&SyntaxToken]) -> String {
+    let mut text = String::new();
+    for (n, t) in tokens.iter().enumerate() {
+        if n > 0 {
+            let prev = tokens[n - 1].text.as_str();
+            let before = n.checked_sub(2).map(|b| tokens[b].text.as_str());
+            let tight = matches!(t.text.as_str(), "," | ";" | ":" | ")" | "]")
+                || matches!(prev, "(" | "!")
+                || (t.text == "[" && prev == "foundIn")
+                || (prev == "[" && tokens[n - 1].kind == SyntaxKind::Punct && t.text != "]" && before == Some("foundIn"))
+                || (prev == ":" && before == Some(":"))
+                || (t.kind == SyntaxKind::Args && tokens[n - 1].kind == SyntaxKind::Word);
+            if !tight {
+                text.push(' ');
+            }
+        }
+        if t.kind == SyntaxKind::Args {
+            text.push_str(&format_args(&t.text));
+        } else if t.kind == SyntaxKind::Calculation {
+            text.push_str(&format!("{{{}}}", t.text[1..t.text.len() - 1].trim()));
+        } else {
+            text.push_str(&t.text);
+        }
+    }
+    text
+}
+
+/* Arguments of a script step, one space after each comma: "(x, y + 1)". */
+fn format_args(args: &str) -> String {
+    let inner = &args[1..args.len() - 1];
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '(' if !in_string => depth += 1,
+            ')' if !in_string => depth -= 1,
+            ',' if !in_string && depth == 0 => {
+                parts.push(inner[start..i].trim());
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    parts.push(inner[start..].trim());
+    if parts == [""] {
+        return String::from("()");
+    }
+    format!("({})", parts.join(", "))
+}
+
+/* Where a field option is placed: data type, description, flags, value
+ * list, calculations and validation, then the field id. */
+fn option_rank(option: &[&SyntaxToken]) -> usize {
+    let first = option[0];
+    match first.text.as_str() {
+        t if DATA_TYPES.contains(&t) => 0,
+        _ if first.kind == SyntaxKind::String => 1,
+        "Unique" => 2,
+        "Required" => 3,
+        "Existing" => 4,
+        "foundIn" => 5,
+        _ if first.kind == SyntaxKind::Calculation => 6,
+        "!" if option.get(1).is_some_and(|t| t.kind == SyntaxKind::Calculation) => 7,
+        "!" => 8,
+        "id" => 9,
+        _ => 10,
+    }
+}
+
+/* Where an entry of a construct is placed. Keywords naming what the
+ * construct is based on come first. */
+fn entry_rank(keyword: &str, item: &SyntaxNode) -> usize {
+    let tokens = item.significant();
+    let first = tokens.first().map(|t| t.text.as_str()).unwrap_or("");
+    match (keyword, first) {
+        ("layout", "table_occurence") | ("table_occurence", "table") | ("privilege_set", "access") | ("data", "source") => 0,
+        ("privilege_set", "description") => 1,
+        /* The two occurrences of a relationship come before its predicates. */
+        ("relationship", _) if tokens.len() > 2 && tokens[1].text == ":" && tokens[2].text != ":" => 0,
+        _ => 2,
+    }
+}
+
+struct Printer {
+    out: String,
+    depth: usize,
+}
+
+impl Printer {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn blank(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") && !self.out.ends_with(":\n") && !self.out.ends_with("[\n")
+            && !self.out.ends_with("{\n") {
+            self.out.push('\n');
+        }
+    }
+
+    /* Adds to the last line written. */
+    fn append(&mut self, text: &str) {
+        self.out.pop();
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn comments(&mut self, comments: &[(bool, &str)]) {
+        for (blank, comment) in comments {
+            if *blank {
+                self.blank();
+            }
+            self.line(comment);
+        }
+    }
+
+    fn trailing(&mut self, comments: &[&str]) {
+        for comment in comments {
+            self.append(&format!(" {}", comment));
+        }
+    }
+
+    fn file(&mut self, file: &SyntaxNode) {
+        let Entries { head, list, rest } = entries(&file.children);
+        for comment in head {
+            self.line(comment);
+        }
+        let mut last_keyword = "";
+        for (n, entry) in list.iter().enumerate() {
+            let keyword = entry.node.keyword();
+            /* Constructs are a blank line apart, imports can be listed together. */
+            if entry.blank || (n > 0 && !(keyword == "import" && last_keyword == "import")) {
+                self.blank();
+            }
+            self.comments(&entry.leading);
+            self.construct(entry.node);
+            self.trailing(&entry.trailing);
+            last_keyword = keyword;
+        }
+        self.comments(&rest);
+    }
+
+    fn construct(&mut self, construct: &SyntaxNode) {
+        let keyword = construct.keyword();
+        let Entries { list, .. } = entries(&construct.children);
+        let (mut items, mut end) = (vec![], None);
+        for entry in list {
+            match entry.node.kind {
+                SyntaxKind::Header => {
+                    let header = entry.node.significant();
+                    self.line(&join(&header));
+                    self.trailing(&comment_text(&entry.node.significant_and_comments()));
+                    self.trailing(&entry.trailing);
+                },
+                SyntaxKind::End => end = Some(entry),
+                _ => items.push(entry),
+            }
+        }
+        if keyword == "import" {
+            return;
+        }
+
+        self.depth += 1;
+        items.sort_by_key(|e| if e.node.kind == SyntaxKind::Item { entry_rank(keyword, e.node) } else { 0 });
+        let width = items.iter()
+            .filter(|_| keyword == "table")
+            .map(|e| e.node.significant().first().map(|t| t.text.chars().count()).unwrap_or(0))
+            .max().unwrap_or(0);
+        for entry in &items {
+            if entry.blank {
+                self.blank();
+            }
+            self.comments(&entry.leading);
+            if entry.node.kind == SyntaxKind::ScriptBody {
+                self.script(entry.node);
+            } else {
+                self.item(keyword, entry.node, width);
+            }
+            self.trailing(&entry.trailing);
+        }
+        let Some(end) = end else {
+            self.depth -= 1;
+            return;
+        };
+        self.comments(&end.leading);
+        self.depth -= 1;
+
+        let end_text = match (keyword, end.node.significant().last()) {
+            ("script", Some(t)) if t.text == ";" => String::from("];"),
+            ("script", _) => String::from("]"),
+            _ => format!("end {};", keyword),
+        };
+        self.line(&end_text);
+        self.trailing(&comment_text(&end.node.significant_and_comments()));
+        self.trailing(&end.trailing);
+    }
+
+    fn item(&mut self, keyword: &str, item: &SyntaxNode, width: usize) {
+        let comments = comment_text(&item.significant_and_comments());
+        /* A test's script: "script: [", its statements, then "],". */
+        if let Some(body) = item.child(SyntaxKind::ScriptBody) {
+            let tokens = item.significant();
+            let open = tokens.iter().position(|t| t.text == "[").unwrap_or(0);
+            self.line(&join(&tokens[..=open]));
+            self.trailing(&comments);
+            self.depth += 1;
+            self.script(body);
+            self.depth -= 1;
+            self.line(&join(&tokens[open + 1..]));
+            return;
+        }
+
+        let mut tokens = item.significant();
+        let terminator = if keyword == "data" && tokens.first().is_some_and(|t| t.text != "source") { ";" } else { "," };
+        if tokens.last().is_some_and(|t| t.text == terminator) {
+            tokens.pop();
+        }
+        let text = if keyword == "table" && tokens.len() > 1 && tokens[1].text == ":" {
+            /* Options of every field start in the same column, in order. */
+            let mut options = tokens[2..].split(|t| t.text == ",").filter(|o| !o.is_empty()).collect::<Vec<_>>();
+            options.sort_by_key(|o| option_rank(o));
+            let name = format!("{}:", tokens[0].text);
+            let options = options.iter().map(|o| join(o)).collect::<Vec<_>>().join(", ");
+            format!("{:width$} {}", name, options, width = width + 1).trim_end().to_string()
+        } else if keyword == "relationship" && tokens.len() == 3 && tokens[1].text == ":" {
+            format!("{}:{}", tokens[0].text, tokens[2].text)
+        } else {
+            join(&tokens)
+        };
+        self.line(&format!("{}{}", text, terminator));
+        self.trailing(&comments);
+    }
+
+    /* Statements of a script block, after the line opening it. */
+    fn script(&mut self, body: &SyntaxNode) {
+        let Entries { head, list: entries, rest } = entries(&body.children);
+        self.trailing(&head);
+        for (n, entry) in entries.iter().enumerate() {
+            let keyword = entry.node.keyword();
+            /* "} elif(x) {" and "} else {" close the block before them. */
+            let chained = matches!(keyword, "elif" | "else") && n > 0 && entry.leading.is_empty()
+                && entries[n - 1].trailing.is_empty() && entries[n - 1].node.kind == SyntaxKind::Block;
+            if entry.blank && !chained {
+                self.blank();
+            }
+            self.comments(&entry.leading);
+            self.statement(entry.node, chained);
+            self.trailing(&entry.trailing);
+        }
+        self.comments(&rest);
+    }
+
+    fn statement(&mut self, statement: &SyntaxNode, chained: bool) {
+        let tokens = statement.significant();
+        if statement.kind == SyntaxKind::Step {
+            self.line(&join(&tokens));
+            self.trailing(&comment_text(&statement.significant_and_comments()));
+            return;
+        }
+        /* The block's statements are everything between its braces. */
+        let open = statement.children.iter().position(|c| matches!(c, SyntaxElement::Token(t) if t.text == "{")).unwrap();
+        let header = SyntaxNode { kind: SyntaxKind::Header, children: statement.children[..=open].to_vec() };
+        let body = SyntaxNode { kind: SyntaxKind::ScriptBody, children: statement.children[open + 1..statement.children.len() - 1].to_vec() };
+        if chained {
+            self.append(&format!(" {}", join(&header.significant())));
+        } else {
+            self.line(&join(&header.significant()));
+        }
+        self.trailing(&comment_text(&header.significant_and_comments()));
+        self.depth += 1;
+        self.script(&body);
+        self.depth -= 1;
+        self.line("}");
+    }
+}
+
+/* Formats Burn source. Only whitespace, the order of field options and of
+ * entries within a construct, and optional punctuation change, comments
+ * are kept where they were written. */
+pub fn format_burn(code: &str) -> Result<String, Vec<Diagnostic>> {
+    let file = syntax::parse(code)?;
+    let mut printer = Printer { out: String::new(), depth: 0 };
+    printer.file(&file);
+    Ok(printer.out)
+}
+
+/* The Burn files named, or found in the directories named. */
+fn burn_files(paths: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut files = vec![];
+    for path in paths {
+        if Path::new(path).is_dir() {
+            let pattern = Path::new(path).join("**").join("*.burn").to_string_lossy().to_string();
+            let found = glob::glob(&pattern)
+                .map_err(|e| format!("Invalid path {}: {}", path, e))?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Unable to read {}: {}", e.path().display(), e.error()))?;
+            files.extend(found);
+        } else {
+            files.push(PathBuf::from(path));
+        }
+    }
+    Ok(files)
+}
+
+/* Formats files in place, or with `check` only reports the files that
+ * aren't formatted. Returns the exit code. */
+pub fn run(check: bool, paths: &[String]) -> i32 {
+    let paths = if paths.is_empty() { vec![String::from(".")] } else { paths.to_vec() };
+    let files = match burn_files(&paths) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 1;
+        }
+    };
+    let mut code = 0;
+    for path in files {
+        let name = path.to_string_lossy().to_string();
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("error: Unable to read {}: {}", name, e);
+                code = 1;
+                continue;
+            }
+        };
+        let formatted = match format_burn(&text) {
+            Ok(formatted) => formatted,
+            Err(diagnostics) => {
+                for d in diagnostics {
+                    eprintln!("{}", d.in_file(&name).render(&text));
+                }
+                code = 1;
+                continue;
+            }
+        };
+        if formatted == text {
+            continue;
+        }
+        if check {
+            println!("Would reformat {}", name);
+            code = 1;
+        } else if let Err(e) = std::fs::write(&path, formatted) {
+            eprintln!("error: Unable to write {}: {}", name, e);
+            code = 1;
+        }
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::compiler::compile_burn;
+    use super::format_burn;
+
+    #[test]
+    fn format_test() {
+        let code = "\
+// People and their jobs.
+table Person:
+    full_name:Text,\"Full name\" ,Required,
+  id: Unique, Number, /* the key */
+
+
+     salary: !\"Too much\", !{ salary < 100 }, Number, foundIn [ Salaries ],
+end table;
+table Job:
+  person: Number,
+end table;
+table_occurence Person: table : Person end table_occurence;
+table_occurence Job:
+  table: Job,
+end table_occurence;
+value_list Salaries:
+ \"1\",\"2\"
+end value_list;
+layout People:
+  full_name, id,
+  table_occurence: Person,
+end layout;
+relationship: Person : Job, id==person end relationship;
+data Person:
+  id,full_name;
+  1,\"Kim\";
+end data;
+script: [
+define hire( x,y) {
+  // Only some.
+  if(x>y){exit_script( x);}elif( x == y) {
+  set_field(Person::full_name,\"a, b\");
+  } else {
+      loop { exit_loop_if(x); }
+  }
+  new_record_request( );
+}
+]
+";
+        let formatted = format_burn(code).unwrap();
+        assert_eq!(formatted, "\
+// People and their jobs.
+table Person:
+  full_name: Text, \"Full name\", Required,
+  id:        Number, Unique, /* the key */
+
+  salary:    Number, foundIn[Salaries], !{salary < 100}, !\"Too much\",
+end table;
+
+table Job:
+  person: Number,
+end table;
+
+table_occurence Person:
+  table: Person,
+end table_occurence;
+
+table_occurence Job:
+  table: Job,
+end table_occurence;
+
+value_list Salaries:
+  \"1\",
+  \"2\",
+end value_list;
+
+layout People:
+  table_occurence: Person,
+  full_name,
+  id,
+end layout;
+
+relationship:
+  Person:Job,
+  id == person,
+end relationship;
+
+data Person:
+  id, full_name;
+  1, \"Kim\";
+end data;
+
+script: [
+  define hire(x, y) {
+    // Only some.
+    if(x>y) {
+      exit_script(x);
+    } elif(x == y) {
+      set_field(Person::full_name, \"a, b\");
+    } else {
+      loop {
+        exit_loop_if(x);
+      }
+    }
+    new_record_request();
+  }
+]
+");
+        assert_eq!(format_burn(&formatted).unwrap(), formatted);
+
+        /* Formatting doesn't change what's compiled. */
+        let uncommented = |code: &str| code.lines()
+            .map(|l| l.split("//").next().unwrap().split("/*").next().unwrap())
+            .collect::<Vec<_>>().join("\n");
+        let compiled = |code: &str| serde_json::to_string(&compile_burn(&uncommented(code))).unwrap();
+        assert_eq!(compiled(code), compiled(&formatted));
+    }
+
+    #[test]
+    fn format_escape_test() {
+        let code = "value_list Sizes:\n  \"6\\\" \\\\\",\nend value_list;\n\nscript: [\n  define f() {\n    set_variable(x, \"a\\\", b\");\n  }\n]\n";
+        assert_eq!(format_burn(code).unwrap(), code);
+    }
+
+    #[test]
+    fn format_example_test() {
+        let code = std::fs::read_to_string("tests/input/blank_tests.burn").unwrap();
+        let formatted = format_burn(&code).unwrap();
+        assert_eq!(formatted, code);
+    }
+}
@@ -0,0 +1,531 @@
+use crate::compile::diagnostic::{Diagnostic, LineIndex, Span};
+use crate::compile::lexer::string_end;
+
+/* A lossless syntax tree of Burn source, for tools that rewrite it. Every
+ * character of the source is kept in a token, whitespace and comments
+ * included, so the text of the tree is the text it was read from. Script
+ * blocks are read by their own layer into statements, like the compiler
+ * does. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxKind {
+    Whitespace,
+    Newline,
+    Comment,
+    Word,
+    String,
+    /* "{calculation}", braces included. */
+    Calculation,
+    /* The arguments of a script step, "(x, y)", parentheses included. */
+    Args,
+    Punct,
+
+    File,
+    /* A top level construct, from its keyword to its end. */
+    Construct,
+    Header,
+    /* A field, a predicate, a row of data, or any one entry of a construct. */
+    Item,
+    End,
+    ScriptBody,
+    /* A statement opening a "{" block: define, if, elif, else and loop. */
+    Block,
+    Step,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub kind: SyntaxKind,
+    pub text: String,
+    pub span: Span,
+}
+
+impl SyntaxToken {
+    pub fn is_trivia(&self) -> bool {
+        matches!(self.kind, SyntaxKind::Whitespace | SyntaxKind::Newline | SyntaxKind::Comment)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    fn new(kind: SyntaxKind) -> Self {
+        Self { kind, children: vec![] }
+    }
+
+    fn push(&mut self, token: SyntaxToken) {
+        self.children.push(SyntaxElement::Token(token));
+    }
+
+    pub fn text(&self) -> String {
+        self.children.iter().map(|c| match c {
+            SyntaxElement::Node(n) => n.text(),
+            SyntaxElement::Token(t) => t.text.clone(),
+        }).collect()
+    }
+
+    /* Tokens directly under this node that aren't whitespace or comments. */
+    pub fn significant(&self) -> Vec<&SyntaxToken> {
+        self.children.iter().filter_map(|c| match c {
+            SyntaxElement::Token(t) if !t.is_trivia() => Some(t),
+            _ => None,
+        }).collect()
+    }
+
+    /* Tokens directly under this node other than whitespace. */
+    pub fn significant_and_comments(&self) -> Vec<&SyntaxToken> {
+        self.children.iter().filter_map(|c| match c {
+            SyntaxElement::Token(t) if !matches!(t.kind, SyntaxKind::Whitespace | SyntaxKind::Newline) => Some(t),
+            _ => None,
+        }).collect()
+    }
+
+    pub fn child(&self, kind: SyntaxKind) -> Option<&SyntaxNode> {
+        self.children.iter().find_map(|c| match c {
+            SyntaxElement::Node(n) if n.kind == kind => Some(n),
+            _ => None,
+        })
+    }
+
+    /* The first word, which names what a construct or statement is. */
+    pub fn keyword(&self) -> &str {
+        let header = self.child(SyntaxKind::Header).unwrap_or(self);
+        header.significant().first().map(|t| t.text.as_str()).unwrap_or("")
+    }
+}
+
+const TOP_LEVEL: [&str; 10] = [
+    "table", "relationship", "value_list", "layout", "data", "privilege_set", "script", "table_occurence", "test", "import",
+];
+
+/* Reads tokens from `chars`, with spans from `lines` placed relative to `base`. */
+struct Scanner<'a> {
+    chars: &'a [char],
+    lines: LineIndex,
+    base: Span,
+    i: usize,
+    tokens: Vec<SyntaxToken>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(text: &'a [char], source: &str, base: Span) -> Self {
+        Self { chars: text, lines: LineIndex::new(source), base, i: 0, tokens: vec![], diagnostics: vec![] }
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        self.lines.span(start, end).relative_to(self.base)
+    }
+
+    fn at(&self, text: &str) -> bool {
+        text.chars().enumerate().all(|(n, c)| self.chars.get(self.i + n) == Some(&c))
+    }
+
+    fn find(&self, from: usize, text: &str) -> Option<usize> {
+        let pattern = text.chars().collect::<Vec<_>>();
+        (from..self.chars.len()).find(|i| self.chars[*i..].starts_with(&pattern))
+    }
+
+    fn push(&mut self, kind: SyntaxKind, end: usize) {
+        let text = self.chars[self.i..end].iter().collect();
+        self.tokens.push(SyntaxToken { kind, text, span: self.span(self.i, end) });
+        self.i = end;
+    }
+
+    fn error(&mut self, message: &str) {
+        let span = self.span(self.i, self.i + 1);
+        self.diagnostics.push(Diagnostic::error(message, span));
+        self.i = self.chars.len();
+    }
+
+    /* Reads whitespace, a newline or a comment, if there is one. */
+    fn trivia(&mut self) -> bool {
+        let c = self.chars[self.i];
+        if c == '\n' || self.at("\r\n") {
+            let len = if c == '\n' { 1 } else { 2 };
+            self.push(SyntaxKind::Newline, self.i + len);
+        } else if c.is_whitespace() {
+            let end = (self.i..self.chars.len())
+                .find(|j| !self.chars[*j].is_whitespace() || self.chars[*j] == '\n' || self.chars[*j] == '\r')
+                .unwrap_or(self.chars.len());
+            self.push(SyntaxKind::Whitespace, end.max(self.i + 1));
+        } else if self.at("//") {
+            let end = self.find(self.i, "\n").unwrap_or(self.chars.len());
+            let end = if end > self.i && self.chars[end - 1] == '\r' { end - 1 } else { end };
+            self.push(SyntaxKind::Comment, end);
+        } else if self.at("/*") {
+            match self.find(self.i + 2, "*/") {
+                Some(end) => self.push(SyntaxKind::Comment, end + 2),
+                None => self.error("Unterminated comment, expected \"*/\"."),
+            }
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /* Reads from an opening character to `close`, which is included. */
+    fn delimited(&mut self, kind: SyntaxKind, close: char, message: &str) {
+        let end = match close {
+            '"' => string_end(self.chars, self.i + 1),
+            _ => self.find(self.i + 1, &close.to_string()),
+        };
+        match end {
+            Some(end) => self.push(kind, end + 1),
+            None => self.error(message),
+        }
+    }
+
+    /* Reads a word, which ends at whitespace, a delimiter or a comment. */
+    fn word(&mut self, delimiters: &str) {
+        let ends = |j: usize| {
+            let c = self.chars[j];
+            c.is_whitespace() || delimiters.contains(c) || (c == '/' && matches!(self.chars.get(j + 1), Some('/') | Some('*')))
+        };
+        let end = (self.i..self.chars.len()).find(|j| ends(*j)).unwrap_or(self.chars.len());
+        self.push(SyntaxKind::Word, end.max(self.i + 1));
+    }
+
+    fn last_significant(&self) -> Option<&SyntaxToken> {
+        self.tokens.iter().rev().find(|t| !t.is_trivia())
+    }
+}
+
+/* Splits Burn source into tokens. The text between "[" and "]", other than
+ * after foundIn, is a script block and kept whole. */
+fn lex(text: &str) -> Result<Vec<SyntaxToken>, Vec<Diagnostic>> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut scanner = Scanner::new(&chars, text, Span::new(1, 1, 0));
+    while scanner.i < chars.len() {
+        if scanner.trivia() {
+            continue;
+        }
+        let i = scanner.i;
+        match chars[i] {
+            '"' => scanner.delimited(SyntaxKind::String, '"', "Unterminated string."),
+            '{' => scanner.delimited(SyntaxKind::Calculation, '}', "Unterminated calculation, expected \"}\"."),
+            '[' => {
+                let found_in = scanner.last_significant().is_some_and(|t| t.text == "foundIn");
+                scanner.push(SyntaxKind::Punct, i + 1);
+                if !found_in {
+                    match scanner.find(i + 1, "]") {
+                        /* Kept as a script token until it's read by `parse_script`. */
+                        Some(end) => {
+                            let block = chars[i + 1..end].iter().collect();
+                            let span = scanner.span(i + 1, end);
+                            scanner.tokens.push(SyntaxToken { kind: SyntaxKind::ScriptBody, text: block, span });
+                            scanner.i = end;
+                        },
+                        None => scanner.error("Unterminated script block, expected \"]\"."),
+                    }
+                }
+            },
+            '=' | '!' | '<' | '>' if chars.get(i + 1) == Some(&'=') => scanner.push(SyntaxKind::Punct, i + 2),
+            '=' | '!' | '<' | '>' | '(' | ')' | ']' | '}' | ';' | ':' | ',' | '*' | '×' => scanner.push(SyntaxKind::Punct, i + 1),
+            _ => scanner.word("=[]()\"!{};:,<>*×"),
+        }
+    }
+    if scanner.diagnostics.is_empty() {
+        Ok(scanner.tokens)
+    } else {
+        Err(scanner.diagnostics)
+    }
+}
+
+/* Splits a script block into tokens, keeping the arguments of each step whole. */
+fn lex_script(text: &str, base: Span) -> Result<Vec<SyntaxToken>, Vec<Diagnostic>> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut scanner = Scanner::new(&chars, text, base);
+    while scanner.i < chars.len() {
+        if scanner.trivia() {
+            continue;
+        }
+        let i = scanner.i;
+        match chars[i] {
+            '"' => scanner.delimited(SyntaxKind::String, '"', "Unterminated string."),
+            '(' => {
+                let mut depth = 0;
+                let mut in_string = false;
+                let mut escaped = false;
+                let end = (i..chars.len()).find(|j| {
+                    if escaped {
+                        escaped = false;
+                        return false;
+                    }
+                    match chars[*j] {
+                        '\\' if in_string => escaped = true,
+                        '"' => in_string = !in_string,
+                        '(' if !in_string => depth += 1,
+                        ')' if !in_string => depth -= 1,
+                        _ => {},
+                    }
+                    depth == 0
+                });
+                match end {
+                    Some(end) => scanner.push(SyntaxKind::Args, end + 1),
+                    None => scanner.error("Unterminated arguments, expected \")\"."),
+                }
+            },
+            '{' | '}' | ';' | ',' | ')' | '=' | '+' | '-' | '*' | '/' | '&' | '<' | '>' | '!' => scanner.push(SyntaxKind::Punct, i + 1),
+            _ => scanner.word("(){};,\"=+-*/&<>!"),
+        }
+    }
+    if scanner.diagnostics.is_empty() {
+        Ok(scanner.tokens)
+    } else {
+        Err(scanner.diagnostics)
+    }
+}
+
+fn next_significant(tokens: &[SyntaxToken], from: usize) -> Option<usize> {
+    (from..tokens.len()).find(|i| !tokens[*i].is_trivia())
+}
+
+/* Reads statements until the "}" closing the block they're in, which is left
+ * for the block to read. */
+fn parse_statements(tokens: &[SyntaxToken], i: &mut usize, nested: bool, into: &mut SyntaxNode) -> Result<(), Diagnostic> {
+    while *i < tokens.len() {
+        let t = &tokens[*i];
+        if t.is_trivia() {
+            into.push(t.clone());
+            *i += 1;
+            continue;
+        }
+        if t.text == "}" {
+            if nested {
+                return Ok(());
+            }
+            return Err(Diagnostic::error("Unexpected \"}\" outside of a script.", t.span));
+        }
+        let first = t.clone();
+        let end = (*i..tokens.len()).find(|j| matches!(tokens[*j].text.as_str(), ";" | "{" | "}"));
+        match end.map(|end| tokens[end].text.as_str()) {
+            Some(";") => {
+                let mut step = SyntaxNode::new(SyntaxKind::Step);
+                for t in &tokens[*i..=end.unwrap()] {
+                    step.push(t.clone());
+                }
+                *i = end.unwrap() + 1;
+                into.children.push(SyntaxElement::Node(step));
+            },
+            Some("{") => {
+                let mut block = SyntaxNode::new(SyntaxKind::Block);
+                for t in &tokens[*i..=end.unwrap()] {
+                    block.push(t.clone());
+                }
+                *i = end.unwrap() + 1;
+                parse_statements(tokens, i, true, &mut block)?;
+                match tokens.get(*i) {
+                    Some(close) => block.push(close.clone()),
+                    None => return Err(Diagnostic::error(format!("Expected \"}}\" to close {}.", first.text), first.span)),
+                }
+                *i += 1;
+                into.children.push(SyntaxElement::Node(block));
+            },
+            _ => return Err(Diagnostic::error(format!("Expected \";\" after {}.", first.text), first.span)),
+        }
+    }
+    if nested {
+        let span = tokens.last().map(|t| t.span).unwrap_or_default();
+        return Err(Diagnostic::error("Expected \"}\" to close block.", span));
+    }
+    Ok(())
+}
+
+fn parse_script(block: &SyntaxToken) -> Result<SyntaxNode, Vec<Diagnostic>> {
+    let tokens = lex_script(&block.text, block.span)?;
+    let mut body = SyntaxNode::new(SyntaxKind::ScriptBody);
+    parse_statements(&tokens, &mut 0, false, &mut body).map_err(|e| vec![e])?;
+    Ok(body)
+}
+
+/* Whether `t` starts a new entry of a construct, rather than carrying on the
+ * one before it. Fields start at "name:", rows of data follow a ";", and
+ * entries of every other construct follow a ",". */
+fn starts_item(keyword: &str, prev: &SyntaxToken, t: &SyntaxToken, next: Option<&SyntaxToken>) -> bool {
+    let keyed = t.kind == SyntaxKind::Word && next.is_some_and(|n| n.text == ":") && prev.text != ":";
+    match keyword {
+        "table" => keyed,
+        "data" => prev.text == ";" || keyed,
+        _ => prev.text == "," || keyed,
+    }
+}
+
+fn parse_construct(tokens: &[SyntaxToken]) -> Result<SyntaxNode, Vec<Diagnostic>> {
+    let keyword = tokens[0].text.as_str();
+    let mut construct = SyntaxNode::new(SyntaxKind::Construct);
+    let mut header = SyntaxNode::new(SyntaxKind::Header);
+    let opens = if keyword == "script" { "[" } else if keyword == "import" { ";" } else { ":" };
+    let Some(header_end) = tokens.iter().position(|t| t.text == opens) else {
+        return Err(vec![Diagnostic::error(format!("Expected \"{}\" after {}.", opens, keyword), tokens[0].span)]);
+    };
+    for t in &tokens[..=header_end] {
+        header.push(t.clone());
+    }
+    construct.children.push(SyntaxElement::Node(header));
+    if keyword == "import" {
+        return Ok(construct);
+    }
+
+    /* The end is "]", or "end KEYWORD", either followed by an optional ";". */
+    let close = if keyword == "script" {
+        tokens.iter().rposition(|t| t.text == "]")
+    } else {
+        tokens.iter().rposition(|t| t.text == "end")
+    }.unwrap_or(tokens.len());
+    let body = &tokens[header_end + 1..close];
+
+    let mut item: Option<SyntaxNode> = None;
+    let mut pending = vec![];
+    let mut prev: Option<&SyntaxToken> = None;
+    for (n, t) in body.iter().enumerate() {
+        if t.is_trivia() {
+            pending.push(t.clone());
+            continue;
+        }
+        if t.kind == SyntaxKind::ScriptBody && keyword == "script" {
+            construct.children.extend(pending.drain(..).map(SyntaxElement::Token));
+            construct.children.push(SyntaxElement::Node(parse_script(t)?));
+            continue;
+        }
+        let next = next_significant(body, n + 1).map(|j| &body[j]);
+        let current = match item.take() {
+            Some(current) if !starts_item(keyword, prev.unwrap(), t, next) => current,
+            done => {
+                construct.children.extend(done.map(SyntaxElement::Node));
+                construct.children.extend(pending.drain(..).map(SyntaxElement::Token));
+                SyntaxNode::new(SyntaxKind::Item)
+            },
+        };
+        let mut current = current;
+        current.children.extend(pending.drain(..).map(SyntaxElement::Token));
+        if t.kind == SyntaxKind::ScriptBody {
+            current.children.push(SyntaxElement::Node(parse_script(t)?));
+        } else {
+            current.push(t.clone());
+        }
+        item = Some(current);
+        prev = Some(t);
+    }
+    construct.children.extend(item.map(SyntaxElement::Node));
+    construct.children.extend(pending.into_iter().map(SyntaxElement::Token));
+
+    let mut end = SyntaxNode::new(SyntaxKind::End);
+    for t in &tokens[close..] {
+        end.push(t.clone());
+    }
+    construct.children.push(SyntaxElement::Node(end));
+    Ok(construct)
+}
+
+/* Where the construct starting at `start` ends, just past its last token. */
+fn construct_end(tokens: &[SyntaxToken], start: usize) -> Option<usize> {
+    let keyword = tokens[start].text.as_str();
+    let close = match keyword {
+        "import" => return (start..tokens.len()).find(|i| tokens[*i].text == ";").map(|i| i + 1),
+        "script" => (start..tokens.len()).find(|i| tokens[*i].text == "]")?,
+        _ => (start + 1..tokens.len()).find(|i| tokens[*i].text == "end"
+            && next_significant(tokens, i + 1).is_some_and(|j| tokens[j].text == keyword))
+            .and_then(|i| next_significant(tokens, i + 1))?,
+    };
+    match next_significant(tokens, close + 1) {
+        Some(semi) if tokens[semi].text == ";" => Some(semi + 1),
+        _ => Some(close + 1),
+    }
+}
+
+/* Reads Burn source into a syntax tree, as long as every construct is closed. */
+pub fn parse(text: &str) -> Result<SyntaxNode, Vec<Diagnostic>> {
+    let tokens = lex(text)?;
+    let mut file = SyntaxNode::new(SyntaxKind::File);
+    let mut diagnostics = vec![];
+    let mut i = 0;
+    while i < tokens.len() {
+        let t = &tokens[i];
+        if t.is_trivia() {
+            file.push(t.clone());
+            i += 1;
+            continue;
+        }
+        if t.kind != SyntaxKind::Word || !TOP_LEVEL.contains(&t.text.as_str()) {
+            diagnostics.push(Diagnostic::error(format!("Unexpected {} outside of a top level construct.", t.text), t.span));
+            break;
+        }
+        let Some(end) = construct_end(&tokens, i) else {
+            let expected = match t.text.as_str() {
+                "script" => String::from("]"),
+                "import" => String::from(";"),
+                keyword => format!("end {};", keyword),
+            };
+            diagnostics.push(Diagnostic::error(format!("Expected \"{}\" to close {}.", expected, t.text), t.span));
+            break;
+        };
+        match parse_construct(&tokens[i..end]) {
+            Ok(construct) => file.children.push(SyntaxElement::Node(construct)),
+            Err(e) => diagnostics.extend(e),
+        }
+        i = end;
+    }
+    if diagnostics.is_empty() {
+        Ok(file)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile::diagnostic::Span;
+    use super::{parse, SyntaxElement, SyntaxKind};
+
+    #[test]
+    fn lossless_test() {
+        let code = "\
+// People and their jobs.
+table Person:
+  id: Number, Unique, /* the key */
+  name: Text,\"Name\" , foundIn[Names],
+end table;\r
+relationship:
+  Person:Job, id == person
+end relationship
+script: [
+  define hire(x) { // Starts a job.
+    if(x > 1) { exit_script(x); } else { set_variable(y, \"a // b\"); }
+  }
+];
+";
+        let file = parse(code).unwrap();
+        assert_eq!(file.text(), code);
+
+        let constructs = file.children.iter().filter_map(|c| match c {
+            SyntaxElement::Node(n) => Some(n),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(constructs.iter().map(|c| c.keyword()).collect::<Vec<_>>(), vec!["table", "relationship", "script"]);
+        let items = constructs[0].children.iter().filter_map(|c| match c {
+            SyntaxElement::Node(n) if n.kind == SyntaxKind::Item => Some(n.significant().len()),
+            _ => None,
+        }).collect::<Vec<_>>();
+        assert_eq!(items, vec![6, 11]);
+
+        let script = constructs[2].child(SyntaxKind::ScriptBody).unwrap();
+        let define = script.child(SyntaxKind::Block).unwrap();
+        assert_eq!(define.keyword(), "define");
+        assert_eq!(define.significant_and_comments()[4].span, Span::new(10, 20, 16));
+
+        let errors = parse("table Person:\n  id: Number,\n").unwrap_err();
+        assert_eq!((errors[0].message.as_str(), errors[0].span), ("Expected \"end table;\" to close table.", Span::new(1, 1, 5)));
+        let errors = parse("script: [ define f() { exit_script(1); ]").unwrap_err();
+        assert_eq!(errors[0].message, "Expected \"}\" to close block.");
+    }
+}
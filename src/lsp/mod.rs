@@ -0,0 +1,88 @@
+use std::io::{BufRead, Write};
+
+use serde_json::Value;
+
+use server::Server;
+
+pub mod server;
+
+/* Reads one message framed by a Content-Length header, or None once the
+ * input ends. */
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, String> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>().map_err(|e| format!("Invalid Content-Length: {}", e))?);
+            }
+        }
+    }
+    let length = length.ok_or("Message has no Content-Length.")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&body).map(Some).map_err(|e| e.to_string())
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> std::io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/* Serves the language server protocol over stdin and stdout, until the
+ * client says to exit. Returns the exit code. */
+pub fn run() -> i32 {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout().lock();
+    let mut server = Server::new();
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            /* The client went away without asking the server to exit. */
+            Ok(None) => return 1,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return 1;
+            }
+        };
+        for reply in server.handle(&message) {
+            if write_message(&mut output, &reply).is_err() {
+                return 1;
+            }
+        }
+        if let Some(code) = server.exit_code() {
+            return code;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::{read_message, write_message};
+
+    #[test]
+    fn framing_test() {
+        let mut buffer = vec![];
+        write_message(&mut buffer, &json!({"jsonrpc": "2.0", "id": 1, "method": "shutdown"})).unwrap();
+        write_message(&mut buffer, &json!({"jsonrpc": "2.0", "method": "exit"})).unwrap();
+        let text = String::from_utf8(buffer.clone()).unwrap();
+        assert!(text.starts_with("Content-Length: 44\r\n\r\n{"));
+
+        let mut input = buffer.as_slice();
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({"jsonrpc": "2.0", "id": 1, "method": "shutdown"})));
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({"jsonrpc": "2.0", "method": "exit"})));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+
+        let mut input = "Content-Type: application/json\r\n\r\n{}".as_bytes();
+        assert!(read_message(&mut input).is_err());
+    }
+}
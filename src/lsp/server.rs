@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::compile::diagnostic::{Diagnostic, Severity, Span};
+use crate::compile::lexer::KEYWORDS;
+use crate::compile::project::{Project, MANIFEST};
+use crate::compile::symbols::{Definition, SymbolTable};
+use crate::file::FmpFile;
+use crate::fm_script_engine::fm_script_engine_instructions::STEP_NAMES;
+
+/* Kinds of completion item, as numbered by the protocol. */
+const FUNCTION: u8 = 3;
+const FIELD: u8 = 5;
+const CLASS: u8 = 7;
+const INTERFACE: u8 = 8;
+const MODULE: u8 = 9;
+const ENUM: u8 = 13;
+const KEYWORD: u8 = 14;
+
+/* A project compiled as far as it could be, from the point of view of one
+ * of its files. */
+struct Analysis {
+    project: Project,
+    file: FmpFile,
+    diagnostics: Vec<Diagnostic>,
+    symbols: SymbolTable,
+    /* The file the analysis was asked for, in `project.sources`. */
+    source: Option<usize>,
+}
+
+impl Analysis {
+    fn new(mut project: Project, path: &Path) -> Self {
+        let (file, diagnostics) = project.analyze();
+        let symbols = SymbolTable::new(&file);
+        let source = project.source_of(path);
+        Self { project, file, diagnostics, symbols, source }
+    }
+
+    fn uri_of(&self, source: usize) -> Option<String> {
+        self.project.sources.get(source).map(|s| path_to_uri(&s.path))
+    }
+
+    fn location(&self, span: Span) -> Option<Value> {
+        Some(json!({ "uri": self.uri_of(span.source)?, "range": range(span) }))
+    }
+}
+
+/* Path of a file:// uri, undoing percent encoding. */
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = vec![];
+    let mut chars = path.bytes();
+    while let Some(b) = chars.next() {
+        if b == b'%' {
+            let hex = [chars.next()?, chars.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    let mut uri = String::from("file://");
+    for b in path.bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", b));
+        }
+    }
+    uri
+}
+
+/* Spans count lines and columns from 1, the protocol from 0. Both count
+ * characters, which only differ from the protocol's UTF-16 units outside
+ * the basic multilingual plane. */
+fn range(span: Span) -> Value {
+    let line = span.line.saturating_sub(1);
+    let column = span.column.saturating_sub(1);
+    json!({
+        "start": { "line": line, "character": column },
+        "end": { "line": line, "character": column + span.len },
+    })
+}
+
+fn to_lsp_diagnostic(d: &Diagnostic) -> Value {
+    json!({
+        "range": range(d.span),
+        "severity": if d.severity == Severity::Error { 1 } else { 2 },
+        "source": "burn",
+        "message": d.message,
+    })
+}
+
+fn completion(label: &str, kind: u8, detail: &str) -> Value {
+    json!({ "label": label, "kind": kind, "detail": detail })
+}
+
+/* What's being completed, from the text before the cursor. */
+enum Context {
+    /* After "Occurrence::" */
+    Field(String),
+    /* Inside "script: [" or "script export: [" */
+    Script,
+    TopLevel,
+}
+
+fn context(before: &str) -> Context {
+    let word = before.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+    if let Some(rest) = word.strip_suffix("::") {
+        let occurrence = &rest[rest.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_').len()..];
+        return Context::Field(occurrence.to_string());
+    }
+    let start = ["script:", "script export:"].iter().filter_map(|s| before.rfind(s)).max();
+    match start {
+        Some(i) if before[i..].matches('[').count() > before[i..].matches(']').count() => Context::Script,
+        _ => Context::TopLevel,
+    }
+}
+
+/* A language server for Burn. Open documents are compiled with the rest of
+ * their project, found through the closest burn.toml above them, or on their
+ * own when there isn't one. Documents are compiled again for every request,
+ * keeping nothing between them but the text of open documents. */
+pub struct Server {
+    documents: HashMap<PathBuf, String>,
+    shutdown: bool,
+    exit_code: Option<i32>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self {
+            documents: HashMap::new(),
+            shutdown: false,
+            exit_code: None,
+        }
+    }
+
+    /* Set once the client has asked the server to exit. */
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    fn analyze(&self, path: &Path) -> Analysis {
+        let manifest = path.ancestors().skip(1).map(|dir| dir.join(MANIFEST)).find(|m| m.is_file());
+        if let Some(project) = manifest.and_then(|m| Project::from_manifest(&m).ok()) {
+            let analysis = Analysis::new(project.with_documents(self.documents.clone()), path);
+            if analysis.source.is_some() {
+                return analysis;
+            }
+        }
+        let project = Project::from_files(&[path.to_string_lossy().to_string()]);
+        Analysis::new(project.with_documents(self.documents.clone()), path)
+    }
+
+    /* Diagnostics for every file of the project `path` is in, so files
+     * without mistakes are cleared. */
+    fn publish_diagnostics(&self, path: &Path) -> Vec<Value> {
+        let analysis = self.analyze(path);
+        (0..analysis.project.sources.len()).map(|source| {
+            let diagnostics = analysis.diagnostics.iter()
+                .filter(|d| d.span.source == source)
+                .map(to_lsp_diagnostic)
+                .collect::<Vec<_>>();
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/publishDiagnostics",
+                "params": { "uri": analysis.uri_of(source), "diagnostics": diagnostics },
+            })
+        }).collect()
+    }
+
+    /* Handles one message, returning the response to a request and any
+     * notifications for the client. */
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let path = params["textDocument"]["uri"].as_str().and_then(uri_to_path);
+        let result = match (method, path) {
+            ("initialize", _) => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 1, "save": true },
+                    "completionProvider": { "triggerCharacters": [":"] },
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "referencesProvider": true,
+                },
+                "serverInfo": { "name": "burn_fm" },
+            })),
+            ("shutdown", _) => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            },
+            ("exit", _) => {
+                self.exit_code = Some(if self.shutdown { 0 } else { 1 });
+                return vec![];
+            },
+            ("textDocument/didOpen", Some(path)) => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(path.clone(), text.to_string());
+                return self.publish_diagnostics(&path);
+            },
+            /* Documents are synced in full, the last change holds all of the text. */
+            ("textDocument/didChange", Some(path)) => {
+                if let Some(text) = params["contentChanges"].as_array().and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                    self.documents.insert(path.clone(), text.to_string());
+                }
+                return self.publish_diagnostics(&path);
+            },
+            ("textDocument/didSave", Some(path)) => return self.publish_diagnostics(&path),
+            ("textDocument/didClose", Some(path)) => {
+                self.documents.remove(&path);
+                return self.publish_diagnostics(&path);
+            },
+            ("textDocument/completion", Some(path)) => Ok(self.completion(&path, &params["position"])),
+            ("textDocument/definition", Some(path)) => Ok(self.definition(&path, &params["position"])),
+            ("textDocument/hover", Some(path)) => Ok(self.hover(&path, &params["position"])),
+            ("textDocument/references", Some(path)) => {
+                let declarations = params["context"]["includeDeclaration"].as_bool().unwrap_or(false);
+                Ok(self.references(&path, &params["position"], declarations))
+            },
+            _ => Err(json!({ "code": -32601, "message": format!("Unknown method {}.", method) })),
+        };
+        /* Notifications have no id and get no response. */
+        let Some(id) = message.get("id") else {
+            return vec![];
+        };
+        match result {
+            Ok(result) => vec![json!({ "jsonrpc": "2.0", "id": id, "result": result })],
+            Err(error) => vec![json!({ "jsonrpc": "2.0", "id": id, "error": error })],
+        }
+    }
+
+    /* Definitions of the symbol at a protocol position. */
+    fn definitions_at<'a>(analysis: &'a Analysis, position: &Value) -> Vec<&'a Definition> {
+        let (Some(source), Some(line), Some(column)) =
+            (analysis.source, position["line"].as_u64(), position["character"].as_u64()) else {
+            return vec![];
+        };
+        match analysis.symbols.symbol_at(source, line as usize + 1, column as usize + 1) {
+            Some(symbol) => analysis.symbols.definitions_of(symbol).collect(),
+            None => vec![],
+        }
+    }
+
+    fn definition(&self, path: &Path, position: &Value) -> Value {
+        let analysis = self.analyze(path);
+        Self::definitions_at(&analysis, position).iter()
+            .filter_map(|d| analysis.location(d.span))
+            .collect()
+    }
+
+    fn hover(&self, path: &Path, position: &Value) -> Value {
+        let analysis = self.analyze(path);
+        let Some(definition) = Self::definitions_at(&analysis, position).first().copied() else {
+            return Value::Null;
+        };
+        let mut text = format!("```burn\n{}\n```", definition.detail);
+        if !definition.description.is_empty() {
+            text.push_str("\n\n");
+            text.push_str(&definition.description);
+        }
+        json!({ "contents": { "kind": "markdown", "value": text } })
+    }
+
+    fn references(&self, path: &Path, position: &Value, declarations: bool) -> Value {
+        let analysis = self.analyze(path);
+        let (Some(source), Some(line), Some(column)) =
+            (analysis.source, position["line"].as_u64(), position["character"].as_u64()) else {
+            return json!([]);
+        };
+        let Some(symbol) = analysis.symbols.symbol_at(source, line as usize + 1, column as usize + 1) else {
+            return json!([]);
+        };
+        let definitions = analysis.symbols.definitions_of(symbol)
+            .filter(|_| declarations)
+            .map(|d| d.span);
+        definitions.chain(analysis.symbols.references_to(symbol))
+            .filter_map(|span| analysis.location(span))
+            .collect()
+    }
+
+    fn completion(&self, path: &Path, position: &Value) -> Value {
+        let analysis = self.analyze(path);
+        let file = &analysis.file;
+        let text = analysis.source.map(|s| analysis.project.sources[s].text.as_str()).unwrap_or("");
+        let line = position["line"].as_u64().unwrap_or(0) as usize;
+        let column = position["character"].as_u64().unwrap_or(0) as usize;
+        /* Everything before the cursor, so blocks opened on earlier lines count. */
+        let before = text.split_inclusive('\n').take(line).collect::<String>()
+            + &text.lines().nth(line).unwrap_or("").chars().take(column).collect::<String>();
+
+        let mut items = vec![];
+        let occurrences = file.table_occurrences.values()
+            .map(|to| completion(&to.table_occurence_name, INTERFACE, &format!("table_occurence of {}", to.table_actual_name)));
+        let layouts = file.layouts.values().map(|l| completion(&l.layout_name, MODULE, "layout"));
+        let scripts = file.scripts.values().map(|s| completion(&s.script_name, FUNCTION, "script"));
+        match context(&before) {
+            Context::Field(occurrence) => {
+                let table = file.table_occurrences.values()
+                    .find(|to| to.table_occurence_name == occurrence)
+                    .and_then(|to| file.tables.values().find(|t| t.table_name == to.table_actual_name));
+                if let Some(table) = table {
+                    items.extend(table.fields.values().map(|f| completion(&f.field_name, FIELD, &f.data_type)));
+                }
+            },
+            Context::Script => {
+                items.extend(STEP_NAMES.iter().map(|(name, _)| completion(name, FUNCTION, "script step")));
+                items.extend(scripts.chain(layouts).chain(occurrences));
+            },
+            Context::TopLevel => {
+                items.extend(KEYWORDS.iter().map(|k| completion(k, KEYWORD, "keyword")));
+                items.extend(file.tables.values().map(|t| completion(&t.table_name, CLASS, "table")));
+                items.extend(file.value_lists.values().map(|l| completion(&l.list_name, ENUM, "value_list")));
+                items.extend(scripts.chain(layouts).chain(occurrences));
+            },
+        }
+        Value::Array(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use super::{context, path_to_uri, uri_to_path, Context, Server};
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    fn at(uri: &str, line: u64, character: u64) -> Value {
+        json!({ "textDocument": { "uri": uri }, "position": { "line": line, "character": character } })
+    }
+
+    fn labels(response: &[Value]) -> Vec<String> {
+        response[0]["result"].as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn uri_test() {
+        let uri = "file:///tmp/my%20app/app.burn";
+        assert_eq!(uri_to_path(uri).unwrap().to_str(), Some("/tmp/my app/app.burn"));
+        assert_eq!(path_to_uri(&uri_to_path(uri).unwrap()), uri);
+        assert_eq!(uri_to_path("untitled:Untitled-1"), None);
+    }
+
+    #[test]
+    fn context_test() {
+        assert!(matches!(context("script: [\n  define a() {\n    "), Context::Script));
+        assert!(matches!(context("script export: [\n  define a() {\n    "), Context::Script));
+        assert!(matches!(context("script export: [\n]\ntab"), Context::TopLevel));
+        assert!(matches!(context("script export: [\n]\nscript: [\n  "), Context::Script));
+        assert!(matches!(context("    set_field(People::na"), Context::Field(o) if o == "People"));
+    }
+
+    #[test]
+    fn server_test() {
+        let dir = std::env::temp_dir().join(format!("burn_lsp_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("burn.toml"), "[project]\nname = \"app\"\nsources = [\"*.burn\"]\n").unwrap();
+        std::fs::write(dir.join("person.burn"), "\
+table Person:
+  name: Text, \"Full name\",
+  photo: Container,
+end table;
+table_occurence People:
+  table: Person,
+end table_occurence;
+").unwrap();
+        std::fs::write(dir.join("app.burn"), "").unwrap();
+        let person = path_to_uri(&dir.join("person.burn"));
+        let app = path_to_uri(&dir.join("app.burn"));
+        let mut server = Server::new();
+
+        let response = server.handle(&request(1, "initialize", json!({ "capabilities": {} })));
+        assert_eq!(response[0]["result"]["capabilities"]["hoverProvider"], json!(true));
+        assert!(server.handle(&notification("initialized", json!({}))).is_empty());
+
+        /* The open document is compiled with the rest of the project, from the text sent. */
+        let published = server.handle(&notification("textDocument/didOpen", json!({ "textDocument": {
+            "uri": app, "languageId": "burn", "version": 1,
+            "text": "script export: [\n  define rename(n) {\n    set_field(People::nickname, n);\n  }\n]\n",
+        }})));
+        let diagnostics = published.iter()
+            .map(|p| (p["params"]["uri"].as_str().unwrap(), p["params"]["diagnostics"].clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(diagnostics.len(), 2);
+        let (_, errors) = diagnostics.iter().find(|(uri, _)| *uri == app).unwrap();
+        assert_eq!(errors, &json!([{
+            "range": { "start": { "line": 2, "character": 4 }, "end": { "line": 2, "character": 13 } },
+            "severity": 1,
+            "source": "burn",
+            "message": "Table Person has no field named nickname.",
+        }]));
+
+        let text = "script export: [\n  define rename(n) {\n    set_field(People::name, n);\n    go_to_layout(\"People\");\n  }\n]\n";
+        let published = server.handle(&notification("textDocument/didChange", json!({
+            "textDocument": { "uri": app, "version": 2 },
+            "contentChanges": [{ "text": text }],
+        })));
+        assert!(published.iter().all(|p| p["params"]["diagnostics"] == json!([])));
+
+        let response = server.handle(&request(2, "textDocument/completion", at(&app, 2, 22)));
+        assert_eq!(labels(&response), vec!["name", "photo"]);
+        let response = server.handle(&request(3, "textDocument/completion", at(&app, 3, 4)));
+        assert!(labels(&response).iter().any(|l| l == "go_to_layout"));
+        assert!(labels(&response).iter().any(|l| l == "rename"));
+        assert!(!labels(&response).iter().any(|l| l == "table"));
+        let response = server.handle(&request(4, "textDocument/completion", at(&person, 7, 0)));
+        assert!(labels(&response).iter().any(|l| l == "table_occurence"));
+        assert!(labels(&response).iter().any(|l| l == "Person"));
+
+        let response = server.handle(&request(5, "textDocument/definition", at(&app, 2, 24)));
+        assert_eq!(response[0]["result"], json!([{ "uri": person,
+            "range": { "start": { "line": 1, "character": 2 }, "end": { "line": 1, "character": 6 } } }]));
+
+        let response = server.handle(&request(6, "textDocument/hover", at(&app, 2, 24)));
+        assert_eq!(response[0]["result"]["contents"]["value"], "```burn\nPerson::name: Text\n```\n\nFull name");
+
+        let mut params = at(&person, 1, 3);
+        params["context"] = json!({ "includeDeclaration": true });
+        let response = server.handle(&request(7, "textDocument/references", params));
+        assert_eq!(response[0]["result"], json!([
+            { "uri": person, "range": { "start": { "line": 1, "character": 2 }, "end": { "line": 1, "character": 6 } } },
+            { "uri": app, "range": { "start": { "line": 2, "character": 22 }, "end": { "line": 2, "character": 26 } } },
+        ]));
+
+        let response = server.handle(&request(8, "workspace/symbol", json!({ "query": "" })));
+        assert_eq!(response[0]["error"]["code"], json!(-32601));
+
+        assert_eq!(server.handle(&request(9, "shutdown", Value::Null))[0]["result"], Value::Null);
+        server.handle(&notification("exit", Value::Null));
+        assert_eq!(server.exit_code(), Some(0));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
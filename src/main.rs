@@ -1,88 +1,275 @@
 #![allow(unused)]
-use std::{fs::{write, File}, io::Read, path::Path};
+use std::{fs::{create_dir_all, write, File}, io::Read, path::{Path, PathBuf}};
 use clap::Parser;
-use compile::{assembler::Assembler, compiler::compile_burn};
-use decompile::decompiler::{decompile_fmp12_file, decompile_fmp12_file_with_header};
+use compile::{assembler::Assembler, diagnostic::{Diagnostic, Severity}, project::Project};
+use decompile::{components::Components, decompiler::{decompile_fmp12_file_with_header, Decompiler}};
 use file::FmpFile;
 
 mod cli;
 mod component;
 mod compile;
+mod conformance;
 mod decompile;
 mod file;
 mod burn_script;
+mod calc_bytecode;
 mod metadata_constants;
 mod chunk;
 mod encoding_util;
 mod fm_script_engine;
 mod testing;
 mod dbcharconv;
+mod lsp;
+mod formatter;
+
+/* Where compiled output goes. Without a path it's NAME.EXT in the current
+ * directory, a directory gets NAME.EXT inside it, and any other path has
+ * its extension replaced. */
+fn output_path(output: Option<&str>, name: &str, extension: &str) -> PathBuf {
+    match output {
+        None => PathBuf::from(format!("{}.{}", name, extension)),
+        Some(o) if o.ends_with('/') || Path::new(o).is_dir() => Path::new(o).join(format!("{}.{}", name, extension)),
+        Some(o) => Path::new(o).with_extension(extension),
+    }
+}
+
+/* Whether two paths name the same existing file. */
+fn same_file(a: &Path, b: &Path) -> bool {
+    matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+}
+
+/* Writes `contents` to `path`, creating its directory if needed, or reports why it couldn't and exits. */
+fn write_or_exit(path: &Path, contents: impl AsRef<[u8]>) {
+    let written = match path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        Some(dir) => create_dir_all(dir).and_then(|_| write(path, contents)),
+        None => write(path, contents),
+    };
+    if let Err(e) = written {
+        eprintln!("error: Unable to write {}. {}", path.display(), e);
+        std::process::exit(1);
+    }
+}
+
+/* Compiles a project, or reports what's wrong with it and exits. */
+fn compile_or_exit(project: &mut Project, format: cli::ErrorFormat) -> FmpFile {
+    match project.compile() {
+        Ok((file, warnings)) => {
+            report(&warnings, project, format);
+            file
+        },
+        Err(diagnostics) => {
+            report(&diagnostics, project, format);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn report(diagnostics: &[Diagnostic], project: &Project, format: cli::ErrorFormat) {
+    for d in diagnostics {
+        match format {
+            cli::ErrorFormat::Human => eprintln!("{}", d.render(project.text_of(d))),
+            cli::ErrorFormat::Json => eprintln!("{}", d.to_json()),
+        }
+    }
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    if format == cli::ErrorFormat::Human && errors > 0 {
+        eprintln!("error: could not compile due to {} previous error(s)", errors);
+    }
+}
+
+/* The components named with --components, or all of them. */
+fn selected_components(selection: Option<&[cli::Component]>) -> Components {
+    let Some(selection) = selection else {
+        return Components::ALL;
+    };
+    let mut components = Components::NONE;
+    for component in selection {
+        components |= match component {
+            cli::Component::Tables => Components::TABLES,
+            cli::Component::Relationships => Components::RELATIONSHIPS,
+            cli::Component::Layouts => Components::LAYOUTS,
+            cli::Component::Scripts => Components::SCRIPTS,
+            cli::Component::DataSources => Components::DATA_SOURCES,
+            cli::Component::Records => Components::RECORDS,
+            cli::Component::ValueLists => Components::VALUE_LISTS,
+            cli::Component::Security => Components::SECURITY,
+        };
+    }
+    components
+}
+
+/* Burn files given with -c are one project, as is a manifest or a directory holding one. */
+fn load_project(paths: &[String]) -> Project {
+    match paths {
+        [path] if path.ends_with(".toml") || Path::new(path).is_dir() => {
+            Project::from_manifest(Path::new(path)).unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            })
+        },
+        _ => Project::from_files(paths),
+    }
+}
 
 fn main() {
 
     let args = cli::CLI::parse();
+    match &args.command {
+        Some(cli::Command::Lsp) => std::process::exit(lsp::run()),
+        Some(cli::Command::Fmt { check, paths }) => std::process::exit(formatter::run(*check, paths)),
+        None => {},
+    }
 
     let mut file = FmpFile::new();
 
-    if args.test.is_some() {
-        for f in args.test.unwrap() {
-            let input = Path::new(&f);
-            let mut code = File::open(input).expect("Unable to open file.");
-            let mut text = String::new();
-            code.read_to_string(&mut text).expect("Unable to parse file to string");
-            file.tests.append(&mut compile_burn(&text).tests);
-        }
+    if let Some(tests) = &args.test {
+        file.tests = compile_or_exit(&mut Project::from_files(tests), args.error_format).tests;
     }
 
-    if args.op.compile.is_some() {
-        for f in args.op.compile.unwrap() {
-            let input = Path::new(&f);
-            let mut code = File::open(input).expect("Unable to open file.");
-            let mut text = String::new();
-            code.read_to_string(&mut text).expect("Unable to parse file to string");
-            let tmp = compile::compiler::compile_burn(&text);
-            file.name = input.file_name().unwrap()
-                .to_str().unwrap().to_string()
-                .strip_suffix(".burn").unwrap().to_string();
+    if let Some(paths) = &args.op.compile {
+        let mut project = load_project(paths);
+        let tests = std::mem::take(&mut file.tests);
+        file = compile_or_exit(&mut project, args.error_format);
+        file.tests.splice(0..0, tests);
+        let output = args.output.as_deref().or(project.output.as_deref());
 
-            file.tables.extend(tmp.tables);
-            file.relationships.extend(tmp.relationships);
-            file.value_lists.extend(tmp.value_lists);
-            file.table_occurrences.extend(tmp.table_occurrences);
-            file.scripts.extend(tmp.scripts);
-            file.layouts.extend(tmp.layouts);
-            file.tests.extend(tmp.tests);
-            let json = serde_json::to_string_pretty(&file).expect("Unable to generate json file");
-            write("inspect_output/test_compile", json).expect("Unable to write to file.");
-            if args.no_testing == false && !file.tests.is_empty() {
-                let mut env = testing::test::TestEnvironment::new(&file);
-                env.generate_test_environment();
-                env.run_tests_with_cleanup();
+        /* Data sources were made relative to the project when compiling. */
+        compile::seed::load_seed_sources(&mut file, Path::new("")).expect("Unable to load data.");
+        compile::seed::seed_records(&mut file).expect("Unable to seed records.");
+        if args.emit != cli::Emit::Fmp12 {
+            let path = output_path(output, &file.name, "json");
+            match serde_json::to_string_pretty(&file) {
+                Ok(json) => write_or_exit(&path, json),
+                Err(e) => {
+                    eprintln!("error: Unable to generate JSON for {}. {}", path.display(), e);
+                    std::process::exit(1);
+                },
+            }
+        }
+        if args.no_testing == false && !file.tests.is_empty() {
+            let mut env = testing::test::TestEnvironment::new(&file);
+            env.generate_test_environment();
+            env.run_tests_with_cleanup();
+        }
+        if let Some(target) = &args.patch {
+            let original = std::fs::read(target).unwrap_or_else(|e| {
+                eprintln!("error: Unable to read {}. {}", target, e);
+                std::process::exit(1);
+            });
+            let mut existing = decompile::decompiler::Decompiler::new(Path::new(target)).run_on(&original);
+            let patched = compile::patch::merge_schema(&mut existing, &file, args.prune)
+                .and_then(|_| compile::patch::patch_fmp12(&original, &existing));
+            let patched = match patched {
+                Ok(patched) => patched,
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                },
+            };
+            /* Without another output path FILE is patched in place. Patched files haven't
+             * been opened in FileMaker yet, so the original is kept beside it. */
+            let path = match output {
+                Some(output) => output_path(Some(output), &file.name, "fmp12"),
+                None => PathBuf::from(target),
+            };
+            if same_file(&path, Path::new(target)) {
+                let backup = PathBuf::from(format!("{}.bak", target));
+                write_or_exit(&backup, &original);
+                let staged = PathBuf::from(format!("{}.patching", target));
+                write_or_exit(&staged, patched.buffer);
+                if let Err(e) = std::fs::rename(&staged, &path) {
+                    eprintln!("error: Unable to write {}. {}", path.display(), e);
+                    std::process::exit(1);
+                }
+                println!("Patched {} in place, rewrote {} sector(s) and added {}, the original is in {}", target,
+                    patched.rewritten.len(), patched.added.len(), backup.display());
+            } else {
+                write_or_exit(&path, patched.buffer);
+                println!("Patched {} into {}, rewrote {} sector(s) and added {}", target, path.display(),
+                    patched.rewritten.len(), patched.added.len());
             }
+            eprintln!("warning: patched files haven't been checked in FileMaker yet, keep the original.");
+        } else if args.emit != cli::Emit::Json {
             let mut assembler = Assembler::new(&file);
-            assembler.assemble_fmp12(&file);
-            assembler.emit_assembly(&file.name);
-            // write(format!("{}.fmp12", file.name), assembler.buffer).expect("unable to write to file.");
+            if let Some(creator) = &args.creator {
+                assembler = assembler.with_creator(creator, creator);
+            }
+            if let Some(admin) = &args.admin_account {
+                assembler = assembler.with_admin_account(admin);
+            }
+            if let Err(e) = assembler.assemble_fmp12(&file) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            let path = output_path(output, &file.name, "fmp12");
+            if let Err(e) = assembler.emit_assembly(&path) {
+                eprintln!("error: Unable to write {}. {}", path.display(), e);
+                std::process::exit(1);
+            }
+            eprintln!("warning: {} has a full access account {} with an empty password, set one in FileMaker before sharing it.",
+                path.display(), assembler.admin_account());
         }
     } else if args.op.decompile.is_some() {
+        let mut failed = false;
         for f in args.op.decompile.unwrap() {
             let path = f;
             let input = Path::new(&path);
+            if args.check_round_trip {
+                let buffer = std::fs::read(input).expect("Unable to read file.");
+                let report = conformance::check_round_trip(input, &buffer);
+                report.print(&path);
+                failed |= !report.passed();
+                continue;
+            }
+            let components = selected_components(args.components.as_deref());
             let tmp: FmpFile;
             if args.print_header == true {
-                tmp = decompile_fmp12_file_with_header(&input);
+                tmp = decompile_fmp12_file_with_header(&input, components);
             } else {
-                tmp = decompile_fmp12_file(&input);
+                tmp = Decompiler::new(input).with(components).run();
             }
 
-            let json = serde_json::to_string_pretty(&tmp).expect("Unable to generate json file");
-            write("inspect_output/test_decompile", json).expect("Unable to write to file.");
+            let name = input.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+            if let Some(output) = &args.emit_json {
+                let path = output_path(Some(output), &name, "json");
+                match serde_json::to_string_pretty(&tmp) {
+                    Ok(json) => write_or_exit(&path, json),
+                    Err(e) => {
+                        eprintln!("error: Unable to generate JSON for {}. {}", path.display(), e);
+                        std::process::exit(1);
+                    },
+                }
+                println!("Wrote {}", path.display());
+            }
+            if let Some(output) = &args.emit_burn {
+                let (burn, warnings) = decompile::burn::generate_burn(&tmp);
+                for warning in warnings {
+                    eprintln!("warning: {}", warning);
+                }
+                let path = output_path(Some(output), &name, "burn");
+                write_or_exit(&path, burn);
+                println!("Wrote {}", path.display());
+            }
+            if let Some(dir) = &args.export_containers {
+                let n = match decompile::container::export_containers(&tmp, Path::new(dir)) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        eprintln!("error: {}", e);
+                        std::process::exit(1);
+                    },
+                };
+                println!("Exported {} container(s) to {}", n, dir);
+                if n > 0 {
+                    eprintln!("warning: containers are read by a layout that hasn't been checked against FileMaker files, check the exported files.");
+                }
+            }
             file.tables.extend(tmp.tables);
             file.relationships.extend(tmp.relationships);
             file.value_lists.extend(tmp.value_lists);
             file.table_occurrences.extend(tmp.table_occurrences);
             file.scripts.extend(tmp.scripts);
             file.layouts.extend(tmp.layouts);
+            file.records.extend(tmp.records);
             file.tests.extend(tmp.tests);
             if args.no_testing == false && !file.tests.is_empty() {
                 let mut env = testing::test::TestEnvironment::new(&file);
@@ -91,6 +278,9 @@ fn main() {
             }
 
         }
+        if failed {
+            std::process::exit(1);
+        }
     }
 
 }
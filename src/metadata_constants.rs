@@ -2,6 +2,10 @@
 
 pub const FIELD_TYPE : u16 = 2;
 pub const COMPONENT_DESC : u16 = 3;
+pub const FIELD_SERIAL_NEXT : u16 = 12;
+pub const FIELD_SERIAL_INCREMENT : u16 = 13;
+pub const FIELD_VALUE_LIST : u16 = 10;
+pub const FIELD_VALIDATION_MESSAGE : u16 = 11;
 pub const COMPONENT_NAME : u16 = 16;
 pub const CREATOR_ACCOUNT_NAME : u16 = 129;
 pub const CREATOR_USER_NAME : u16 = 130;
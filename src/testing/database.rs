@@ -49,12 +49,48 @@ impl Table {
 }
 
 #[derive(Clone, Debug)]
-pub struct Relationship {
+pub struct JoinCriterion {
     join_by: RelationComparison,
     field1: usize,
     field2: usize,
 }
 
+impl JoinCriterion {
+    /* Compares a value of field1 with a value of field2. */
+    fn matches(&self, lhs: &str, rhs: &str) -> bool {
+        match self.join_by {
+            RelationComparison::Equal => lhs == rhs,
+            RelationComparison::NotEqual => lhs != rhs,
+            RelationComparison::Less => lhs < rhs,
+            RelationComparison::LessEqual => lhs <= rhs,
+            RelationComparison::Greater => lhs > rhs,
+            RelationComparison::GreaterEqual => lhs >= rhs,
+            RelationComparison::Cartesian => true,
+        }
+    }
+
+    /* The same criterion seen from the other side of the relationship. */
+    fn reversed(&self) -> Self {
+        Self {
+            join_by: match self.join_by {
+                RelationComparison::Less => RelationComparison::Greater,
+                RelationComparison::LessEqual => RelationComparison::GreaterEqual,
+                RelationComparison::Greater => RelationComparison::Less,
+                RelationComparison::GreaterEqual => RelationComparison::LessEqual,
+                ref other => other.clone(),
+            },
+            field1: self.field2,
+            field2: self.field1,
+        }
+    }
+}
+
+/* Records are related when every criterion matches. */
+#[derive(Clone, Debug)]
+pub struct Relationship {
+    criteria: Vec<JoinCriterion>,
+}
+
 #[derive(Clone, Debug)]
 pub struct RelatedRecordSet {
     relationship: Relationship,
@@ -160,13 +196,21 @@ impl Database {
 
         /* Generate Relationships */ 
         for (_, rel) in &file.relationships {
+            if rel.criteria.is_empty() {
+                continue;
+            }
+            let criteria = rel.criteria.iter()
+                .map(|c| JoinCriterion {
+                    field1: c.field1 as usize,
+                    field2: c.field2 as usize,
+                    join_by: c.comparison.clone(),
+                })
+                .collect::<Vec<_>>();
             self.table_occurrences[rel.table1 as usize].related_records.push(
                 RelatedRecordSet {
                     occurrence: rel.table2 as usize,
                     relationship: Relationship {
-                        field1: rel.field1 as usize,
-                        field2: rel.field2 as usize,
-                        join_by: rel.comparison.clone(),
+                        criteria: criteria.clone(),
                     },
                     records: vec![],
                 }
@@ -175,9 +219,7 @@ impl Database {
                 RelatedRecordSet {
                     occurrence: rel.table1 as usize,
                     relationship: Relationship {
-                        field1: rel.field2 as usize,
-                        field2: rel.field1 as usize,
-                        join_by: rel.comparison.clone(),
+                        criteria: criteria.iter().map(JoinCriterion::reversed).collect(),
                     },
                     records: vec![],
                 }
@@ -393,6 +435,7 @@ impl Database {
         }
 
         let path_uw = path.clone().unwrap();
+        /* Records of the current occurrence's table that the next step joins from. */
         let mut current_set = vec![];
 
         for (current, next) in path_uw.windows(2).map(|x| (x[0], x[1])) {
@@ -413,46 +456,23 @@ impl Database {
             }
 
             if current_set.is_empty() {
-                let tmp = &self.get_current_table()
-                    .fields[relation[0].relationship.field1]
-                    .records[current_record.unwrap()];
-                current_set.push((current_record.unwrap(), tmp.to_string()));
+                current_set.push(current_record.unwrap());
             }
 
-            let next_occurrence = self.get_occurrence(next);
-            let next_table = &self.tables[next_occurrence.table_ptr as usize];
-
-            let rhs_list = &next_table
-                .fields[relation[0].relationship.field2]
-                .records;
-
-            // println!("list: {:?}", rhs_list);
-            let mut related_set = vec![];
-            for lhs in &mut current_set {
-                for rhs in rhs_list.iter().enumerate() {
-                    let related = match relation[0].relationship.join_by {
-                        RelationComparison::Equal => *lhs.1 == *rhs.1,
-                        RelationComparison::NotEqual => *lhs.1 != *rhs.1,
-                        RelationComparison::Less => *lhs.1 <= *rhs.1.to_string(),
-                        RelationComparison::LessEqual => *lhs.1 <= *rhs.1.to_string(),
-                        RelationComparison::Greater => *lhs.1 >= *rhs.1.to_string(),
-                        RelationComparison::GreaterEqual => *lhs.1 >= *rhs.1.to_string(),
-                        RelationComparison::Cartesian => true,
-                        _ => false
-                    };
-
-                    // println!("relation: {}: {} :: {:?} :: {}: {}", current, lhs.1,
-                    // relation[0].relationship.join_by, next, rhs.1);
-                    if related {
-                        related_set.push((rhs.0, rhs.1.to_string()));
-                    }
-                }
-            }
+            let lhs_table = &self.tables[current_occurrence.table_ptr as usize];
+            let rhs_table = &self.tables[self.get_occurrence(next).table_ptr as usize];
+            let criteria = &relation[0].relationship.criteria;
+            let rhs_records = rhs_table.fields[criteria[0].field2].records.len();
+
+            let related_set = (0..rhs_records)
+                .filter(|rhs| current_set.iter().any(|lhs| criteria.iter().all(|c| {
+                    c.matches(&lhs_table.fields[c.field1].records[*lhs], &rhs_table.fields[c.field2].records[*rhs])
+                })))
+                .collect::<Vec<_>>();
             if related_set.is_empty() {
                 return Err("Cannot access unrelated record.");
             }
-            current_set.clear();
-            current_set.clone_from(&related_set);
+            current_set = related_set;
         }
 
         let n = path_uw.last().unwrap();
@@ -462,7 +482,7 @@ impl Database {
             .iter()
             .filter(|x| x.name == field_target)
             .collect::<Vec<_>>()[0]
-            .records[current_set[0].0].to_string());
+            .records[current_set[0]].to_string());
     }
 
     pub fn get_related_record_field_mut(&mut self, occurrence: &str, field: &str) -> &mut str {
@@ -9,7 +9,7 @@ use color_print::cprintln;
 use crate::component::FMComponentScript;
 use crate::component::FMComponentTest;
 use crate::file;
-use crate::fm_script_engine::fm_script_engine_instructions::Instruction;
+use crate::fm_script_engine::fm_script_engine_instructions::{active_steps, Instruction};
 use crate::fm_script_engine::fm_script_engine_instructions::ScriptStep;
 use crate::testing::calc_eval;
 use crate::testing::database;
@@ -84,10 +84,9 @@ pub struct TestEnvironment<'a> {
     pub instruction_ptr: Vec<(String, usize)>,
     pub variables: Vec<HashMap<String, Variable>>,
     pub current_test: Option<FMComponentTest>, 
-    pub loop_scopes: Vec<usize>,
     pub test_state: TestState,
-    pub punc_stack: Vec<Instruction>,
-    pub branch_taken: bool,
+    /* The If steps being run, and whether one of their branches was taken. */
+    pub branches_taken: Vec<(usize, bool)>,
     mode: Mode,
 
     /* Data storage and behaviour */
@@ -95,6 +94,50 @@ pub struct TestEnvironment<'a> {
     pub layout_mgr: LayoutMgr,
     pub find_criteria: Vec<(String, String)>,
 }
+/* The first running step after `from` that is one of `stops`, skipping over
+ * the blocks nested in between. */
+fn block_marker(steps: &[ScriptStep], active: &[bool], from: usize, stops: &[Instruction]) -> usize {
+    let mut depth = 0;
+    for i in from + 1..steps.len() {
+        if !active[i] {
+            continue;
+        }
+        let opcode = &steps[i].opcode;
+        if depth == 0 && stops.contains(opcode) {
+            return i;
+        }
+        match opcode {
+            Instruction::If | Instruction::Loop => depth += 1,
+            Instruction::EndIf | Instruction::EndLoop if depth > 0 => depth -= 1,
+            _ => {}
+        }
+    }
+    steps.len()
+}
+
+/* The Loop step of the loop `from` is in. */
+fn loop_start(steps: &[ScriptStep], active: &[bool], from: usize) -> Option<usize> {
+    let mut depth = 0;
+    for i in (0..from).rev() {
+        if !active[i] {
+            continue;
+        }
+        match steps[i].opcode {
+            Instruction::EndLoop => depth += 1,
+            Instruction::Loop if depth == 0 => return Some(i),
+            Instruction::Loop => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/* The Loop and End Loop steps around `from`. */
+fn enclosing_loop(steps: &[ScriptStep], active: &[bool], from: usize) -> Option<(usize, usize)> {
+    let start = loop_start(steps, active, from)?;
+    Some((start, block_marker(steps, active, from, &[Instruction::EndLoop])))
+}
+
 impl<'a> TestEnvironment<'a> {
     pub fn new(file: &'a file::FmpFile) -> Self {
         Self {
@@ -102,10 +145,8 @@ impl<'a> TestEnvironment<'a> {
             instruction_ptr: vec![],
             variables: vec![],
             current_test: None,
-            loop_scopes: vec![],
             test_state: TestState::Pass,
-            punc_stack: vec![],
-            branch_taken: false,
+            branches_taken: vec![],
             layout_mgr: LayoutMgr::new(),
             database: Database::new(),
             find_criteria: vec![],
@@ -119,7 +160,15 @@ impl<'a> TestEnvironment<'a> {
 
     pub fn generate_layout_mgr(&mut self) {
         for (id, layout) in &self.file_handle.layouts {
-            self.layout_mgr.add_mapping(layout.layout_name.clone(), layout.table_occurrence);
+            /* Layouts compiled from Burn only name their occurrence. */
+            let occurrence = match layout.table_occurrence {
+                0 => self.file_handle.table_occurrences.iter()
+                    .find(|(_, to)| to.table_occurence_name == layout.table_occurrence_name)
+                    .map(|(id, _)| *id)
+                    .unwrap_or(0),
+                id => id,
+            };
+            self.layout_mgr.add_mapping(layout.layout_name.clone(), occurrence);
         }
     }
 
@@ -199,8 +248,14 @@ impl<'a> TestEnvironment<'a> {
                 return;
         }
 
-        let mut cur_instruction = &script_handle.instructions[ip_handle.1];
+        let steps = &script_handle.instructions;
+        let active = active_steps(steps);
+        let cur_instruction = &script_handle.instructions[ip_handle.1];
         // println!("instr: {:?}", cur_instruction);
+        if !active[ip_handle.1] {
+            self.instruction_ptr[n_stack].1 += 1;
+            return;
+        }
         match &cur_instruction.opcode {
             Instruction::PerformScript => {
                 let script_name = self.eval_calculation(&cur_instruction.switches[0])
@@ -254,12 +309,11 @@ impl<'a> TestEnvironment<'a> {
                             }
                         }
                         if exit && self.database.get_current_occurrence().record_ptr == cache_pos {
-                            while cur_instruction.opcode != Instruction::EndLoop {
-                                cur_instruction = &script_handle.instructions[ip_handle.1];
-                                ip_handle.1 += 1;
+                            if let Some((start, end)) = enclosing_loop(steps, &active, ip_handle.1) {
+                                self.branches_taken.retain(|b| b.0 < start);
+                                self.instruction_ptr[n_stack].1 = end + 1;
+                                return;
                             }
-                            self.instruction_ptr[n_stack].1 = ip_handle.1; 
-                            return;
                         }
                     },
                     Mode::Find => {
@@ -333,106 +387,63 @@ impl<'a> TestEnvironment<'a> {
                 self.instruction_ptr[n_stack].1 += 1;
             },
             Instruction::Loop => {
-                self.loop_scopes.push(ip_handle.1);
-                self.punc_stack.push(Instruction::Loop);
                 self.instruction_ptr[n_stack].1 += 1;
             },
             Instruction::If => {
                 let val = &self.eval_calculation(&cur_instruction.switches[0]);
+                self.branches_taken.push((ip_handle.1, val == "true"));
                 if val == "true" {
                     self.instruction_ptr[n_stack].1 += 1;
-                    self.branch_taken = true;
                 } else {
-                    while self.instruction_ptr[n_stack].1 < script_handle.instructions.len() {
-                        cur_instruction = &script_handle.instructions[self.instruction_ptr[n_stack].1];
-                        match cur_instruction.opcode {
-                            Instruction::EndIf => {
-                                return;
-                            },
-                            Instruction::Else => {
-                                return;
-                            },
-                            Instruction::ElseIf => {
-                                return;
-                            },
-                            _ => {}
-                        }
-                        self.instruction_ptr[n_stack].1 += 1;
-                    }
+                    self.instruction_ptr[n_stack].1 = block_marker(steps, &active, ip_handle.1,
+                        &[Instruction::Else, Instruction::ElseIf, Instruction::EndIf]);
                 }
             },
             Instruction::ElseIf => {
-                if self.branch_taken == true {
-                    while self.instruction_ptr[n_stack].1 < script_handle.instructions.len() {
-                        cur_instruction = &script_handle.instructions[self.instruction_ptr[n_stack].1];
-                        match cur_instruction.opcode {
-                            Instruction::EndIf => {
-                                self.branch_taken = false;
-                                return;
-                            },
-                            _ => {self.instruction_ptr[n_stack].1 += 1;}
-                        }
-                    }
+                if self.branches_taken.last().is_some_and(|b| b.1) {
+                    self.instruction_ptr[n_stack].1 = block_marker(steps, &active, ip_handle.1, &[Instruction::EndIf]);
+                    return;
                 }
                 let val = &self.eval_calculation(&cur_instruction.switches[0]);
                 if val == "true" {
+                    if let Some(branch) = self.branches_taken.last_mut() {
+                        branch.1 = true;
+                    }
                     self.instruction_ptr[n_stack].1 += 1;
-                    self.branch_taken = true;
                 } else {
-                    self.instruction_ptr[n_stack].1 += 1;
-                    while self.instruction_ptr[n_stack].1 < script_handle.instructions.len() {
-                        cur_instruction = &script_handle.instructions[self.instruction_ptr[n_stack].1];
-                        match cur_instruction.opcode {
-                            Instruction::EndIf => {
-                                return;
-                            },
-                            Instruction::Else => {
-                                return;
-                            },
-                            Instruction::ElseIf => {
-                                return;
-                            },
-                            _ => { self.instruction_ptr[n_stack].1 += 1; }
-                        }
-                    }
+                    self.instruction_ptr[n_stack].1 = block_marker(steps, &active, ip_handle.1,
+                        &[Instruction::Else, Instruction::ElseIf, Instruction::EndIf]);
                 }
             }
             Instruction::Else => {
-                if self.branch_taken == true {
-                    while self.instruction_ptr[n_stack].1 < script_handle.instructions.len() {
-                        cur_instruction = &script_handle.instructions[self.instruction_ptr[n_stack].1];
-                        match cur_instruction.opcode {
-                            Instruction::EndIf => {
-                                self.branch_taken = false;
-                                return;
-                            },
-                            _ => {self.instruction_ptr[n_stack].1 += 1;}
-                        }
-                    }
+                if self.branches_taken.last().is_some_and(|b| b.1) {
+                    self.instruction_ptr[n_stack].1 = block_marker(steps, &active, ip_handle.1, &[Instruction::EndIf]);
+                } else {
+                    self.instruction_ptr[n_stack].1 += 1;
                 }
-                self.instruction_ptr[n_stack].1 += 1;
             }
 
             Instruction::EndIf => {
-                self.branch_taken = false;
+                self.branches_taken.pop();
                 self.instruction_ptr[n_stack].1 += 1;
             }
             Instruction::EndLoop => {
-                if *self.punc_stack.last().unwrap_or(&Instruction::EndLoop) != Instruction::Loop {
-                    eprintln!("invalid scope resultion. Please check that loop and if blocks are terminated correctly.");
+                match loop_start(steps, &active, ip_handle.1) {
+                    Some(start) => self.instruction_ptr[n_stack].1 = start + 1,
+                    None => {
+                        eprintln!("invalid scope resultion. Please check that loop and if blocks are terminated correctly.");
+                        self.instruction_ptr[n_stack].1 += 1;
+                    }
                 }
-                self.instruction_ptr[n_stack].1 = self.loop_scopes.last().unwrap() + 1;
             },
             Instruction::ExitLoopIf => {
                 let val : &str = &self.eval_calculation(&cur_instruction.switches[0]);
-                if val == "true" {
-                    while cur_instruction.opcode != Instruction::EndLoop {
-                        cur_instruction = &script_handle.instructions[ip_handle.1];
-                        ip_handle.1 += 1;
-                    }
-                    self.instruction_ptr[n_stack].1 = ip_handle.1; 
-                } else {
-                    self.instruction_ptr[n_stack].1 += 1;
+                match enclosing_loop(steps, &active, ip_handle.1) {
+                    Some((start, end)) if val == "true" => {
+                        self.branches_taken.retain(|b| b.0 < start);
+                        self.instruction_ptr[n_stack].1 = end + 1;
+                    },
+                    _ => self.instruction_ptr[n_stack].1 += 1,
                 }
             }
             Instruction::NewRecordRequest => {
@@ -902,6 +913,7 @@ impl<'a> TestEnvironment<'a> {
 mod tests {
     use std::path::Path;
     use crate::{compile::{self, compiler::compile_burn}, decompile::decompiler::decompile_fmp12_file, file::FmpFile, testing::test::TestState};
+    use crate::fm_script_engine::fm_script_engine_instructions::{active_steps, Instruction, ScriptStep};
     use super::TestEnvironment;
 
     #[test]
@@ -979,6 +991,113 @@ mod tests {
         te.run_tests();
         assert_eq!(te.test_state, TestState::Pass);
     }
+    #[test]
+    pub fn disabled_step_test() {
+        let mut file = FmpFile::new();
+        let code = "
+        test disabledTest:
+            script: [
+                define disabled_test() {
+                    set_variable(x, 1);
+                    assert(x == 2);
+                    assert(x == 1);
+                }
+            ]
+        end test;
+        ";
+
+        let mut test = compile_burn(code);
+        test.tests[0].script.instructions[1].enabled = false;
+        file.tests.append(&mut test.tests);
+        let mut te : TestEnvironment = TestEnvironment::new(&file);
+        te.generate_test_environment();
+        te.run_tests();
+        assert_eq!(te.test_state, TestState::Pass);
+    }
+
+    #[test]
+    pub fn nested_block_test() {
+        let mut file = FmpFile::new();
+        let code = "
+        test nestedTest:
+            script: [
+                define nested_test() {
+                    set_variable(x, 0);
+                    set_variable(y, 0);
+                    loop {
+                        set_variable(x, x + 1);
+                        if(x == 2) {
+                            if(x == 5) {
+                                assert(x == 0);
+                            } else {
+                                set_variable(y, y + 1);
+                            }
+                        } else {
+                            assert(x != 2);
+                        }
+                        if(x == 3) {
+                            loop {
+                                exit_loop_if(x == 3);
+                            }
+                            exit_loop_if(x == 3);
+                        }
+                    }
+                    assert(x == 3);
+                    assert(y == 1);
+                }
+            ]
+        end test;
+        ";
+
+        let mut test = compile_burn(code);
+        file.tests.append(&mut test.tests);
+        let mut te : TestEnvironment = TestEnvironment::new(&file);
+        te.generate_test_environment();
+        te.run_tests();
+        assert_eq!(te.test_state, TestState::Pass);
+    }
+
+    #[test]
+    pub fn disabled_block_test() {
+        let mut file = FmpFile::new();
+        let code = "
+        test disabledBlockTest:
+            script: [
+                define disabled_block_test() {
+                    set_variable(x, 0);
+                    set_variable(y, 0);
+                    loop {
+                        set_variable(x, x + 1);
+                        if(x == 5) {
+                            set_variable(y, 1);
+                        } else {
+                            set_variable(y, y + 1);
+                        }
+                    }
+                    assert(x == 1);
+                    assert(y == 2);
+                }
+            ]
+        end test;
+        ";
+
+        /* Disabling the Loop and the End If leaves both blocks out, so the
+         * steps inside run once, in order. */
+        let mut test = compile_burn(code);
+        let steps = &mut test.tests[0].script.instructions;
+        let at = |steps: &[ScriptStep], opcode: Instruction| steps.iter().position(|s| s.opcode == opcode).unwrap();
+        let lp = at(steps, Instruction::Loop);
+        steps[lp].enabled = false;
+        let end_if = at(steps, Instruction::EndIf);
+        steps[end_if].enabled = false;
+        assert_eq!(active_steps(steps).iter().filter(|a| !**a).count(), 5);
+        file.tests.append(&mut test.tests);
+        let mut te : TestEnvironment = TestEnvironment::new(&file);
+        te.generate_test_environment();
+        te.run_tests();
+        assert_eq!(te.test_state, TestState::Pass);
+    }
+
     #[test]
     pub fn basic_loop_test() {
         let code = "
@@ -1054,5 +1173,63 @@ mod tests {
         assert_eq!(te.database.get_related_record_field("second_table", "add").unwrap(), "\"secret\"");
         assert_eq!(te.test_state, TestState::Pass);
     }
-}
 
+    #[test]
+    pub fn related_record_test() {
+        let code = "
+        table Person:
+          id: Text,
+          kind: Text,
+        end table;
+        table Job:
+          person: Text,
+          kind: Text,
+          title: Text,
+        end table;
+        table_occurence Person:
+          table: Person,
+        end table_occurence;
+        table_occurence Job:
+          table: Job,
+        end table_occurence;
+        relationship:
+          Person:Job,
+          id == person,
+          kind == kind,
+        end relationship;
+        layout Person:
+          table_occurence: Person,
+          id,
+        end layout;
+        layout Job:
+          table_occurence: Job,
+          title,
+        end layout;
+        test RelatedTest:
+          script: [
+            define related_test() {
+              go_to_layout(\"Job\");
+              new_record_request();
+              set_field(Job::person, \"1\");
+              set_field(Job::kind, \"a\");
+              set_field(Job::title, \"first\");
+              new_record_request();
+              set_field(Job::person, \"1\");
+              set_field(Job::kind, \"b\");
+              set_field(Job::title, \"second\");
+              go_to_layout(\"Person\");
+              new_record_request();
+              set_field(Person::id, \"1\");
+              set_field(Person::kind, \"b\");
+            }
+          ],
+        end test;";
+        let file = compile_burn(code);
+        let mut te : TestEnvironment = TestEnvironment::new(&file);
+        te.generate_test_environment();
+        te.run_tests();
+        assert_eq!(te.test_state, TestState::Pass);
+        /* Both criteria have to match, the first one alone would find "first". */
+        assert_eq!(te.database.get_related_record_field("Job", "title").unwrap(), "\"second\"");
+    }
+}